@@ -13,5 +13,6 @@
 // You should have received a copy of the GNU General Public License
 // along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
 fn main() {
-    build_shaders::build().unwrap()
+    build_shaders::build().unwrap();
+    build_shaders::write_case_labels("plugin_ops.glsl").unwrap();
 }
@@ -0,0 +1,104 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// The inputs shared by every way of drawing the layers flat: the layers for the
+// slot on screen, and the settings of the final pass.
+layout(binding = 0) uniform texture2D r_texture;
+layout(binding = 1) uniform sampler r_sampler;
+layout(binding = 2) uniform texture2D g_texture;
+layout(binding = 3) uniform sampler g_sampler;
+layout(binding = 4) uniform texture2D b_texture;
+layout(binding = 5) uniform sampler b_sampler;
+// Must match MAX_PALETTE in src/display.rs.
+#define MAX_PALETTE 16
+
+// Must match DrawConfiguration in src/display.rs.
+layout(binding = 6) uniform readonly DrawConfiguration {
+    uint depth_layer;
+    float stereo_separation;
+    uint color_vision;
+    float brightness;
+    float contrast;
+    float saturation;
+    vec2 canvas_scale;
+    vec2 canvas_center;
+    vec4 border_color;
+    vec4 posterize_levels;
+    vec4 posterize_thresholds;
+    vec4 layer_gain;
+    vec4 layer_bias;
+    vec4 palette[MAX_PALETTE];
+    uint palette_size;
+};
+// What to scale the light by before anything else; see src/exposure.rs.
+layout(binding = 7) readonly buffer Exposure {
+    float exposure;
+};
+
+// Where a point in the window falls on the canvas, which may be fitted into the
+// window with borders or cropped to fill it. Must match Display::window_to_canvas.
+vec2 window_to_canvas(vec2 uv) {
+    return (uv - canvas_center) / canvas_scale + 0.5;
+}
+
+bool on_canvas(vec2 uv) {
+    return all(greaterThanEqual(uv, vec2(0))) && all(lessThanEqual(uv, vec2(1)));
+}
+
+// Rebalanced by each layer's gain and bias; must match LayerBalance in
+// src/display.rs.
+vec3 sample_layers(vec2 uv) {
+    vec3 layers = vec3(
+        texture(sampler2D(r_texture, r_sampler), uv).r,
+        texture(sampler2D(g_texture, g_sampler), uv).r,
+        texture(sampler2D(b_texture, b_sampler), uv).r
+    );
+    return layers * layer_gain.xyz + layer_bias.xyz;
+}
+
+// Each channel with two or more levels cut to that many flat steps, rounding up
+// past its threshold in each step. Must match Posterize in src/display.rs.
+vec3 posterize(vec3 srgb) {
+    vec3 steps = max(posterize_levels.rgb - 1.0, 1.0);
+    vec3 cut = clamp(floor(srgb * steps + 1.0 - posterize_thresholds.rgb), 0.0, steps) / steps;
+    return mix(srgb, cut, step(2.0, posterize_levels.rgb));
+}
+
+// The nearest color in the palette, weighing the channels by how bright they look;
+// without a palette, the color as it is.
+vec3 to_palette(vec3 srgb) {
+    if (palette_size == 0) {
+        return srgb;
+    }
+    const vec3 weight = vec3(0.299, 0.587, 0.114);
+    vec3 nearest = palette[0].rgb;
+    float nearest_distance = dot(weight, (srgb - nearest) * (srgb - nearest));
+    for (uint i = 1; i < min(palette_size, MAX_PALETTE); ++i) {
+        vec3 d = srgb - palette[i].rgb;
+        float distance = dot(weight, d * d);
+        if (distance < nearest_distance) {
+            nearest = palette[i].rgb;
+            nearest_distance = distance;
+        }
+    }
+    return nearest;
+}
+
+// Must match ColorAdjustment in src/display.rs.
+vec3 adjust_color(vec3 srgb) {
+    vec3 c = (srgb + brightness - 0.5) * contrast + 0.5;
+    float gray = dot(c, vec3(0.2126, 0.7152, 0.0722));
+    return clamp(mix(vec3(gray), c, saturation), 0.0, 1.0);
+}
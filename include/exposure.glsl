@@ -0,0 +1,40 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+// The bindings shared by the two halves of auto-exposure: a histogram of the
+// picture's lightness, and the controller that turns it into an exposure. Must
+// match src/exposure.rs.
+#define BIN_COUNT 64
+
+#define EXPOSURE_MANUAL 0u
+#define EXPOSURE_AUTO 1u
+#define EXPOSURE_HOLD 2u
+
+layout(binding = 0) uniform readonly ExposureConfiguration {
+    float dt;
+    float manual_exposure;
+    uint mode;
+    uint lod;
+};
+layout(binding = 1) uniform sampler layer_sampler;
+layout(binding = 2) uniform texture2D r_texture;
+layout(binding = 3) uniform texture2D g_texture;
+layout(binding = 4) uniform texture2D b_texture;
+// Counts of CIELAB lightness, in [0,1], as it would be shown.
+layout(binding = 5) buffer Histogram {
+    uint bins[BIN_COUNT];
+};
+layout(binding = 6) buffer Exposure {
+    float exposure;
+};
@@ -0,0 +1,440 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#define PI 3.141592653589793
+
+// In order to facilitate fixed frame rates, we specify a fixed size instruction stream. If the current
+// invocation is shorter, it will just get padded with nops, which the header lets us skip. The stream and constant pool live in
+// storage buffers, so neither is bound by the (often 16KiB) uniform buffer size limit.
+#define INSTRUCTION_COUNT 128
+
+// The stream opens with (version, instructions used, constants used, unused); see
+// ENCODING_VERSION in src/tree.rs. Only the instructions used are run.
+#define ENCODING_VERSION 1
+#define HEADER_SIZE 4
+
+// Values are kept in a fixed array, deep enough for any tree that fits in
+// INSTRUCTION_COUNT; see STACK_DEPTH in src/tree.rs.
+#define STACK_DEPTH (INSTRUCTION_COUNT / 2 + 1)
+
+// Likewise for the positions saved by ops that remap the plane under their child;
+// see REMAP_DEPTH in src/tree.rs. A remap's child ends in RESTORE_OPCODE.
+#define REMAP_DEPTH 8
+#define RESTORE_OPCODE 255
+
+// The includer may pick a different workgroup size; see src/workgroup.rs.
+#ifndef WORKGROUP_X
+#define WORKGROUP_X 8
+#endif
+#ifndef WORKGROUP_Y
+#define WORKGROUP_Y 8
+#endif
+layout(local_size_x = WORKGROUP_X, local_size_y = WORKGROUP_Y, local_size_z = 1) in;
+layout(binding = 0) uniform readonly Configuration {
+    ivec2 texture_size;
+    ivec2 texture_offsets;
+    vec2 mouse_position; // [0,1] across the texture
+    float time;
+    float aspect_ratio; // of the display, width over height
+    vec2 view_center;
+    float view_scale;
+    // Zero for a flat picture; otherwise the texture is a volume, cut into this
+    // many square slices stacked from top to bottom.
+    uint volume_slices;
+    // Each invocation computes every pass_stride-th texel on each side, from
+    // pass_offset, and fills a square of block_size texels on a side with it. A
+    // preview or the first of a progressive pass leaves no gaps, later passes
+    // fill them in.
+    uint block_size;
+    uint pass_stride;
+    uvec2 pass_offset;
+    // Nonzero when a quick look is all that is wanted. Ops that iterate cut
+    // their iteration counts, as warp does its noise octaves; the CPU evaluator
+    // sees the same through EvalContext::draft.
+    uint draft;
+};
+// The includer picks the storage format of the output texture.
+layout(binding = 1, RESULT_FORMAT) uniform writeonly image2D result_texture;
+layout(binding = 2) readonly buffer InstructionStream {
+    uint instrs[];
+};
+// Each constant is stored as (base, rate, low, high) and animated here.
+layout(binding = 3) readonly buffer ConstantPool {
+    vec4 constant_pool[];
+};
+// The reaction-diffusion state, u in red and v in green, tiled over the plane.
+// It is 1x1 when there is no simulation running.
+layout(binding = 4) uniform texture2D reaction_texture;
+layout(binding = 5) uniform sampler reaction_sampler;
+
+// Bit N is set if the Nth constant of the current instruction mirrors at its limits.
+uint wrap_mask;
+
+// The third coordinate, in [-1,1] through a volume; a flat picture is at zero.
+float depth = 0.0;
+
+// The positions that remaps have moved away from, innermost last.
+vec2 saved_positions[REMAP_DEPTH];
+uint saved_count = 0;
+
+void save_position(vec2 position) {
+    saved_positions[saved_count] = position;
+    saved_count += 1;
+}
+
+uint get_instr(in uint offset) {
+    return instrs[HEADER_SIZE + offset];
+}
+
+// Must match Constant::value_at.
+float animate_constant(vec4 c, bool mirror) {
+    float range = c.w - c.z;
+    if (!(range > 0.0)) {
+        return c.z;
+    }
+    float offset = c.x - c.z + c.y * time;
+    if (mirror) {
+        return c.z + range - abs(mod(offset, 2.0 * range) - range);
+    }
+    return c.z + mod(offset, range);
+}
+
+float pop_const(inout uint position) {
+    bool mirror = (wrap_mask & 1u) == 1u;
+    wrap_mask >>= 1;
+    float c = animate_constant(constant_pool[position], mirror);
+    position += 1;
+    return c;
+}
+
+// The longer side of the display spans [-1,1] and the shorter side is scaled to
+// match, so that shapes stay round however the texture gets stretched onto the
+// screen. Offsets shift the canvas by whole texels. Finally we apply the view, to
+// allow panning and zooming around the plane. Must match View::position.
+vec2 texture_to_position(vec2 pixel, vec2 size) {
+    vec2 uv = (pixel + vec2(texture_offsets)) / size;
+    vec2 extent = aspect_ratio >= 1.0 ? vec2(1.0, 1.0 / aspect_ratio) : vec2(aspect_ratio, 1.0);
+    return (uv * 2.0 - 1.0) * extent * view_scale + view_center;
+}
+
+// How many octaves of noise warp sums, and how many for a draft. Must match
+// WARP_OCTAVES and DRAFT_WARP_OCTAVES in src/ops.rs.
+#define WARP_OCTAVES 3
+#define DRAFT_WARP_OCTAVES 1
+
+// Integer hashing, so that the CPU and the interpreter see the same noise.
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return x;
+}
+
+// In [-1,1], at the corners of the unit grid.
+float lattice(ivec2 cell, uint seed) {
+    uint h = hash(uint(cell.x) ^ hash(uint(cell.y) ^ hash(seed)));
+    return float(h >> 8) / 16777215.0 * 2.0 - 1.0;
+}
+
+float value_noise(vec2 p, uint seed) {
+    vec2 cell = floor(p);
+    vec2 f = p - cell;
+    vec2 u = f * f * (3.0 - 2.0 * f);
+    ivec2 c = ivec2(cell);
+    return mix(
+        mix(lattice(c, seed), lattice(c + ivec2(1, 0), seed), u.x),
+        mix(lattice(c + ivec2(0, 1), seed), lattice(c + ivec2(1, 1), seed), u.x),
+        u.y
+    );
+}
+
+// Octaves of value noise, each at twice the frequency and half the weight of the
+// last, kept in [-1,1].
+float fractal_noise(vec2 p, uint seed, int octaves) {
+    float sum = 0.0;
+    float weight = 1.0;
+    float total = 0.0;
+    float scale = 1.0;
+    for (int i = 0; i < octaves; ++i) {
+        sum += weight * value_noise(p * scale, seed);
+        total += weight;
+        weight *= 0.5;
+        scale *= 2.0;
+    }
+    return sum / total;
+}
+
+float interpret(vec2 position)
+{
+    float stack[STACK_DEPTH];
+    uint stack_offset = 0;
+    uint coff = 0;
+    float size;
+
+    // A stream in some other format is drawn flat rather than misread.
+    if (instrs[0] != ENCODING_VERSION) {
+        return 0.0;
+    }
+    uint instr_count = min(instrs[1], uint(INSTRUCTION_COUNT));
+
+    for (uint i = 0; i < instr_count; ++i) {
+        uint instr = get_instr(i);
+        uint const_count = (instr >> 16) & 0xFF;
+        uint child_count = (instr >> 8) & 0xFF;
+        uint op = instr & 0xFF;
+        wrap_mask = (instr >> 24) & 0xFF;
+
+        switch(op) {
+        case 1: // const
+            stack[stack_offset] = pop_const(coff);
+            break;
+        case 2: // ellipse
+            {
+                vec2 x0 = vec2(pop_const(coff), pop_const(coff));
+                vec2 x1 = vec2(pop_const(coff), pop_const(coff));
+                float size = pop_const(coff);
+                float sharp = pop_const(coff);
+                float dist = distance(position, x0) + distance(position, x1);
+                stack[stack_offset] = clamp(size - dist, -1, 1) * sharp;
+            }
+            break;
+        case 3: // flower
+            {
+                vec2 p = vec2(pop_const(coff), pop_const(coff));
+                float angle = pop_const(coff);
+                float size = pop_const(coff);
+                float ratio = pop_const(coff);
+                float n_points = pop_const(coff);
+                float sharpness = pop_const(coff);
+                vec2 v0 = position - p;
+                float d = length(v0);
+                vec2 v1 = vec2(
+                    v0.x * cos(angle) - v0.y * sin(angle),
+                    v0.x * sin(angle) + v0.y * cos(angle)
+                );
+                float theta = (atan(v1.y, v1.x) / PI + 1.0) / 2.0; // [0,1] on full circle
+                float expanded = theta * floor(n_points); // [0,n] on full circle
+                float offset = fract(expanded); // [0,1] on each segment
+                offset = offset * 2 - 1; // [-1,1] centered on segment
+                float inner = size * ratio;
+                float r = ((d - inner) * (1.0 / (size - inner))); // ratio from outer to inner
+                float dist = r - abs(offset);// - (d / size * ratio * 20);
+                stack[stack_offset] = clamp(-dist, -1, 1) * sharpness;
+            }
+            break;
+        case 4: // linear gradient
+            {
+                vec3 x0 = vec3(pop_const(coff), pop_const(coff), 0);
+                vec3 x1 = vec3(pop_const(coff), pop_const(coff), 0);
+                float sharpness = pop_const(coff);
+                vec3 c = cross(x1 - x0, vec3(position, 0) - x0);
+                stack[stack_offset] = smoothstep(-1, 1, c.z * sharpness) * 2 - 1;
+            }
+            break;
+        case 5: // radial gradient
+            {
+                vec2 x0 = vec2(pop_const(coff), pop_const(coff));
+                float w = pop_const(coff);
+                float h = pop_const(coff);
+                float angle = pop_const(coff);
+                vec2 v0 = position - x0;
+                vec2 v1 = vec2(
+                    v0.x * cos(angle) - v0.y * sin(angle),
+                    v0.x * sin(angle) + v0.y * cos(angle)
+                );
+                vec2 v2 = vec2(v1.x / w, v1.y / h);
+                float tmp = -length(v2) * 2 / sqrt(2) + 1;
+                stack[stack_offset] = clamp(tmp, -1, 1);
+            }
+            break;
+        case 6: // polar theta
+            {
+                vec2 x0 = vec2(pop_const(coff), pop_const(coff));
+                float angle = pop_const(coff);
+                vec2 v0 = position - x0;
+                vec2 v1 = vec2(
+                    v0.x * cos(angle) - v0.y * sin(angle),
+                    v0.x * sin(angle) + v0.y * cos(angle)
+                );
+                stack[stack_offset] = atan(v1.y, v1.x) / PI;
+            }
+            break;
+        case 7: // mouse
+            {
+                // The mouse is over the whole picture, not the part at the offsets.
+                vec2 mouse = texture_to_position(mouse_position * vec2(texture_size) - vec2(texture_offsets), vec2(texture_size));
+                float size = pop_const(coff);
+                float sharp = pop_const(coff);
+                stack[stack_offset] = clamp((size - distance(position, mouse)) * sharp, -1, 1);
+            }
+            break;
+        case 8: // absolute
+            stack[stack_offset - 1] = abs(stack[stack_offset - 1]);
+            break;
+        case 9: // invert
+            stack[stack_offset - 1] = -stack[stack_offset - 1];
+            break;
+        case 10: // add
+            stack[stack_offset - 2] = stack[stack_offset - 2] + stack[stack_offset - 1];
+            break;
+        case 11: // sub
+            stack[stack_offset - 2] = stack[stack_offset - 2] - stack[stack_offset - 1];
+            break;
+        case 12: // multiply
+            stack[stack_offset - 2] = stack[stack_offset - 2] * stack[stack_offset - 1];
+            break;
+        case 13: // divide
+            stack[stack_offset - 2] = stack[stack_offset - 2] / stack[stack_offset - 1];
+            break;
+        case 14: // modulus
+            stack[stack_offset - 2] = mod(stack[stack_offset - 2], stack[stack_offset - 1]);
+            break;
+        case 15: // exponentiate
+            stack[stack_offset - 2] = pow(stack[stack_offset - 2], stack[stack_offset - 1]);
+            break;
+        case 16: // sinc
+            {
+                float freq = pop_const(coff);
+                float phase = pop_const(coff);
+                float denom = stack[stack_offset - 1] * freq + phase;
+                stack[stack_offset - 1] = clamp(sin(denom) / denom, -1, 1);
+            }
+            break;
+        case 17: // sine
+            {
+                float freq = pop_const(coff);
+                float phase = pop_const(coff);
+                stack[stack_offset - 1] = sin(stack[stack_offset - 1] * freq + phase);
+            }
+            break;
+        case 18: // spiral
+            {
+                vec2 center = vec2(pop_const(coff), pop_const(coff));
+                float n = pop_const(coff);
+                float b = pop_const(coff);
+                vec2 v0 = position - center;
+
+                float r = (v0.x * v0.x + v0.y * v0.y) * 2 / sqrt(2) - 1;
+                float theta = atan(v0.y, v0.x) / PI;
+                float tmp = abs(abs(stack[stack_offset - 1]) - 0.5);
+                stack[stack_offset - 1] = 4 * tmp - 1;
+            }
+            break;
+        case 19: // squircle
+            {
+                vec2 x0 = vec2(pop_const(coff), pop_const(coff));
+                float r = pop_const(coff);
+                float n = pop_const(coff);
+                vec2 v0 = position - x0;
+                float a = abs(v0.x - stack[stack_offset - 2]);
+                float b = abs(v0.y - stack[stack_offset - 1]);
+                float numer = -(pow(a, n) + pow(b, n));
+                float denom = pow(r, n);
+                stack[stack_offset - 2] = clamp(numer / denom, -1, 1);
+            }
+            break;
+        case 21: // sphere
+            {
+                vec3 p = vec3(pop_const(coff), pop_const(coff), pop_const(coff));
+                float size = pop_const(coff);
+                float sharp = pop_const(coff);
+                float dist = distance(vec3(position, depth), p);
+                stack[stack_offset] = clamp((size - dist) * sharp, -1, 1);
+            }
+            break;
+        case 20: // reaction
+            {
+                float scale = pop_const(coff);
+                float v = 0.0;
+                if (textureSize(sampler2D(reaction_texture, reaction_sampler), 0).x > 1) {
+                    vec2 uv = position / scale * 0.5 + 0.5;
+                    v = textureLod(sampler2D(reaction_texture, reaction_sampler), uv, 0).g;
+                }
+                stack[stack_offset] = clamp(v * 4.0 - 1.0, -1, 1);
+            }
+            break;
+        case 22: // polar
+            {
+                vec2 center = vec2(pop_const(coff), pop_const(coff));
+                float angle = pop_const(coff);
+                float scale = pop_const(coff);
+                save_position(position);
+                vec2 v0 = position - center;
+                vec2 v1 = vec2(
+                    v0.x * cos(angle) - v0.y * sin(angle),
+                    v0.x * sin(angle) + v0.y * cos(angle)
+                );
+                position = vec2(length(v1) * scale - 1.0, atan(v1.y, v1.x) / PI);
+            }
+            // The child has yet to run, so there is nothing on the stack.
+            continue;
+        case 23: // warp
+            {
+                float amplitude = pop_const(coff);
+                float frequency = pop_const(coff);
+                float offset = pop_const(coff);
+                save_position(position);
+                vec2 q = position * frequency + offset;
+                int octaves = draft != 0u ? DRAFT_WARP_OCTAVES : WARP_OCTAVES;
+                position += amplitude * vec2(
+                    fractal_noise(q, 0u, octaves),
+                    fractal_noise(q, 1u, octaves)
+                );
+            }
+            continue;
+        case RESTORE_OPCODE:
+            saved_count -= 1;
+            position = saved_positions[saved_count];
+            break;
+#include "plugin_ops.glsl"
+        default:
+            continue;
+        }
+
+        stack_offset -= (child_count - 1);
+    }
+
+    return stack[0];
+}
+
+void main()
+{
+    int block = int(max(block_size, 1u));
+    int stride = int(max(pass_stride, 1u));
+    ivec2 image_size = imageSize(result_texture);
+    ivec2 pixel_index = ivec2(gl_GlobalInvocationID.xy) * stride + ivec2(pass_offset);
+    if (any(greaterThanEqual(pixel_index, image_size))) {
+        return;
+    }
+    vec2 position;
+    if (volume_slices > 0) {
+        int side = texture_size.y / int(volume_slices);
+        int slice = pixel_index.y / side;
+        depth = volume_slices > 1 ? float(slice) / float(volume_slices - 1) * 2.0 - 1.0 : 0.0;
+        vec2 pixel = vec2(pixel_index.x, pixel_index.y - slice * side);
+        position = texture_to_position(pixel, vec2(texture_size.x, side));
+    } else {
+        position = texture_to_position(vec2(pixel_index), vec2(texture_size));
+    }
+
+    float result = (interpret(position) + 1.0) / 2.0;
+    ivec2 block_end = min(pixel_index + block, image_size);
+    for (int y = pixel_index.y; y < block_end.y; ++y) {
+        for (int x = pixel_index.x; x < block_end.x; ++x) {
+            imageStore(result_texture, ivec2(x, y), vec4(result, 0, 0, 0));
+        }
+    }
+}
@@ -0,0 +1,19 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// Cases for ops registered from outside stampede, spliced into the interpreter's
+// switch. Write the output of ops::plugin_cases here and rebuild to give the
+// interpreter the same ops as the CPU side; ops::register turns away any op
+// whose case is missing. Stampede itself registers none.
@@ -0,0 +1,30 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// The inputs shared by every post pass: its settings and the picture so far.
+// Must match PassConfiguration in src/post.rs.
+layout(binding = 0) uniform readonly PassConfiguration {
+    vec2 screen_size;
+    float time;
+    // What the two sets of parameters mean is up to each pass; see PostPass.
+    vec4 params0;
+    vec4 params1;
+};
+layout(binding = 1) uniform texture2D source_texture;
+layout(binding = 2) uniform sampler source_sampler;
+
+vec3 sample_source(vec2 uv) {
+    return texture(sampler2D(source_texture, source_sampler), uv).rgb;
+}
@@ -0,0 +1,62 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// A view for each eye. The depth layer pushes brighter areas toward the viewer by
+// moving them apart between the eyes; the includer picks how the two views are
+// put together by defining SIDE_BY_SIDE or not, for red/cyan glasses.
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+#include <draw.glsl>
+#include <color.glsl>
+#include <vision.glsl>
+
+float depth_at(vec2 uv) {
+    if (depth_layer == 1) {
+        return texture(sampler2D(g_texture, g_sampler), uv).r;
+    } else if (depth_layer == 2) {
+        return texture(sampler2D(b_texture, b_sampler), uv).r;
+    }
+    return texture(sampler2D(r_texture, r_sampler), uv).r;
+}
+
+// The left eye is at -1 and the right at 1.
+vec3 eye_view(vec2 uv, float eye) {
+    float nearness = depth_at(uv) - 0.5;
+    return present(layers2rgb(sample_layers(uv + vec2(eye * nearness * stereo_separation, 0))));
+}
+
+void main() {
+#ifdef SIDE_BY_SIDE
+    // Each eye gets half of the width, squeezed, as 3D displays expect.
+    float eye = v_tex_coord.x < 0.5 ? -1.0 : 1.0;
+    vec2 uv = window_to_canvas(vec2(fract(v_tex_coord.x * 2.0), v_tex_coord.y));
+    if (!on_canvas(uv)) {
+        f_color = border_color;
+        return;
+    }
+    f_color = vec4(eye_view(uv, eye), 1);
+#else
+    vec2 uv = window_to_canvas(v_tex_coord);
+    if (!on_canvas(uv)) {
+        f_color = border_color;
+        return;
+    }
+    vec3 left = eye_view(uv, -1.0);
+    vec3 right = eye_view(uv, 1.0);
+    f_color = vec4(left.r, right.g, right.b, 1);
+#endif
+}
@@ -0,0 +1,42 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// Shared by the terrain shaders: the display's configuration and layers, and the
+// orbiting camera.
+layout(binding = 0) uniform readonly Configuration {
+    ivec2 texture_size;
+    ivec2 texture_offsets;
+    vec2 mouse_position;
+    float time;
+    float aspect_ratio; // of the display, width over height
+    vec2 view_center;
+    float view_scale;
+    uint volume_slices;
+};
+layout(binding = 1) uniform texture2D r_texture;
+layout(binding = 2) uniform texture2D g_texture;
+layout(binding = 3) uniform texture2D b_texture;
+layout(binding = 4) uniform sampler terrain_sampler;
+
+// How far the red layer lifts the ground, over a square two units across.
+#define HEIGHT_SCALE 0.5
+
+float terrain_height(vec2 uv) {
+    return (textureLod(sampler2D(r_texture, terrain_sampler), uv, 0).r - 0.5) * HEIGHT_SCALE;
+}
+
+vec3 terrain_position(vec2 uv) {
+    return vec3(uv.x * 2.0 - 1.0, terrain_height(uv), 1.0 - uv.y * 2.0);
+}
@@ -0,0 +1,71 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+// How the picture looks to, or can be adjusted for, the red-green colorblind. The
+// includer must also include draw.glsl, which has the color_vision setting, and
+// color.glsl; the values must match ColorVision in src/display.rs.
+#define VISION_NORMAL 0u
+#define VISION_PROTANOPIA 1u
+#define VISION_DEUTERANOPIA 2u
+#define VISION_PROTANOPIA_CORRECTED 3u
+#define VISION_DEUTERANOPIA_CORRECTED 4u
+
+// Machado, Oliveira and Fernandes (2009) at full severity, in linear RGB. GLSL
+// fills matrices by column, so these are written out by row and transposed.
+vec3 simulate_dichromacy(vec3 srgb, bool protan) {
+    mat3 protanopia = transpose(mat3(
+         0.152286, 1.052583, -0.204868,
+         0.114503, 0.786281,  0.099216,
+        -0.003882, -0.048116, 1.051998
+    ));
+    mat3 deuteranopia = transpose(mat3(
+         0.367322, 0.860646, -0.227968,
+         0.280085, 0.672501,  0.047413,
+        -0.011820, 0.042940,  0.968881
+    ));
+    vec3 linear = srgb_to_linear(clamp(srgb, 0.0, 1.0));
+    vec3 seen = (protan ? protanopia : deuteranopia) * linear;
+    return linear_to_srgb(clamp(seen, 0.0, 1.0));
+}
+
+// Daltonize: whatever difference is lost to the missing cone is moved over into
+// lightness and the blue-yellow axis, where it can still be seen.
+vec3 compensate_dichromacy(vec3 srgb, bool protan) {
+    mat3 shift = transpose(mat3(
+        0.0, 0.0, 0.0,
+        0.7, 1.0, 0.0,
+        0.7, 0.0, 1.0
+    ));
+    vec3 lost = srgb - simulate_dichromacy(srgb, protan);
+    return clamp(srgb + shift * lost, 0.0, 1.0);
+}
+
+// The last step before a color reaches the screen: first the exposure, then the
+// adjustments made while watching, then posterizing and the palette, which are
+// part of the picture, and last the color vision.
+vec3 present(vec3 color) {
+    vec3 exposed = linear_to_srgb(srgb_to_linear(max(color, 0.0)) * exposure);
+    vec3 srgb = to_palette(posterize(adjust_color(exposed)));
+    switch (color_vision) {
+    case VISION_PROTANOPIA:
+        return simulate_dichromacy(srgb, true);
+    case VISION_DEUTERANOPIA:
+        return simulate_dichromacy(srgb, false);
+    case VISION_PROTANOPIA_CORRECTED:
+        return compensate_dichromacy(srgb, true);
+    case VISION_DEUTERANOPIA_CORRECTED:
+        return compensate_dichromacy(srgb, false);
+    }
+    return srgb;
+}
@@ -21,7 +21,7 @@
  *     DUMP_SPIRV=1   Dump disassembled code next to bytecode.
  *     DEBUG=1        Compile with debug settings.
  */
-use failure::Fallible;
+use anyhow::Result;
 use log::trace;
 use shaderc::{
     CompileOptions, Compiler, Error, IncludeType, OptimizationLevel, ResolvedInclude, ShaderKind,
@@ -47,10 +47,10 @@ fn type_for_filename(name: &str) -> ShaderKind {
     }
 }
 
-fn output_for_name(name: &str) -> String {
+fn output_for_name(name: &str, extension: &str) -> String {
     assert!(name.ends_with(".glsl"));
     assert!(name.len() > 5);
-    let file_name = format!("{}.spirv", &name[..name.len() - 5]);
+    let file_name = format!("{}.{}", &name[..name.len() - 5], extension);
 
     let project_cargo_root = env::var("CARGO_MANIFEST_DIR").unwrap();
 
@@ -74,15 +74,18 @@ fn find_included_file(
     _include_depth: usize,
 ) -> Result<ResolvedInclude, String> {
     let project_cargo_root = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let libs_dir = Path::new(&project_cargo_root)
+    let project_dir = Path::new(&project_cargo_root);
+    let mut include_dirs = vec![project_dir.join("include")];
+    // Crates under <workspace>/libs/<group>/<name> may also share the renderer's includes.
+    let libs_dir = project_dir
         .parent()
-        .expect("non-root")
-        .parent()
-        .expect("non-root")
-        .parent()
-        .expect("non-root");
-    assert_eq!(libs_dir.file_stem().expect("non-root"), "libs");
-    let include_dirs = vec![libs_dir.join("render-wgpu")];
+        .and_then(|p| p.parent())
+        .and_then(|p| p.parent());
+    if let Some(libs_dir) = libs_dir {
+        if libs_dir.file_stem().map(|s| s == "libs").unwrap_or(false) {
+            include_dirs.push(libs_dir.join("render-wgpu"));
+        }
+    }
     let input_path: PathBuf = name.split('/').collect();
     trace!("Using include dirs: {:?}", include_dirs);
     for path in &include_dirs {
@@ -100,7 +103,31 @@ fn find_included_file(
     Err("NOT_FOUND".to_owned())
 }
 
-pub fn build() -> Fallible<()> {
+// Write the labels of the cases in include/<name> to target/<name>.cases, as a
+// Rust array, so that the crate can tell which cases its shaders were compiled
+// with. Only labels that start a line count: `case 24:`.
+pub fn write_case_labels(name: &str) -> Result<()> {
+    let include_path = Path::new("include").join(name);
+    println!(
+        "cargo:rerun-if-changed={}",
+        include_path.to_str().expect("a path")
+    );
+    let mut labels = Vec::new();
+    for line in fs::read_to_string(&include_path)?.lines() {
+        let line = line.trim_start();
+        if !line.starts_with("case ") {
+            continue;
+        }
+        if let Some(end) = line.find(':') {
+            labels.push(line[5..end].trim().parse::<u32>()?.to_string());
+        }
+    }
+    let target_path = output_for_name(name, "cases");
+    fs::write(&target_path, format!("[{}]", labels.join(", ")))?;
+    Ok(())
+}
+
+pub fn build() -> Result<()> {
     println!("cargo:rerun-if-env-changed=DUMP_SPIRV");
     println!("cargo:rerun-if-env-changed=DEBUG");
     let shaders_dir = env::current_dir()?.as_path().join("shaders");
@@ -158,6 +185,7 @@ pub fn build() -> Fallible<()> {
                 .expect("a file name")
                 .to_str()
                 .expect("a string"),
+            "spirv",
         );
         fs::write(&target_path, spirv.as_binary_u8())?;
 
@@ -181,7 +209,7 @@ mod tests {
     use super::*;
 
     #[test]
-    fn it_works() -> Fallible<()> {
+    fn it_works() -> Result<()> {
         build()
     }
 }
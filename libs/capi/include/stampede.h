@@ -0,0 +1,75 @@
+/*
+ * This file is part of Stampede.
+ *
+ * Stampede is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Stampede is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+ */
+#ifndef STAMPEDE_H
+#define STAMPEDE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Draws stampede into a window owned by the caller:
+ *
+ *   StampedeRenderer *r = stampede_create_xlib(display, window, 1280, 720);
+ *   stampede_set_tree_json(r, json);  // optional; starts on a random tree
+ *   while (running)
+ *       stampede_step(r, seconds_since_last_step);
+ *   stampede_destroy(r);
+ *
+ * A renderer must only be used from the thread that created it. Functions
+ * returning int give 0 on success and -1 on failure; stampede_last_error then
+ * says why.
+ */
+typedef struct StampedeRenderer StampedeRenderer;
+
+#if defined(_WIN32)
+StampedeRenderer *stampede_create_win32(void *hwnd, uint32_t width, uint32_t height);
+#elif defined(__APPLE__)
+StampedeRenderer *stampede_create_macos(void *ns_window, void *ns_view, uint32_t width,
+                                        uint32_t height);
+#else
+StampedeRenderer *stampede_create_xlib(void *display, unsigned long window, uint32_t width,
+                                       uint32_t height);
+#endif
+
+/* Replace the tree with one saved from stampede with ctrl+c. */
+int stampede_set_tree_json(StampedeRenderer *renderer, const char *json);
+
+/* Must be called whenever the window changes size. */
+int stampede_resize(StampedeRenderer *renderer, uint32_t width, uint32_t height);
+
+/*
+ * Nudge the colors on screen: brightness in [-1,1] is added to every channel,
+ * and contrast and saturation in [0,4] are 1 to leave the picture as it is.
+ */
+int stampede_set_color_adjustment(StampedeRenderer *renderer, float brightness, float contrast,
+                                  float saturation);
+
+/* Animate by dt seconds and present a frame. */
+int stampede_step(StampedeRenderer *renderer, float dt);
+
+void stampede_destroy(StampedeRenderer *renderer);
+
+const char *stampede_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
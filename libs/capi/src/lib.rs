@@ -0,0 +1,282 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// A C interface for drawing into a window that some other application owns; see
+// include/stampede.h. The host creates a renderer on its window, hands it trees as
+// json and calls stampede_step once per frame. Functions that can fail return 0
+// on success and -1 on failure, with the reason in stampede_last_error.
+use anyhow::{anyhow, bail, Result};
+use gpu::{GPUConfig, GpuError, GPU};
+use rand::prelude::*;
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use stampede::{
+    display::{ColorAdjustment, Display, DisplayConfig},
+    tree::Tree,
+};
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+use winit::dpi::PhysicalSize;
+
+// The window handle as the host gave it to us.
+struct ForeignWindow(RawWindowHandle);
+
+unsafe impl HasRawWindowHandle for ForeignWindow {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        self.0
+    }
+}
+
+pub struct StampedeRenderer {
+    gpu: GPU,
+    display: Display,
+    tree: Tree,
+}
+
+impl StampedeRenderer {
+    fn new(handle: RawWindowHandle, width: u32, height: u32) -> Result<Self> {
+        if width == 0 || height == 0 {
+            bail!("the window must not be empty");
+        }
+        let size = PhysicalSize::new(f64::from(width), f64::from(height));
+        let mut gpu = GPU::from_raw_window(&ForeignWindow(handle), size, GPUConfig::default())?;
+        // Compute at the size the window starts at; resizing only rescales.
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        };
+        let display = Display::new(
+            &mut gpu,
+            extent,
+            wgpu::TextureFormat::R32Float,
+            DisplayConfig::default(),
+        )?;
+        Ok(Self {
+            gpu,
+            display,
+            tree: Tree::new(&mut StdRng::from_entropy())?,
+        })
+    }
+
+    fn set_tree_json(&mut self, json: &str) -> Result<()> {
+        self.tree = Tree::from_json(json)?;
+        self.display.note_tree_changed();
+        Ok(())
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.gpu
+            .resize(PhysicalSize::new(f64::from(width), f64::from(height)));
+        self.display.config_mut().aspect_ratio = 1f32 / self.gpu.aspect_ratio_f32();
+    }
+
+    fn step(&mut self, dt: f32) -> Result<()> {
+        self.tree.animate(dt);
+        let upload = self.display.encode_upload_buffers(&self.gpu, &self.tree)?;
+        // While the host's window is minimized there is nothing to draw; the host
+        // need not know.
+        let mut frame = match self.gpu.begin_frame() {
+            Ok(frame) => frame,
+            Err(GpuError::EmptyWindow) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        self.display.upload(&upload, &mut frame);
+        self.display.draw(&mut frame.begin_render_pass());
+        frame.finish();
+        self.display.compute(&mut self.gpu, upload);
+        Ok(())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: String) {
+    // Interior nuls cannot be passed on; drop them rather than the message.
+    let message = CString::new(message.replace('\0', "")).expect("no nuls");
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+// Never let a panic unwind into the host's frames.
+fn guard<T, F: FnOnce() -> Result<T>>(f: F) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            None
+        }
+        Err(_) => {
+            set_last_error("stampede panicked".to_owned());
+            None
+        }
+    }
+}
+
+fn status(result: Option<()>) -> c_int {
+    match result {
+        Some(()) => 0,
+        None => -1,
+    }
+}
+
+fn create(handle: RawWindowHandle, width: u32, height: u32) -> *mut StampedeRenderer {
+    match guard(|| StampedeRenderer::new(handle, width, height)) {
+        Some(renderer) => Box::into_raw(Box::new(renderer)),
+        None => ptr::null_mut(),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+#[no_mangle]
+pub unsafe extern "C" fn stampede_create_xlib(
+    display: *mut std::os::raw::c_void,
+    window: std::os::raw::c_ulong,
+    width: u32,
+    height: u32,
+) -> *mut StampedeRenderer {
+    use raw_window_handle::unix::XlibHandle;
+    create(
+        RawWindowHandle::Xlib(XlibHandle {
+            window,
+            display,
+            ..XlibHandle::empty()
+        }),
+        width,
+        height,
+    )
+}
+
+#[cfg(target_os = "windows")]
+#[no_mangle]
+pub unsafe extern "C" fn stampede_create_win32(
+    hwnd: *mut std::os::raw::c_void,
+    width: u32,
+    height: u32,
+) -> *mut StampedeRenderer {
+    use raw_window_handle::windows::WindowsHandle;
+    create(
+        RawWindowHandle::Windows(WindowsHandle {
+            hwnd,
+            ..WindowsHandle::empty()
+        }),
+        width,
+        height,
+    )
+}
+
+#[cfg(target_os = "macos")]
+#[no_mangle]
+pub unsafe extern "C" fn stampede_create_macos(
+    ns_window: *mut std::os::raw::c_void,
+    ns_view: *mut std::os::raw::c_void,
+    width: u32,
+    height: u32,
+) -> *mut StampedeRenderer {
+    use raw_window_handle::macos::MacOSHandle;
+    create(
+        RawWindowHandle::MacOS(MacOSHandle {
+            ns_window,
+            ns_view,
+            ..MacOSHandle::empty()
+        }),
+        width,
+        height,
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stampede_set_tree_json(
+    renderer: *mut StampedeRenderer,
+    json: *const c_char,
+) -> c_int {
+    status(guard(|| {
+        let renderer = renderer.as_mut().ok_or_else(|| anyhow!("no renderer"))?;
+        if json.is_null() {
+            bail!("no json");
+        }
+        renderer.set_tree_json(CStr::from_ptr(json).to_str()?)
+    }))
+}
+
+// The host must call this whenever its window changes size.
+#[no_mangle]
+pub unsafe extern "C" fn stampede_resize(
+    renderer: *mut StampedeRenderer,
+    width: u32,
+    height: u32,
+) -> c_int {
+    status(guard(|| {
+        let renderer = renderer.as_mut().ok_or_else(|| anyhow!("no renderer"))?;
+        if width == 0 || height == 0 {
+            bail!("the window must not be empty");
+        }
+        renderer.resize(width, height);
+        Ok(())
+    }))
+}
+
+// Brightness is added to every channel; contrast and saturation are 1 to leave the
+// picture as it is. Values out of range are clamped.
+#[no_mangle]
+pub unsafe extern "C" fn stampede_set_color_adjustment(
+    renderer: *mut StampedeRenderer,
+    brightness: f32,
+    contrast: f32,
+    saturation: f32,
+) -> c_int {
+    status(guard(|| {
+        let renderer = renderer.as_mut().ok_or_else(|| anyhow!("no renderer"))?;
+        renderer
+            .display
+            .draw_config_mut()
+            .set_color_adjustment(ColorAdjustment {
+                brightness,
+                contrast,
+                saturation,
+            });
+        Ok(())
+    }))
+}
+
+// Advance the animation by dt seconds and present a frame.
+#[no_mangle]
+pub unsafe extern "C" fn stampede_step(renderer: *mut StampedeRenderer, dt: f32) -> c_int {
+    status(guard(|| {
+        renderer
+            .as_mut()
+            .ok_or_else(|| anyhow!("no renderer"))?
+            .step(dt)
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stampede_destroy(renderer: *mut StampedeRenderer) {
+    if !renderer.is_null() {
+        drop(Box::from_raw(renderer));
+    }
+}
+
+// Why the last call on this thread failed. The string belongs to stampede and is
+// only good until the next failing call.
+#[no_mangle]
+pub extern "C" fn stampede_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
@@ -12,12 +12,29 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with OpenFA.  If not, see <http://www.gnu.org/licenses/>.
-use failure::{err_msg, Fallible};
-use std::io::Cursor;
+mod readback;
+
+pub use readback::{Readback, ReadbackQueue};
+
+use raw_window_handle::HasRawWindowHandle;
+use std::io::{self, Cursor};
+use thiserror::Error;
 use wgpu;
 use winit::{window::Window, dpi::PhysicalSize};
 use zerocopy::{AsBytes, FromBytes};
 
+#[derive(Debug, Error)]
+pub enum GpuError {
+    #[error("there is no graphics adapter {index}; there are {count}")]
+    NoSuchAdapter { index: usize, count: usize },
+    #[error("no suitable graphics adapter")]
+    NoAdapter,
+    #[error("the shader is not SPIR-V: {0}")]
+    Shader(#[source] io::Error),
+    #[error("the window has no area to draw into")]
+    EmptyWindow,
+}
+
 #[repr(C)]
 #[derive(AsBytes, FromBytes, Copy, Clone, Debug)]
 pub struct DrawIndirectCommand {
@@ -28,27 +45,46 @@ pub struct DrawIndirectCommand {
 }
 
 pub struct GPUConfig {
+    adapter: Option<usize>,
     anisotropic_filtering: bool,
     max_bind_groups: u32,
     preset_mode: wgpu::PresentMode,
+    sample_count: u32,
 }
 impl Default for GPUConfig {
     fn default() -> Self {
         Self {
+            adapter: None,
             anisotropic_filtering: false,
             max_bind_groups: 6,
             preset_mode: wgpu::PresentMode::Vsync,
+            sample_count: 1,
         }
     }
 }
+impl GPUConfig {
+    // Render into a multisampled target that gets resolved into the swap chain.
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    // Run on the adapter at this index in GPU::adapter_names, rather than on
+    // whichever adapter the platform thinks is fastest.
+    pub fn with_adapter(mut self, adapter: Option<usize>) -> Self {
+        self.adapter = adapter;
+        self
+    }
+}
 
 pub struct GPU {
     surface: wgpu::Surface,
-    _adapter: wgpu::Adapter,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
     swap_chain: wgpu::SwapChain,
     depth_texture: wgpu::TextureView,
+    msaa_texture: Option<wgpu::TextureView>,
 
     config: GPUConfig,
     size: PhysicalSize,
@@ -76,17 +112,72 @@ impl GPU {
         self.size
     }
 
-    pub fn new(window: &Window, config: GPUConfig) -> Fallible<Self> {
+    // Pipelines that draw into a Frame's render pass must use this sample count.
+    pub fn sample_count(&self) -> u32 {
+        self.config.sample_count
+    }
+
+    fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &GPUConfig,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> Option<wgpu::TextureView> {
+        if config.sample_count <= 1 {
+            return None;
+        }
+        Some(
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width: sc_desc.width,
+                        height: sc_desc.height,
+                        depth: 1,
+                    },
+                    array_layer_count: 1,
+                    mip_level_count: 1,
+                    sample_count: config.sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: sc_desc.format,
+                    usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+                })
+                .create_default_view(),
+        )
+    }
+
+    pub fn new(window: &Window, config: GPUConfig) -> Result<Self, GpuError> {
         window.set_title("OpenFA");
+        let size = window
+            .inner_size()
+            .to_physical(window.hidpi_factor());
+        Self::from_raw_window(window, size, config)
+    }
+
+    // For windows that belong to someone else, e.g. a host application embedding
+    // us; the owner has to report the size, here and on every resize.
+    pub fn from_raw_window<W: HasRawWindowHandle>(
+        window: &W,
+        size: PhysicalSize,
+        config: GPUConfig,
+    ) -> Result<Self, GpuError> {
         let surface = wgpu::Surface::create(window);
 
-        let adapter = wgpu::Adapter::request(
-            &wgpu::RequestAdapterOptions {
+        let adapter = match config.adapter {
+            Some(index) => {
+                let mut adapters = wgpu::Adapter::enumerate(wgpu::BackendBit::PRIMARY);
+                if index >= adapters.len() {
+                    return Err(GpuError::NoSuchAdapter {
+                        index,
+                        count: adapters.len(),
+                    });
+                }
+                adapters.swap_remove(index)
+            }
+            None => wgpu::Adapter::request(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
                 backends: wgpu::BackendBit::PRIMARY,
-            },
-        )
-        .ok_or_else(|| err_msg("no suitable graphics adapter"))?;
+            })
+            .ok_or(GpuError::NoAdapter)?,
+        };
 
         let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
             extensions: wgpu::Extensions {
@@ -97,9 +188,6 @@ impl GPU {
             },
         });
 
-        let size = window
-            .inner_size()
-            .to_physical(window.hidpi_factor());
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
             format: Self::texture_format(),
@@ -116,32 +204,64 @@ impl GPU {
             },
             array_layer_count: 1,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count: config.sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
         });
 
+        let msaa_texture = Self::create_msaa_texture(&device, &config, &sc_desc);
+
         let empty_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { bindings: &[] });
 
         Ok(Self {
             surface,
-            _adapter: adapter,
+            adapter,
             device,
             queue,
             swap_chain,
             depth_texture: depth_texture.create_default_view(),
+            msaa_texture,
             config,
             size,
             empty_layout,
         })
     }
 
-    pub fn note_resize(&mut self, window: &Window) {
-        self.size = window
+    // Every adapter that GPUConfig::with_adapter can pick, in the same order.
+    pub fn adapter_names() -> Vec<String> {
+        wgpu::Adapter::enumerate(wgpu::BackendBit::PRIMARY)
+            .iter()
+            .map(|adapter| adapter.get_info().name)
+            .collect()
+    }
+
+    pub fn adapter_name(&self) -> String {
+        self.adapter.get_info().name
+    }
+
+    // Whether the window has changed size or scale since the swap chain was made
+    // for it. wgpu cannot acquire from a surface that is outdated, so this has to
+    // be caught before begin_frame, in case the event saying so is still to come.
+    pub fn is_outdated(&self, window: &Window) -> bool {
+        let size = window
             .inner_size()
             .to_physical(window.hidpi_factor());
+        size.width.floor() != self.size.width.floor()
+            || size.height.floor() != self.size.height.floor()
+    }
+
+    pub fn note_resize(&mut self, window: &Window) {
+        self.resize(
+            window
+                .inner_size()
+                .to_physical(window.hidpi_factor()),
+        );
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize) {
+        self.size = size;
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
             format: Self::texture_format(),
@@ -160,16 +280,17 @@ impl GPU {
                 },
                 array_layer_count: 1,
                 mip_level_count: 1,
-                sample_count: 1,
+                sample_count: self.config.sample_count,
                 dimension: wgpu::TextureDimension::D2,
                 format: Self::DEPTH_FORMAT,
                 usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
             })
             .create_default_view();
+        self.msaa_texture = Self::create_msaa_texture(&self.device, &self.config, &sc_desc);
     }
 
-    pub fn create_shader_module(&self, spirv: &[u8]) -> Fallible<wgpu::ShaderModule> {
-        let spirv_words = wgpu::read_spirv(Cursor::new(spirv))?;
+    pub fn create_shader_module(&self, spirv: &[u8]) -> Result<wgpu::ShaderModule, GpuError> {
+        let spirv_words = wgpu::read_spirv(Cursor::new(spirv)).map_err(GpuError::Shader)?;
         Ok(self.device.create_shader_module(&spirv_words))
     }
 
@@ -193,10 +314,14 @@ impl GPU {
         &self.empty_layout
     }
 
-    pub fn begin_frame(&mut self) -> Fallible<Frame> {
-        let color_attachment = self
-            .swap_chain
-            .get_next_texture();
+    // A minimized window has nothing to draw into, so its frames fail with
+    // EmptyWindow and should be skipped. Windows that we own should be checked with
+    // is_outdated first.
+    pub fn begin_frame(&mut self) -> Result<Frame, GpuError> {
+        if self.size.width < 1f64 || self.size.height < 1f64 {
+            return Err(GpuError::EmptyWindow);
+        }
+        let color_attachment = self.swap_chain.get_next_texture();
         Ok(Frame {
             queue: &mut self.queue,
             encoder: self
@@ -204,6 +329,7 @@ impl GPU {
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 }),
             color_attachment,
             depth_attachment: &self.depth_texture,
+            msaa_attachment: self.msaa_texture.as_ref(),
         })
     }
 }
@@ -213,6 +339,7 @@ pub struct Frame<'a> {
     encoder: wgpu::CommandEncoder,
     color_attachment: wgpu::SwapChainOutput<'a>,
     depth_attachment: &'a wgpu::TextureView,
+    msaa_attachment: Option<&'a wgpu::TextureView>,
 }
 
 impl<'a> Frame<'a> {
@@ -221,30 +348,35 @@ impl<'a> Frame<'a> {
     }
 
     pub fn begin_render_pass(&mut self) -> wgpu::RenderPass {
-        self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                attachment: &self.color_attachment.view,
-                resolve_target: None,
-                load_op: wgpu::LoadOp::Clear,
-                store_op: wgpu::StoreOp::Store,
-                clear_color: wgpu::Color::GREEN,
-            }],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                attachment: self.depth_attachment,
-                depth_load_op: wgpu::LoadOp::Clear,
-                depth_store_op: wgpu::StoreOp::Store,
-                clear_depth: 1f32,
-                stencil_load_op: wgpu::LoadOp::Clear,
-                stencil_store_op: wgpu::StoreOp::Store,
-                clear_stencil: 0,
-            }),
-        })
+        begin_render_pass(
+            &mut self.encoder,
+            &self.color_attachment.view,
+            self.depth_attachment,
+            self.msaa_attachment,
+        )
+    }
+
+    // As begin_render_pass, but drawing into target rather than the swap chain, for
+    // passes over the whole picture before it is shown. The target must be the size
+    // of the swap chain and in its format, and be usable as an output attachment.
+    pub fn begin_render_pass_into(&mut self, target: &wgpu::TextureView) -> wgpu::RenderPass {
+        begin_render_pass(
+            &mut self.encoder,
+            target,
+            self.depth_attachment,
+            self.msaa_attachment,
+        )
     }
 
     pub fn finish(self) {
         self.queue.submit(&[self.encoder.finish()]);
     }
 
+    // For passes and copies that do not target the swap chain.
+    pub fn encoder_mut(&mut self) -> &mut wgpu::CommandEncoder {
+        &mut self.encoder
+    }
+
     pub fn copy_buffer_to_buffer(
         &mut self,
         source: &wgpu::Buffer,
@@ -262,3 +394,35 @@ impl<'a> Frame<'a> {
         )
     }
 }
+
+// Pipelines draw with the sample count of the swap chain, so multisampled passes
+// go through the msaa attachment and are resolved into target.
+fn begin_render_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    target: &wgpu::TextureView,
+    depth_attachment: &wgpu::TextureView,
+    msaa_attachment: Option<&wgpu::TextureView>,
+) -> wgpu::RenderPass<'a> {
+    let (attachment, resolve_target) = match msaa_attachment {
+        Some(msaa_attachment) => (msaa_attachment, Some(target)),
+        None => (target, None),
+    };
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+            attachment,
+            resolve_target,
+            load_op: wgpu::LoadOp::Clear,
+            store_op: wgpu::StoreOp::Store,
+            clear_color: wgpu::Color::GREEN,
+        }],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+            attachment: depth_attachment,
+            depth_load_op: wgpu::LoadOp::Clear,
+            depth_store_op: wgpu::StoreOp::Store,
+            clear_depth: 1f32,
+            stencil_load_op: wgpu::LoadOp::Clear,
+            stencil_store_op: wgpu::StoreOp::Store,
+            clear_stencil: 0,
+        }),
+    })
+}
@@ -0,0 +1,169 @@
+// This file is part of OpenFA.
+//
+// OpenFA is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// OpenFA is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with OpenFA.  If not, see <http://www.gnu.org/licenses/>.
+use std::sync::{Arc, Mutex};
+use wgpu;
+
+// Rows of a texture to buffer copy must start on this boundary.
+const ROW_PITCH_ALIGNMENT: u32 = 256;
+
+// The contents of one texture, with the row padding removed.
+#[derive(Debug)]
+pub struct Readback {
+    pub tag: u64,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+type MappedData = Arc<Mutex<Option<Result<Vec<u8>, ()>>>>;
+
+enum SlotState {
+    Free,
+    Copied,
+    Mapping(MappedData),
+}
+
+struct Slot {
+    buffer: wgpu::Buffer,
+    tag: u64,
+    state: SlotState,
+}
+
+// Copies textures back to the CPU without ever waiting on the GPU. Each request
+// takes one of a fixed set of staging buffers; if they are all still in flight,
+// the request is refused rather than stalling the caller.
+pub struct ReadbackQueue {
+    extent: wgpu::Extent3d,
+    bytes_per_pixel: u32,
+    row_pitch: u32,
+    slots: Vec<Slot>,
+}
+
+impl ReadbackQueue {
+    pub fn new(
+        device: &wgpu::Device,
+        extent: wgpu::Extent3d,
+        bytes_per_pixel: u32,
+        depth: usize,
+    ) -> Self {
+        let row_bytes = extent.width * bytes_per_pixel;
+        let row_pitch =
+            (row_bytes + ROW_PITCH_ALIGNMENT - 1) / ROW_PITCH_ALIGNMENT * ROW_PITCH_ALIGNMENT;
+        let slots = (0..depth)
+            .map(|_| Slot {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    size: wgpu::BufferAddress::from(row_pitch * extent.height),
+                    usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                }),
+                tag: 0,
+                state: SlotState::Free,
+            })
+            .collect();
+        Self {
+            extent,
+            bytes_per_pixel,
+            row_pitch,
+            slots,
+        }
+    }
+
+    // Record a copy of the top level of texture. Returns false if every staging
+    // buffer is busy. The tag comes back with the result so that callers can
+    // match up results with requests.
+    pub fn request(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        tag: u64,
+    ) -> bool {
+        let slot = match self.slots.iter_mut().find(|slot| match slot.state {
+            SlotState::Free => true,
+            _ => false,
+        }) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d {
+                    x: 0f32,
+                    y: 0f32,
+                    z: 0f32,
+                },
+            },
+            wgpu::BufferCopyView {
+                buffer: &slot.buffer,
+                offset: 0,
+                row_pitch: self.row_pitch,
+                image_height: self.extent.height,
+            },
+            self.extent,
+        );
+        slot.tag = tag;
+        slot.state = SlotState::Copied;
+        true
+    }
+
+    // Must be called after the encoder passed to request has been submitted;
+    // the staging buffers can only be mapped once the copy is queued.
+    pub fn submitted(&mut self) {
+        for slot in &mut self.slots {
+            if let SlotState::Copied = slot.state {
+                let mapped: MappedData = Arc::new(Mutex::new(None));
+                let target = mapped.clone();
+                let size = wgpu::BufferAddress::from(self.row_pitch * self.extent.height);
+                slot.buffer.map_read_async(0, size, move |result| {
+                    *target.lock().unwrap() = Some(result.map(|mapping| mapping.data.to_vec()));
+                });
+                slot.state = SlotState::Mapping(mapped);
+            }
+        }
+    }
+
+    // Collect every readback that has finished since the last poll. This never
+    // blocks on the device.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Vec<Readback> {
+        device.poll(false);
+        let row_bytes = (self.extent.width * self.bytes_per_pixel) as usize;
+        let mut out = Vec::new();
+        for slot in &mut self.slots {
+            let result = match &slot.state {
+                SlotState::Mapping(mapped) => mapped.lock().unwrap().take(),
+                _ => None,
+            };
+            if let Some(result) = result {
+                slot.buffer.unmap();
+                slot.state = SlotState::Free;
+                if let Ok(padded) = result {
+                    out.push(Readback {
+                        tag: slot.tag,
+                        width: self.extent.width,
+                        height: self.extent.height,
+                        data: padded
+                            .chunks(self.row_pitch as usize)
+                            .take(self.extent.height as usize)
+                            .flat_map(|row| &row[..row_bytes])
+                            .cloned()
+                            .collect(),
+                    });
+                }
+            }
+        }
+        out
+    }
+}
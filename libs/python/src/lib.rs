@@ -0,0 +1,205 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// Python bindings, built as the pystampede extension module:
+//
+//   import pystampede
+//   tree = pystampede.Tree(42)
+//   tree.regrow(7, "r/0")
+//   tree.animate(1.5)
+//   pixels = pystampede.render(tree, 640, 360)  # numpy array, (360, 640, 3)
+use anyhow::Result;
+use gpu::{GPUConfig, GPU};
+use numpy::{PyArray, PyArray3};
+use pyo3::{exceptions::RuntimeError, prelude::*, wrap_pyfunction};
+use rand::prelude::*;
+use stampede::{
+    render::{check_size, OffscreenRenderer},
+    tree::Tree,
+};
+use std::cell::RefCell;
+use winit::{
+    event_loop::EventLoop,
+    window::{Window, WindowBuilder},
+};
+
+fn to_py_err<E: ToString>(e: E) -> PyErr {
+    RuntimeError::py_err(e.to_string())
+}
+
+#[pyclass(name = Tree)]
+struct PyTree {
+    tree: Tree,
+}
+
+#[pymethods]
+impl PyTree {
+    // The same tree that stampede draws for a numeric seed.
+    #[new]
+    fn new(seed: u64) -> PyResult<Self> {
+        Ok(Self {
+            tree: Tree::new(&mut StdRng::seed_from_u64(seed)).map_err(to_py_err)?,
+        })
+    }
+
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Self> {
+        Ok(Self {
+            tree: Tree::from_json(json).map_err(to_py_err)?,
+        })
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        self.tree.to_json().map_err(to_py_err)
+    }
+
+    fn show(&self) -> String {
+        self.tree.show()
+    }
+
+    #[getter]
+    fn node_count(&self) -> usize {
+        self.tree.node_count()
+    }
+
+    #[getter]
+    fn constant_count(&self) -> usize {
+        self.tree.constant_count()
+    }
+
+    #[getter]
+    fn time(&self) -> f32 {
+        self.tree.time()
+    }
+
+    fn animate(&mut self, dt: f32) {
+        self.tree.animate(dt);
+    }
+
+    fn set_constant(&mut self, index: usize, value: f32) -> PyResult<()> {
+        self.tree.set_constant(index, value).map_err(to_py_err)
+    }
+
+    // The variations below are drawn from seed, so that a script can repeat them.
+    fn reroll_constants(&mut self, seed: u64) {
+        self.tree.reroll_constants(&mut StdRng::seed_from_u64(seed));
+    }
+
+    fn perturb_constants(&mut self, seed: u64, spread: f32) {
+        self.tree
+            .perturb_constants(&mut StdRng::seed_from_u64(seed), spread);
+    }
+
+    // Grow the node at a path like "r/0/1" again, keeping the rest of the tree.
+    fn regrow(&mut self, seed: u64, path: &str) -> PyResult<()> {
+        self.tree
+            .regrow(&mut StdRng::seed_from_u64(seed), path)
+            .map_err(to_py_err)
+    }
+}
+
+// wgpu needs a surface even to compute offscreen, so the module keeps a hidden
+// window and a device around for as long as it is loaded.
+struct Renderer {
+    _event_loop: EventLoop<()>,
+    _window: Window,
+    gpu: GPU,
+    offscreen: OffscreenRenderer,
+}
+
+impl Renderer {
+    fn new() -> Result<Self> {
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_visible(false)
+            .build(&event_loop)?;
+        let gpu = GPU::new(&window, GPUConfig::default())?;
+        let offscreen = OffscreenRenderer::new(&gpu)?;
+        Ok(Self {
+            _event_loop: event_loop,
+            _window: window,
+            gpu,
+            offscreen,
+        })
+    }
+}
+
+thread_local! {
+    static RENDERER: RefCell<Option<Renderer>> = RefCell::new(None);
+}
+
+// Render a tree at its current time into a (height, width, 3) array of floats in
+// [0,1], top row first.
+#[pyfunction]
+fn render<'py>(
+    py: Python<'py>,
+    tree: PyRef<PyTree>,
+    width: u32,
+    height: u32,
+) -> PyResult<&'py PyArray3<f32>> {
+    check_size([width, height]).map_err(to_py_err)?;
+    let pixels = RENDERER
+        .with(|renderer| -> Result<Vec<f32>> {
+            let mut renderer = renderer.borrow_mut();
+            if renderer.is_none() {
+                *renderer = Some(Renderer::new()?);
+            }
+            let renderer = renderer.as_mut().expect("a renderer");
+            renderer
+                .offscreen
+                .render(&mut renderer.gpu, &tree.tree, width, height)
+        })
+        .map_err(to_py_err)?;
+    PyArray::from_vec(py, pixels).reshape([height as usize, width as usize, 3])
+}
+
+#[pymodule]
+fn pystampede(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyTree>()?;
+    m.add_wrapped(wrap_pyfunction!(render))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variations_change_the_tree() -> PyResult<()> {
+        let tree = PyTree::new(0)?;
+        let mut rerolled = PyTree::new(0)?;
+        rerolled.reroll_constants(1);
+        assert_ne!(tree.to_json()?, rerolled.to_json()?);
+        let mut perturbed = PyTree::new(0)?;
+        perturbed.perturb_constants(1, 0.1);
+        assert_ne!(tree.to_json()?, perturbed.to_json()?);
+        assert_eq!(perturbed.node_count(), tree.node_count());
+        let mut regrown = PyTree::new(0)?;
+        regrown.regrow(1, "r")?;
+        assert!(regrown.regrow(1, "r/9/9/9/9/9/9").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn empty_renders_are_errors() -> PyResult<()> {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        for &(width, height) in &[(0, 16), (16, 0), (0, 0)] {
+            let tree = PyCell::new(py, PyTree::new(0)?)?;
+            assert!(render(py, tree.borrow(), width, height).is_err());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+#include <post.glsl>
+
+// params0: the threshold, in [0,1], over which pixels glow; how far, in pixels,
+// the glow spreads; and how much of it to add.
+vec3 bright_at(vec2 offset) {
+    vec3 color = sample_source(v_tex_coord + offset / screen_size);
+    float brightness = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    return color * smoothstep(params0.x, min(params0.x + 0.1, 1.0), brightness);
+}
+
+// A single wide, sparse gaussian over the bright parts: coarse, but one pass.
+void main() {
+    float step_size = params0.y / 3.0;
+    vec3 glow = vec3(0);
+    float total = 0.0;
+    for (int y = -3; y <= 3; ++y) {
+        for (int x = -3; x <= 3; ++x) {
+            float weight = exp(-float(x * x + y * y) / 4.5);
+            glow += bright_at(vec2(x, y) * step_size) * weight;
+            total += weight;
+        }
+    }
+    vec3 color = sample_source(v_tex_coord) + glow / total * params0.z;
+    f_color = vec4(clamp(color, 0.0, 1.0), 1.0);
+}
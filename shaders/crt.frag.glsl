@@ -0,0 +1,55 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+#define PI 3.141592653589793
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+#include <post.glsl>
+
+// params0: how strong the curvature, scanlines, aperture mask and vignette are,
+// each in [0,1].
+void main() {
+    float curvature = params0.x;
+    float scanlines = params0.y;
+    float mask = params0.z;
+    float vignette = params0.w;
+
+    // Bulge the picture out from the middle, like the glass of a tube.
+    vec2 centered = v_tex_coord * 2.0 - 1.0;
+    centered *= 1.0 + curvature * 0.25 * dot(centered.yx, centered.yx);
+    vec2 uv = centered * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0))) || any(greaterThan(uv, vec2(1)))) {
+        f_color = vec4(0, 0, 0, 1);
+        return;
+    }
+    vec3 color = sample_source(uv);
+
+    // A dark line between every other row of pixels.
+    float line = 0.5 + 0.5 * cos(uv.y * screen_size.y * PI);
+    color *= mix(1.0, line, scanlines * 0.5);
+
+    // Columns of red, green and blue phosphor.
+    vec3 phosphor = vec3(1.0 - mask * 0.5);
+    phosphor[int(gl_FragCoord.x) % 3] = 1.0;
+    color *= phosphor;
+
+    // Darker toward the corners.
+    color *= 1.0 - vignette * 0.5 * dot(centered, centered);
+    f_color = vec4(clamp(color, 0.0, 1.0), 1.0);
+}
@@ -0,0 +1,71 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// Line integral convolution: noise smeared along the field made by the green and
+// blue layers, lit by the tree's own colors.
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+#include <draw.glsl>
+#include <color.glsl>
+#include <vision.glsl>
+
+// How many texels to follow the field in each direction.
+#define STREAMLINE_STEPS 24
+
+float noise(vec2 uv, vec2 size) {
+    vec2 cell = floor(uv * size);
+    return fract(sin(dot(cell, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+// The layers are in [0,1], so the field is centered on a half.
+vec2 direction(vec2 uv) {
+    vec2 v = vec2(
+        textureLod(sampler2D(g_texture, g_sampler), uv, 0).r,
+        textureLod(sampler2D(b_texture, b_sampler), uv, 0).r
+    ) - 0.5;
+    float len = length(v);
+    return len > 1e-5 ? v / len : vec2(0);
+}
+
+void main() {
+    vec2 canvas_uv = window_to_canvas(v_tex_coord);
+    if (!on_canvas(canvas_uv)) {
+        f_color = border_color;
+        return;
+    }
+    vec2 size = vec2(textureSize(sampler2D(g_texture, g_sampler), 0));
+    vec2 texel = 1.0 / size;
+
+    float sum = noise(canvas_uv, size);
+    float weight = 1.0;
+    for (int sign = -1; sign <= 1; sign += 2) {
+        vec2 uv = canvas_uv;
+        for (int i = 1; i <= STREAMLINE_STEPS; ++i) {
+            uv += float(sign) * direction(uv) * texel;
+            // A tent filter keeps the streaks from ending abruptly.
+            float w = 1.0 - float(i) / float(STREAMLINE_STEPS + 1);
+            sum += noise(uv, size) * w;
+            weight += w;
+        }
+    }
+    float lic = sum / weight;
+
+    vec3 color = layers2rgb(sample_layers(canvas_uv));
+    // Averaging noise pulls it toward a half; stretch it back out.
+    f_color = vec4(present(color * clamp((lic - 0.5) * 4.0 + 0.5, 0, 1) * 1.5), 1);
+}
@@ -0,0 +1,69 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// Ease the exposure toward whatever brings the median lightness to a middle gray,
+// then empty the histogram for the next frame. There is only the one invocation.
+layout(local_size_x = 1, local_size_y = 1, local_size_z = 1) in;
+
+#include <exposure.glsl>
+
+// Mid gray, as CIELAB lightness over 100.
+#define TARGET_LIGHTNESS 0.5
+// How quickly the exposure follows, in e-foldings per second.
+#define ADAPT_RATE 1.5
+#define MIN_EXPOSURE 0.125
+#define MAX_EXPOSURE 8.0
+
+// CIELAB lightness to relative luminance, both in [0,1].
+float luminance(float lightness) {
+    float l = lightness * 100.0;
+    return l > 8.0 ? pow((l + 16.0) / 116.0, 3.0) : l / 903.3;
+}
+
+void main()
+{
+    uint total = 0u;
+    for (int i = 0; i < BIN_COUNT; ++i) {
+        total += bins[i];
+    }
+
+    if (mode == EXPOSURE_MANUAL) {
+        exposure = manual_exposure;
+    } else if (mode == EXPOSURE_AUTO && total > 0u) {
+        uint seen = 0u;
+        int median = BIN_COUNT - 1;
+        for (int i = 0; i < BIN_COUNT; ++i) {
+            seen += bins[i];
+            if (seen * 2u >= total) {
+                median = i;
+                break;
+            }
+        }
+        float lightness = float(median) / float(BIN_COUNT - 1);
+        float goal = clamp(
+            luminance(TARGET_LIGHTNESS) / max(luminance(lightness), 1e-4),
+            MIN_EXPOSURE,
+            MAX_EXPOSURE
+        );
+        // Smooth in stops, so that brightening and darkening take as long.
+        float t = 1.0 - exp(-dt * ADAPT_RATE);
+        exposure = exp(mix(log(max(exposure, MIN_EXPOSURE)), log(goal), t));
+    }
+
+    for (int i = 0; i < BIN_COUNT; ++i) {
+        bins[i] = 0u;
+    }
+}
@@ -0,0 +1,40 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// Count the texels of a small mip of the layers into bins, by the lightness of
+// the color that they make on screen.
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+#include <exposure.glsl>
+#include <color.glsl>
+
+float fetch(texture2D layer, ivec2 p) {
+    return texelFetch(sampler2D(layer, layer_sampler), p, int(lod)).r;
+}
+
+void main()
+{
+    ivec2 p = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = textureSize(sampler2D(r_texture, layer_sampler), int(lod));
+    if (any(greaterThanEqual(p, size))) {
+        return;
+    }
+    vec3 srgb = clamp(layers2rgb(vec3(fetch(r_texture, p), fetch(g_texture, p), fetch(b_texture, p))), 0.0, 1.0);
+    float y = dot(srgb_to_linear(srgb), vec3(0.2126, 0.7152, 0.0722));
+    float lightness = y > 0.008856 ? 1.16 * pow(y, 1.0 / 3.0) - 0.16 : 9.033 * y;
+    uint bin = uint(clamp(lightness, 0.0, 1.0) * float(BIN_COUNT - 1) + 0.5);
+    atomicAdd(bins[bin], 1u);
+}
@@ -0,0 +1,36 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+#include <post.glsl>
+
+float hash(vec3 p) {
+    p = fract(p * vec3(0.1031, 0.1030, 0.0973));
+    p += dot(p, p.yzx + 33.33);
+    return fract((p.x + p.y) * p.z);
+}
+
+// params0: how far the grain moves each pixel, in [0,1], and how big each grain
+// is, in pixels. A new grain every frame, from the time.
+void main() {
+    vec2 grain = floor(gl_FragCoord.xy / params0.y);
+    float noise = hash(vec3(grain, floor(time * 60.0))) - 0.5;
+    vec3 color = sample_source(v_tex_coord) + noise * params0.x;
+    f_color = vec4(clamp(color, 0.0, 1.0), 1.0);
+}
@@ -0,0 +1,53 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// Count a grid of points on the picture into bins, per channel and by luminance,
+// as the colors would be shown after the final pass.
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+#include <draw.glsl>
+#include <color.glsl>
+#include <vision.glsl>
+
+// Must match BIN_COUNT and GRID_STRIDE in src/histogram.rs.
+#define BIN_COUNT 64
+#define GRID_STRIDE 4
+
+// Red, green, blue, then luminance, BIN_COUNT bins each. The set before is the
+// display's own, shared with draw.frag.glsl.
+layout(set = 1, binding = 0) buffer Histogram {
+    uint bins[4 * BIN_COUNT];
+};
+
+uint bin_of(float v) {
+    return uint(clamp(v, 0.0, 1.0) * float(BIN_COUNT - 1) + 0.5);
+}
+
+void main()
+{
+    ivec2 size = textureSize(sampler2D(r_texture, r_sampler), 0);
+    ivec2 p = ivec2(gl_GlobalInvocationID.xy) * GRID_STRIDE;
+    if (any(greaterThanEqual(p, size))) {
+        return;
+    }
+    vec2 uv = (vec2(p) + 0.5) / vec2(size);
+    vec3 srgb = present(layers2rgb(sample_layers(uv)));
+    float luminance = dot(srgb, vec3(0.2126, 0.7152, 0.0722));
+    atomicAdd(bins[bin_of(srgb.r)], 1u);
+    atomicAdd(bins[BIN_COUNT + bin_of(srgb.g)], 1u);
+    atomicAdd(bins[2 * BIN_COUNT + bin_of(srgb.b)], 1u);
+    atomicAdd(bins[3 * BIN_COUNT + bin_of(luminance)], 1u);
+}
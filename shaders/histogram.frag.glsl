@@ -0,0 +1,50 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+// Must match BIN_COUNT in src/histogram.rs.
+#define BIN_COUNT 64
+
+// As counted by histogram.comp.glsl.
+layout(binding = 0) readonly buffer Histogram {
+    uint bins[4 * BIN_COUNT];
+};
+
+// The bins as bars, scaled to the fullest: luminance in gray behind, and the
+// channels in their own colors over it, adding where they overlap.
+void main() {
+    uint fullest = 1u;
+    for (int i = 0; i < 4 * BIN_COUNT; ++i) {
+        fullest = max(fullest, bins[i]);
+    }
+    int bin = clamp(int(v_tex_coord.x * float(BIN_COUNT)), 0, BIN_COUNT - 1);
+    float height = 1.0 - v_tex_coord.y;
+    vec3 color = vec3(0.0);
+    for (int channel = 0; channel < 3; ++channel) {
+        float bar = float(bins[channel * BIN_COUNT + bin]) / float(fullest);
+        if (height < bar) {
+            color[channel] = 0.8;
+        }
+    }
+    float luminance = float(bins[3 * BIN_COUNT + bin]) / float(fullest);
+    if (height < luminance && color == vec3(0.0)) {
+        color = vec3(0.4);
+    }
+    f_color = vec4(color, color == vec3(0.0) ? 0.5 : 0.9);
+}
@@ -0,0 +1,76 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// Text is a grid of ascii bytes, packed four to a uint. Glyphs are 3x5 pixels in a
+// 4x6 cell so that neighbors get a pixel of spacing, then scaled up by an integer.
+#define HUD_COLS 64
+#define HUD_ROWS 32
+#define HUD_TEXT_VECS (HUD_COLS * HUD_ROWS / 16)
+#define GLYPH_WIDTH 3
+#define GLYPH_HEIGHT 5
+#define CELL_WIDTH 4
+#define CELL_HEIGHT 6
+
+layout(location = 0) out vec4 f_color;
+
+layout(binding = 0) uniform readonly HudConfiguration {
+    ivec2 screen_size;
+    ivec2 origin;
+    ivec2 extent;
+    int scale;
+};
+layout(binding = 1) uniform readonly HudText {
+    uvec4 text[HUD_TEXT_VECS];
+};
+
+// Ascii 32 (space) through 95 (underscore); bit (y * 3 + x) is set for lit pixels.
+const uint FONT[64] = uint[](
+    0x0000u, 0x2092u, 0x002Du, 0x5F7Du, 0x3C9Eu, 0x42A1u, 0x6AAAu, 0x0012u,
+    0x4494u, 0x1491u, 0x0AA8u, 0x05D0u, 0x1400u, 0x01C0u, 0x2000u, 0x12A4u,
+    0x7B6Fu, 0x749Au, 0x73E7u, 0x79E7u, 0x49EDu, 0x79CFu, 0x7BCFu, 0x4927u,
+    0x7BEFu, 0x79EFu, 0x0410u, 0x1410u, 0x4454u, 0x0E38u, 0x1511u, 0x20A7u,
+    0x636Au, 0x5BEAu, 0x3AEBu, 0x624Eu, 0x3B6Bu, 0x72CFu, 0x12CFu, 0x6B4Eu,
+    0x5BEDu, 0x7497u, 0x2B24u, 0x5AEDu, 0x7249u, 0x5BFDu, 0x5B6Bu, 0x2B6Au,
+    0x12EBu, 0x676Au, 0x5AEBu, 0x388Eu, 0x2497u, 0x7B6Du, 0x2B6Du, 0x5FEDu,
+    0x5AADu, 0x24ADu, 0x72A7u, 0x324Bu, 0x4889u, 0x6926u, 0x002Au, 0x7000u
+);
+
+uint glyph_at(ivec2 cell) {
+    uint i = uint(cell.y * HUD_COLS + cell.x);
+    uint word = text[i / 16u][(i / 4u) % 4u];
+    uint c = (word >> (8u * (i % 4u))) & 0xFFu;
+    if (c < 32u || c >= 96u) {
+        return 0u;
+    }
+    return FONT[c - 32u];
+}
+
+void main() {
+    ivec2 p = ivec2(gl_FragCoord.xy) - origin;
+    f_color = vec4(0, 0, 0, 0.5);
+    if (p.x < 0 || p.y < 0) {
+        return;
+    }
+    p /= scale;
+    ivec2 cell = p / ivec2(CELL_WIDTH, CELL_HEIGHT);
+    ivec2 sub = p % ivec2(CELL_WIDTH, CELL_HEIGHT);
+    if (cell.x >= extent.x || cell.y >= extent.y || sub.x >= GLYPH_WIDTH || sub.y >= GLYPH_HEIGHT) {
+        return;
+    }
+    if (((glyph_at(cell) >> uint(sub.y * GLYPH_WIDTH + sub.x)) & 1u) == 1u) {
+        f_color = vec4(1);
+    }
+}
@@ -0,0 +1,40 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+#define CELL_WIDTH 4
+#define CELL_HEIGHT 6
+
+layout(binding = 0) uniform readonly HudConfiguration {
+    ivec2 screen_size;
+    ivec2 origin;
+    ivec2 extent;
+    int scale;
+};
+
+void main() {
+    // Cover the text block plus a small margin; no vertex buffer needed.
+    vec2 pad = vec2(2 * scale);
+    vec2 lo = vec2(origin) - pad;
+    vec2 hi = vec2(origin + extent * ivec2(CELL_WIDTH, CELL_HEIGHT) * scale) + pad;
+    vec2 corner = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1);
+    vec2 px = mix(lo, hi, corner);
+    gl_Position = vec4(
+        px.x / float(screen_size.x) * 2.0 - 1.0,
+        1.0 - px.y / float(screen_size.y) * 2.0,
+        0.0,
+        1.0
+    );
+}
@@ -0,0 +1,35 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) out vec4 f_color;
+
+// A view of just the next larger mip level.
+layout(binding = 0) uniform texture2D src_texture;
+layout(binding = 1) uniform sampler src_sampler;
+
+void main() {
+    // Box filter the 2x2 block of the larger level under this texel. Fetching by
+    // texel index keeps us independent of the clip-space orientation.
+    ivec2 src_max = textureSize(sampler2D(src_texture, src_sampler), 0) - 1;
+    ivec2 src = ivec2(gl_FragCoord.xy) * 2;
+    vec4 acc = vec4(0);
+    for (int y = 0; y < 2; ++y) {
+        for (int x = 0; x < 2; ++x) {
+            acc += texelFetch(sampler2D(src_texture, src_sampler), min(src + ivec2(x, y), src_max), 0);
+        }
+    }
+    f_color = acc / 4.0;
+}
@@ -0,0 +1,43 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+#include <post.glsl>
+
+// params0: thickness in pixels, blend in [0,1]; params1: ink, as sRGB.
+float luminance_at(vec2 offset) {
+    vec2 uv = v_tex_coord + offset * params0.x / screen_size;
+    return dot(sample_source(uv), vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Sobel on the brightness of the finished picture: ink where it changes sharply.
+void main() {
+    float tl = luminance_at(vec2(-1, -1));
+    float t = luminance_at(vec2(0, -1));
+    float tr = luminance_at(vec2(1, -1));
+    float l = luminance_at(vec2(-1, 0));
+    float r = luminance_at(vec2(1, 0));
+    float bl = luminance_at(vec2(-1, 1));
+    float b = luminance_at(vec2(0, 1));
+    float br = luminance_at(vec2(1, 1));
+    float gx = (tr + 2.0 * r + br) - (tl + 2.0 * l + bl);
+    float gy = (bl + 2.0 * b + br) - (tl + 2.0 * t + tr);
+    float edge = smoothstep(0.1, 0.5, length(vec2(gx, gy)));
+    f_color = vec4(mix(sample_source(v_tex_coord), params1.rgb, edge * params0.y), 1.0);
+}
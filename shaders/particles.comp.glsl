@@ -0,0 +1,69 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// Move every particle uphill on one layer, and start it again somewhere random
+// when it gets old or leaves the screen. Positions are in texture coordinates.
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+layout(binding = 0) uniform Configuration {
+    float dt;
+    float speed;
+    float lifetime;
+    float time;
+};
+
+struct Particle {
+    vec2 position;
+    float age;
+    float seed;
+};
+
+layout(binding = 1) buffer Particles {
+    Particle particles[];
+};
+
+layout(binding = 2) uniform texture2D field_texture;
+layout(binding = 3) uniform sampler field_sampler;
+
+float hash(vec2 p) {
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+float field(vec2 uv) {
+    return textureLod(sampler2D(field_texture, field_sampler), uv, 0).r;
+}
+
+void main()
+{
+    uint i = gl_GlobalInvocationID.x;
+    Particle p = particles[i];
+
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(field_texture, field_sampler), 0));
+    vec2 gradient = vec2(
+        field(p.position + vec2(texel.x, 0)) - field(p.position - vec2(texel.x, 0)),
+        field(p.position + vec2(0, texel.y)) - field(p.position - vec2(0, texel.y))
+    ) / (2.0 * texel);
+    // Steep slopes would fling particles straight off the screen, so only let the
+    // gradient steer up to a top speed.
+    p.position += gradient / (1.0 + length(gradient)) * speed * dt;
+    p.age += dt;
+
+    if (p.age > lifetime || any(lessThan(p.position, vec2(0))) || any(greaterThan(p.position, vec2(1)))) {
+        p.position = vec2(hash(vec2(p.seed, time)), hash(vec2(time, p.seed)));
+        p.age = 0.0;
+    }
+    particles[i] = p;
+}
@@ -0,0 +1,34 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in float age;
+
+layout(location = 0) out float v_alpha;
+
+layout(binding = 0) uniform Configuration {
+    float dt;
+    float speed;
+    float lifetime;
+    float time;
+};
+
+void main() {
+    // Fade in and out over the particle's life, so that respawning does not pop.
+    v_alpha = sin(3.14159265 * clamp(age / lifetime, 0.0, 1.0));
+    gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+    gl_PointSize = 2.0;
+}
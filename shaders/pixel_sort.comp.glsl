@@ -0,0 +1,87 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// Brightness is sorted into this many steps, so that a span can be sorted in two
+// passes over it, by counting, rather than compared pixel by pixel.
+#define BUCKETS 64
+
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+#include <post.glsl>
+
+// params0: the threshold, in [0,1], at or above which spans of pixels are sorted
+// and the rest stay put; and nonzero to sort down the columns, not along the rows.
+layout(binding = 3, rgba8) uniform writeonly image2D target_texture;
+
+ivec2 texel(int line, int i) {
+    return params0.y != 0.0 ? ivec2(line, i) : ivec2(i, line);
+}
+
+vec4 fetch(int line, int i) {
+    return texelFetch(sampler2D(source_texture, source_sampler), texel(line, i), 0);
+}
+
+float brightness(vec4 c) {
+    return dot(c.rgb, vec3(0.2126, 0.7152, 0.0722));
+}
+
+int bucket(vec4 c) {
+    return clamp(int(brightness(c) * float(BUCKETS)), 0, BUCKETS - 1);
+}
+
+// One invocation for each row, or column, which it walks from end to end.
+void main() {
+    float threshold = params0.x;
+    bool vertical = params0.y != 0.0;
+    ivec2 size = ivec2(screen_size);
+    int line = int(gl_GlobalInvocationID.x);
+    int lines = vertical ? size.x : size.y;
+    int length = vertical ? size.y : size.x;
+    if (line >= lines) {
+        return;
+    }
+    uint offsets[BUCKETS];
+    int i = 0;
+    while (i < length) {
+        vec4 c = fetch(line, i);
+        if (brightness(c) < threshold) {
+            imageStore(target_texture, texel(line, i), c);
+            ++i;
+            continue;
+        }
+        int start = i;
+        for (int b = 0; b < BUCKETS; ++b) {
+            offsets[b] = 0;
+        }
+        while (i < length && brightness(fetch(line, i)) >= threshold) {
+            offsets[bucket(fetch(line, i))] += 1;
+            ++i;
+        }
+        // Counts to where each bucket starts, darkest first.
+        uint total = 0;
+        for (int b = 0; b < BUCKETS; ++b) {
+            uint count = offsets[b];
+            offsets[b] = total;
+            total += count;
+        }
+        for (int j = start; j < i; ++j) {
+            vec4 s = fetch(line, j);
+            int b = bucket(s);
+            imageStore(target_texture, texel(line, start + int(offsets[b])), s);
+            offsets[b] += 1;
+        }
+    }
+}
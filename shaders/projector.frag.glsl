@@ -0,0 +1,42 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// The layers as draw.frag.glsl shows them, faded toward the edges that overlap
+// another projector so that the overlap is no brighter than the rest.
+layout(location = 0) in vec2 v_tex_coord;
+layout(location = 1) in vec2 v_output_coord;
+layout(location = 2) flat in vec4 v_blend_edges;
+layout(location = 3) flat in float v_gamma;
+
+layout(location = 0) out vec4 f_color;
+
+#include <draw.glsl>
+#include <color.glsl>
+#include <vision.glsl>
+
+float ramp(float distance_in, float width) {
+    return width > 0.0 ? smoothstep(0.0, 1.0, clamp(distance_in / width, 0.0, 1.0)) : 1.0;
+}
+
+void main() {
+    vec2 p = v_output_coord;
+    float blend = ramp(p.x, v_blend_edges.x)
+        * ramp(1.0 - p.x, v_blend_edges.y)
+        * ramp(p.y, v_blend_edges.z)
+        * ramp(1.0 - p.y, v_blend_edges.w);
+    // The ramps of neighbouring projectors add up to one in light, not in signal.
+    f_color = vec4(present(layers2rgb(sample_layers(v_tex_coord))) * pow(blend, 1.0 / v_gamma), 1);
+}
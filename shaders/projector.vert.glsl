@@ -0,0 +1,34 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 tex_coord;
+layout(location = 2) in vec2 output_coord;
+layout(location = 3) in vec4 blend_edges;
+layout(location = 4) in float gamma;
+
+layout(location = 0) out vec2 v_tex_coord;
+layout(location = 1) out vec2 v_output_coord;
+layout(location = 2) flat out vec4 v_blend_edges;
+layout(location = 3) flat out float v_gamma;
+
+void main() {
+    v_tex_coord = tex_coord;
+    v_output_coord = output_coord;
+    v_blend_edges = blend_edges;
+    v_gamma = gamma;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
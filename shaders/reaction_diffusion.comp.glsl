@@ -0,0 +1,48 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// One step of the Gray-Scott model. Feed and kill vary over the plane, from two
+// layers of a tree, which are stored in [0,1].
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+layout(binding = 0, rgba32f) uniform readonly image2D state_in;
+layout(binding = 1, rgba32f) uniform writeonly image2D state_out;
+layout(binding = 2, r32f) uniform readonly image2D feed_texture;
+layout(binding = 3, r32f) uniform readonly image2D kill_texture;
+
+#define DIFFUSION_U 1.0
+#define DIFFUSION_V 0.5
+
+vec2 load(ivec2 p) {
+    ivec2 size = imageSize(state_in);
+    return imageLoad(state_in, (p + size) % size).xy;
+}
+
+void main()
+{
+    ivec2 p = ivec2(gl_GlobalInvocationID.xy);
+    vec2 c = load(p);
+    vec2 laplacian = -c
+        + 0.2 * (load(p + ivec2(1, 0)) + load(p + ivec2(-1, 0)) + load(p + ivec2(0, 1)) + load(p + ivec2(0, -1)))
+        + 0.05 * (load(p + ivec2(1, 1)) + load(p + ivec2(-1, 1)) + load(p + ivec2(1, -1)) + load(p + ivec2(-1, -1)));
+    float feed = mix(0.01, 0.1, clamp(imageLoad(feed_texture, p).r, 0, 1));
+    float kill = mix(0.045, 0.07, clamp(imageLoad(kill_texture, p).r, 0, 1));
+    float u = c.x;
+    float v = c.y;
+    float uvv = u * v * v;
+    u += DIFFUSION_U * laplacian.x - uvv + feed * (1.0 - u);
+    v += DIFFUSION_V * laplacian.y + uvv - (feed + kill) * v;
+    imageStore(state_out, p, vec4(clamp(u, 0, 1), clamp(v, 0, 1), 0, 0));
+}
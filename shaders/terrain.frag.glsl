@@ -0,0 +1,41 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+#include <terrain.glsl>
+#include <color.glsl>
+
+void main() {
+    // The normal comes from the height map itself, per pixel, so that detail finer
+    // than the grid still catches the light.
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(r_texture, terrain_sampler), 0));
+    vec3 dx = terrain_position(v_tex_coord + vec2(texel.x, 0)) - terrain_position(v_tex_coord - vec2(texel.x, 0));
+    vec3 dy = terrain_position(v_tex_coord + vec2(0, texel.y)) - terrain_position(v_tex_coord - vec2(0, texel.y));
+    vec3 normal = normalize(cross(dx, dy));
+    vec3 sun = normalize(vec3(0.4, 0.8, 0.3));
+    float light = 0.15 + 0.85 * max(dot(normal, sun), 0.0);
+
+    // The light takes the place of the red layer's lightness; the other two layers
+    // keep their meaning as color.
+    f_color = vec4(layers2rgb(vec3(
+        light,
+        texture(sampler2D(g_texture, terrain_sampler), v_tex_coord).r,
+        texture(sampler2D(b_texture, terrain_sampler), v_tex_coord).r
+    )), 1);
+}
@@ -0,0 +1,53 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 tex_coord;
+
+layout(location = 0) out vec2 v_tex_coord;
+
+#include <terrain.glsl>
+
+mat4 look_at(vec3 eye, vec3 target, vec3 up) {
+    vec3 f = normalize(target - eye);
+    vec3 s = normalize(cross(f, up));
+    vec3 u = cross(s, f);
+    return mat4(
+        vec4(s.x, u.x, -f.x, 0),
+        vec4(s.y, u.y, -f.y, 0),
+        vec4(s.z, u.z, -f.z, 0),
+        vec4(-dot(s, eye), -dot(u, eye), dot(f, eye), 1)
+    );
+}
+
+// Right handed, into a depth range of [0,1].
+mat4 perspective(float fov_y, float aspect, float near, float far) {
+    float f = 1.0 / tan(fov_y / 2.0);
+    return mat4(
+        vec4(f / aspect, 0, 0, 0),
+        vec4(0, f, 0, 0),
+        vec4(0, 0, far / (near - far), -1),
+        vec4(0, 0, near * far / (near - far), 0)
+    );
+}
+
+void main() {
+    float angle = time * 0.1;
+    vec3 eye = vec3(sin(angle) * 2.2, 1.3, cos(angle) * 2.2);
+    mat4 view = look_at(eye, vec3(0), vec3(0, 1, 0));
+    mat4 projection = perspective(0.9, aspect_ratio, 0.05, 10.0);
+    v_tex_coord = tex_coord;
+    gl_Position = projection * view * vec4(terrain_position(tex_coord), 1.0);
+}
@@ -0,0 +1,32 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+#include <post.glsl>
+#include <color.glsl>
+
+// params0: how many stops to brighten, or with less than 0 darken, the picture
+// by, and the linear brightness, after that, that comes out as white. Extended
+// Reinhard, on the light rather than on the sRGB that the picture is held in.
+void main() {
+    vec3 light = srgb_to_linear(sample_source(v_tex_coord)) * exp2(params0.x);
+    float white = max(params0.y, 1e-3);
+    vec3 mapped = light * (1.0 + light / (white * white)) / (1.0 + light);
+    f_color = vec4(clamp(linear_to_srgb(mapped), 0.0, 1.0), 1.0);
+}
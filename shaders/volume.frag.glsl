@@ -0,0 +1,101 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+#version 450
+
+// Raymarch the layers as a volume: the red layer is the density of a cloud and
+// all three give its color. The camera circles the volume slowly.
+layout(location = 0) in vec2 v_ndc;
+
+layout(location = 0) out vec4 f_color;
+
+layout(binding = 0) uniform readonly Configuration {
+    ivec2 texture_size;
+    ivec2 texture_offsets;
+    vec2 mouse_position;
+    float time;
+    float aspect_ratio;
+    vec2 view_center;
+    float view_scale;
+    uint volume_slices;
+};
+layout(binding = 1) uniform texture2D r_texture;
+layout(binding = 2) uniform texture2D g_texture;
+layout(binding = 3) uniform texture2D b_texture;
+layout(binding = 4) uniform sampler volume_sampler;
+
+#include <color.glsl>
+
+// Must match VOLUME_SIDE and VOLUME_SLICES in src/volume.rs.
+#define VOLUME_SIDE 96.0
+#define VOLUME_SLICES 64
+#define MARCH_STEPS 128
+
+vec3 sample_slices(vec2 uv) {
+    return vec3(
+        textureLod(sampler2D(r_texture, volume_sampler), uv, 0).r,
+        textureLod(sampler2D(g_texture, volume_sampler), uv, 0).r,
+        textureLod(sampler2D(b_texture, volume_sampler), uv, 0).r
+    );
+}
+
+// The slices are stacked from top to bottom in one texture, so blend between the
+// two nearest by hand, and keep clear of the edges so that filtering does not
+// bleed from one slice into the next.
+vec3 sample_volume(vec3 p) {
+    float z = (p.z * 0.5 + 0.5) * float(VOLUME_SLICES - 1);
+    float z0 = floor(z);
+    float z1 = min(z0 + 1.0, float(VOLUME_SLICES - 1));
+    vec2 uv = clamp(p.xy * 0.5 + 0.5, 0.5 / VOLUME_SIDE, 1.0 - 0.5 / VOLUME_SIDE);
+    vec3 a = sample_slices(vec2(uv.x, (z0 + uv.y) / float(VOLUME_SLICES)));
+    vec3 b = sample_slices(vec2(uv.x, (z1 + uv.y) / float(VOLUME_SLICES)));
+    return mix(a, b, z - z0);
+}
+
+// Where the ray is inside the cube from -1 to 1, if anywhere.
+bool intersect_cube(vec3 origin, vec3 dir, out float near, out float far) {
+    vec3 inv = 1.0 / dir;
+    vec3 t0 = (vec3(-1) - origin) * inv;
+    vec3 t1 = (vec3(1) - origin) * inv;
+    vec3 lo = min(t0, t1);
+    vec3 hi = max(t0, t1);
+    near = max(max(lo.x, lo.y), max(lo.z, 0.0));
+    far = min(min(hi.x, hi.y), hi.z);
+    return near < far;
+}
+
+void main() {
+    float angle = time * 0.2;
+    vec3 eye = vec3(sin(angle), 0.35, cos(angle)) * 3.2;
+    vec3 forward = normalize(-eye);
+    vec3 right = normalize(cross(forward, vec3(0, 1, 0)));
+    vec3 up = cross(right, forward);
+    // The aspect ratio is the display's, width over height.
+    vec3 dir = normalize(forward * 1.8 + right * v_ndc.x * aspect_ratio + up * v_ndc.y);
+
+    vec3 color = vec3(0);
+    float alpha = 0.0;
+    float near, far;
+    if (intersect_cube(eye, dir, near, far)) {
+        float step_length = 2.0 * sqrt(3.0) / float(MARCH_STEPS);
+        for (float t = near; t < far && alpha < 0.99; t += step_length) {
+            vec3 layers = sample_volume(eye + dir * t);
+            float density = smoothstep(0.45, 0.75, layers.x) * 12.0;
+            float a = 1.0 - exp(-density * step_length);
+            color += (1.0 - alpha) * a * layers2rgb(layers);
+            alpha += (1.0 - alpha) * a;
+        }
+    }
+    f_color = vec4(color, 1);
+}
@@ -0,0 +1,177 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::Result;
+use gpu::GPU;
+use rand::prelude::*;
+use stampede::{
+    compute::{self, Configuration, OffscreenLayer},
+    tree::Tree,
+    workgroup::Interpreter,
+};
+use std::time::{Duration, Instant};
+use wgpu;
+
+// Trees are grouped by their total node count: [0,32), [32,64), [64,128), [128,...).
+const BUCKET_BOUNDS: [usize; 3] = [32, 64, 128];
+
+#[derive(Default)]
+struct Bucket {
+    trees: usize,
+    encode_time: Duration,
+    upload_bytes: wgpu::BufferAddress,
+    frame_times: Vec<Duration>,
+}
+
+impl Bucket {
+    fn label(index: usize) -> String {
+        match (
+            index.checked_sub(1).map(|i| BUCKET_BOUNDS[i]),
+            BUCKET_BOUNDS.get(index),
+        ) {
+            (None, Some(hi)) => format!("<{}", hi),
+            (Some(lo), Some(hi)) => format!("{}-{}", lo, hi - 1),
+            (Some(lo), None) => format!("{}+", lo),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    fn percentile(sorted: &[Duration], p: f64) -> Duration {
+        sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+    }
+
+    fn report(&mut self, index: usize) {
+        if self.frame_times.is_empty() {
+            return;
+        }
+        self.frame_times.sort();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let fps = |d: Duration| 1.0 / d.as_secs_f64();
+        let p50 = Self::percentile(&self.frame_times, 0.5);
+        let p90 = Self::percentile(&self.frame_times, 0.9);
+        let p99 = Self::percentile(&self.frame_times, 0.99);
+        println!(
+            "{:>8} {:>6} {:>10.1} {:>10} {:>8.2} {:>8.2} {:>8.2} {:>8.0} {:>8.0} {:>8.0}",
+            Self::label(index),
+            self.trees,
+            self.encode_time.as_secs_f64() * 1_000_000.0 / self.trees as f64,
+            self.upload_bytes / self.trees as wgpu::BufferAddress,
+            ms(p50),
+            ms(p90),
+            ms(p99),
+            fps(p99),
+            fps(p50),
+            fps(self.frame_times[0]),
+        );
+    }
+}
+
+// Renders a run of random trees offscreen and reports where the time goes. The
+// trees are always generated from the same seeds, so that runs are comparable.
+pub fn run(
+    gpu: &mut GPU,
+    tree_count: usize,
+    frame_count: usize,
+    format: wgpu::TextureFormat,
+    extent: wgpu::Extent3d,
+    half: bool,
+    workgroup_size: Option<&str>,
+) -> Result<()> {
+    let layout = compute::create_layout(gpu);
+    let mut config = Configuration::new(extent, extent.width as f32 / extent.height as f32);
+    let config_buffer = config.create_buffer(gpu.device());
+    let interpreter = Interpreter::select(
+        gpu,
+        &layout,
+        &config_buffer,
+        format,
+        extent,
+        half,
+        workgroup_size,
+    )?;
+    let mut buckets = (0..=BUCKET_BOUNDS.len())
+        .map(|_| Bucket::default())
+        .collect::<Vec<_>>();
+
+    for i in 0..tree_count {
+        let tree = Tree::new(&mut StdRng::seed_from_u64(i as u64))?;
+        let node_count = tree.node_count();
+        let bucket = &mut buckets[BUCKET_BOUNDS
+            .iter()
+            .position(|&bound| node_count < bound)
+            .unwrap_or(BUCKET_BOUNDS.len())];
+        bucket.trees += 1;
+
+        // Fresh layers, so that every tree pays for a full upload.
+        let mut layers = (0..3)
+            .map(|_| OffscreenLayer::new(gpu, &layout, &config_buffer, format, extent))
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        let encoded = (0..3)
+            .map(|j| tree.encode_layer(j))
+            .collect::<Result<Vec<_>, _>>()?;
+        bucket.encode_time += start.elapsed();
+        let mut encoder = gpu
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        for (layer, encoded) in layers.iter_mut().zip(encoded) {
+            bucket.upload_bytes += layer.update(encoded, gpu.device(), &mut encoder);
+        }
+        gpu.queue_mut().submit(&[encoder.finish()]);
+
+        for frame in 0..frame_count {
+            config.time = frame as f32 / 60f32;
+            let start = Instant::now();
+            let mut encoder = gpu
+                .device()
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+            config.upload(gpu.device(), &mut encoder, &config_buffer);
+            for layer in &layers {
+                let mut cpass = encoder.begin_compute_pass();
+                cpass.set_pipeline(interpreter.pipeline());
+                cpass.set_bind_group(0, layer.bind_group(), &[]);
+                interpreter.dispatch(&mut cpass, extent);
+            }
+            gpu.queue_mut().submit(&[encoder.finish()]);
+            gpu.device().poll(true);
+            bucket.frame_times.push(start.elapsed());
+        }
+    }
+
+    println!(
+        "{}x{} {}, {} trees of {} frames; encode in us, upload in bytes, per tree",
+        extent.width,
+        extent.height,
+        if half { "f16" } else { "f32" },
+        tree_count,
+        frame_count
+    );
+    println!(
+        "{:>8} {:>6} {:>10} {:>10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+        "nodes",
+        "trees",
+        "encode",
+        "upload",
+        "p50 ms",
+        "p90 ms",
+        "p99 ms",
+        "min fps",
+        "p50 fps",
+        "max fps"
+    );
+    for (index, bucket) in buckets.iter_mut().enumerate() {
+        bucket.report(index);
+    }
+    Ok(())
+}
@@ -0,0 +1,378 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    error::Fallible,
+    ops::{self, OpDescriptor},
+    tree::{Constant, NodeId, Tree, TreeArena},
+};
+
+// Writes a node out by hand rather than rolling it, for trees made on purpose:
+//
+//   let petals = NodeBuilder::flower([0.1, 0.2], 1.0, 7).set("angle", 0.5);
+//   let tree = Tree::build([
+//       NodeBuilder::add(petals, NodeBuilder::value(0.2)),
+//       NodeBuilder::value(0.0),
+//       NodeBuilder::op("radial gradient").animate("angle", 0.0, 0.3),
+//   ])?;
+//
+// Constants are named as in the op's table in ops.rs. Any that are not given sit
+// still in the middle of their range. Mistakes, like an unknown op or constant or
+// too many children, are caught as they are made and reported when the tree is
+// built; too few children are found then too.
+pub struct NodeBuilder {
+    op: Option<&'static OpDescriptor>,
+    constants: Vec<Constant>,
+    children: Vec<NodeBuilder>,
+    error: Option<String>,
+}
+
+impl NodeBuilder {
+    pub fn op(name: &str) -> Self {
+        let op = ops::registered().into_iter().find(|op| op.name == name);
+        let mut builder = Self {
+            op,
+            constants: Vec::new(),
+            children: Vec::new(),
+            error: None,
+        };
+        let op = match op {
+            Some(op) => op,
+            None => {
+                builder.fail(format!("no op named {}", name));
+                return builder;
+            }
+        };
+        let constants = op
+            .constants
+            .iter()
+            .map(|spec| {
+                let middle = (spec.bounds[0] + spec.bounds[1]) / 2f32;
+                Constant::with_value(spec.bounds[0], spec.bounds[1], spec.wrap_mode, middle, 0f32)
+            })
+            .collect::<Fallible<Vec<_>>>();
+        match constants {
+            Ok(constants) => builder.constants = constants,
+            Err(e) => builder.fail(e.to_string()),
+        }
+        builder
+    }
+
+    // Hold a constant still at value.
+    pub fn set(self, name: &str, value: f32) -> Self {
+        self.animate(name, value, 0f32)
+    }
+
+    // Start a constant at value and move it by rate units every second.
+    pub fn animate(mut self, name: &str, value: f32, rate: f32) -> Self {
+        // Without an op, or its constants, there is already an error to report.
+        let op = match self.op {
+            Some(op) if self.constants.len() == op.constants.len() => op,
+            _ => return self,
+        };
+        match op.constants.iter().position(|spec| spec.name == name) {
+            Some(i) => {
+                let spec = &op.constants[i];
+                match Constant::with_value(
+                    spec.bounds[0],
+                    spec.bounds[1],
+                    spec.wrap_mode,
+                    value,
+                    rate,
+                ) {
+                    Ok(constant) => self.constants[i] = constant,
+                    Err(e) => self.fail(e.to_string()),
+                }
+            }
+            None => self.fail(format!("{} has no constant named {}", op.name, name)),
+        }
+        self
+    }
+
+    // Children are taken in the order that the op lists them.
+    pub fn child(mut self, child: NodeBuilder) -> Self {
+        if let Some(op) = self.op {
+            if self.children.len() == op.children.len() {
+                self.fail(format!(
+                    "{} takes {} children, not more",
+                    op.name,
+                    op.children.len()
+                ));
+            }
+        }
+        self.children.push(child);
+        self
+    }
+
+    pub fn value(value: f32) -> Self {
+        Self::op("const").set("value", value)
+    }
+
+    pub fn flower(center: [f32; 2], size: f32, points: u32) -> Self {
+        Self::op("flower")
+            .set("x", center[0])
+            .set("y", center[1])
+            .set("size", size)
+            .set("n_points", points as f32)
+    }
+
+    pub fn ellipse(p0: [f32; 2], p1: [f32; 2], size: f32) -> Self {
+        Self::op("ellipse")
+            .set("p0x", p0[0])
+            .set("p0y", p0[1])
+            .set("p1x", p1[0])
+            .set("p1y", p1[1])
+            .set("size", size)
+    }
+
+    // The input drawn around center in polar coordinates.
+    pub fn polar(center: [f32; 2], input: NodeBuilder) -> Self {
+        Self::op("polar")
+            .set("x", center[0])
+            .set("y", center[1])
+            .child(input)
+    }
+
+    // The input pushed about by noise, by at most amplitude.
+    pub fn warp(amplitude: f32, frequency: f32, input: NodeBuilder) -> Self {
+        Self::op("warp")
+            .set("amplitude", amplitude)
+            .set("frequency", frequency)
+            .child(input)
+    }
+
+    pub fn add(lhs: NodeBuilder, rhs: NodeBuilder) -> Self {
+        Self::op("add").child(lhs).child(rhs)
+    }
+
+    pub fn subtract(lhs: NodeBuilder, rhs: NodeBuilder) -> Self {
+        Self::op("subtract").child(lhs).child(rhs)
+    }
+
+    pub fn multiply(lhs: NodeBuilder, rhs: NodeBuilder) -> Self {
+        Self::op("multiply").child(lhs).child(rhs)
+    }
+
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(message);
+        }
+    }
+
+    pub fn build(self, arena: &mut TreeArena) -> Fallible<NodeId> {
+        if let Some(error) = self.error {
+            bail!(Invalid, "{}", error);
+        }
+        let op = self.op.expect("an op when there is no error");
+        if self.children.len() != op.children.len() {
+            bail!(
+                Invalid,
+                "{} takes {} children, not {}",
+                op.name,
+                op.children.len(),
+                self.children.len()
+            );
+        }
+        let children = self
+            .children
+            .into_iter()
+            .map(|child| child.build(arena))
+            .collect::<Fallible<Vec<_>>>()?;
+        Ok(arena.push(op, self.constants, &children))
+    }
+}
+
+impl Tree {
+    // Red, green and blue.
+    pub fn build(layers: [NodeBuilder; 3]) -> Fallible<Self> {
+        let mut arena = TreeArena::new();
+        let [red, green, blue] = layers;
+        let roots = [
+            red.build(&mut arena)?,
+            green.build(&mut arena)?,
+            blue.build(&mut arena)?,
+        ];
+        let tree = Tree::from_arena(arena, roots);
+        tree.stats().check()?;
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{
+        EvalContext, InstructionEncoder, INSTRUCTION_COUNT, REMAP_DEPTH, STACK_DEPTH,
+    };
+
+    fn stripes() -> NodeBuilder {
+        NodeBuilder::op("linear gradient").set("p1x", 0.5)
+    }
+
+    // The interpreter, stepped through on the CPU, has to end where evaluating the
+    // tree does, in every layer.
+    fn assert_trace_matches(tree: &Tree, ctx: &EvalContext) -> Fallible<()> {
+        let encoded = InstructionEncoder::encode(tree)?;
+        for offset in 0..3 {
+            let steps = encoded.layer(offset).trace(ctx)?;
+            let traced = steps.last().expect("a step").stack[0];
+            assert!((traced - tree.evaluate(offset, ctx)).abs() < 1e-5);
+        }
+        Ok(())
+    }
+
+    // Points spread over the picture, at full quality.
+    fn sample_grid() -> Vec<EvalContext> {
+        (0..64)
+            .map(|i| EvalContext {
+                position: [(i % 8) as f32 / 4.0 - 1.0, (i / 8) as f32 / 5.0 - 0.8],
+                mouse: [0.5, 0.5],
+                time: 0f32,
+                draft: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn built_trees_take_the_given_constants() -> Fallible<()> {
+        let tree = Tree::build([
+            NodeBuilder::flower([0.1, 0.2], 1.0, 7),
+            NodeBuilder::add(NodeBuilder::value(0.5), NodeBuilder::value(-0.5)),
+            NodeBuilder::value(0.0),
+        ])?;
+        assert_eq!(tree.node_count(), 5);
+        assert!(tree.show().contains("n_points=7.00"));
+        Ok(())
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        assert!(Tree::build([
+            NodeBuilder::op("flower").set("petals", 7.0),
+            NodeBuilder::value(0.0),
+            NodeBuilder::value(0.0),
+        ])
+        .is_err());
+        assert!(Tree::build([
+            NodeBuilder::op("add").child(NodeBuilder::value(0.0)),
+            NodeBuilder::value(0.0),
+            NodeBuilder::value(0.0),
+        ])
+        .is_err());
+        let crowded = NodeBuilder::value(0.0).child(NodeBuilder::value(0.0));
+        assert_eq!(
+            crowded.error.as_ref().map(String::as_str),
+            Some("const takes 0 children, not more")
+        );
+    }
+
+    #[test]
+    fn the_stack_holds_any_tree_that_fits_the_instructions() {
+        // Each add down the right side holds one more value on the stack.
+        let comb = |adds| {
+            (0..adds).fold(NodeBuilder::value(0.0), |rest, _| {
+                NodeBuilder::add(NodeBuilder::value(0.0), rest)
+            })
+        };
+        let deepest = (INSTRUCTION_COUNT - 1) / 2;
+        let fits = Tree::build([comb(deepest), comb(1), comb(0)]).expect("a tree");
+        assert_eq!(fits.stats().stack_depth(), deepest + 1);
+        assert!(fits.stats().stack_depth() <= STACK_DEPTH);
+        assert_eq!(fits.stats().layers[2].stack_depth, 1);
+        assert!(Tree::build([comb(deepest + 1), comb(1), comb(0)]).is_err());
+    }
+
+    #[test]
+    fn polar_remaps_its_input_on_the_cpu_and_in_the_encoding() -> Fallible<()> {
+        let tree = Tree::build([
+            NodeBuilder::polar([0.2, -0.1], stripes()),
+            NodeBuilder::add(NodeBuilder::polar([0.0, 0.0], stripes()), stripes()),
+            NodeBuilder::value(0.0),
+        ])?;
+        let decoded = InstructionEncoder::decode(&InstructionEncoder::encode(&tree)?)?;
+        assert_eq!(tree.to_json()?, decoded.to_json()?);
+        for &position in &[[0.5, 0.25], [-0.3, 0.6]] {
+            let ctx = EvalContext {
+                position,
+                mouse: [0.5, 0.5],
+                time: 0f32,
+                draft: false,
+            };
+            // A plain gradient would not care that its input moved.
+            assert_ne!(
+                tree.evaluate(0, &ctx),
+                tree.evaluate_node(tree.node_at("r/0").expect("a node"), &ctx)
+            );
+            assert_trace_matches(&tree, &ctx)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn remaps_too_deep_to_restore_are_turned_away() {
+        let nest = |polars| {
+            (0..polars).fold(NodeBuilder::op("linear gradient"), |inner, _| {
+                NodeBuilder::polar([0.0, 0.0], inner)
+            })
+        };
+        let fits = Tree::build([nest(REMAP_DEPTH), nest(0), nest(0)]).expect("a tree");
+        assert_eq!(fits.stats().layers[0].remap_depth, REMAP_DEPTH);
+        assert!(Tree::build([nest(REMAP_DEPTH + 1), nest(0), nest(0)]).is_err());
+    }
+
+    #[test]
+    fn warp_moves_its_input_unless_its_amplitude_is_zero() -> Fallible<()> {
+        let tree = Tree::build([
+            NodeBuilder::warp(0.3, 2.0, stripes()),
+            NodeBuilder::warp(0.0, 2.0, stripes()),
+            NodeBuilder::polar([0.0, 0.0], NodeBuilder::warp(0.1, 4.0, stripes())),
+        ])?;
+        let (mut moved, mut still) = (0, 0);
+        for ctx in sample_grid() {
+            let plain = tree.evaluate_node(tree.node_at("r/0").expect("a node"), &ctx);
+            if (tree.evaluate(0, &ctx) - plain).abs() > 1e-5 {
+                moved += 1;
+            }
+            if (tree.evaluate(1, &ctx) - plain).abs() < 1e-5 {
+                still += 1;
+            }
+            assert_trace_matches(&tree, &ctx)?;
+        }
+        assert!(moved > 0);
+        assert_eq!(still, 64);
+        Ok(())
+    }
+
+    #[test]
+    fn drafts_warp_with_fewer_octaves() -> Fallible<()> {
+        let tree = Tree::build([
+            NodeBuilder::warp(0.3, 2.0, stripes()),
+            NodeBuilder::value(0.0),
+            NodeBuilder::value(0.0),
+        ])?;
+        let mut differed = 0;
+        for full in sample_grid() {
+            let draft = EvalContext {
+                draft: true,
+                ..full.clone()
+            };
+            if (tree.evaluate(0, &full) - tree.evaluate(0, &draft)).abs() > 1e-5 {
+                differed += 1;
+            }
+            assert_trace_matches(&tree, &draft)?;
+        }
+        assert!(differed > 0);
+        Ok(())
+    }
+}
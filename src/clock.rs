@@ -0,0 +1,49 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use std::time::Instant;
+
+// How far the animation moves each frame. Interactive display follows the wall
+// clock; exports step by exactly one frame at their frame rate, so that they come
+// out the same however long each frame takes to render.
+pub enum Clock {
+    Wall(Instant),
+    Fixed(f32),
+}
+
+impl Clock {
+    pub fn new(fixed_fps: Option<f32>) -> Self {
+        match fixed_fps {
+            Some(fps) => Self::fixed(fps),
+            None => Clock::Wall(Instant::now()),
+        }
+    }
+
+    pub fn fixed(fps: f32) -> Self {
+        Clock::Fixed(1f32 / fps.max(1f32))
+    }
+
+    // Seconds since the last tick.
+    pub fn tick(&mut self) -> f32 {
+        match self {
+            Clock::Wall(last) => {
+                let now = Instant::now();
+                let dt = (now - *last).as_secs_f32();
+                *last = now;
+                dt
+            }
+            Clock::Fixed(dt) => *dt,
+        }
+    }
+}
@@ -0,0 +1,356 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::tree::{EncodedLayer, InstructionEncoder, LayerMirror};
+use gpu::GPU;
+use std::mem;
+use wgpu;
+use zerocopy::{AsBytes, FromBytes};
+
+// How much work to spend on a picture. A draft is for browsing and thumbnails,
+// where a quick look is all that is wanted: the display computes it at half the
+// size on each side, and warp sums one octave of noise rather than three. No
+// other op iterates, so a tree without a warp costs the same in either.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quality {
+    Draft,
+    Full,
+}
+
+impl Default for Quality {
+    fn default() -> Self {
+        Quality::Full
+    }
+}
+
+impl Quality {
+    pub fn toggled(self) -> Self {
+        match self {
+            Quality::Draft => Quality::Full,
+            Quality::Full => Quality::Draft,
+        }
+    }
+
+    // The extent to compute at.
+    pub fn scale_extent(self, extent: wgpu::Extent3d) -> wgpu::Extent3d {
+        let divisor = match self {
+            Quality::Draft => 2,
+            Quality::Full => 1,
+        };
+        let scale = |side: u32| (side / divisor).max(1);
+        wgpu::Extent3d {
+            width: scale(extent.width),
+            height: scale(extent.height),
+            depth: extent.depth,
+        }
+    }
+}
+
+// Must match the Configuration block in include/interpreter.glsl.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
+pub struct Configuration {
+    pub texture_size: [u32; 2],
+    pub texture_offsets: [i32; 2],
+    pub mouse_position: [f32; 2],
+    pub time: f32,
+    pub aspect_ratio: f32,
+    pub view_center: [f32; 2],
+    pub view_scale: f32,
+    // Zero for a flat picture; see include/interpreter.glsl.
+    pub volume_slices: u32,
+    // Invocations compute every pass_stride-th texel on each side, starting from
+    // pass_offset, and fill a square of block_size texels on a side with it. A
+    // full pass computes every texel; see Display for the others.
+    pub block_size: u32,
+    pub pass_stride: u32,
+    pub pass_offset: [u32; 2],
+    // Nonzero for a quick look; see Quality.
+    pub draft: u32,
+}
+
+impl Configuration {
+    pub fn new(extent: wgpu::Extent3d, aspect_ratio: f32) -> Self {
+        Self {
+            texture_size: [extent.width, extent.height],
+            texture_offsets: [0, 0],
+            mouse_position: [0.5, 0.5],
+            time: 0f32,
+            aspect_ratio,
+            view_center: [0f32, 0f32],
+            view_scale: 1f32,
+            volume_slices: 0,
+            block_size: 1,
+            pass_stride: 1,
+            pass_offset: [0, 0],
+            draft: 0,
+        }
+    }
+
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.draft = (quality == Quality::Draft) as u32;
+    }
+
+    pub fn buffer_size() -> wgpu::BufferAddress {
+        mem::size_of::<Self>() as wgpu::BufferAddress
+    }
+
+    pub fn create_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device
+            .create_buffer_mapped(
+                1,
+                wgpu::BufferUsage::UNIFORM
+                    | wgpu::BufferUsage::MAP_READ
+                    | wgpu::BufferUsage::COPY_DST,
+            )
+            .fill_from_slice(&[*self])
+    }
+
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        buffer: &wgpu::Buffer,
+    ) {
+        let upload_buffer = device
+            .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&[*self]);
+        encoder.copy_buffer_to_buffer(&upload_buffer, 0, buffer, 0, Self::buffer_size());
+    }
+}
+
+// The interpreter's bindings: configuration, output image, instructions and
+// constants, and the reaction-diffusion state for the reaction op.
+pub fn create_layout(gpu: &GPU) -> wgpu::BindGroupLayout {
+    gpu.device()
+        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutBinding {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        readonly: true,
+                    },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 4,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 5,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::Sampler,
+                },
+            ],
+        })
+}
+
+pub fn create_bind_group(
+    gpu: &GPU,
+    layout: &wgpu::BindGroupLayout,
+    config_buffer: &wgpu::Buffer,
+    texture_view: &wgpu::TextureView,
+    instr_buffer: &wgpu::Buffer,
+    pool_buffer: &wgpu::Buffer,
+    layer: usize,
+    reaction_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    let instr_start = layer as wgpu::BufferAddress * InstructionEncoder::instruction_layer_stride();
+    let pool_start = layer as wgpu::BufferAddress * InstructionEncoder::pool_layer_stride();
+    // The simulation wraps around at the edges of the plane.
+    let reaction_sampler = gpu.device().create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        lod_min_clamp: 0f32,
+        lod_max_clamp: 0f32,
+        compare_function: wgpu::CompareFunction::Never,
+    });
+    gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        bindings: &[
+            wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: config_buffer,
+                    range: 0..Configuration::buffer_size(),
+                },
+            },
+            wgpu::Binding {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(texture_view),
+            },
+            wgpu::Binding {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: instr_buffer,
+                    range: instr_start..instr_start + InstructionEncoder::instruction_buffer_size(),
+                },
+            },
+            wgpu::Binding {
+                binding: 3,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: pool_buffer,
+                    range: pool_start..pool_start + InstructionEncoder::pool_buffer_size(),
+                },
+            },
+            wgpu::Binding {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(reaction_view),
+            },
+            wgpu::Binding {
+                binding: 5,
+                resource: wgpu::BindingResource::Sampler(&reaction_sampler),
+            },
+        ],
+    })
+}
+
+// Stands in for the reaction-diffusion state when no simulation is running. The
+// reaction op treats a 1x1 state as empty, so its contents never matter.
+pub fn create_placeholder_state(gpu: &GPU) -> wgpu::TextureView {
+    gpu.device()
+        .create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsage::SAMPLED,
+        })
+        .create_default_view()
+}
+
+// Room for layer_count layers, each bound at its own offset by create_bind_group
+// and kept up to date by a LayerMirror of as many layers.
+pub fn create_instr_buffer(gpu: &GPU, layer_count: usize) -> wgpu::Buffer {
+    gpu.device().create_buffer(&wgpu::BufferDescriptor {
+        size: layer_count as wgpu::BufferAddress * InstructionEncoder::instruction_layer_stride(),
+        usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+    })
+}
+
+pub fn create_pool_buffer(gpu: &GPU, layer_count: usize) -> wgpu::Buffer {
+    gpu.device().create_buffer(&wgpu::BufferDescriptor {
+        size: layer_count as wgpu::BufferAddress * InstructionEncoder::pool_layer_stride(),
+        usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+    })
+}
+
+// A layer computed into a single texture that is never displayed; for measuring
+// and exporting rather than for the interactive view.
+pub struct OffscreenLayer {
+    instr_buffer: wgpu::Buffer,
+    pool_buffer: wgpu::Buffer,
+    mirror: LayerMirror,
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl OffscreenLayer {
+    pub fn new(
+        gpu: &GPU,
+        layout: &wgpu::BindGroupLayout,
+        config_buffer: &wgpu::Buffer,
+        format: wgpu::TextureFormat,
+        extent: wgpu::Extent3d,
+    ) -> Self {
+        let instr_buffer = create_instr_buffer(gpu, 1);
+        let pool_buffer = create_pool_buffer(gpu, 1);
+        let texture = gpu.device().create_texture(&wgpu::TextureDescriptor {
+            size: extent,
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_SRC,
+        });
+        let texture_view = texture.create_default_view();
+        let bind_group = create_bind_group(
+            gpu,
+            layout,
+            config_buffer,
+            &texture_view,
+            &instr_buffer,
+            &pool_buffer,
+            0,
+            &create_placeholder_state(gpu),
+        );
+        Self {
+            instr_buffer,
+            pool_buffer,
+            mirror: LayerMirror::new(1),
+            texture,
+            bind_group,
+        }
+    }
+
+    // Returns the number of bytes copied to the GPU.
+    pub fn update(
+        &mut self,
+        layer: EncodedLayer,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> wgpu::BufferAddress {
+        match self.mirror.update(vec![layer], device) {
+            Some(upload) => {
+                upload.copy_to(encoder, &self.instr_buffer, &self.pool_buffer);
+                upload.byte_count()
+            }
+            None => 0,
+        }
+    }
+
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
@@ -0,0 +1,190 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    export,
+    hud::{CELL_HEIGHT, CELL_WIDTH, FONT, GLYPH_HEIGHT, GLYPH_WIDTH},
+    open_tree,
+    recipe::Recipe,
+    scene::Scene,
+};
+use anyhow::{bail, Result};
+use gpu::GPU;
+use stampede::{
+    compute::Quality,
+    render::{srgb_to_8bit, OffscreenRenderer},
+    tree::Tree,
+};
+use std::{fs, path::Path};
+
+const TEXT_SCALE: u32 = 2;
+
+// Room under each picture for two lines of text, and between pictures.
+const LABEL_HEIGHT: u32 = 2 * CELL_HEIGHT * TEXT_SCALE + 8;
+const GUTTER: u32 = 8;
+const BACKGROUND: f32 = 0.1;
+
+// An sRGB image being put together, rows from the top.
+struct Sheet {
+    width: u32,
+    height: u32,
+    rgb: Vec<f32>,
+}
+
+impl Sheet {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            rgb: vec![BACKGROUND; (width * height * 3) as usize],
+        }
+    }
+
+    fn paste(&mut self, rgb: &[f32], width: u32, height: u32, x0: u32, y0: u32) {
+        for y in 0..height {
+            let from = (y * width * 3) as usize;
+            let to = (((y0 + y) * self.width + x0) * 3) as usize;
+            self.rgb[to..to + (width * 3) as usize]
+                .copy_from_slice(&rgb[from..from + (width * 3) as usize]);
+        }
+    }
+
+    // White text, uppercased as the font has no lowercase, cut short at max_width.
+    fn write(&mut self, text: &str, x0: u32, y0: u32, max_width: u32) {
+        let columns = (max_width / (CELL_WIDTH * TEXT_SCALE)) as usize;
+        for (i, c) in text.to_ascii_uppercase().bytes().take(columns).enumerate() {
+            let glyph = match c {
+                32..=95 => FONT[(c - 32) as usize],
+                _ => continue,
+            };
+            for gy in 0..GLYPH_HEIGHT {
+                for gx in 0..GLYPH_WIDTH {
+                    if (glyph >> (gy * GLYPH_WIDTH + gx)) & 1 == 0 {
+                        continue;
+                    }
+                    let x = x0 + (i as u32 * CELL_WIDTH + gx) * TEXT_SCALE;
+                    let y = y0 + gy * TEXT_SCALE;
+                    for (dx, dy) in
+                        (0..TEXT_SCALE).flat_map(|dx| (0..TEXT_SCALE).map(move |dy| (dx, dy)))
+                    {
+                        let at = (((y + dy) * self.width + x + dx) * 3) as usize;
+                        self.rgb[at..at + 3].copy_from_slice(&[1f32; 3]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Renders every tree in dir, saved as json, an exported PNG or a stamp, to a
+// thumbnail at time seconds into its animation, and lays them out in a grid in
+// one PNG at out, each labeled with the tree's name and the seed or file it came
+// from.
+pub fn run(
+    gpu: &mut GPU,
+    dir: &Path,
+    out: &Path,
+    columns: u32,
+    thumbnail: [u32; 2],
+    time: f32,
+) -> Result<()> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.sort();
+    let trees = paths
+        .iter()
+        .filter_map(|path| {
+            let (seed, tree) = open_tree(path).ok()?;
+            let stem = path.file_stem()?.to_string_lossy().into_owned();
+            let source = seed.unwrap_or(stem);
+            Some(([tree.name(), source], tree))
+        })
+        .collect::<Vec<_>>();
+    if trees.is_empty() {
+        bail!("no trees in {}", dir.display());
+    }
+    write_sheet(gpu, trees, out, columns, thumbnail, time)
+}
+
+// Renders every node of the tree at path on its own, as gray, and lays them out
+// in a grid like run, leaves first and each layer last, labeled with the node's
+// op and path, to show how the picture is built up.
+pub fn run_nodes(
+    gpu: &mut GPU,
+    path: &Path,
+    out: &Path,
+    columns: u32,
+    thumbnail: [u32; 2],
+    time: f32,
+) -> Result<()> {
+    let (_, tree) = open_tree(path)?;
+    let nodes = tree
+        .node_paths()
+        .into_iter()
+        .filter_map(|path| {
+            let op = tree.node_op(&path)?.to_owned();
+            Some(([op, path.clone()], tree.node_alone(&path)?))
+        })
+        .collect::<Vec<_>>();
+    write_sheet(gpu, nodes, out, columns, thumbnail, time)
+}
+
+fn write_sheet(
+    gpu: &mut GPU,
+    trees: Vec<([String; 2], Tree)>,
+    out: &Path,
+    columns: u32,
+    thumbnail: [u32; 2],
+    time: f32,
+) -> Result<()> {
+    if columns == 0 {
+        bail!("there must be at least one column");
+    }
+    let [width, height] = thumbnail;
+    if width == 0 || height == 0 {
+        bail!(
+            "a thumbnail must be at least a pixel across, not {}x{}",
+            width,
+            height
+        );
+    }
+    let rows = (trees.len() as u32 + columns - 1) / columns;
+    let columns = columns.min(trees.len() as u32);
+    let mut sheet = Sheet::new(
+        GUTTER + columns * (width + GUTTER),
+        GUTTER + rows * (height + LABEL_HEIGHT + GUTTER),
+    );
+    // Nobody inspects a thumbnail closely.
+    let renderer = OffscreenRenderer::new(gpu)?.with_quality(Quality::Draft);
+    for (i, ([first, second], mut tree)) in trees.into_iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let x = GUTTER + column * (width + GUTTER);
+        let y = GUTTER + row * (height + LABEL_HEIGHT + GUTTER);
+        tree.animate(time);
+        let rgb = Scene::single(tree).render(&renderer, gpu, width, height)?;
+        sheet.paste(&rgb, width, height, x, y);
+        let line = CELL_HEIGHT * TEXT_SCALE;
+        sheet.write(&first, x, y + height + 4, width);
+        sheet.write(&second, x, y + height + 4 + line, width);
+        println!("{} {}", first, second);
+    }
+    export::write_png(
+        out,
+        sheet.width,
+        sheet.height,
+        &srgb_to_8bit(&sheet.rgb),
+        &Recipe::default(),
+    )
+}
@@ -0,0 +1,201 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    builder::NodeBuilder,
+    compute::{self, Configuration, OffscreenLayer},
+    error::Fallible,
+    ops::{self, OpDescriptor},
+    tree::Tree,
+    workgroup::Interpreter,
+};
+use gpu::GPU;
+use std::time::{Duration, Instant};
+use wgpu;
+
+// Small enough to measure every op quickly, large enough to keep the GPU busy.
+const MEASURE_EXTENT: wgpu::Extent3d = wgpu::Extent3d {
+    width: 512,
+    height: 512,
+    depth: 1,
+};
+const MEASURE_DISPATCHES: usize = 8;
+// How many of an op each measured layer holds.
+const CHAIN_LENGTH: usize = 32;
+
+// What each op costs on this GPU, in seconds per texel, as measured at startup.
+// A tree costs the sum of its nodes, which is rough, but it only has to tell
+// monsters from the rest.
+pub struct CostModel {
+    // Indexed by opcode.
+    costs: Vec<f64>,
+}
+
+impl CostModel {
+    pub fn measure(gpu: &mut GPU, format: wgpu::TextureFormat, half: bool) -> Fallible<Self> {
+        let layout = compute::create_layout(gpu);
+        let config = Configuration::new(MEASURE_EXTENT, 1f32);
+        let config_buffer = config.create_buffer(gpu.device());
+        let interpreter = Interpreter::new(gpu, &layout, [8, 8], half)?;
+        let mut layer = OffscreenLayer::new(gpu, &layout, &config_buffer, format, MEASURE_EXTENT);
+        let mut time = |node: NodeBuilder| -> Fallible<f64> {
+            let tree = Tree::build([node, NodeBuilder::value(0f32), NodeBuilder::value(0f32)])?;
+            time_layer(gpu, &interpreter, &mut layer, &tree)
+        };
+
+        // The interpreter steps through a fixed number of instructions whatever
+        // the tree, so only time beyond that of a bare constant is the op's.
+        let base = time(NodeBuilder::value(0f32))?;
+        let per_node = |elapsed: f64| (elapsed - base).max(0f64) / CHAIN_LENGTH as f64;
+        let add = per_node(time(chain(
+            ops::registered()
+                .into_iter()
+                .find(|op| op.name == "add")
+                .expect("an add op"),
+        ))?);
+        let registered = ops::registered();
+        let opcode_limit = registered.iter().map(|op| op.opcode + 1).max().unwrap_or(0);
+        let mut costs = vec![0f64; opcode_limit];
+        for op in registered {
+            let elapsed = time(chain(op))?;
+            // Leaves are chained together with adds, which are not theirs to pay for.
+            let cost = if is_chained_by_adds(op) {
+                per_node(elapsed) - add * (CHAIN_LENGTH - 1) as f64 / CHAIN_LENGTH as f64
+            } else {
+                per_node(elapsed)
+            };
+            let texels = f64::from(MEASURE_EXTENT.width * MEASURE_EXTENT.height);
+            costs[op.opcode] = cost.max(0f64) / texels;
+        }
+        Ok(Self { costs })
+    }
+
+    // How long the tree takes to compute, all three layers, at extent.
+    pub fn estimate(&self, tree: &Tree, extent: wgpu::Extent3d) -> Duration {
+        let per_texel = tree
+            .ops()
+            .iter()
+            .map(|op| self.costs.get(op.opcode).copied().unwrap_or(0f64))
+            .sum::<f64>();
+        Duration::from_secs_f64(per_texel * f64::from(extent.width * extent.height))
+    }
+}
+
+// Leaves have nothing to chain through, and remaps can only be nested so deep, so
+// both are chained side by side under adds.
+fn is_chained_by_adds(op: &OpDescriptor) -> bool {
+    op.is_leaf() || op.remap.is_some()
+}
+
+// CHAIN_LENGTH of op, with constants wherever something else is needed.
+fn chain(op: &OpDescriptor) -> NodeBuilder {
+    let one = || {
+        if op.is_leaf() {
+            NodeBuilder::op(op.name)
+        } else {
+            link(op, NodeBuilder::value(0.5f32))
+        }
+    };
+    let mut chain = one();
+    for _ in 1..CHAIN_LENGTH {
+        chain = if is_chained_by_adds(op) {
+            NodeBuilder::add(one(), chain)
+        } else {
+            link(op, chain)
+        };
+    }
+    chain
+}
+
+// The last child is the rest of the chain; any before it are constants.
+fn link(op: &OpDescriptor, rest: NodeBuilder) -> NodeBuilder {
+    let mut node = NodeBuilder::op(op.name);
+    for _ in 1..op.children.len() {
+        node = node.child(NodeBuilder::value(0.5f32));
+    }
+    node.child(rest)
+}
+
+// Seconds for one dispatch of the tree's first layer, after one to warm up.
+fn time_layer(
+    gpu: &mut GPU,
+    interpreter: &Interpreter,
+    layer: &mut OffscreenLayer,
+    tree: &Tree,
+) -> Fallible<f64> {
+    let mut encoder = gpu
+        .device()
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+    layer.update(tree.encode_layer(0)?, gpu.device(), &mut encoder);
+    gpu.queue_mut().submit(&[encoder.finish()]);
+    let mut elapsed = Duration::from_secs(0);
+    for &count in &[1, MEASURE_DISPATCHES] {
+        let mut encoder = gpu
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        for _ in 0..count {
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_pipeline(interpreter.pipeline());
+            cpass.set_bind_group(0, layer.bind_group(), &[]);
+            interpreter.dispatch(&mut cpass, MEASURE_EXTENT);
+        }
+        let start = Instant::now();
+        gpu.queue_mut().submit(&[encoder.finish()]);
+        gpu.device().poll(true);
+        elapsed = start.elapsed();
+    }
+    Ok(elapsed.as_secs_f64() / MEASURE_DISPATCHES as f64)
+}
+
+// The most that a tree may cost to compute at the size that it is shown at.
+pub struct CostBudget {
+    model: CostModel,
+    extent: wgpu::Extent3d,
+    limit: Duration,
+}
+
+impl CostBudget {
+    pub fn new(model: CostModel, extent: wgpu::Extent3d, limit: Duration) -> Self {
+        Self {
+            model,
+            extent,
+            limit,
+        }
+    }
+
+    pub fn estimate(&self, tree: &Tree) -> Duration {
+        self.model.estimate(tree, self.extent)
+    }
+
+    pub fn allows(&self, tree: &Tree) -> bool {
+        self.estimate(tree) <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_op_chains_into_a_tree_that_builds() -> Fallible<()> {
+        for op in ops::registered() {
+            Tree::build([
+                chain(op),
+                NodeBuilder::value(0f32),
+                NodeBuilder::value(0f32),
+            ])?;
+        }
+        Ok(())
+    }
+}
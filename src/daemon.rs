@@ -0,0 +1,282 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+// A command from a client, one json object per line:
+//
+//   {"command": "load", "path": "tree.json"}     a tree saved with ctrl+c, or an exported PNG
+//   {"command": "seed", "seed": "42"}            build the tree from a seed
+//   {"command": "regenerate"}                    switch to a random tree
+//   {"command": "screenshot", "path": "a.png"}   export the picture as it is now
+//   {"command": "quit"}
+//
+// Each gets a line back once the event loop has carried it out: {"ok": true}, or
+// {"ok": false, "error": "..."}. Paths are as the daemon sees them, so clients
+// should send absolute ones.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DaemonCommand {
+    Load { path: PathBuf },
+    Seed { seed: String },
+    Regenerate,
+    Screenshot { path: PathBuf },
+    Quit,
+}
+
+pub type Reply = Sender<Result<(), String>>;
+
+// Listens on a unix socket, or on Windows a named pipe, for clients, each on its
+// own thread. Commands are queued for the event loop, which applies them between
+// frames just as it does a script's, so the window never needs focus to be driven.
+pub struct Daemon {
+    path: PathBuf,
+    commands: Receiver<(DaemonCommand, Reply)>,
+}
+
+impl Daemon {
+    #[cfg(unix)]
+    pub fn listen(path: &Path) -> Result<Self> {
+        use std::{
+            fs,
+            os::unix::net::{UnixListener, UnixStream},
+            thread,
+        };
+
+        // A socket left behind by a daemon that did not exit cleanly refuses
+        // connections and can be replaced; a live one cannot.
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("a daemon is already listening on {}", path.display());
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let writer = match stream.try_clone() {
+                            Ok(writer) => writer,
+                            Err(e) => {
+                                println!("daemon: failed to serve a client: {}", e);
+                                continue;
+                            }
+                        };
+                        let sender = sender.clone();
+                        thread::spawn(move || serve(stream, writer, sender));
+                    }
+                    Err(e) => println!("daemon: failed to accept a client: {}", e),
+                }
+            }
+        });
+        println!("daemon: listening on {}", path.display());
+        Ok(Self {
+            path: path.to_owned(),
+            commands,
+        })
+    }
+
+    // Path is the pipe's name, like \\.\pipe\stampede; anything else is taken as
+    // the name of a pipe under \\.\pipe\.
+    #[cfg(windows)]
+    pub fn listen(path: &Path) -> Result<Self> {
+        use std::thread;
+
+        const PIPE_PREFIX: &str = r"\\.\pipe\";
+        let name = if path.to_string_lossy().starts_with(PIPE_PREFIX) {
+            path.to_owned()
+        } else {
+            let name = match path.file_name() {
+                Some(name) => name,
+                None => bail!("{} does not name a pipe", path.display()),
+            };
+            PathBuf::from(format!("{}{}", PIPE_PREFIX, name.to_string_lossy()))
+        };
+        // The first instance fails if another daemon already has the name.
+        let mut next = pipe::create(&name, true)?;
+        let (sender, commands) = mpsc::channel();
+        let listening = name.clone();
+        thread::spawn(move || loop {
+            let stream = next;
+            if let Err(e) = pipe::connect(&stream) {
+                println!("daemon: failed to accept a client: {}", e);
+            } else {
+                // A handle of our own to write with, as the unix socket's clone.
+                match stream.try_clone() {
+                    Ok(writer) => {
+                        let sender = sender.clone();
+                        thread::spawn(move || serve(stream, writer, sender));
+                    }
+                    Err(e) => println!("daemon: failed to serve a client: {}", e),
+                }
+            }
+            next = match pipe::create(&listening, false) {
+                Ok(pipe) => pipe,
+                Err(e) => {
+                    println!("daemon: stopped listening: {}", e);
+                    return;
+                }
+            };
+        });
+        println!("daemon: listening on {}", name.display());
+        Ok(Self {
+            path: name,
+            commands,
+        })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn listen(path: &Path) -> Result<Self> {
+        bail!(
+            "cannot listen on {}: the daemon needs unix sockets or named pipes",
+            path.display()
+        )
+    }
+
+    // Everything that has come in since the last look.
+    pub fn pending(&self) -> Vec<(DaemonCommand, Reply)> {
+        self.commands.try_iter().collect()
+    }
+
+    // The event loop exits the process rather than returning, so the socket has
+    // to be removed by hand. A named pipe goes with its last handle.
+    pub fn close(&self) {
+        if cfg!(unix) {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                println!("daemon: failed to remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+// Carries out a client's commands, reading from stream and answering on writer,
+// until it hangs up.
+fn serve<R: Read, W: Write>(stream: R, mut writer: W, commands: Sender<(DaemonCommand, Reply)>) {
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match serde_json::from_str::<DaemonCommand>(&line) {
+            Ok(command) => {
+                let (reply, replied) = mpsc::channel();
+                if commands.send((command, reply)).is_err() {
+                    return;
+                }
+                replied
+                    .recv()
+                    .unwrap_or_else(|_| Err("the daemon is shutting down".to_owned()))
+            }
+            Err(e) => Err(format!("bad command: {}", e)),
+        };
+        let response = match result {
+            Ok(()) => serde_json::json!({ "ok": true }),
+            Err(error) => serde_json::json!({ "ok": false, "error": error }),
+        };
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(windows)]
+mod pipe {
+    use anyhow::{bail, Result};
+    use std::{
+        ffi::OsStr,
+        fs::File,
+        os::windows::{
+            ffi::OsStrExt,
+            io::{AsRawHandle, FromRawHandle},
+        },
+        path::Path,
+        ptr,
+    };
+    use winapi::{
+        shared::winerror::ERROR_PIPE_CONNECTED,
+        um::{
+            errhandlingapi::GetLastError,
+            handleapi::INVALID_HANDLE_VALUE,
+            namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW},
+            winbase::{
+                FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE,
+                PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+            },
+        },
+    };
+
+    const BUFFER_SIZE: u32 = 4096;
+
+    // An instance of the pipe for the next client to connect to, which is read and
+    // written as a file once one has.
+    pub fn create(name: &Path, first: bool) -> Result<File> {
+        let wide = OsStr::new(name)
+            .encode_wide()
+            .chain(Some(0))
+            .collect::<Vec<u16>>();
+        let mut open_mode = PIPE_ACCESS_DUPLEX;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide.as_ptr(),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let error = unsafe { GetLastError() };
+            if first {
+                bail!(
+                    "cannot listen on {}; is a daemon already listening? (error {})",
+                    name.display(),
+                    error
+                );
+            }
+            bail!("cannot make {} (error {})", name.display(), error);
+        }
+        Ok(unsafe { File::from_raw_handle(handle) })
+    }
+
+    // Waits for a client.
+    pub fn connect(pipe: &File) -> Result<()> {
+        unsafe {
+            // A client that connects between create and here is already connected.
+            if ConnectNamedPipe(pipe.as_raw_handle(), ptr::null_mut()) == 0 {
+                let error = GetLastError();
+                if error != ERROR_PIPE_CONNECTED {
+                    bail!("error {}", error);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1424 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    compute::{self, Configuration, Quality},
+    error::{Fallible, StampedeError},
+    exposure::{AutoExposure, ExposureControl},
+    histogram::Histogram,
+    mipmap::{mip_level_count, MipChain, MipmapGenerator},
+    particles::Particles,
+    projection::{Calibration, Projection},
+    reaction::ReactionDiffusion,
+    terrain::Terrain,
+    tree::{LayerMirror, LayerUpload, Tree, INSTRUCTION_COUNT},
+    volume::Volume,
+    workgroup::Interpreter,
+};
+use gpu::{Frame, GPU};
+use serde::{Deserialize, Serialize};
+use std::{
+    mem,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use wgpu;
+use zerocopy::{AsBytes, FromBytes};
+
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
+pub struct Vertex {
+    position: [f32; 2],
+    tex_coord: [f32; 2],
+}
+
+// How many sets of layer textures are in flight. While one set is being displayed,
+// the next frame is computed into the other.
+const FRAME_SLOTS: usize = 2;
+
+// How many texels on a side a preview fills from each one that it computes.
+const PREVIEW_BLOCK_SIZE: u32 = 4;
+
+// Trees with a layer this large are refined progressively, if asked, computing a
+// quarter of the texels each frame.
+const PROGRESSIVE_NODE_COUNT: usize = INSTRUCTION_COUNT * 3 / 4;
+const PROGRESSIVE_STRIDE: u32 = 2;
+
+// One layer's output texture, for one slot of the frame ring.
+struct LayerTarget {
+    // Kept for reading single texels back; see Display::read_layers_at.
+    texture: wgpu::Texture,
+    texture_view: wgpu::TextureView,
+    sampled_view: wgpu::TextureView,
+    mip_chain: MipChain,
+    bind_group: wgpu::BindGroup,
+}
+
+// The layers' instructions and constants are in the display's shared buffers.
+struct ComputeLayer {
+    targets: Vec<LayerTarget>,
+}
+
+// Half floats have no place in std yet.
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1f32 } else { 1f32 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0f32 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1f32 + mantissa / 1024f32) * 2f32.powi(exponent - 15),
+    }
+}
+
+// The most colors a Palette can have. Must match MAX_PALETTE in include/draw.glsl.
+pub const MAX_PALETTE: usize = 16;
+
+// A limited set of sRGB colors that every color on screen is snapped to, for a
+// retro look; see to_palette in include/draw.glsl.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette(Vec<[f32; 3]>);
+
+impl Palette {
+    fn from_hex(colors: &[u32]) -> Self {
+        Palette(
+            colors
+                .iter()
+                .map(|c| {
+                    let channel = |shift: u32| ((c >> shift) & 0xFF) as f32 / 255f32;
+                    [channel(16), channel(8), channel(0)]
+                })
+                .collect(),
+        )
+    }
+}
+
+impl FromStr for Palette {
+    type Err = StampedeError;
+
+    // One of the classic palettes by name, or RRGGBB colors separated by commas.
+    fn from_str(s: &str) -> Fallible<Self> {
+        let palette = match s {
+            "pico-8" => Self::from_hex(&[
+                0x000000, 0x1D2B53, 0x7E2553, 0x008751, 0xAB5236, 0x5F574F, 0xC2C3C7, 0xFFF1E8,
+                0xFF004D, 0xFFA300, 0xFFEC27, 0x00E436, 0x29ADFF, 0x83769C, 0xFF77A8, 0xFFCCAA,
+            ]),
+            "game-boy" => Self::from_hex(&[0x0F380F, 0x306230, 0x8BAC0F, 0x9BBC0F]),
+            "cga" => Self::from_hex(&[0x000000, 0x55FFFF, 0xFF55FF, 0xFFFFFF]),
+            _ => {
+                let colors = s
+                    .split(',')
+                    .map(|hex| {
+                        let hex = hex.trim().trim_start_matches('#');
+                        if hex.len() != 6 {
+                            bail!(Invalid, "expected RRGGBB for a palette color, not {}", hex);
+                        }
+                        u32::from_str_radix(hex, 16)
+                            .map_err(|e| StampedeError::Invalid(e.to_string()))
+                    })
+                    .collect::<Fallible<Vec<_>>>()
+                    .map_err(|e| {
+                        StampedeError::Invalid(format!(
+                            "unknown palette {}; expected pico-8, game-boy, cga or RRGGBB,...: {}",
+                            s, e
+                        ))
+                    })?;
+                Self::from_hex(&colors)
+            }
+        };
+        if palette.0.len() > MAX_PALETTE {
+            bail!(
+                Invalid,
+                "a palette has at most {} colors, not {}",
+                MAX_PALETTE,
+                palette.0.len()
+            );
+        }
+        Ok(palette)
+    }
+}
+
+// Cuts each channel of the finished picture to a few flat levels, like a screen
+// print. A channel's threshold is where in each step it rounds up; a half rounds
+// to the nearest level. Must match posterize in include/draw.glsl.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Posterize {
+    // At least 2 for a channel to be cut, in red, green, blue order.
+    pub levels: [u32; 3],
+    pub thresholds: [f32; 3],
+}
+
+impl Default for Posterize {
+    fn default() -> Self {
+        Self {
+            levels: [4; 3],
+            thresholds: [0.5; 3],
+        }
+    }
+}
+
+// Rebalances the three layers as they are drawn, for trees where one of them
+// overpowers the others: each is scaled by its gain, then its bias is added. Must
+// match sample_layers in include/draw.glsl.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayerBalance {
+    pub gain: [f32; 3],
+    pub bias: [f32; 3],
+}
+
+impl Default for LayerBalance {
+    fn default() -> Self {
+        Self {
+            gain: [1f32; 3],
+            bias: [0f32; 3],
+        }
+    }
+}
+
+impl LayerBalance {
+    pub const MAX_GAIN: f32 = 4f32;
+
+    pub fn clamped(self) -> Self {
+        let mut out = self;
+        for i in 0..3 {
+            out.gain[i] = self.gain[i].max(0f32).min(Self::MAX_GAIN);
+            out.bias[i] = self.bias[i].max(-1f32).min(1f32);
+        }
+        out
+    }
+}
+
+// Settings for the final pass, as opposed to the tree. Must match the
+// DrawConfiguration block in include/draw.glsl.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug)]
+pub struct DrawConfiguration {
+    // The layer that the stereo modes take depth from.
+    pub depth_layer: u32,
+    // How far the eyes' views of the nearest points are apart, in widths.
+    pub stereo_separation: f32,
+    // A ColorVision, applied to every color on the way to the screen.
+    color_vision: u32,
+    // A ColorAdjustment, applied before color_vision.
+    brightness: f32,
+    contrast: f32,
+    saturation: f32,
+    // Where the canvas is in the window, in window uv; see Canvas.
+    canvas_scale: [f32; 2],
+    canvas_center: [f32; 2],
+    _pad: [f32; 2],
+    // sRGB, with alpha unused, for the window outside the canvas.
+    border_color: [f32; 4],
+    // A Posterize, with levels as floats; zero levels leaves a channel alone.
+    posterize_levels: [f32; 4],
+    posterize_thresholds: [f32; 4],
+    // A LayerBalance, with the fourth of each unused.
+    layer_gain: [f32; 4],
+    layer_bias: [f32; 4],
+    // A Palette, as sRGB with alpha unused; colors are left alone if it is empty.
+    palette: [[f32; 4]; MAX_PALETTE],
+    palette_size: u32,
+}
+
+impl Default for DrawConfiguration {
+    fn default() -> Self {
+        Self {
+            depth_layer: 0,
+            stereo_separation: 0.01,
+            color_vision: ColorVision::Normal as u32,
+            brightness: 0f32,
+            contrast: 1f32,
+            saturation: 1f32,
+            canvas_scale: [1f32, 1f32],
+            canvas_center: [0.5f32, 0.5f32],
+            _pad: [0f32; 2],
+            border_color: [0f32, 0f32, 0f32, 1f32],
+            posterize_levels: [0f32; 4],
+            posterize_thresholds: [0.5f32; 4],
+            layer_gain: [1f32; 4],
+            layer_bias: [0f32; 4],
+            palette: [[0f32; 4]; MAX_PALETTE],
+            palette_size: 0,
+        }
+    }
+}
+
+impl DrawConfiguration {
+    pub fn buffer_size() -> wgpu::BufferAddress {
+        mem::size_of::<Self>() as wgpu::BufferAddress
+    }
+
+    pub fn color_vision(&self) -> ColorVision {
+        ColorVision::from_code(self.color_vision)
+    }
+
+    pub fn set_color_vision(&mut self, vision: ColorVision) {
+        self.color_vision = vision as u32;
+    }
+
+    pub fn color_adjustment(&self) -> ColorAdjustment {
+        ColorAdjustment {
+            brightness: self.brightness,
+            contrast: self.contrast,
+            saturation: self.saturation,
+        }
+    }
+
+    pub fn set_posterize(&mut self, posterize: Option<Posterize>) {
+        let posterize = posterize.unwrap_or(Posterize {
+            levels: [0; 3],
+            thresholds: [0.5; 3],
+        });
+        for i in 0..3 {
+            self.posterize_levels[i] = posterize.levels[i] as f32;
+            self.posterize_thresholds[i] = posterize.thresholds[i].max(0f32).min(1f32);
+        }
+    }
+
+    pub fn layer_balance(&self) -> LayerBalance {
+        let [gr, gg, gb, _] = self.layer_gain;
+        let [br, bg, bb, _] = self.layer_bias;
+        LayerBalance {
+            gain: [gr, gg, gb],
+            bias: [br, bg, bb],
+        }
+    }
+
+    pub fn set_layer_balance(&mut self, balance: LayerBalance) {
+        let balance = balance.clamped();
+        for i in 0..3 {
+            self.layer_gain[i] = balance.gain[i];
+            self.layer_bias[i] = balance.bias[i];
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Option<&Palette>) {
+        let colors = palette.map(|p| &p.0[..]).unwrap_or(&[]);
+        for (slot, color) in self.palette.iter_mut().zip(colors) {
+            *slot = [color[0], color[1], color[2], 1f32];
+        }
+        self.palette_size = colors.len() as u32;
+    }
+
+    pub fn set_color_adjustment(&mut self, adjustment: ColorAdjustment) {
+        let adjustment = adjustment.clamped();
+        self.brightness = adjustment.brightness;
+        self.contrast = adjustment.contrast;
+        self.saturation = adjustment.saturation;
+    }
+}
+
+// A last nudge to the colors on screen, for pictures that come out a little dark
+// or flat. Must match adjust_color in include/draw.glsl.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorAdjustment {
+    // Added to every channel, in [-1,1].
+    pub brightness: f32,
+    // How far channels are pushed away from a half; 1 leaves them be.
+    pub contrast: f32,
+    // How far colors are pushed away from their gray; 1 leaves them be.
+    pub saturation: f32,
+}
+
+impl Default for ColorAdjustment {
+    fn default() -> Self {
+        Self {
+            brightness: 0f32,
+            contrast: 1f32,
+            saturation: 1f32,
+        }
+    }
+}
+
+impl ColorAdjustment {
+    pub fn clamped(self) -> Self {
+        Self {
+            brightness: self.brightness.max(-1f32).min(1f32),
+            contrast: self.contrast.max(0f32).min(4f32),
+            saturation: self.saturation.max(0f32).min(4f32),
+        }
+    }
+}
+
+// Everything that has to reach the GPU before a frame is drawn.
+pub struct DisplayUpload {
+    config_buffer: wgpu::Buffer,
+    draw_config_buffer: wgpu::Buffer,
+    tree_upload: Option<LayerUpload>,
+}
+
+// How the layers are put on the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayMode {
+    // As colors, one layer for each channel of CIELAB.
+    Color,
+    // The green and blue layers as a vector field, drawn as streaks of noise that
+    // follow it, in the tree's colors.
+    FlowField,
+    // Experimental: the tree over a cube instead of a plane, drawn as a cloud.
+    Volume,
+    // The red layer as the height of a landscape, colored by the other two.
+    Terrain,
+    // In 3D, for red/cyan glasses, with depth from one of the layers.
+    Anaglyph,
+    // In 3D, with a view for each eye in either half of the screen.
+    SideBySide,
+}
+
+impl Default for DisplayMode {
+    fn default() -> Self {
+        DisplayMode::Color
+    }
+}
+
+impl FromStr for DisplayMode {
+    type Err = StampedeError;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "color" => DisplayMode::Color,
+            "flow" => DisplayMode::FlowField,
+            "volume" => DisplayMode::Volume,
+            "terrain" => DisplayMode::Terrain,
+            "anaglyph" => DisplayMode::Anaglyph,
+            "side-by-side" => DisplayMode::SideBySide,
+            _ => bail!(
+                Invalid,
+                "unknown display mode {}; expected color, flow, volume, terrain, anaglyph or side-by-side",
+                s
+            ),
+        })
+    }
+}
+
+// How the picture is shown with respect to red-green colorblindness, to check
+// that it still reads without red-green contrast or to put that contrast back.
+// Must match the VISION_ defines in include/vision.glsl.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorVision {
+    Normal = 0,
+    // As seen without red cones.
+    Protanopia = 1,
+    // As seen without green cones.
+    Deuteranopia = 2,
+    // With what protanopes miss moved into contrasts that they can see.
+    ProtanopiaCorrected = 3,
+    // With what deuteranopes miss moved into contrasts that they can see.
+    DeuteranopiaCorrected = 4,
+}
+
+impl ColorVision {
+    fn from_code(code: u32) -> Self {
+        match code {
+            1 => ColorVision::Protanopia,
+            2 => ColorVision::Deuteranopia,
+            3 => ColorVision::ProtanopiaCorrected,
+            4 => ColorVision::DeuteranopiaCorrected,
+            _ => ColorVision::Normal,
+        }
+    }
+
+    // The next setting, for flipping through them with a key.
+    pub fn next(self) -> Self {
+        Self::from_code((self as u32 + 1) % 5)
+    }
+}
+
+impl Default for ColorVision {
+    fn default() -> Self {
+        ColorVision::Normal
+    }
+}
+
+impl FromStr for ColorVision {
+    type Err = StampedeError;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "normal" => ColorVision::Normal,
+            "protanopia" => ColorVision::Protanopia,
+            "deuteranopia" => ColorVision::Deuteranopia,
+            "protanopia-corrected" => ColorVision::ProtanopiaCorrected,
+            "deuteranopia-corrected" => ColorVision::DeuteranopiaCorrected,
+            _ => bail!(
+                Invalid,
+                "unknown color vision {}; expected normal, protanopia, deuteranopia, protanopia-corrected or deuteranopia-corrected",
+                s
+            ),
+        })
+    }
+}
+
+// How a layer is sampled when it is drawn at another size than it was computed at.
+// Nearest shows the texels as squares, for a deliberate mosaic when the layers are
+// computed smaller than the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerFilter {
+    Linear,
+    Nearest,
+}
+
+impl Default for LayerFilter {
+    fn default() -> Self {
+        LayerFilter::Linear
+    }
+}
+
+impl FromStr for LayerFilter {
+    type Err = StampedeError;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "linear" => LayerFilter::Linear,
+            "nearest" => LayerFilter::Nearest,
+            _ => bail!(
+                Invalid,
+                "unknown layer filter {}; expected linear or nearest",
+                s
+            ),
+        })
+    }
+}
+
+impl LayerFilter {
+    fn create_sampler(self, gpu: &GPU) -> wgpu::Sampler {
+        let filter = match self {
+            LayerFilter::Linear => wgpu::FilterMode::Linear,
+            LayerFilter::Nearest => wgpu::FilterMode::Nearest,
+        };
+        gpu.device().create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            lod_min_clamp: 0f32,
+            lod_max_clamp: 9_999_999f32,
+            compare_function: wgpu::CompareFunction::Never,
+        })
+    }
+}
+
+// How the canvas, the picture as computed, is shaped to the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CanvasFit {
+    // Over the whole window, stretched to match its shape.
+    Stretch,
+    // Kept to its own shape, as large as fits, with borders filling the rest.
+    Fit,
+    // Kept to its own shape, covering the window, with whatever is over cropped.
+    Fill,
+}
+
+impl FromStr for CanvasFit {
+    type Err = StampedeError;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "stretch" => CanvasFit::Stretch,
+            "fit" => CanvasFit::Fit,
+            "fill" => CanvasFit::Fill,
+            _ => bail!(
+                Invalid,
+                "unknown canvas fit {}; expected stretch, fit or fill",
+                s
+            ),
+        })
+    }
+}
+
+// Where the canvas goes in the window. Computing keeps shapes round on the canvas,
+// so when it is stretched the shape of the window is what is computed for.
+#[derive(Clone, Copy, Debug)]
+pub struct Canvas {
+    pub fit: CanvasFit,
+    // Where the canvas sits in the room that it leaves, or which part of it is
+    // cropped, on each side: -1 to the left or bottom, 0 centered and 1 to the
+    // right or top.
+    pub align: [f32; 2],
+    // The color around a fitted canvas, in sRGB.
+    pub border_color: [f32; 3],
+}
+
+impl Default for Canvas {
+    fn default() -> Self {
+        Self {
+            fit: CanvasFit::Stretch,
+            align: [0f32, 0f32],
+            border_color: [0f32, 0f32, 0f32],
+        }
+    }
+}
+
+impl Canvas {
+    // The size of the canvas, as a fraction of the window on each side, and the
+    // aspect ratio, width over height, to compute for.
+    fn layout(&self, extent: wgpu::Extent3d, window_aspect: f32) -> ([f32; 2], f32) {
+        let canvas_aspect = extent.width as f32 / extent.height as f32;
+        let wider = canvas_aspect > window_aspect;
+        let across = [1f32, window_aspect / canvas_aspect];
+        let down = [canvas_aspect / window_aspect, 1f32];
+        match self.fit {
+            CanvasFit::Stretch => ([1f32, 1f32], window_aspect),
+            CanvasFit::Fit => (if wider { across } else { down }, canvas_aspect),
+            CanvasFit::Fill => (if wider { down } else { across }, canvas_aspect),
+        }
+    }
+
+    // Where the middle of a canvas of scale goes, in window uv.
+    fn center(&self, scale: [f32; 2]) -> [f32; 2] {
+        let center = |i: usize| 0.5 + self.align[i].max(-1f32).min(1f32) * (1f32 - scale[i]) / 2f32;
+        [center(0), center(1)]
+    }
+}
+
+// What to compute and draw beyond the tree itself; everything is off by default.
+#[derive(Clone, Default)]
+pub struct DisplayConfig {
+    mode: DisplayMode,
+    half: bool,
+    workgroup_size: Option<String>,
+    reaction_control: Option<Tree>,
+    particle_layer: Option<usize>,
+    calibration: Option<Calibration>,
+    canvas: Canvas,
+    progressive: bool,
+    quality: Quality,
+    filters: [LayerFilter; 3],
+}
+
+impl DisplayConfig {
+    pub fn with_mode(mut self, mode: DisplayMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_half_precision(mut self, half: bool) -> Self {
+        self.half = half;
+        self
+    }
+
+    // Without a size, the fastest is measured at startup.
+    pub fn with_workgroup_size(mut self, workgroup_size: Option<&str>) -> Self {
+        self.workgroup_size = workgroup_size.map(str::to_owned);
+        self
+    }
+
+    // Run a reaction-diffusion simulation, steered by control, for the reaction op.
+    pub fn with_reaction_diffusion(mut self, control: Tree) -> Self {
+        self.reaction_control = Some(control);
+        self
+    }
+
+    // Draw particles that drift uphill on layer.
+    pub fn with_particles(mut self, layer: usize) -> Self {
+        self.particle_layer = Some(layer);
+        self
+    }
+
+    // Split the picture over several projectors, warped and blended at the seams.
+    pub fn with_projection(mut self, calibration: Calibration) -> Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    pub fn with_canvas(mut self, canvas: Canvas) -> Self {
+        self.canvas = canvas;
+        self
+    }
+
+    // Compute expensive trees a quarter at a time, over several frames, to keep
+    // them responsive.
+    pub fn with_progressive_refinement(mut self) -> Self {
+        self.progressive = true;
+        self
+    }
+
+    pub fn with_quality(mut self, quality: Quality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub fn quality(&self) -> Quality {
+        self.quality
+    }
+
+    // How to sample each of the three layers when drawing them.
+    pub fn with_layer_filters(mut self, filters: [LayerFilter; 3]) -> Self {
+        self.filters = filters;
+        self
+    }
+}
+
+// Computes a tree into layer textures and draws them, full screen, into a frame.
+// Each frame shows the layers computed on the frame before, so that computing the
+// next one can overlap presenting this one:
+//
+//   let upload = display.encode_upload_buffers(&gpu, &tree)?;
+//   let mut frame = gpu.begin_frame()?;
+//   display.upload(&upload, &mut frame);
+//   display.draw(&mut frame.begin_render_pass());
+//   frame.finish();
+//   display.compute(&mut gpu, upload);
+pub struct Display {
+    config: Configuration,
+    config_buffer: wgpu::Buffer,
+    draw_config: DrawConfiguration,
+    draw_config_buffer: wgpu::Buffer,
+    extent: wgpu::Extent3d,
+    layer_format: wgpu::TextureFormat,
+    interpreter: Interpreter,
+    mipmap_generator: MipmapGenerator,
+    reaction: Option<ReactionDiffusion>,
+    particles: Option<Particles>,
+    volume: Option<Volume>,
+    terrain: Option<Terrain>,
+    projection: Option<Projection>,
+    exposure: AutoExposure,
+    histogram: Histogram,
+    canvas: Canvas,
+    // All three layers' instructions and constants, one after the other.
+    instr_buffer: wgpu::Buffer,
+    pool_buffer: wgpu::Buffer,
+    mirror: LayerMirror,
+    layers: Vec<ComputeLayer>,
+    pipeline: wgpu::RenderPipeline,
+    bind_groups: Vec<wgpu::BindGroup>,
+    vertex_buffer: wgpu::Buffer,
+    display_slot: usize,
+    upload_tree: bool,
+    preview: bool,
+    progressive: bool,
+    // Whether the tree on screen is expensive enough to refine, and how many
+    // frames have been spent on it.
+    refining: bool,
+    refine_frame: u64,
+}
+
+impl Display {
+    pub fn new(
+        gpu: &mut GPU,
+        extent: wgpu::Extent3d,
+        layer_format: wgpu::TextureFormat,
+        display_config: DisplayConfig,
+    ) -> Fallible<Self> {
+        // Volumes, terrain and projectors have their own ways onto the screen, and
+        // particles are drawn over the whole window.
+        let is_flat = display_config.mode != DisplayMode::Volume
+            && display_config.mode != DisplayMode::Terrain
+            && display_config.particle_layer.is_none()
+            && display_config.calibration.is_none();
+        if display_config.canvas.fit != CanvasFit::Stretch && !is_flat {
+            bail!(
+                Invalid,
+                "only flat pictures without particles or projectors can be fitted to the window"
+            );
+        }
+
+        // Compute Resources
+        let extent = display_config.quality.scale_extent(extent);
+        let uni_shader_layout = compute::create_layout(gpu);
+        let mut config = Configuration::new(extent, 1f32 / gpu.aspect_ratio_f32());
+        config.set_quality(display_config.quality);
+        let config_buffer = config.create_buffer(gpu.device());
+        let interpreter = Interpreter::select(
+            gpu,
+            &uni_shader_layout,
+            &config_buffer,
+            layer_format,
+            extent,
+            display_config.half,
+            display_config.workgroup_size.as_deref(),
+        )?;
+        let samplers = [
+            display_config.filters[0].create_sampler(gpu),
+            display_config.filters[1].create_sampler(gpu),
+            display_config.filters[2].create_sampler(gpu),
+        ];
+        // Keep a full mip chain so that display in a smaller window does not alias.
+        let mipmap_generator = MipmapGenerator::new(gpu, layer_format)?;
+        let reaction = display_config
+            .reaction_control
+            .map(|control| ReactionDiffusion::new(gpu, control))
+            .transpose()?;
+        let instr_buffer = compute::create_instr_buffer(gpu, 3);
+        let pool_buffer = compute::create_pool_buffer(gpu, 3);
+        let placeholder_state = compute::create_placeholder_state(gpu);
+        let reaction_view = reaction
+            .as_ref()
+            .map(|rd| rd.state_view())
+            .unwrap_or(&placeholder_state);
+        let volume = if display_config.mode == DisplayMode::Volume {
+            Some(Volume::new(
+                gpu,
+                &uni_shader_layout,
+                layer_format,
+                &config_buffer,
+                &instr_buffer,
+                &pool_buffer,
+                reaction_view,
+            )?)
+        } else {
+            None
+        };
+        let layer_mip_level_count = mip_level_count(extent);
+        let layers = (0..3)
+            .map(|i| {
+                let targets = (0..FRAME_SLOTS)
+                    .map(|_| {
+                        let texture = gpu.device().create_texture(&wgpu::TextureDescriptor {
+                            size: extent,
+                            array_layer_count: 1,
+                            mip_level_count: layer_mip_level_count,
+                            sample_count: 1,
+                            dimension: wgpu::TextureDimension::D2,
+                            format: layer_format,
+                            usage: wgpu::TextureUsage::all(),
+                        });
+                        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                            format: layer_format,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            aspect: wgpu::TextureAspect::All,
+                            base_mip_level: 0,
+                            level_count: 1, // mip level
+                            base_array_layer: 0,
+                            array_layer_count: 1,
+                        });
+                        let sampled_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                            format: layer_format,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            aspect: wgpu::TextureAspect::All,
+                            base_mip_level: 0,
+                            level_count: layer_mip_level_count,
+                            base_array_layer: 0,
+                            array_layer_count: 1,
+                        });
+                        let mip_chain = mipmap_generator.create_chain(
+                            gpu,
+                            &texture,
+                            layer_format,
+                            layer_mip_level_count,
+                        );
+                        let bind_group = compute::create_bind_group(
+                            gpu,
+                            &uni_shader_layout,
+                            &config_buffer,
+                            &texture_view,
+                            &instr_buffer,
+                            &pool_buffer,
+                            i,
+                            reaction_view,
+                        );
+                        LayerTarget {
+                            texture,
+                            texture_view,
+                            sampled_view,
+                            mip_chain,
+                            bind_group,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                ComputeLayer { targets }
+            })
+            .collect::<Vec<_>>();
+
+        // Screen Resources
+        let draw_config = DrawConfiguration::default();
+        let draw_config_buffer = gpu
+            .device()
+            .create_buffer_mapped(1, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST)
+            .fill_from_slice(&[draw_config]);
+        let slot_views = (0..FRAME_SLOTS)
+            .map(|slot| {
+                [
+                    &layers[0].targets[slot].sampled_view,
+                    &layers[1].targets[slot].sampled_view,
+                    &layers[2].targets[slot].sampled_view,
+                ]
+            })
+            .collect::<Vec<_>>();
+        let exposure = AutoExposure::new(gpu, extent, layer_mip_level_count, &slot_views)?;
+        let terrain = if display_config.mode == DisplayMode::Terrain {
+            Some(Terrain::new(gpu, &config_buffer, &slot_views)?)
+        } else {
+            None
+        };
+        let particles = match display_config.particle_layer {
+            Some(_)
+                if volume.is_some()
+                    || terrain.is_some()
+                    || display_config.calibration.is_some() =>
+            {
+                bail!(Invalid, "particles are only drawn over flat pictures")
+            }
+            Some(layer) if layer < layers.len() => Some(Particles::new(
+                gpu,
+                &layers[layer]
+                    .targets
+                    .iter()
+                    .map(|target| &target.sampled_view)
+                    .collect::<Vec<_>>(),
+            )?),
+            Some(layer) => bail!(
+                Invalid,
+                "there is no layer {} for particles to follow",
+                layer
+            ),
+            None => None,
+        };
+        // Shared with the histogram, which counts the colors as they are drawn.
+        let graphics_layout =
+            gpu.device()
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    bindings: &[
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 0,
+                            visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::SampledTexture {
+                                multisampled: false,
+                                dimension: wgpu::TextureViewDimension::D2,
+                            },
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 1,
+                            visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::Sampler,
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 2,
+                            visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::SampledTexture {
+                                multisampled: false,
+                                dimension: wgpu::TextureViewDimension::D2,
+                            },
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 3,
+                            visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::Sampler,
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 4,
+                            visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::SampledTexture {
+                                multisampled: false,
+                                dimension: wgpu::TextureViewDimension::D2,
+                            },
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 5,
+                            visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::Sampler,
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 6,
+                            visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 7,
+                            visibility: wgpu::ShaderStage::FRAGMENT | wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::StorageBuffer {
+                                dynamic: false,
+                                readonly: true,
+                            },
+                        },
+                    ],
+                });
+        let histogram = Histogram::new(gpu, &graphics_layout)?;
+        let vert_shader = gpu.create_shader_module(include_bytes!("../target/draw.vert.spirv"))?;
+        // Volumes and terrain draw themselves, so the flat pipeline goes unused.
+        let frag_shader = gpu.create_shader_module(match display_config.mode {
+            DisplayMode::FlowField => &include_bytes!("../target/draw_flow.frag.spirv")[..],
+            DisplayMode::Anaglyph => &include_bytes!("../target/draw_anaglyph.frag.spirv")[..],
+            DisplayMode::SideBySide => {
+                &include_bytes!("../target/draw_side_by_side.frag.spirv")[..]
+            }
+            DisplayMode::Color | DisplayMode::Volume | DisplayMode::Terrain => {
+                &include_bytes!("../target/draw.frag.spirv")[..]
+            }
+        })?;
+        let pipeline = gpu
+            .device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[&graphics_layout],
+                    }),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &vert_shader,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &frag_shader,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::Back,
+                    depth_bias: 0,
+                    depth_bias_slope_scale: 0.0,
+                    depth_bias_clamp: 0.0,
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: GPU::texture_format(),
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: GPU::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_read_mask: 0,
+                    stencil_write_mask: 0,
+                }),
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: 8,
+                            shader_location: 1,
+                        },
+                    ],
+                }],
+                sample_count: gpu.sample_count(),
+                sample_mask: !0,
+                alpha_to_coverage_enabled: false,
+            });
+        let verts = [
+            Vertex {
+                position: [-1f32, -1f32],
+                tex_coord: [0f32, 0f32],
+            },
+            Vertex {
+                position: [-1f32, 1f32],
+                tex_coord: [0f32, 1f32],
+            },
+            Vertex {
+                position: [1f32, -1f32],
+                tex_coord: [1f32, 0f32],
+            },
+            Vertex {
+                position: [1f32, 1f32],
+                tex_coord: [1f32, 1f32],
+            },
+        ];
+        let vertex_buffer = gpu
+            .device()
+            .create_buffer_mapped(verts.len(), wgpu::BufferUsage::all())
+            .fill_from_slice(&verts);
+        let bind_groups = (0..FRAME_SLOTS)
+            .map(|slot| {
+                gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &graphics_layout,
+                    bindings: &[
+                        wgpu::Binding {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(
+                                &layers[0].targets[slot].sampled_view,
+                            ),
+                        },
+                        wgpu::Binding {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&samplers[0]),
+                        },
+                        wgpu::Binding {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(
+                                &layers[1].targets[slot].sampled_view,
+                            ),
+                        },
+                        wgpu::Binding {
+                            binding: 3,
+                            resource: wgpu::BindingResource::Sampler(&samplers[1]),
+                        },
+                        wgpu::Binding {
+                            binding: 4,
+                            resource: wgpu::BindingResource::TextureView(
+                                &layers[2].targets[slot].sampled_view,
+                            ),
+                        },
+                        wgpu::Binding {
+                            binding: 5,
+                            resource: wgpu::BindingResource::Sampler(&samplers[2]),
+                        },
+                        wgpu::Binding {
+                            binding: 6,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: &draw_config_buffer,
+                                range: 0..DrawConfiguration::buffer_size(),
+                            },
+                        },
+                        wgpu::Binding {
+                            binding: 7,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: exposure.exposure_buffer(),
+                                range: 0..AutoExposure::exposure_buffer_size(),
+                            },
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+        let projection = match &display_config.calibration {
+            Some(_) if display_config.mode != DisplayMode::Color => {
+                bail!(Invalid, "projection only works with the color display mode")
+            }
+            Some(calibration) => Some(Projection::new(gpu, &graphics_layout, calibration)?),
+            None => None,
+        };
+
+        let mut display = Self {
+            config,
+            config_buffer,
+            draw_config,
+            draw_config_buffer,
+            extent,
+            layer_format,
+            interpreter,
+            mipmap_generator,
+            reaction,
+            particles,
+            volume,
+            terrain,
+            projection,
+            exposure,
+            histogram,
+            canvas: display_config.canvas,
+            instr_buffer,
+            pool_buffer,
+            mirror: LayerMirror::new(3),
+            layers,
+            pipeline,
+            bind_groups,
+            vertex_buffer,
+            display_slot: 0,
+            upload_tree: true,
+            preview: false,
+            progressive: display_config.progressive,
+            refining: false,
+            refine_frame: 0,
+        };
+        display.note_resize(gpu);
+        Ok(display)
+    }
+
+    // Lays the canvas out again for the window's new shape.
+    pub fn note_resize(&mut self, gpu: &GPU) {
+        let (scale, aspect_ratio) = self
+            .canvas
+            .layout(self.extent, 1f32 / gpu.aspect_ratio_f32());
+        self.config.aspect_ratio = aspect_ratio;
+        self.draw_config.canvas_scale = scale;
+        self.draw_config.canvas_center = self.canvas.center(scale);
+        let [r, g, b] = self.canvas.border_color;
+        self.draw_config.border_color = [r, g, b, 1f32];
+        self.histogram.note_resize(gpu);
+    }
+
+    // Where a point in the window, in uv, falls on the canvas. Must match
+    // window_to_canvas in include/draw.glsl.
+    pub fn window_to_canvas(&self, uv: [f32; 2]) -> [f32; 2] {
+        let scale = self.draw_config.canvas_scale;
+        let center = self.draw_config.canvas_center;
+        [
+            (uv[0] - center[0]) / scale[0] + 0.5,
+            (uv[1] - center[1]) / scale[1] + 0.5,
+        ]
+    }
+
+    // The size of the layers as computed, which is not the window's.
+    pub fn extent(&self) -> wgpu::Extent3d {
+        self.extent
+    }
+
+    // View, mouse and aspect ratio; the time is taken from the tree every frame.
+    pub fn config(&self) -> &Configuration {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut Configuration {
+        &mut self.config
+    }
+
+    // How the layers are put on the screen, as opposed to how they are computed.
+    pub fn draw_config(&self) -> &DrawConfiguration {
+        &self.draw_config
+    }
+
+    pub fn draw_config_mut(&mut self) -> &mut DrawConfiguration {
+        &mut self.draw_config
+    }
+
+    pub fn exposure_control(&self) -> ExposureControl {
+        self.exposure.control()
+    }
+
+    // Volumes and terrain are lit on their own and are not exposed.
+    pub fn set_exposure_control(&mut self, control: ExposureControl) {
+        self.exposure.set_control(control);
+    }
+
+    // The texels of the three layers on screen under a point on the canvas, in uv,
+    // as computed; waits for the GPU, so it is only for inspecting.
+    pub fn read_layers_at(&self, gpu: &mut GPU, uv: [f32; 2]) -> Fallible<[f32; 3]> {
+        let texel = |v: f32, size: u32| ((v * size as f32) as u32).min(size - 1);
+        let origin = wgpu::Origin3d {
+            x: texel(uv[0], self.extent.width) as f32,
+            y: texel(uv[1], self.extent.height) as f32,
+            z: 0f32,
+        };
+        // Each layer's texel goes at the start of its own row.
+        const ROW_PITCH: u32 = 256;
+        let buffer = gpu.device().create_buffer(&wgpu::BufferDescriptor {
+            size: wgpu::BufferAddress::from(ROW_PITCH * 3),
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        });
+        let mut encoder = gpu
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        for (i, layer) in self.layers.iter().enumerate() {
+            encoder.copy_texture_to_buffer(
+                wgpu::TextureCopyView {
+                    texture: &layer.targets[self.display_slot].texture,
+                    mip_level: 0,
+                    array_layer: 0,
+                    origin,
+                },
+                wgpu::BufferCopyView {
+                    buffer: &buffer,
+                    offset: wgpu::BufferAddress::from(ROW_PITCH * i as u32),
+                    row_pitch: ROW_PITCH,
+                    image_height: 1,
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+            );
+        }
+        gpu.queue_mut().submit(&[encoder.finish()]);
+        let mapped = Arc::new(Mutex::new(None));
+        let target = mapped.clone();
+        buffer.map_read_async(0, wgpu::BufferAddress::from(ROW_PITCH * 3), move |result| {
+            *target.lock().unwrap() = Some(result.map(|mapping| mapping.data.to_vec()));
+        });
+        gpu.device().poll(true);
+        let data = mapped
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| StampedeError::Gpu("the layers were not read back".to_owned()))?
+            .map_err(|()| StampedeError::Gpu("failed to map the layers".to_owned()))?;
+        let mut values = [0f32; 3];
+        for (i, value) in values.iter_mut().enumerate() {
+            let b = &data[ROW_PITCH as usize * i..];
+            *value = match self.layer_format {
+                wgpu::TextureFormat::R16Float => f16_to_f32(u16::from_le_bytes([b[0], b[1]])),
+                _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            };
+        }
+        Ok(values)
+    }
+
+    // Drawn by the caller, over everything else on screen.
+    pub fn histogram(&self) -> &Histogram {
+        &self.histogram
+    }
+
+    pub fn histogram_mut(&mut self) -> &mut Histogram {
+        &mut self.histogram
+    }
+
+    // A preview computes one texel in every PREVIEW_BLOCK_SIZE square, for a
+    // sixteenth of the work, and is blocky to match.
+    pub fn set_preview(&mut self, preview: bool) {
+        if self.preview && !preview {
+            self.refine_frame = 0;
+        }
+        self.preview = preview;
+    }
+
+    // Picks the texels to compute this frame. Refining goes through the texels of
+    // every square of PROGRESSIVE_STRIDE on a side in turn, one pass a frame. The
+    // slots take turns, so each pass is computed twice, once into each. The first
+    // pass into each fills the squares around its texels, so that there are no
+    // gaps left over from whatever was there before.
+    fn choose_pass(&mut self) {
+        let config = &mut self.config;
+        if self.preview {
+            config.block_size = PREVIEW_BLOCK_SIZE;
+            config.pass_stride = PREVIEW_BLOCK_SIZE;
+            config.pass_offset = [0, 0];
+        } else if self.refining {
+            let pass = self.refine_frame / FRAME_SLOTS as u64;
+            let phase = (pass % u64::from(PROGRESSIVE_STRIDE * PROGRESSIVE_STRIDE)) as u32;
+            config.block_size = if pass == 0 { PROGRESSIVE_STRIDE } else { 1 };
+            config.pass_stride = PROGRESSIVE_STRIDE;
+            config.pass_offset = [phase % PROGRESSIVE_STRIDE, phase / PROGRESSIVE_STRIDE];
+            self.refine_frame += 1;
+        } else {
+            config.block_size = 1;
+            config.pass_stride = 1;
+            config.pass_offset = [0, 0];
+        }
+    }
+
+    // Constants are animated on the GPU, so the tree itself only needs to be
+    // uploaded when it changes.
+    pub fn note_tree_changed(&mut self) {
+        self.upload_tree = true;
+    }
+
+    // Whether the tree has changed since it was last uploaded.
+    pub fn is_upload_pending(&self) -> bool {
+        self.upload_tree
+    }
+
+    // A tree that does not fit the interpreter is an error, and the one before it
+    // stays up.
+    pub fn encode_upload_buffers(&mut self, gpu: &GPU, tree: &Tree) -> Fallible<DisplayUpload> {
+        let tree_upload = if self.upload_tree {
+            self.upload_tree = false;
+            let encoded = (0..3)
+                .map(|i| tree.encode_layer(i))
+                .collect::<Fallible<Vec<_>>>()?;
+            self.refining =
+                self.progressive && tree.largest_layer_node_count() >= PROGRESSIVE_NODE_COUNT;
+            self.refine_frame = 0;
+            self.mirror.update(encoded, gpu.device())
+        } else {
+            None
+        };
+        self.config.time = tree.time();
+        self.choose_pass();
+        let config_buffer = gpu
+            .device()
+            .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&[self.config]);
+        let draw_config_buffer = gpu
+            .device()
+            .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&[self.draw_config]);
+        Ok(DisplayUpload {
+            config_buffer,
+            draw_config_buffer,
+            tree_upload,
+        })
+    }
+
+    pub fn upload(&self, upload: &DisplayUpload, frame: &mut Frame) {
+        frame.copy_buffer_to_buffer(
+            &upload.config_buffer,
+            0,
+            &self.config_buffer,
+            0,
+            Configuration::buffer_size(),
+        );
+        frame.copy_buffer_to_buffer(
+            &upload.draw_config_buffer,
+            0,
+            &self.draw_config_buffer,
+            0,
+            DrawConfiguration::buffer_size(),
+        );
+    }
+
+    // Draws the layers that were computed last time around.
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
+        if let Some(volume) = &self.volume {
+            volume.draw(rpass);
+            return;
+        }
+        if let Some(terrain) = &self.terrain {
+            terrain.draw(rpass, self.display_slot);
+            return;
+        }
+        if let Some(projection) = &self.projection {
+            projection.draw(rpass, &self.bind_groups[self.display_slot]);
+            return;
+        }
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_groups[self.display_slot], &[]);
+        rpass.set_vertex_buffers(0, &[(&self.vertex_buffer, 0)]);
+        rpass.draw(0..4, 0..1);
+        if let Some(particles) = &self.particles {
+            particles.draw(rpass);
+        }
+    }
+
+    // Computes the next frame into the other slot in a separate submission, so
+    // that the GPU can work on it while this frame is waiting to be presented.
+    pub fn compute(&mut self, gpu: &mut GPU, upload: DisplayUpload) {
+        let compute_slot = (self.display_slot + 1) % FRAME_SLOTS;
+        let mut encoder = gpu
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        // The volume reads from the same buffers, so it is brought up to date too.
+        if let Some(upload) = &upload.tree_upload {
+            upload.copy_to(&mut encoder, &self.instr_buffer, &self.pool_buffer);
+        }
+        if let Some(reaction) = &mut self.reaction {
+            reaction.step(gpu.device(), &mut encoder, self.config.time);
+        }
+        // A volume stands in for the flat layers entirely.
+        if let Some(volume) = &self.volume {
+            volume.compute(gpu.device(), &mut encoder, &self.interpreter, &self.config);
+            gpu.queue_mut().submit(&[encoder.finish()]);
+            return;
+        }
+        for layer in &self.layers {
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_pipeline(self.interpreter.pipeline());
+            cpass.set_bind_group(0, &layer.targets[compute_slot].bind_group, &[]);
+            self.interpreter
+                .dispatch_strided(&mut cpass, self.extent, self.config.pass_stride);
+        }
+        for layer in &self.layers {
+            self.mipmap_generator
+                .generate(&layer.targets[compute_slot].mip_chain, &mut encoder);
+        }
+        self.exposure
+            .update(gpu.device(), &mut encoder, compute_slot, self.config.time);
+        self.histogram
+            .update(&mut encoder, &self.bind_groups[compute_slot], self.extent);
+        // The particles follow the layer that is on screen, not the one on its way.
+        if let Some(particles) = &mut self.particles {
+            particles.update(
+                gpu.device(),
+                &mut encoder,
+                self.display_slot,
+                self.config.time,
+            );
+        }
+        gpu.queue_mut().submit(&[encoder.finish()]);
+        self.display_slot = compute_slot;
+    }
+}
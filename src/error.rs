@@ -0,0 +1,68 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use gpu::GpuError;
+use std::io;
+use thiserror::Error;
+
+pub type Fallible<T> = Result<T, StampedeError>;
+
+// Return early with one kind of error and a formatted reason, e.g.
+// bail!(Encoding, "instruction {} underflows the stack", i).
+macro_rules! bail {
+    ($kind:ident, $($arg:tt)*) => {
+        return Err($crate::error::StampedeError::$kind(format!($($arg)*)))
+    };
+}
+
+// Everything that can go wrong in the library, by where it went wrong, so that
+// callers can tell a tree that will not fit from a GPU that is not there.
+#[derive(Debug, Error)]
+pub enum StampedeError {
+    // No adapter, or none that would do, or a readback that never came.
+    #[error("gpu: {0}")]
+    Gpu(String),
+
+    // A built in shader that wgpu would not take.
+    #[error("shader: {0}")]
+    Shader(#[source] io::Error),
+
+    // Instructions or bytes that do not make a tree, or a tree that does not fit
+    // the interpreter.
+    #[error("encoding: {0}")]
+    Encoding(String),
+
+    // A tree that could not be grown, e.g. with no ops to grow it from.
+    #[error("growth: {0}")]
+    Growth(String),
+
+    // A setting, name or description that makes no sense.
+    #[error("{0}")]
+    Invalid(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+impl From<GpuError> for StampedeError {
+    fn from(e: GpuError) -> Self {
+        match e {
+            GpuError::Shader(e) => Self::Shader(e),
+            e => Self::Gpu(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{clock::Clock, recipe::Recipe, scene::Scene};
+use anyhow::Result;
+use gpu::GPU;
+use stampede::{
+    compute::Quality,
+    render::{srgb_to_8bit, OffscreenRenderer},
+    tree::Tree,
+};
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+use wgpu;
+
+// An 8 bit RGB PNG with its header and recipe written, ready for rows from the
+// top.
+pub fn create_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    recipe: &Recipe,
+) -> Result<png::Writer<BufWriter<File>>> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    recipe.write_chunks(&mut writer)?;
+    Ok(writer)
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, rgb: &[u8], recipe: &Recipe) -> Result<()> {
+    create_png(path, width, height, recipe)?.write_image_data(rgb)?;
+    Ok(())
+}
+
+// The scene as it is now, with its recipe.
+pub fn write_frame(
+    gpu: &mut GPU,
+    renderer: &OffscreenRenderer,
+    scene: &Scene,
+    seed: Option<&str>,
+    extent: wgpu::Extent3d,
+    path: &Path,
+) -> Result<()> {
+    let rgb = scene.render(renderer, gpu, extent.width, extent.height)?;
+    let recipe = Recipe {
+        seed: seed.map(str::to_owned),
+        tree: scene.tree().map(Tree::to_json).transpose()?,
+        scene: scene
+            .source()
+            .map(|source| (source.to_owned(), scene.time())),
+    };
+    write_png(
+        path,
+        extent.width,
+        extent.height,
+        &srgb_to_8bit(&rgb),
+        &recipe,
+    )
+}
+
+// Writes frames of the scene's animation to out as frame_00000.png and so on. Time
+// steps by exactly 1/fps between frames, so the same scene always exports the same
+// frames.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    gpu: &mut GPU,
+    mut scene: Scene,
+    seed: Option<String>,
+    frame_count: usize,
+    fps: f32,
+    extent: wgpu::Extent3d,
+    quality: Quality,
+    out: &Path,
+) -> Result<()> {
+    fs::create_dir_all(out)?;
+    let renderer = OffscreenRenderer::new(gpu)?.with_quality(quality);
+    let mut clock = Clock::fixed(fps);
+    for i in 0..frame_count {
+        let path = out.join(format!("frame_{:05}.png", i));
+        write_frame(gpu, &renderer, &scene, seed.as_deref(), extent, &path)?;
+        scene.animate(clock.tick());
+    }
+    println!("wrote {} frames to {}", frame_count, out.display());
+    Ok(())
+}
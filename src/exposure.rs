@@ -0,0 +1,331 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::{Fallible, StampedeError};
+use gpu::GPU;
+use std::{mem, str::FromStr};
+use wgpu;
+use zerocopy::{AsBytes, FromBytes};
+
+// Must match include/exposure.glsl.
+const BIN_COUNT: usize = 64;
+
+// The histogram is taken from a mip this many halvings down from the layer,
+// which is plenty to judge brightness by.
+const HISTOGRAM_LOD: u32 = 3;
+
+// How bright the picture is shown, in multiples of its own light.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExposureControl {
+    // Fixed, at this exposure.
+    Manual(f32),
+    // Follow the picture, so that it keeps around a middle gray.
+    Auto,
+    // Stay wherever Auto got to.
+    Hold,
+}
+
+impl Default for ExposureControl {
+    fn default() -> Self {
+        ExposureControl::Manual(1f32)
+    }
+}
+
+impl FromStr for ExposureControl {
+    type Err = StampedeError;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "auto" => ExposureControl::Auto,
+            _ => match s.parse::<f32>() {
+                Ok(exposure) if exposure > 0f32 => ExposureControl::Manual(exposure),
+                _ => bail!(
+                    Invalid,
+                    "unknown exposure {}; expected auto or a positive number",
+                    s
+                ),
+            },
+        })
+    }
+}
+
+// Must match the ExposureConfiguration block in include/exposure.glsl.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
+struct ExposureConfiguration {
+    dt: f32,
+    manual_exposure: f32,
+    mode: u32,
+    lod: u32,
+}
+
+impl ExposureConfiguration {
+    fn buffer_size() -> wgpu::BufferAddress {
+        mem::size_of::<Self>() as wgpu::BufferAddress
+    }
+}
+
+// Keeps the exposure that the final pass scales the picture's light by. Each frame
+// the picture's lightness is counted into a histogram on the GPU and the exposure
+// eased toward one that would put its median at a middle gray, so nothing has to
+// be read back.
+pub struct AutoExposure {
+    control: ExposureControl,
+    config_buffer: wgpu::Buffer,
+    exposure_buffer: wgpu::Buffer,
+    histogram_pipeline: wgpu::ComputePipeline,
+    adapt_pipeline: wgpu::ComputePipeline,
+    // One for each slot of the layers.
+    bind_groups: Vec<wgpu::BindGroup>,
+    histogram_extent: wgpu::Extent3d,
+    lod: u32,
+    last_time: Option<f32>,
+}
+
+impl AutoExposure {
+    pub fn new(
+        gpu: &GPU,
+        extent: wgpu::Extent3d,
+        mip_level_count: u32,
+        slot_views: &[[&wgpu::TextureView; 3]],
+    ) -> Fallible<Self> {
+        let lod = HISTOGRAM_LOD.min(mip_level_count - 1);
+        let histogram_extent = wgpu::Extent3d {
+            width: (extent.width >> lod).max(1),
+            height: (extent.height >> lod).max(1),
+            depth: 1,
+        };
+        let config_buffer = gpu
+            .device()
+            .create_buffer_mapped(1, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST)
+            .fill_from_slice(&[ExposureConfiguration {
+                lod,
+                ..Default::default()
+            }]);
+        let histogram_buffer = gpu
+            .device()
+            .create_buffer_mapped(BIN_COUNT, wgpu::BufferUsage::STORAGE)
+            .fill_from_slice(&[0u32; BIN_COUNT]);
+        let exposure_buffer = gpu
+            .device()
+            .create_buffer_mapped(1, wgpu::BufferUsage::STORAGE)
+            .fill_from_slice(&[1f32]);
+        let layer_sampler = gpu.device().create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0f32,
+            lod_max_clamp: 9_999_999f32,
+            compare_function: wgpu::CompareFunction::Never,
+        });
+
+        let texture_binding = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        let storage_binding = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: false,
+            },
+        };
+        let layout = gpu
+            .device()
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::COMPUTE,
+                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                    },
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::COMPUTE,
+                        ty: wgpu::BindingType::Sampler,
+                    },
+                    texture_binding(2),
+                    texture_binding(3),
+                    texture_binding(4),
+                    storage_binding(5),
+                    storage_binding(6),
+                ],
+            });
+        let pipeline_layout =
+            gpu.device()
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    bind_group_layouts: &[&layout],
+                });
+        let histogram_shader =
+            gpu.create_shader_module(include_bytes!("../target/exposure_histogram.comp.spirv"))?;
+        let histogram_pipeline =
+            gpu.device()
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    layout: &pipeline_layout,
+                    compute_stage: wgpu::ProgrammableStageDescriptor {
+                        module: &histogram_shader,
+                        entry_point: "main",
+                    },
+                });
+        let adapt_shader =
+            gpu.create_shader_module(include_bytes!("../target/exposure_adapt.comp.spirv"))?;
+        let adapt_pipeline =
+            gpu.device()
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    layout: &pipeline_layout,
+                    compute_stage: wgpu::ProgrammableStageDescriptor {
+                        module: &adapt_shader,
+                        entry_point: "main",
+                    },
+                });
+        let bind_groups = slot_views
+            .iter()
+            .map(|views| {
+                gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &layout,
+                    bindings: &[
+                        wgpu::Binding {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: &config_buffer,
+                                range: 0..ExposureConfiguration::buffer_size(),
+                            },
+                        },
+                        wgpu::Binding {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&layer_sampler),
+                        },
+                        wgpu::Binding {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(views[0]),
+                        },
+                        wgpu::Binding {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(views[1]),
+                        },
+                        wgpu::Binding {
+                            binding: 4,
+                            resource: wgpu::BindingResource::TextureView(views[2]),
+                        },
+                        wgpu::Binding {
+                            binding: 5,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: &histogram_buffer,
+                                range: 0..Self::histogram_buffer_size(),
+                            },
+                        },
+                        wgpu::Binding {
+                            binding: 6,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: &exposure_buffer,
+                                range: 0..Self::exposure_buffer_size(),
+                            },
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            control: ExposureControl::default(),
+            config_buffer,
+            exposure_buffer,
+            histogram_pipeline,
+            adapt_pipeline,
+            bind_groups,
+            histogram_extent,
+            lod,
+            last_time: None,
+        })
+    }
+
+    fn histogram_buffer_size() -> wgpu::BufferAddress {
+        (mem::size_of::<u32>() * BIN_COUNT) as wgpu::BufferAddress
+    }
+
+    pub fn exposure_buffer_size() -> wgpu::BufferAddress {
+        mem::size_of::<f32>() as wgpu::BufferAddress
+    }
+
+    // A single float, for the final pass to read.
+    pub fn exposure_buffer(&self) -> &wgpu::Buffer {
+        &self.exposure_buffer
+    }
+
+    pub fn control(&self) -> ExposureControl {
+        self.control
+    }
+
+    pub fn set_control(&mut self, control: ExposureControl) {
+        self.control = control;
+    }
+
+    // Measure the picture in slot and move the exposure toward it by
+    // however much time has passed on the tree's clock.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        slot: usize,
+        time: f32,
+    ) {
+        let dt = self
+            .last_time
+            .map(|last| (time - last).max(0f32).min(0.1))
+            .unwrap_or(0f32);
+        self.last_time = Some(time);
+        let (mode, manual_exposure) = match self.control {
+            ExposureControl::Manual(exposure) => (0, exposure),
+            ExposureControl::Auto => (1, 1f32),
+            ExposureControl::Hold => (2, 1f32),
+        };
+        let config = ExposureConfiguration {
+            dt,
+            manual_exposure,
+            mode,
+            lod: self.lod,
+        };
+        let upload_buffer = device
+            .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&[config]);
+        encoder.copy_buffer_to_buffer(
+            &upload_buffer,
+            0,
+            &self.config_buffer,
+            0,
+            ExposureConfiguration::buffer_size(),
+        );
+        let mut cpass = encoder.begin_compute_pass();
+        if self.control == ExposureControl::Auto {
+            cpass.set_pipeline(&self.histogram_pipeline);
+            cpass.set_bind_group(0, &self.bind_groups[slot], &[]);
+            cpass.dispatch(
+                (self.histogram_extent.width + 7) / 8,
+                (self.histogram_extent.height + 7) / 8,
+                1,
+            );
+        }
+        cpass.set_pipeline(&self.adapt_pipeline);
+        cpass.set_bind_group(0, &self.bind_groups[slot], &[]);
+        cpass.dispatch(1, 1, 1);
+    }
+}
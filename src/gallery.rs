@@ -0,0 +1,208 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::library::{Entry, Library};
+use anyhow::{bail, Result};
+use chrono::{Local, Timelike};
+use rand::prelude::*;
+use serde::Deserialize;
+use stampede::tree::Tree;
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+fn default_duration() -> f32 {
+    60f32
+}
+
+// A favorite to show, by name or canonical hash, for as long as it says or else
+// as long as its playlist says.
+#[derive(Debug, Deserialize)]
+struct Slide {
+    tree: String,
+    #[serde(default)]
+    duration: Option<f32>,
+}
+
+// What to show over part of the day. Times are local, as "HH:MM"; a playlist
+// whose end is before its start runs over midnight. It shows the favorites named
+// in trees, or those with every one of tags, or all of them if neither is given.
+#[derive(Debug, Deserialize)]
+struct Playlist {
+    from: String,
+    to: String,
+    #[serde(default)]
+    trees: Vec<Slide>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    duration: Option<f32>,
+    #[serde(default)]
+    shuffle: Option<bool>,
+}
+
+// A day of playlists from the favorites, e.g.:
+//
+//   {"duration": 300, "shuffle": true, "playlists": [
+//     {"from": "07:00", "to": "19:00", "tags": ["calm"]},
+//     {"from": "19:00", "to": "07:00", "shuffle": false, "trees": [
+//       {"tree": "crimson-meadow", "duration": 900}, {"tree": "3f2a"}]}
+//   ]}
+//
+// The first playlist to cover the time of day is the one that plays; when none
+// does, whatever is showing stays.
+#[derive(Debug, Deserialize)]
+struct Schedule {
+    #[serde(default = "default_duration")]
+    duration: f32,
+    #[serde(default)]
+    shuffle: bool,
+    playlists: Vec<Playlist>,
+}
+
+fn minutes(time: &str) -> Result<u32> {
+    let mut parts = time.splitn(2, ':');
+    let (hours, minutes) = match (parts.next(), parts.next()) {
+        (Some(h), Some(m)) => (h.trim().parse::<u32>()?, m.trim().parse::<u32>()?),
+        _ => bail!("expected HH:MM, not {}", time),
+    };
+    if hours > 23 || minutes >= 60 {
+        bail!("{} is not a time of day", time);
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl Playlist {
+    fn covers(&self, now: u32) -> Result<bool> {
+        let (from, to) = (minutes(&self.from)?, minutes(&self.to)?);
+        Ok(if from <= to {
+            from <= now && now < to
+        } else {
+            now >= from || now < to
+        })
+    }
+}
+
+// Plays favorites by schedule, for a display that runs unattended: the library is
+// read again whenever a playlist starts over, so that new favorites and tags are
+// picked up, and favorites that cannot be read are passed over.
+pub struct Gallery {
+    schedule: Schedule,
+    playing: Option<usize>,
+    queue: Vec<(String, Tree, Duration)>,
+    next_at: Option<Instant>,
+    rng: StdRng,
+}
+
+impl Gallery {
+    pub fn load(path: &Path) -> Result<Self> {
+        let schedule: Schedule = serde_json::from_str(&fs::read_to_string(path)?)?;
+        // Check every time up front, rather than weeks in.
+        for playlist in &schedule.playlists {
+            minutes(&playlist.from)?;
+            minutes(&playlist.to)?;
+        }
+        Ok(Self {
+            schedule,
+            playing: None,
+            queue: Vec::new(),
+            next_at: None,
+            rng: StdRng::from_entropy(),
+        })
+    }
+
+    // The next favorite to show, with its name, when it is time for it.
+    pub fn poll(&mut self, now: Instant) -> Option<(String, Tree)> {
+        let time = Local::now();
+        let minute = time.hour() * 60 + time.minute();
+        let active = self
+            .schedule
+            .playlists
+            .iter()
+            .position(|playlist| playlist.covers(minute).unwrap_or(false));
+        let changed = active != self.playing;
+        if !changed && self.next_at.map(|at| now < at).unwrap_or(false) {
+            return None;
+        }
+        self.playing = active;
+        let index = active?;
+        if changed || self.queue.is_empty() {
+            self.queue = self.fill(index);
+        }
+        if self.queue.is_empty() {
+            // Nothing to show; look again in a while rather than every frame.
+            self.next_at = Some(now + Duration::from_secs(60));
+            return None;
+        }
+        let (name, tree, duration) = self.queue.remove(0);
+        self.next_at = Some(now + duration);
+        Some((name, tree))
+    }
+
+    // Moves on to the next favorite at the next poll, rather than when this one's
+    // time is up.
+    pub fn skip(&mut self) {
+        self.next_at = None;
+    }
+
+    fn fill(&mut self, index: usize) -> Vec<(String, Tree, Duration)> {
+        let library = match Library::open() {
+            Ok(library) => library,
+            Err(e) => {
+                println!("gallery: failed to open the favorites: {}", e);
+                return Vec::new();
+            }
+        };
+        let playlist = &self.schedule.playlists[index];
+        let duration = playlist.duration.unwrap_or(self.schedule.duration);
+        let entries: Vec<(&Entry, f32)> = if playlist.trees.is_empty() {
+            library
+                .tagged(&playlist.tags)
+                .map(|entry| (entry, duration))
+                .collect()
+        } else {
+            playlist
+                .trees
+                .iter()
+                .filter_map(|slide| match library.find(&slide.tree) {
+                    Ok(entry) => Some((entry, slide.duration.unwrap_or(duration))),
+                    Err(e) => {
+                        println!("gallery: {}", e);
+                        None
+                    }
+                })
+                .collect()
+        };
+        let mut queue = entries
+            .into_iter()
+            .filter_map(|(entry, seconds)| match entry.load_tree() {
+                Ok(tree) => Some((
+                    entry.name.clone(),
+                    tree,
+                    Duration::from_secs_f32(seconds.max(1f32)),
+                )),
+                Err(e) => {
+                    println!("gallery: failed to load {}: {}", entry.name, e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if playlist.shuffle.unwrap_or(self.schedule.shuffle) {
+            queue.shuffle(&mut self.rng);
+        }
+        queue
+    }
+}
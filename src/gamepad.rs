@@ -0,0 +1,120 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::keymap::Action;
+use anyhow::{anyhow, Result};
+use gilrs::{Axis, Button, EventType, Gilrs};
+use std::time::Instant;
+
+// How far a stick must lean before it nudges anything, for sticks that do not
+// quite center.
+const DEAD_ZONE: f32 = 0.2;
+
+// How much a stick held all the way over moves its color setting in a second.
+const NUDGE_RATE: f32 = 0.5;
+
+// Any gamepads there are, for driving an installation or a couch setup without a
+// keyboard:
+//
+//   A (south)        next tree              B (east)      previous tree
+//   X (west)         mutate the constants   Y (north)     reroll the constants
+//   left, right bumper    weaker, stronger mutations
+//   d-pad left, right     previous, next favorite of the gallery
+//   start            pause                  select        save to favorites
+//   mode             help
+//   left stick       brightness (up, down) and contrast (left, right)
+//   right stick      saturation (up, down)
+//
+// Pads may come and go while running; whichever is used is the one listened to.
+pub struct Gamepad {
+    gilrs: Gilrs,
+    last_poll: Instant,
+}
+
+impl Gamepad {
+    pub fn new() -> Result<Self> {
+        let gilrs = Gilrs::new().map_err(|e| anyhow!("gamepad: {}", e))?;
+        for (_, pad) in gilrs.gamepads() {
+            println!("gamepad: found {}", pad.name());
+        }
+        Ok(Self {
+            gilrs,
+            last_poll: Instant::now(),
+        })
+    }
+
+    // Every press since the last look, as the actions that the keys would give.
+    pub fn pending(&mut self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            let button = match event.event {
+                EventType::ButtonPressed(button, _) => button,
+                EventType::Connected => {
+                    println!("gamepad: connected {}", self.gilrs.gamepad(event.id).name());
+                    continue;
+                }
+                EventType::Disconnected => {
+                    println!(
+                        "gamepad: disconnected {}",
+                        self.gilrs.gamepad(event.id).name()
+                    );
+                    continue;
+                }
+                _ => continue,
+            };
+            actions.push(match button {
+                Button::South => Action::Next,
+                Button::East | Button::DPadLeft => Action::Previous,
+                Button::West => Action::Mutate,
+                Button::North => Action::Reroll,
+                Button::DPadRight => Action::Skip,
+                Button::Start => Action::Pause,
+                Button::Select => Action::SaveFavorite,
+                Button::Mode => Action::Help,
+                Button::LeftTrigger => Action::WeakerMutations,
+                Button::RightTrigger => Action::StrongerMutations,
+                _ => continue,
+            });
+        }
+        actions
+    }
+
+    // How far the sticks, as they are held now, move the brightness, contrast and
+    // saturation since the last look, if at all. They move smoothly rather than
+    // by steps, so they are not actions.
+    pub fn stick_colors(&mut self) -> Option<[f32; 3]> {
+        let now = Instant::now();
+        let dt = (now - self.last_poll).as_secs_f32();
+        self.last_poll = now;
+        let mut colors = [0f32; 3];
+        for (_, pad) in self.gilrs.gamepads() {
+            let lean = |axis| {
+                let value = pad.value(axis);
+                if value.abs() < DEAD_ZONE {
+                    0f32
+                } else {
+                    value * NUDGE_RATE * dt
+                }
+            };
+            colors[0] += lean(Axis::LeftStickY);
+            colors[1] += lean(Axis::LeftStickX);
+            colors[2] += lean(Axis::RightStickY);
+        }
+        if colors.iter().any(|&v| v != 0f32) {
+            Some(colors)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::view::View;
+use anyhow::{anyhow, Result};
+use gpu::{ReadbackQueue, GPU};
+use rand::prelude::*;
+use stampede::{
+    compute::{self, Configuration, OffscreenLayer},
+    ops::opcode_name,
+    tree::{EvalContext, InstructionEncoder, Tree},
+    workgroup::Interpreter,
+};
+use std::collections::BTreeMap;
+use wgpu;
+
+// Deviations for one opcode, over every layer that contains it.
+#[derive(Default)]
+struct Deviation {
+    layers: usize,
+    samples: usize,
+    // Points where only one side produced a finite value.
+    mismatched: usize,
+    max: f32,
+    total: f64,
+}
+
+// Check that random trees survive an encode/decode round trip, then render them
+// on the GPU, evaluate them on the CPU at a grid of points, and report how far
+// apart the two are. A layer's deviation is charged to every
+// opcode in it, so an op with a broken shader or encoder stands out as the one
+// that is never clean.
+pub fn run(
+    gpu: &mut GPU,
+    tree_count: usize,
+    step: usize,
+    time: f32,
+    extent: wgpu::Extent3d,
+) -> Result<()> {
+    let step = step.max(1);
+    // Always compare at full precision.
+    let format = wgpu::TextureFormat::R32Float;
+    let layout = compute::create_layout(gpu);
+    let aspect_ratio = extent.width as f32 / extent.height as f32;
+    let mut config = Configuration::new(extent, aspect_ratio);
+    config.time = time;
+    let config_buffer = config.create_buffer(gpu.device());
+    let interpreter = Interpreter::new(gpu, &layout, [8, 8], false)?;
+    let mut readback = ReadbackQueue::new(gpu.device(), extent, 4, 1);
+    let view = View::new();
+    let mouse = view.position(config.mouse_position, aspect_ratio);
+
+    let mut deviations: BTreeMap<usize, Deviation> = BTreeMap::new();
+    for i in 0..tree_count {
+        let tree = Tree::new(&mut StdRng::seed_from_u64(i as u64))?;
+        if let Err(e) = InstructionEncoder::encode(&tree)
+            .and_then(|encoded| InstructionEncoder::decode(&encoded))
+        {
+            println!("seed {}: {}", i, e);
+        }
+        for offset in 0..3 {
+            let mut layer = OffscreenLayer::new(gpu, &layout, &config_buffer, format, extent);
+            let mut encoder = gpu
+                .device()
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+            layer.update(tree.encode_layer(offset)?, gpu.device(), &mut encoder);
+            {
+                let mut cpass = encoder.begin_compute_pass();
+                cpass.set_pipeline(interpreter.pipeline());
+                cpass.set_bind_group(0, layer.bind_group(), &[]);
+                interpreter.dispatch(&mut cpass, extent);
+            }
+            if !readback.request(&mut encoder, layer.texture(), i as u64) {
+                return Err(anyhow!("readback queue is busy"));
+            }
+            gpu.queue_mut().submit(&[encoder.finish()]);
+            readback.submitted();
+            gpu.device().poll(true);
+            let result = readback
+                .poll(gpu.device())
+                .pop()
+                .ok_or_else(|| anyhow!("readback failed"))?;
+            let texels = result
+                .data
+                .chunks(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect::<Vec<_>>();
+
+            let mut samples = 0;
+            let mut mismatched = 0;
+            let mut max = 0f32;
+            let mut total = 0f64;
+            for y in (0..extent.height as usize).step_by(step) {
+                for x in (0..extent.width as usize).step_by(step) {
+                    let uv = [
+                        x as f32 / extent.width as f32,
+                        y as f32 / extent.height as f32,
+                    ];
+                    let ctx = EvalContext {
+                        position: view.position(uv, aspect_ratio),
+                        mouse,
+                        time,
+                        draft: false,
+                    };
+                    let expect = (tree.evaluate(offset, &ctx) + 1f32) / 2f32;
+                    let actual = texels[y * extent.width as usize + x];
+                    match (expect.is_finite(), actual.is_finite()) {
+                        (true, true) => {
+                            let d = (expect - actual).abs();
+                            max = max.max(d);
+                            total += f64::from(d);
+                            samples += 1;
+                        }
+                        (false, false) => {}
+                        _ => mismatched += 1,
+                    }
+                }
+            }
+            for opcode in tree.opcodes(offset) {
+                let deviation = deviations.entry(opcode).or_default();
+                deviation.layers += 1;
+                deviation.samples += samples;
+                deviation.mismatched += mismatched;
+                deviation.max = deviation.max.max(max);
+                deviation.total += total;
+            }
+        }
+    }
+
+    println!(
+        "{}x{} at t={}, {} trees, every {} texels",
+        extent.width, extent.height, time, tree_count, step
+    );
+    println!(
+        "{:>16} {:>7} {:>10} {:>10} {:>10}",
+        "opcode", "layers", "max", "mean", "non-finite"
+    );
+    for (opcode, deviation) in &deviations {
+        println!(
+            "{:>16} {:>7} {:>10.6} {:>10.6} {:>10}",
+            opcode_name(*opcode),
+            deviation.layers,
+            deviation.max,
+            deviation.total / deviation.samples.max(1) as f64,
+            deviation.mismatched
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    error::Fallible,
+    ops::{self, OpDescriptor},
+    tree::{guided_random_walk, GrowthSite, GrowthStrategy, NodeId, Tree},
+};
+use rand::prelude::*;
+use std::{collections::HashMap, fs, path::Path};
+
+// How many trees' worth of the ops' own rates every site starts from, so that ops
+// that were never liked still come up, and a few favorites nudge generation rather
+// than take it over.
+const PRIOR_WEIGHT: f32 = 4f32;
+
+// A probabilistic grammar for trees, learned from trees someone liked: for every
+// site, the root of a layer or each child of each op, how often each op was
+// found there.
+#[derive(Clone, Debug, Default)]
+pub struct Grammar {
+    // Counts by opcode, for each site as in GrowthSite::parent.
+    counts: HashMap<Option<(usize, usize)>, HashMap<usize, f32>>,
+}
+
+impl Grammar {
+    pub fn learn(trees: &[Tree]) -> Self {
+        let mut grammar = Self::default();
+        for tree in trees {
+            for &root in tree.layers() {
+                grammar.count(tree, root, None);
+            }
+        }
+        grammar
+    }
+
+    // Learn from every tree saved as json in dir, e.g. the favorites.
+    pub fn from_dir(dir: &Path) -> Fallible<Self> {
+        let mut trees = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            match Tree::from_json(&fs::read_to_string(&path)?) {
+                Ok(tree) => trees.push(tree),
+                Err(e) => bail!(Invalid, "{} is not a tree: {}", path.display(), e),
+            }
+        }
+        if trees.is_empty() {
+            bail!(Invalid, "no trees to learn from in {}", dir.display());
+        }
+        Ok(Self::learn(&trees))
+    }
+
+    fn count(&mut self, tree: &Tree, id: NodeId, parent: Option<(usize, usize)>) {
+        let op = tree.arena().op(id);
+        *self
+            .counts
+            .entry(parent)
+            .or_default()
+            .entry(op.opcode)
+            .or_default() += 1f32;
+        for (i, &child) in tree.arena().children(id).iter().enumerate() {
+            self.count(tree, child, Some((op.opcode, i)));
+        }
+    }
+
+    // How likely op is at a site, before normalizing. Ops that generation has been
+    // told to leave out, with a rate of zero, stay out.
+    fn weight(&self, site: Option<(usize, usize)>, op: &OpDescriptor, total_rate: f32) -> f32 {
+        if op.rate <= 0f32 {
+            return 0f32;
+        }
+        let count = self
+            .counts
+            .get(&site)
+            .and_then(|counts| counts.get(&op.opcode))
+            .cloned()
+            .unwrap_or(0f32);
+        count + PRIOR_WEIGHT * op.rate / total_rate
+    }
+}
+
+impl GrowthStrategy for Grammar {
+    fn is_leaf(&mut self, rng: &mut StdRng, site: &GrowthSite) -> bool {
+        let ops = ops::registered();
+        let total_rate = ops.iter().map(|op| op.rate).sum::<f32>();
+        let weights = ops
+            .iter()
+            .map(|op| (op.is_leaf(), self.weight(site.parent, op, total_rate)))
+            .collect::<Vec<_>>();
+        let total = weights.iter().map(|(_, w)| w).sum::<f32>();
+        let leaves = weights
+            .iter()
+            .filter(|(leaf, _)| *leaf)
+            .map(|(_, w)| w)
+            .sum::<f32>();
+        total > 0f32 && rng.gen_range(0f32, total) < leaves
+    }
+
+    fn choose_op(
+        &mut self,
+        rng: &mut StdRng,
+        site: &GrowthSite,
+        leaf: bool,
+    ) -> Fallible<&'static OpDescriptor> {
+        let ops = ops::registered();
+        let total_rate = ops.iter().map(|op| op.rate).sum::<f32>();
+        let candidates = ops
+            .into_iter()
+            .filter(|op| op.is_leaf() == leaf)
+            .map(|op| (op, self.weight(site.parent, op, total_rate)))
+            .filter(|(_, w)| *w > 0f32)
+            .collect::<Vec<_>>();
+        let total = candidates.iter().map(|(_, w)| w).sum::<f32>();
+        if !(total > 0f32) {
+            // Every op of the kind is left out, so there is nothing to learn from;
+            // growing without a grammar says as much.
+            return guided_random_walk(rng, leaf);
+        }
+        let mut f = rng.gen_range(0f32, total);
+        for &(op, w) in &candidates {
+            if f < w {
+                return Ok(op);
+            }
+            f -= w;
+        }
+        // Rounding can leave a sliver past the last.
+        Ok(candidates[candidates.len() - 1].0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::InstructionEncoder;
+
+    #[test]
+    fn grown_trees_fit() -> Fallible<()> {
+        let liked = (0..10)
+            .map(|seed| Tree::new(&mut StdRng::seed_from_u64(seed)))
+            .collect::<Fallible<Vec<_>>>()?;
+        let grammar = Grammar::learn(&liked);
+        for seed in 0..50 {
+            let tree = Tree::grow(&mut StdRng::seed_from_u64(seed), &mut grammar.clone())?;
+            let decoded = InstructionEncoder::decode(&InstructionEncoder::encode(&tree)?)?;
+            assert_eq!(tree.to_json()?, decoded.to_json()?);
+        }
+        Ok(())
+    }
+}
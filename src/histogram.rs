@@ -0,0 +1,240 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::Fallible;
+use gpu::GPU;
+use std::mem;
+use wgpu;
+
+// Must match shaders/histogram.comp.glsl and shaders/histogram.frag.glsl.
+const BIN_COUNT: usize = 64;
+const CHANNELS: usize = 4;
+const GRID_STRIDE: u32 = 4;
+
+// How big the overlay is, in pixels, and how far in from the window's corner.
+const OVERLAY_SIZE: [f32; 2] = [256f32, 128f32];
+const OVERLAY_MARGIN: f32 = 16f32;
+
+// Counts the colors of the picture as shown, per channel and by luminance, and
+// draws them as bars in the bottom right of the window, so that clipping and a
+// lopsided exposure can be seen at a glance. Counted on the GPU every frame from
+// a sparse grid over the layers, so nothing has to be read back.
+pub struct Histogram {
+    bins_buffer: wgpu::Buffer,
+    // To empty the bins with before counting.
+    zero_buffer: wgpu::Buffer,
+    count_pipeline: wgpu::ComputePipeline,
+    bins_bind_group: wgpu::BindGroup,
+    draw_pipeline: wgpu::RenderPipeline,
+    draw_bind_group: wgpu::BindGroup,
+    // In physical pixels.
+    window_size: [f32; 2],
+    visible: bool,
+}
+
+impl Histogram {
+    fn bins_buffer_size() -> wgpu::BufferAddress {
+        (mem::size_of::<u32>() * BIN_COUNT * CHANNELS) as wgpu::BufferAddress
+    }
+
+    // The draw layout is the display's, which the count shares so that it sees the
+    // colors exactly as they are drawn.
+    pub fn new(gpu: &GPU, draw_layout: &wgpu::BindGroupLayout) -> Fallible<Self> {
+        let bins_buffer = gpu
+            .device()
+            .create_buffer_mapped(
+                BIN_COUNT * CHANNELS,
+                wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            )
+            .fill_from_slice(&[0u32; BIN_COUNT * CHANNELS]);
+        let zero_buffer = gpu
+            .device()
+            .create_buffer_mapped(BIN_COUNT * CHANNELS, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&[0u32; BIN_COUNT * CHANNELS]);
+        let bins_layout = |visibility, readonly| {
+            gpu.device()
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    bindings: &[wgpu::BindGroupLayoutBinding {
+                        binding: 0,
+                        visibility,
+                        ty: wgpu::BindingType::StorageBuffer {
+                            dynamic: false,
+                            readonly,
+                        },
+                    }],
+                })
+        };
+        let count_layout = bins_layout(wgpu::ShaderStage::COMPUTE, false);
+        let draw_bins_layout = bins_layout(wgpu::ShaderStage::FRAGMENT, true);
+        let bins_bind_group = |layout| {
+            gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                bindings: &[wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &bins_buffer,
+                        range: 0..Self::bins_buffer_size(),
+                    },
+                }],
+            })
+        };
+
+        let count_shader =
+            gpu.create_shader_module(include_bytes!("../target/histogram.comp.spirv"))?;
+        let count_pipeline =
+            gpu.device()
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    layout: &gpu
+                        .device()
+                        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            bind_group_layouts: &[draw_layout, &count_layout],
+                        }),
+                    compute_stage: wgpu::ProgrammableStageDescriptor {
+                        module: &count_shader,
+                        entry_point: "main",
+                    },
+                });
+
+        let vert_shader = gpu.create_shader_module(include_bytes!("../target/post.vert.spirv"))?;
+        let frag_shader =
+            gpu.create_shader_module(include_bytes!("../target/histogram.frag.spirv"))?;
+        let draw_pipeline = gpu
+            .device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[&draw_bins_layout],
+                    }),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &vert_shader,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &frag_shader,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::None,
+                    depth_bias: 0,
+                    depth_bias_slope_scale: 0.0,
+                    depth_bias_clamp: 0.0,
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: GPU::texture_format(),
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: GPU::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_read_mask: 0,
+                    stencil_write_mask: 0,
+                }),
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[],
+                sample_count: gpu.sample_count(),
+                sample_mask: !0,
+                alpha_to_coverage_enabled: false,
+            });
+
+        let physical_size = gpu.physical_size();
+        Ok(Self {
+            bins_bind_group: bins_bind_group(&count_layout),
+            draw_bind_group: bins_bind_group(&draw_bins_layout),
+            bins_buffer,
+            zero_buffer,
+            count_pipeline,
+            draw_pipeline,
+            window_size: [physical_size.width as f32, physical_size.height as f32],
+            visible: false,
+        })
+    }
+
+    // The overlay stays in the corner.
+    pub fn note_resize(&mut self, gpu: &GPU) {
+        let physical_size = gpu.physical_size();
+        self.window_size = [physical_size.width as f32, physical_size.height as f32];
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    // Counts the layers behind draw_bind_group, which are extent in size. Skipped
+    // while hidden.
+    pub fn update(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        draw_bind_group: &wgpu::BindGroup,
+        extent: wgpu::Extent3d,
+    ) {
+        if !self.visible {
+            return;
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.zero_buffer,
+            0,
+            &self.bins_buffer,
+            0,
+            Self::bins_buffer_size(),
+        );
+        let grid = |size: u32| (size + GRID_STRIDE - 1) / GRID_STRIDE;
+        let mut cpass = encoder.begin_compute_pass();
+        cpass.set_pipeline(&self.count_pipeline);
+        cpass.set_bind_group(0, draw_bind_group, &[]);
+        cpass.set_bind_group(1, &self.bins_bind_group, &[]);
+        cpass.dispatch(
+            (grid(extent.width) + 7) / 8,
+            (grid(extent.height) + 7) / 8,
+            1,
+        );
+    }
+
+    // Over whatever is on screen; the viewport is put back to the whole window
+    // after.
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
+        if !self.visible {
+            return;
+        }
+        let [width, height] = self.window_size;
+        let [w, h] = OVERLAY_SIZE;
+        rpass.set_viewport(
+            (width - w - OVERLAY_MARGIN).max(0f32),
+            (height - h - OVERLAY_MARGIN).max(0f32),
+            w.min(width),
+            h.min(height),
+            0f32,
+            1f32,
+        );
+        rpass.set_pipeline(&self.draw_pipeline);
+        rpass.set_bind_group(0, &self.draw_bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+        rpass.set_viewport(0f32, 0f32, width, height, 0f32, 1f32);
+    }
+}
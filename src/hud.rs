@@ -0,0 +1,268 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::Result;
+use gpu::{Frame, GPU};
+use std::mem;
+use wgpu;
+use zerocopy::{AsBytes, FromBytes};
+
+// Must match hud.frag.glsl.
+pub const HUD_COLS: usize = 64;
+pub const HUD_ROWS: usize = 32;
+const HUD_TEXT_WORDS: usize = HUD_COLS * HUD_ROWS / 4;
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+pub const CELL_WIDTH: u32 = 4;
+pub const CELL_HEIGHT: u32 = 6;
+const HUD_MARGIN: u32 = 8;
+
+// Ascii 32 (space) through 95 (underscore); bit (y * 3 + x) is set for lit
+// pixels. Drawn by hud.frag.glsl, and here for anything else that draws text.
+pub const FONT: [u16; 64] = [
+    0x0000, 0x2092, 0x002D, 0x5F7D, 0x3C9E, 0x42A1, 0x6AAA, 0x0012, 0x4494, 0x1491, 0x0AA8, 0x05D0,
+    0x1400, 0x01C0, 0x2000, 0x12A4, 0x7B6F, 0x749A, 0x73E7, 0x79E7, 0x49ED, 0x79CF, 0x7BCF, 0x4927,
+    0x7BEF, 0x79EF, 0x0410, 0x1410, 0x4454, 0x0E38, 0x1511, 0x20A7, 0x636A, 0x5BEA, 0x3AEB, 0x624E,
+    0x3B6B, 0x72CF, 0x12CF, 0x6B4E, 0x5BED, 0x7497, 0x2B24, 0x5AED, 0x7249, 0x5BFD, 0x5B6B, 0x2B6A,
+    0x12EB, 0x676A, 0x5AEB, 0x388E, 0x2497, 0x7B6D, 0x2B6D, 0x5FED, 0x5AAD, 0x24AD, 0x72A7, 0x324B,
+    0x4889, 0x6926, 0x002A, 0x7000,
+];
+
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
+pub struct HudConfiguration {
+    screen_size: [u32; 2],
+    origin: [u32; 2],
+    extent: [u32; 2],
+    scale: u32,
+    _pad: u32,
+}
+
+pub struct HudUpload {
+    config: wgpu::Buffer,
+    text: wgpu::Buffer,
+}
+
+// A block of monospace text drawn over the top-left corner of the screen.
+pub struct Hud {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    config_buffer: wgpu::Buffer,
+    text_buffer: wgpu::Buffer,
+    text: Vec<String>,
+    scale: u32,
+    visible: bool,
+}
+
+impl Hud {
+    pub fn config_buffer_size() -> wgpu::BufferAddress {
+        mem::size_of::<HudConfiguration>() as wgpu::BufferAddress
+    }
+
+    pub fn text_buffer_size() -> wgpu::BufferAddress {
+        mem::size_of::<[u32; HUD_TEXT_WORDS]>() as wgpu::BufferAddress
+    }
+
+    pub fn new(gpu: &GPU, visible: bool) -> Result<Self> {
+        let vert_shader = gpu.create_shader_module(include_bytes!("../target/hud.vert.spirv"))?;
+        let frag_shader = gpu.create_shader_module(include_bytes!("../target/hud.frag.spirv"))?;
+        let layout = gpu
+            .device()
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                    },
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                    },
+                ],
+            });
+        let pipeline = gpu
+            .device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[&layout],
+                    }),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &vert_shader,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &frag_shader,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::None,
+                    depth_bias: 0,
+                    depth_bias_slope_scale: 0.0,
+                    depth_bias_clamp: 0.0,
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: GPU::texture_format(),
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: GPU::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_read_mask: 0,
+                    stencil_write_mask: 0,
+                }),
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[],
+                sample_count: gpu.sample_count(),
+                sample_mask: !0,
+                alpha_to_coverage_enabled: false,
+            });
+        let config_buffer = gpu.device().create_buffer(&wgpu::BufferDescriptor {
+            size: Self::config_buffer_size(),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let text_buffer = gpu.device().create_buffer(&wgpu::BufferDescriptor {
+            size: Self::text_buffer_size(),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let bind_group = gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &config_buffer,
+                        range: 0..Self::config_buffer_size(),
+                    },
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &text_buffer,
+                        range: 0..Self::text_buffer_size(),
+                    },
+                },
+            ],
+        });
+        Ok(Self {
+            pipeline,
+            bind_group,
+            config_buffer,
+            text_buffer,
+            text: Vec::new(),
+            scale: 2,
+            visible,
+        })
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // The font only has upper case; anything else that it cannot draw becomes '?'.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text
+            .lines()
+            .take(HUD_ROWS)
+            .map(|line| {
+                line.chars()
+                    .take(HUD_COLS)
+                    .map(|c| {
+                        let c = c.to_ascii_uppercase();
+                        if (' '..='_').contains(&c) {
+                            c
+                        } else {
+                            '?'
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
+    pub fn encode_upload_buffers(&self, gpu: &GPU) -> HudUpload {
+        let mut words = [0u32; HUD_TEXT_WORDS];
+        for (row, line) in self.text.iter().enumerate() {
+            for (col, b) in line.bytes().enumerate() {
+                let i = row * HUD_COLS + col;
+                words[i / 4] |= u32::from(b) << (8 * (i % 4));
+            }
+        }
+        let size = gpu.physical_size();
+        let config = HudConfiguration {
+            screen_size: [size.width.floor() as u32, size.height.floor() as u32],
+            origin: [HUD_MARGIN, HUD_MARGIN],
+            extent: [
+                self.text.iter().map(|l| l.len()).max().unwrap_or(0) as u32,
+                self.text.len() as u32,
+            ],
+            scale: self.scale,
+            _pad: 0,
+        };
+        HudUpload {
+            config: gpu
+                .device()
+                .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
+                .fill_from_slice(&[config]),
+            text: gpu
+                .device()
+                .create_buffer_mapped(words.len(), wgpu::BufferUsage::COPY_SRC)
+                .fill_from_slice(&words),
+        }
+    }
+
+    pub fn upload(&self, upload: &HudUpload, frame: &mut Frame) {
+        frame.copy_buffer_to_buffer(
+            &upload.config,
+            0,
+            &self.config_buffer,
+            0,
+            Self::config_buffer_size(),
+        );
+        frame.copy_buffer_to_buffer(
+            &upload.text,
+            0,
+            &self.text_buffer,
+            0,
+            Self::text_buffer_size(),
+        );
+    }
+
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
+        if !self.visible || self.text.is_empty() {
+            return;
+        }
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
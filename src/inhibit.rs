@@ -0,0 +1,106 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::Result;
+
+// Keeps the screensaver from blanking the display, and the machine from going to
+// sleep, for as long as it is held: nobody touches the keyboard during a show.
+pub struct ScreensaverInhibitor {
+    #[cfg(target_os = "linux")]
+    _session: linux::Inhibition,
+}
+
+impl ScreensaverInhibitor {
+    #[cfg(target_os = "linux")]
+    pub fn inhibit() -> Result<Self> {
+        Ok(Self {
+            _session: linux::Inhibition::new()?,
+        })
+    }
+
+    #[cfg(windows)]
+    pub fn inhibit() -> Result<Self> {
+        windows::inhibit()?;
+        Ok(Self {})
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    pub fn inhibit() -> Result<Self> {
+        anyhow::bail!("don't know how to hold off the screensaver on this platform")
+    }
+}
+
+// Through the freedesktop screensaver service, which desktops also take as a
+// request to stay awake. The inhibition is released when the cookie is handed
+// back, or when the connection closes, e.g. because we crashed.
+#[cfg(target_os = "linux")]
+mod linux {
+    use anyhow::Result;
+    use dbus::blocking::Connection;
+    use std::time::Duration;
+
+    const SERVICE: &str = "org.freedesktop.ScreenSaver";
+    const PATH: &str = "/org/freedesktop/ScreenSaver";
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub struct Inhibition {
+        connection: Connection,
+        cookie: u32,
+    }
+
+    impl Inhibition {
+        pub fn new() -> Result<Self> {
+            let connection = Connection::new_session()?;
+            let (cookie,): (u32,) = connection.with_proxy(SERVICE, PATH, TIMEOUT).method_call(
+                SERVICE,
+                "Inhibit",
+                ("stampede", "showing an animation"),
+            )?;
+            Ok(Self { connection, cookie })
+        }
+    }
+
+    impl Drop for Inhibition {
+        fn drop(&mut self) {
+            let released: Result<(), dbus::Error> = self
+                .connection
+                .with_proxy(SERVICE, PATH, TIMEOUT)
+                .method_call(SERVICE, "UnInhibit", (self.cookie,));
+            if let Err(e) = released {
+                println!("failed to let the screensaver back: {}", e);
+            }
+        }
+    }
+}
+
+// The execution state belongs to the thread and lasts until it is changed again or
+// the process exits, which is as long as we want it.
+#[cfg(windows)]
+mod windows {
+    use anyhow::{bail, Result};
+    use winapi::um::{
+        winbase::SetThreadExecutionState,
+        winnt::{ES_CONTINUOUS, ES_DISPLAY_REQUIRED, ES_SYSTEM_REQUIRED},
+    };
+
+    pub fn inhibit() -> Result<()> {
+        let previous = unsafe {
+            SetThreadExecutionState(ES_CONTINUOUS | ES_DISPLAY_REQUIRED | ES_SYSTEM_REQUIRED)
+        };
+        if previous == 0 {
+            bail!("SetThreadExecutionState failed");
+        }
+        Ok(())
+    }
+}
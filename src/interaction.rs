@@ -0,0 +1,41 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use std::time::{Duration, Instant};
+
+// How long after the last input the picture is computed in full again.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+// Tracks whether someone is in the middle of moving things around: dragging,
+// zooming or flicking through trees. Frames can be previewed cheaply while they
+// are, as nobody looks closely at a picture that is about to change again.
+pub struct Interaction {
+    last: Option<Instant>,
+}
+
+impl Interaction {
+    pub fn new() -> Self {
+        Self { last: None }
+    }
+
+    pub fn note(&mut self) {
+        self.last = Some(Instant::now());
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.last
+            .map(|last| last.elapsed() < SETTLE_TIME)
+            .unwrap_or(false)
+    }
+}
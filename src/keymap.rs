@@ -0,0 +1,473 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use stampede::post::PostPass;
+use std::{collections::HashMap, fs, path::Path};
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+// Everything that a key can be bound to. Media keys over D-Bus and gamepad
+// buttons come in as these too, so that each is carried out in one place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Help,
+    Status,
+    ResetView,
+    AddWaypoint,
+    ClearPath,
+    PlayTour,
+    Next,
+    Previous,
+    Pause,
+    Skip,
+    Reroll,
+    Mutate,
+    WeakerMutations,
+    StrongerMutations,
+    RateGood,
+    RateBad,
+    CopyTree,
+    PasteTree,
+    SaveFavorite,
+    Exposure,
+    Posterize,
+    Outline,
+    PixelSort,
+    Crt,
+    Histogram,
+    Inspect,
+    Step,
+    StepLayer,
+    Quality,
+    ColorVision,
+    BrightnessDown,
+    BrightnessUp,
+    ContrastDown,
+    ContrastUp,
+    SaturationDown,
+    SaturationUp,
+    ResetColors,
+    Layer1GainDown,
+    Layer1GainUp,
+    Layer2GainDown,
+    Layer2GainUp,
+    Layer3GainDown,
+    Layer3GainUp,
+    Layer1BiasDown,
+    Layer1BiasUp,
+    Layer2BiasDown,
+    Layer2BiasUp,
+    Layer3BiasDown,
+    Layer3BiasUp,
+    ResetLayers,
+}
+
+impl Action {
+    // In the order that help lists them.
+    const ALL: [Action; 51] = [
+        Action::Quit,
+        Action::Help,
+        Action::Status,
+        Action::ResetView,
+        Action::AddWaypoint,
+        Action::ClearPath,
+        Action::PlayTour,
+        Action::Next,
+        Action::Previous,
+        Action::Pause,
+        Action::Skip,
+        Action::Reroll,
+        Action::Mutate,
+        Action::WeakerMutations,
+        Action::StrongerMutations,
+        Action::RateGood,
+        Action::RateBad,
+        Action::CopyTree,
+        Action::PasteTree,
+        Action::SaveFavorite,
+        Action::Exposure,
+        Action::Posterize,
+        Action::Outline,
+        Action::PixelSort,
+        Action::Crt,
+        Action::Histogram,
+        Action::Inspect,
+        Action::Step,
+        Action::StepLayer,
+        Action::Quality,
+        Action::ColorVision,
+        Action::BrightnessDown,
+        Action::BrightnessUp,
+        Action::ContrastDown,
+        Action::ContrastUp,
+        Action::SaturationDown,
+        Action::SaturationUp,
+        Action::ResetColors,
+        Action::Layer1GainDown,
+        Action::Layer1GainUp,
+        Action::Layer2GainDown,
+        Action::Layer2GainUp,
+        Action::Layer3GainDown,
+        Action::Layer3GainUp,
+        Action::Layer1BiasDown,
+        Action::Layer1BiasUp,
+        Action::Layer2BiasDown,
+        Action::Layer2BiasUp,
+        Action::Layer3BiasDown,
+        Action::Layer3BiasUp,
+        Action::ResetLayers,
+    ];
+
+    fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Help => "these keys",
+            Action::Status => "status",
+            Action::ResetView => "reset the view",
+            Action::AddWaypoint => "add a waypoint",
+            Action::ClearPath => "clear the waypoints",
+            Action::PlayTour => "play the waypoints",
+            Action::Next => "next tree",
+            Action::Previous => "previous tree",
+            Action::Pause => "pause the animation",
+            Action::Skip => "next favorite of the gallery",
+            Action::Reroll => "reroll constants",
+            Action::Mutate => "mutate constants",
+            Action::WeakerMutations => "weaker mutations",
+            Action::StrongerMutations => "stronger mutations",
+            Action::RateGood => "rate good",
+            Action::RateBad => "rate bad",
+            Action::CopyTree => "copy the tree",
+            Action::PasteTree => "paste a tree",
+            Action::SaveFavorite => "save a favorite",
+            Action::Exposure => "hold or auto exposure",
+            Action::Posterize => "posterize",
+            Action::Outline => "outline",
+            Action::PixelSort => "pixel sort",
+            Action::Crt => "crt",
+            Action::Histogram => "histogram",
+            Action::Inspect => "inspect a clicked pixel",
+            Action::Step => "step its instructions",
+            Action::StepLayer => "step a layer of them",
+            Action::Quality => "draft or full quality",
+            Action::ColorVision => "color vision",
+            Action::BrightnessDown => "brightness down",
+            Action::BrightnessUp => "brightness up",
+            Action::ContrastDown => "contrast down",
+            Action::ContrastUp => "contrast up",
+            Action::SaturationDown => "saturation down",
+            Action::SaturationUp => "saturation up",
+            Action::ResetColors => "reset colors",
+            Action::Layer1GainDown => "layer 1 gain down",
+            Action::Layer1GainUp => "layer 1 gain up",
+            Action::Layer2GainDown => "layer 2 gain down",
+            Action::Layer2GainUp => "layer 2 gain up",
+            Action::Layer3GainDown => "layer 3 gain down",
+            Action::Layer3GainUp => "layer 3 gain up",
+            Action::Layer1BiasDown => "layer 1 bias down",
+            Action::Layer1BiasUp => "layer 1 bias up",
+            Action::Layer2BiasDown => "layer 2 bias down",
+            Action::Layer2BiasUp => "layer 2 bias up",
+            Action::Layer3BiasDown => "layer 3 bias down",
+            Action::Layer3BiasUp => "layer 3 bias up",
+            Action::ResetLayers => "reset layer gains and biases",
+        }
+    }
+
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Quit => &["escape", "q"],
+            Action::Help => &["f1", "shift+slash"],
+            Action::Status => &["f3"],
+            Action::ResetView => &["home"],
+            Action::AddWaypoint => &["b"],
+            Action::ClearPath => &["n"],
+            Action::PlayTour => &["p"],
+            Action::Next => &["right"],
+            Action::Previous => &["left"],
+            Action::Pause => &["pause"],
+            Action::Skip => &["shift+right"],
+            Action::Reroll => &["r"],
+            Action::Mutate => &["shift+r"],
+            Action::WeakerMutations => &["lbracket"],
+            Action::StrongerMutations => &["rbracket"],
+            Action::RateGood => &["equals"],
+            Action::RateBad => &["minus"],
+            Action::CopyTree => &["ctrl+c"],
+            Action::PasteTree => &["ctrl+v"],
+            Action::SaveFavorite => &["ctrl+s"],
+            Action::Exposure => &["e"],
+            Action::Posterize => &["t"],
+            Action::Outline => &["o"],
+            Action::PixelSort => &["g"],
+            Action::Crt => &["m"],
+            Action::Histogram => &["h"],
+            Action::Inspect => &["i"],
+            Action::Step => &["space"],
+            Action::StepLayer => &["shift+space"],
+            Action::Quality => &["f2"],
+            Action::ColorVision => &["f4"],
+            Action::BrightnessDown => &["f5"],
+            Action::BrightnessUp => &["f6"],
+            Action::ContrastDown => &["f7"],
+            Action::ContrastUp => &["f8"],
+            Action::SaturationDown => &["f9"],
+            Action::SaturationUp => &["f10"],
+            Action::ResetColors => &["f12"],
+            Action::Layer1GainDown => &["1"],
+            Action::Layer1GainUp => &["2"],
+            Action::Layer2GainDown => &["3"],
+            Action::Layer2GainUp => &["4"],
+            Action::Layer3GainDown => &["5"],
+            Action::Layer3GainUp => &["6"],
+            Action::Layer1BiasDown => &["shift+1"],
+            Action::Layer1BiasUp => &["shift+2"],
+            Action::Layer2BiasDown => &["shift+3"],
+            Action::Layer2BiasUp => &["shift+4"],
+            Action::Layer3BiasDown => &["shift+5"],
+            Action::Layer3BiasUp => &["shift+6"],
+            Action::ResetLayers => &["0"],
+        }
+    }
+}
+
+// The names that keys go by in a keymap.
+const KEY_NAMES: &[(&str, VirtualKeyCode)] = &[
+    ("a", VirtualKeyCode::A),
+    ("b", VirtualKeyCode::B),
+    ("c", VirtualKeyCode::C),
+    ("d", VirtualKeyCode::D),
+    ("e", VirtualKeyCode::E),
+    ("f", VirtualKeyCode::F),
+    ("g", VirtualKeyCode::G),
+    ("h", VirtualKeyCode::H),
+    ("i", VirtualKeyCode::I),
+    ("j", VirtualKeyCode::J),
+    ("k", VirtualKeyCode::K),
+    ("l", VirtualKeyCode::L),
+    ("m", VirtualKeyCode::M),
+    ("n", VirtualKeyCode::N),
+    ("o", VirtualKeyCode::O),
+    ("p", VirtualKeyCode::P),
+    ("q", VirtualKeyCode::Q),
+    ("r", VirtualKeyCode::R),
+    ("s", VirtualKeyCode::S),
+    ("t", VirtualKeyCode::T),
+    ("u", VirtualKeyCode::U),
+    ("v", VirtualKeyCode::V),
+    ("w", VirtualKeyCode::W),
+    ("x", VirtualKeyCode::X),
+    ("y", VirtualKeyCode::Y),
+    ("z", VirtualKeyCode::Z),
+    ("0", VirtualKeyCode::Key0),
+    ("1", VirtualKeyCode::Key1),
+    ("2", VirtualKeyCode::Key2),
+    ("3", VirtualKeyCode::Key3),
+    ("4", VirtualKeyCode::Key4),
+    ("5", VirtualKeyCode::Key5),
+    ("6", VirtualKeyCode::Key6),
+    ("7", VirtualKeyCode::Key7),
+    ("8", VirtualKeyCode::Key8),
+    ("9", VirtualKeyCode::Key9),
+    ("f1", VirtualKeyCode::F1),
+    ("f2", VirtualKeyCode::F2),
+    ("f3", VirtualKeyCode::F3),
+    ("f4", VirtualKeyCode::F4),
+    ("f5", VirtualKeyCode::F5),
+    ("f6", VirtualKeyCode::F6),
+    ("f7", VirtualKeyCode::F7),
+    ("f8", VirtualKeyCode::F8),
+    ("f9", VirtualKeyCode::F9),
+    ("f10", VirtualKeyCode::F10),
+    ("f11", VirtualKeyCode::F11),
+    ("f12", VirtualKeyCode::F12),
+    ("escape", VirtualKeyCode::Escape),
+    ("space", VirtualKeyCode::Space),
+    ("return", VirtualKeyCode::Return),
+    ("tab", VirtualKeyCode::Tab),
+    ("back", VirtualKeyCode::Back),
+    ("delete", VirtualKeyCode::Delete),
+    ("insert", VirtualKeyCode::Insert),
+    ("home", VirtualKeyCode::Home),
+    ("end", VirtualKeyCode::End),
+    ("pageup", VirtualKeyCode::PageUp),
+    ("pagedown", VirtualKeyCode::PageDown),
+    ("left", VirtualKeyCode::Left),
+    ("right", VirtualKeyCode::Right),
+    ("up", VirtualKeyCode::Up),
+    ("down", VirtualKeyCode::Down),
+    ("pause", VirtualKeyCode::Pause),
+    ("equals", VirtualKeyCode::Equals),
+    ("minus", VirtualKeyCode::Minus),
+    ("slash", VirtualKeyCode::Slash),
+    ("backslash", VirtualKeyCode::Backslash),
+    ("comma", VirtualKeyCode::Comma),
+    ("period", VirtualKeyCode::Period),
+    ("semicolon", VirtualKeyCode::Semicolon),
+    ("apostrophe", VirtualKeyCode::Apostrophe),
+    ("grave", VirtualKeyCode::Grave),
+    ("lbracket", VirtualKeyCode::LBracket),
+    ("rbracket", VirtualKeyCode::RBracket),
+];
+
+// A key with exactly these modifiers held; the logo key is not told apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Binding {
+    key: VirtualKeyCode,
+    ctrl: bool,
+    shift: bool,
+    alt: bool,
+}
+
+impl Binding {
+    // e.g. "q", "ctrl+s" or "shift+slash".
+    fn parse(s: &str) -> Result<Self> {
+        let parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+        let (name, modifiers) = parts.split_last().expect("split yields one part at least");
+        let name = name.to_ascii_lowercase();
+        let key = KEY_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, key)| key)
+            .ok_or_else(|| anyhow!("unknown key {} in {}", name, s))?;
+        let mut binding = Self {
+            key,
+            ctrl: false,
+            shift: false,
+            alt: false,
+        };
+        for modifier in modifiers {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" => binding.ctrl = true,
+                "shift" => binding.shift = true,
+                "alt" => binding.alt = true,
+                _ => bail!(
+                    "unknown modifier {} in {}; expected ctrl, shift or alt",
+                    modifier,
+                    s
+                ),
+            }
+        }
+        Ok(binding)
+    }
+
+    fn name(&self) -> String {
+        let key = KEY_NAMES
+            .iter()
+            .find(|&&(_, key)| key == self.key)
+            .map(|&(name, _)| name)
+            .unwrap_or("?");
+        let mut name = String::new();
+        for (held, modifier) in &[
+            (self.ctrl, "ctrl+"),
+            (self.shift, "shift+"),
+            (self.alt, "alt+"),
+        ] {
+            if *held {
+                name.push_str(modifier);
+            }
+        }
+        name + key
+    }
+}
+
+// The settings file given with --config. Anything left out keeps its default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Each action to the keys for it, e.g. {"quit": ["escape"], "pause": ["space"]},
+    // in place of its own; an empty list leaves it unbound.
+    pub keymap: HashMap<Action, Vec<String>>,
+    // The passes to run the finished picture through, first to last, e.g.:
+    //
+    //   "post": [
+    //     {"pass": "tone_map", "exposure": 0.5},
+    //     {"pass": "bloom", "threshold": 0.8, "radius": 12},
+    //     {"pass": "grain", "amount": 0.05}
+    //   ]
+    //
+    // Anything left out of a pass takes its default.
+    pub post: Vec<PostPass>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+pub struct Keymap {
+    actions: HashMap<Binding, Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(&HashMap::new()).expect("the default keys to parse")
+    }
+}
+
+impl Keymap {
+    // The defaults, with the actions given rebound. A key given to an action is
+    // taken from whatever had it by default.
+    pub fn new(rebound: &HashMap<Action, Vec<String>>) -> Result<Self> {
+        let mut actions = HashMap::new();
+        for &action in Action::ALL.iter() {
+            if rebound.contains_key(&action) {
+                continue;
+            }
+            for key in action.default_keys() {
+                actions.insert(Binding::parse(key)?, action);
+            }
+        }
+        for (&action, keys) in rebound {
+            for key in keys {
+                actions.insert(Binding::parse(key)?, action);
+            }
+        }
+        Ok(Self { actions })
+    }
+
+    pub fn action(&self, key: VirtualKeyCode, modifiers: ModifiersState) -> Option<Action> {
+        let binding = Binding {
+            key,
+            ctrl: modifiers.ctrl,
+            shift: modifiers.shift,
+            alt: modifiers.alt,
+        };
+        self.actions.get(&binding).cloned()
+    }
+
+    // A line for each bound action, with its keys, for the help overlay.
+    pub fn describe(&self) -> Vec<String> {
+        Action::ALL
+            .iter()
+            .filter_map(|&action| {
+                let mut keys = self
+                    .actions
+                    .iter()
+                    .filter(|&(_, &a)| a == action)
+                    .map(|(binding, _)| binding.name())
+                    .collect::<Vec<_>>();
+                if keys.is_empty() {
+                    return None;
+                }
+                keys.sort();
+                Some(format!("{:<14}{}", keys.join(" "), action.description()))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,41 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// The trees, the ops they are built from and the compute side of drawing them,
+// for the stampede binary and for crates that embed it or add ops of their own;
+// see ops::register.
+
+// First, so that the modules after it have bail!.
+#[macro_use]
+pub mod error;
+pub mod builder;
+pub mod compute;
+pub mod cost;
+pub mod display;
+pub mod exposure;
+pub mod grammar;
+pub mod histogram;
+pub mod mipmap;
+pub mod names;
+pub mod ops;
+pub mod particles;
+pub mod post;
+pub mod projection;
+pub mod reaction;
+pub mod render;
+pub mod terrain;
+pub mod tree;
+pub mod volume;
+pub mod workgroup;
@@ -0,0 +1,198 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use stampede::tree::Tree;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// What is known about a favorite beyond the tree itself.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    // The tree's file, in the favorites directory.
+    pub file: PathBuf,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub notes: String,
+    // Seconds since the epoch when it was first saved.
+    pub created: u64,
+    // The canonical hashes of the trees it was made from, e.g. the tree whose
+    // constants were re-rolled to make it.
+    #[serde(default)]
+    pub parents: Vec<String>,
+}
+
+// Every favorite's entry, by the canonical hash of its tree, kept in the data
+// directory beside the favorites rather than among them, so that the favorites
+// directory holds nothing but trees.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Library {
+    #[serde(skip)]
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+}
+
+fn data_dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| anyhow!("no data directory on this platform"))?
+        .join("stampede");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub fn favorites_dir() -> Result<PathBuf> {
+    let dir = data_dir()?.join("favorites");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+impl Library {
+    pub fn open() -> Result<Self> {
+        Self::load(&data_dir()?.join("library.json"))
+    }
+
+    // Nothing saved yet is an empty library.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut library = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            Self::default()
+        };
+        library.path = path.to_owned();
+        Ok(library)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn entry(&self, hash: &str) -> Option<&Entry> {
+        self.entries.get(hash)
+    }
+
+    // The hash of the favorite with key as its canonical hash, the start of it, or
+    // its name; as long as only one matches.
+    fn hash_of(&self, key: &str) -> Result<String> {
+        let matches = self
+            .entries
+            .iter()
+            .filter(|(hash, entry)| hash.starts_with(key) || entry.name == key)
+            .map(|(hash, _)| hash.to_owned())
+            .collect::<Vec<_>>();
+        match &matches[..] {
+            [hash] => Ok(hash.to_owned()),
+            [] => bail!("no favorite matches {}", key),
+            _ => bail!(
+                "{} favorites match {}; use more of the hash",
+                matches.len(),
+                key
+            ),
+        }
+    }
+
+    pub fn find(&self, key: &str) -> Result<&Entry> {
+        let hash = self.hash_of(key)?;
+        Ok(&self.entries[&hash])
+    }
+
+    pub fn find_mut(&mut self, key: &str) -> Result<&mut Entry> {
+        let hash = self.hash_of(key)?;
+        Ok(self.entries.get_mut(&hash).expect("a matching entry"))
+    }
+
+    // Every favorite with all of tags.
+    pub fn tagged<'a>(&'a self, tags: &'a [String]) -> impl Iterator<Item = &'a Entry> + 'a {
+        self.entries
+            .values()
+            .filter(move |entry| tags.iter().all(|tag| entry.tags.contains(tag)))
+    }
+}
+
+impl Entry {
+    pub fn load_tree(&self) -> Result<Tree> {
+        Tree::from_json(&fs::read_to_string(favorites_dir()?.join(&self.file))?)
+    }
+}
+
+// Save a tree to the favorites, named for the tree and numbered if a different
+// tree has the same name, and note it in the library. Saving a favorite again
+// keeps its tags and notes.
+pub fn save_favorite(tree: &Tree, parents: &[String]) -> Result<PathBuf> {
+    let dir = favorites_dir()?;
+    let (name, hash) = (tree.name(), tree.canonical_hash());
+    let mut path = dir.join(format!("{}.json", name));
+    let mut n = 2;
+    while path.exists() && Tree::from_json(&fs::read_to_string(&path)?)?.canonical_hash() != hash {
+        path = dir.join(format!("{}-{}.json", name, n));
+        n += 1;
+    }
+    fs::write(&path, tree.to_json()?)?;
+
+    let mut library = Library::open()?;
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let entry = library.entries.entry(hash).or_insert_with(|| Entry {
+        name,
+        created,
+        parents: parents.to_vec(),
+        ..Entry::default()
+    });
+    entry.file = path.strip_prefix(&dir).unwrap_or(&path).to_owned();
+    library.save()?;
+    Ok(path)
+}
+
+// List the favorites with all of tags, oldest first.
+pub fn list(tags: &[String]) -> Result<()> {
+    let library = Library::open()?;
+    let mut entries = library.tagged(tags).collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.created);
+    for entry in entries {
+        println!(
+            "{}\t{}\t{}\t{}",
+            entry.name,
+            entry.file.display(),
+            entry.tags.iter().cloned().collect::<Vec<_>>().join(","),
+            entry.notes
+        );
+    }
+    Ok(())
+}
+
+// Change a favorite's tags and notes from the command line.
+pub fn tag(key: &str, add: &[String], remove: &[String], notes: Option<&str>) -> Result<()> {
+    let mut library = Library::open()?;
+    let entry = library.find_mut(key)?;
+    entry.tags.extend(add.iter().cloned());
+    for tag in remove {
+        entry.tags.remove(tag);
+    }
+    if let Some(notes) = notes {
+        entry.notes = notes.to_owned();
+    }
+    println!(
+        "{} ({}): {}",
+        entry.name,
+        entry.file.display(),
+        entry.tags.iter().cloned().collect::<Vec<_>>().join(", ")
+    );
+    library.save()
+}
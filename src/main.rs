@@ -12,25 +12,97 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
-mod tree;
+mod bench;
+mod clock;
+mod contact;
+mod daemon;
+mod export;
+mod gallery;
+mod gamepad;
+mod golden;
+mod hud;
+mod inhibit;
+mod interaction;
+mod keymap;
+mod library;
+mod mastodon;
+mod media;
+mod overlay;
+mod power;
+mod ratings;
+mod recipe;
+mod recording;
+mod scene;
+mod script;
+mod search;
+mod session;
+mod sound;
+mod stamp;
+mod stepper;
+mod tiled;
+mod view;
+mod watch;
 
-use crate::tree::{InstructionEncoder, Tree, CONSTANT_POOL_SIZE};
-use failure::Fallible;
-use gpu::GPU;
+use crate::{
+    clock::Clock,
+    daemon::{Daemon, DaemonCommand},
+    gallery::Gallery,
+    gamepad::Gamepad,
+    hud::Hud,
+    inhibit::ScreensaverInhibitor,
+    interaction::Interaction,
+    keymap::{Action, Config, Keymap},
+    mastodon::PostConfig,
+    media::MediaService,
+    overlay::ExitHotkey,
+    power::{PowerMonitor, PowerState},
+    ratings::{Rating, Ratings},
+    recipe::Recipe,
+    recording::{Playback, RecordedFrame, Recorder},
+    scene::Scene,
+    script::{Script, ScriptCommand},
+    search::Query,
+    session::{Recovery, Session},
+    sound::Sonifier,
+    stamp::{GenerationConfig, Stamp},
+    stepper::Stepper,
+    view::{View, ViewPath},
+};
+use anyhow::{anyhow, bail, Result};
+use clipboard::{ClipboardContext, ClipboardProvider};
+use gpu::{GPUConfig, GpuError, GPU};
 use rand::prelude::*;
 use sha3::{Digest, Sha3_256};
+use stampede::{
+    compute::Quality,
+    cost::{CostBudget, CostModel},
+    display::{
+        Canvas, CanvasFit, ColorAdjustment, ColorVision, Display, DisplayConfig, DisplayMode,
+        LayerBalance, LayerFilter, Palette, Posterize,
+    },
+    exposure::ExposureControl,
+    grammar::Grammar,
+    ops,
+    post::{Crt, Outline, PixelSort, PostPass, PostProcess},
+    projection::Calibration,
+    render::OffscreenRenderer,
+    tree::{self, Channels, EvalContext, Growth, Tree},
+};
 use std::{
-    mem,
+    collections::{HashSet, VecDeque},
+    fs,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    slice,
     time::{Duration, Instant},
 };
 use structopt::StructOpt;
 use wgpu;
 use winit::{
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
-use zerocopy::{AsBytes, FromBytes};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "stampede", about = "Just some artwork")]
@@ -44,38 +116,1145 @@ struct Opt {
     #[structopt(short, long, help = "Specify a seed")]
     seed: Option<String>,
 
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Start from a tree saved with ctrl+c, or from a PNG that stampede exported; or drop one on the window"
+    )]
+    open: Option<PathBuf>,
+
     #[structopt(short, long, default_value = "1080p", help = "Set draw dimension")]
     dimensions: String,
+
+    #[structopt(long, help = "Restore the tree and window from the last session")]
+    resume: bool,
+
+    #[structopt(
+        long,
+        help = "Restore the tree and window autosaved by a run that did not exit cleanly"
+    )]
+    recover: bool,
+
+    #[structopt(
+        long,
+        help = "Show seed, frame time and tree size in the corner (toggle: F3)"
+    )]
+    show_hud: bool,
+
+    #[structopt(
+        long,
+        default_value = "f32",
+        possible_values = &["f32", "f16"],
+        help = "Precision of the computed layers; f16 halves texture bandwidth"
+    )]
+    precision: String,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Antialias the final composite with N samples"
+    )]
+    msaa: u32,
+
+    #[structopt(
+        long,
+        help = "Use this compute workgroup size (e.g. 16x8) instead of measuring at startup"
+    )]
+    workgroup_size: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Run on this graphics adapter, by its number in the adapters subcommand"
+    )]
+    adapter: Option<usize>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Run a Lua script that can build trees and react to keys and frames"
+    )]
+    script: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Record every frame of the animation to this file, for --replay"
+    )]
+    record: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Play back a recording made with --record, then carry on live"
+    )]
+    replay: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Advance the animation by exactly 1/N seconds per frame, whatever the frame rate"
+    )]
+    fixed_fps: Option<f32>,
+
+    #[structopt(
+        long,
+        default_value = "color",
+        help = "Show the layers as color, flow, volume, terrain, anaglyph or side-by-side"
+    )]
+    mode: DisplayMode,
+
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "The layer that anaglyph and side-by-side take depth from"
+    )]
+    depth_layer: u32,
+
+    #[structopt(
+        long,
+        help = "Run a reaction-diffusion simulation, steered by its own tree, that trees can read"
+    )]
+    reaction_diffusion: bool,
+
+    #[structopt(
+        long,
+        help = "Draw particles that drift uphill on layer N (0, 1 or 2) over the picture"
+    )]
+    particles: Option<usize>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Split the picture over several projectors, as described in this calibration file"
+    )]
+    projection: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Play a subtree as sound, named like r or g/1/0: a layer, then child indices"
+    )]
+    sonify: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "normal",
+        help = "Show the picture as seen with protanopia or deuteranopia, or corrected for it (cycle: F4)"
+    )]
+    color_vision: ColorVision,
+
+    #[structopt(
+        long,
+        help = "Generate trees with little red-green contrast, so that they read without it"
+    )]
+    red_green_safe: bool,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Scale the picture's light by this much, or follow its brightness with auto (toggle: E)"
+    )]
+    exposure: ExposureControl,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Take json commands, a line at a time, from clients of a unix socket at this path, or on Windows a named pipe, e.g. \\\\.\\pipe\\stampede"
+    )]
+    daemon: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Serve Next, Previous, Pause, SaveFavorite and TreeName on the D-Bus session bus"
+    )]
+    dbus: bool,
+
+    #[structopt(
+        long,
+        help = "Take next, previous, mutations, colors and gallery skips from any gamepads"
+    )]
+    gamepad: bool,
+
+    #[structopt(
+        long,
+        help = "Let the screensaver blank the display and the machine sleep while running"
+    )]
+    allow_screensaver: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Settings in json: a keymap of actions to keys and the post passes to run, in order, e.g. {\"keymap\": {\"pause\": [\"space\"]}, \"post\": [{\"pass\": \"bloom\"}]}"
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Float over everything as an undecorated, see-through window that clicks go through; ctrl+alt+q quits"
+    )]
+    overlay: bool,
+
+    #[structopt(
+        long,
+        default_value = "0.5",
+        help = "How much of the picture shows through as an overlay, in [0,1]"
+    )]
+    overlay_opacity: f32,
+
+    #[structopt(
+        long,
+        help = "Keep full quality on battery, instead of computing smaller and drawing less often"
+    )]
+    ignore_battery: bool,
+
+    #[structopt(
+        long,
+        help = "Compute the view at draft quality, for browsing quickly (toggle: F2)"
+    )]
+    draft: bool,
+
+    #[structopt(
+        long,
+        default_value = "stretch",
+        help = "Stretch the picture over the window, fit it inside with borders, or fill it and crop"
+    )]
+    canvas: CanvasFit,
+
+    #[structopt(
+        long,
+        default_value = "0,0",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_canvas_align),
+        help = "Where a fitted or filled picture sits, from -1,-1 (bottom left) to 1,1 (top right)"
+    )]
+    canvas_align: [f32; 2],
+
+    #[structopt(
+        long,
+        default_value = "000000",
+        parse(try_from_str = parse_border_color),
+        help = "The color, as hex RRGGBB, around a fitted picture"
+    )]
+    border_color: [f32; 3],
+
+    #[structopt(
+        long,
+        default_value = "linear",
+        parse(try_from_str = parse_layer_filters),
+        help = "Draw the layers with linear or nearest filtering; one for all, or three, e.g. linear,nearest,linear"
+    )]
+    layer_filter: [LayerFilter; 3],
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_size),
+        help = "Compute the view this small, e.g. 160x90, and draw its pixels as squares"
+    )]
+    pixel_art: Option<[u32; 2]>,
+
+    #[structopt(
+        long,
+        help = "Snap the colors on screen to pico-8, game-boy, cga, or a list of RRGGBB colors"
+    )]
+    palette: Option<Palette>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_posterize_levels),
+        help = "Cut the picture to this many levels a channel; one for all, or three (toggle: T)"
+    )]
+    posterize: Option<[u32; 3]>,
+
+    #[structopt(
+        long,
+        default_value = "0.5",
+        parse(try_from_str = parse_posterize_thresholds),
+        help = "Where in each posterized step a channel rounds up, in [0,1]; one for all, or three"
+    )]
+    posterize_thresholds: [f32; 3],
+
+    #[structopt(
+        long,
+        help = "Ink the edges of the picture with lines this many pixels thick (toggle: O)"
+    )]
+    outline: Option<f32>,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "How much ink to lay over the edges, in [0,1]"
+    )]
+    outline_blend: f32,
+
+    #[structopt(
+        long,
+        help = "Sort runs of pixels at least this bright, in [0,1], for a glitch (toggle: G)"
+    )]
+    pixel_sort: Option<f32>,
+
+    #[structopt(long, help = "Sort down the columns instead of along the rows")]
+    pixel_sort_vertical: bool,
+
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "How far the pixel sort threshold swings either way over time"
+    )]
+    pixel_sort_swing: f32,
+
+    #[structopt(
+        long,
+        default_value = "10",
+        help = "How many seconds a full swing of the pixel sort threshold takes"
+    )]
+    pixel_sort_period: f32,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_crt),
+        help = "Show the picture as if on an old tube (toggle: M); curvature, scanlines, aperture mask and vignette, in [0,1]; one for all, or four"
+    )]
+    crt: Option<Crt>,
+
+    #[structopt(
+        long,
+        help = "Always compute every texel of every frame, even while interacting or for huge trees"
+    )]
+    no_preview: bool,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_duration),
+        help = "Exit cleanly after this long, e.g. 30s, 5m or 2h; seconds if there is no unit"
+    )]
+    duration: Option<Duration>,
+
+    #[structopt(long, help = "Exit cleanly after drawing this many frames")]
+    frames: Option<u64>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "On exiting after --duration or --frames, export the last picture here as a PNG"
+    )]
+    final_png: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "On exiting after --duration or --frames, save the tree here as json"
+    )]
+    final_tree: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "filling",
+        help = "How trees grow: filling, the default, or full, grow or ramped half-and-half"
+    )]
+    growth: Growth,
+
+    #[structopt(
+        long,
+        default_value = "independent",
+        help = "Grow each color apart, vary one for the others (coherent), or share a branch among them (shared)"
+    )]
+    channels: Channels,
+
+    #[structopt(
+        long,
+        help = "Fold and prune what makes no difference in generated trees; constant paths change"
+    )]
+    simplify: bool,
+
+    #[structopt(
+        long,
+        help = "Weight ops by the ratings given with = and -; seeds grow different trees"
+    )]
+    adapt_rates: bool,
+
+    #[structopt(long, help = "Forget every rating given so far")]
+    reset_ratings: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Grow trees from a grammar learned from the trees in this directory, e.g. the favorites"
+    )]
+    grammar: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Roll random trees again until one takes at most this many ms to compute"
+    )]
+    max_tree_ms: Option<f32>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Play favorites through the day from a schedule of playlists, in json"
+    )]
+    gallery: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(about = "Render random trees offscreen and report timings")]
+    Bench {
+        #[structopt(long, default_value = "50", help = "How many trees to render")]
+        trees: usize,
+
+        #[structopt(
+            long,
+            default_value = "200",
+            help = "How many frames to render each tree"
+        )]
+        frames: usize,
+    },
+
+    #[structopt(about = "Compare GPU output against the CPU evaluator, per opcode")]
+    Golden {
+        #[structopt(long, default_value = "50", help = "How many trees to compare")]
+        trees: usize,
+
+        #[structopt(long, default_value = "16", help = "Compare every Nth texel")]
+        step: usize,
+
+        #[structopt(long, default_value = "0", help = "Animation time to compare at")]
+        time: f32,
+    },
+
+    #[structopt(about = "Render frames of the animation to numbered PNG files")]
+    Export {
+        #[structopt(long, default_value = "300", help = "How many frames to write")]
+        frames: usize,
+
+        #[structopt(long, default_value = "60", help = "Animation frames per second")]
+        fps: f32,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Export a tree saved with ctrl+c instead of the seed's"
+        )]
+        json: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            conflicts_with = "json",
+            help = "Export a scene of several trees layered together, described in json"
+        )]
+        scene: Option<PathBuf>,
+
+        #[structopt(parse(from_os_str), help = "Where to write the frames")]
+        out: PathBuf,
+    },
+
+    #[structopt(about = "Render one frame at any size, for print, a tile at a time")]
+    Tiled {
+        #[structopt(long, help = "Width of the picture in pixels")]
+        width: u32,
+
+        #[structopt(long, help = "Height of the picture in pixels")]
+        height: u32,
+
+        #[structopt(
+            long,
+            default_value = "2048",
+            help = "Render this many pixels on a side at once"
+        )]
+        tile_size: u32,
+
+        #[structopt(
+            long,
+            use_delimiter = true,
+            help = "Split the tiles between these adapters, e.g. 0,1; see the adapters subcommand"
+        )]
+        adapters: Vec<usize>,
+
+        #[structopt(long, default_value = "0", help = "Animation time to render at")]
+        time: f32,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Render a tree saved with ctrl+c instead of the seed's"
+        )]
+        json: Option<PathBuf>,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "The PNG, or for more than 4GiB of pixels .tif BigTIFF, to write; run again with the same arguments to resume"
+        )]
+        out: PathBuf,
+    },
+
+    #[structopt(about = "Render every tree dropped into a directory, until interrupted")]
+    Watch {
+        #[structopt(
+            long,
+            default_value = "1",
+            help = "How many frames to render each tree; more than one writes a directory of frames"
+        )]
+        frames: usize,
+
+        #[structopt(long, default_value = "60", help = "Animation frames per second")]
+        fps: f32,
+
+        #[structopt(
+            long,
+            help = "Encode the frames into <name>.mp4 with ffmpeg, which must be on the path, instead of keeping them"
+        )]
+        video: bool,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "The directory to watch for trees, scenes and exported PNGs"
+        )]
+        dir: PathBuf,
+
+        #[structopt(parse(from_os_str), help = "Where to write the results")]
+        out: PathBuf,
+    },
+
+    #[structopt(about = "Render every tree in a directory into one labeled grid")]
+    Contact {
+        #[structopt(long, default_value = "6", help = "How many pictures to a row")]
+        columns: u32,
+
+        #[structopt(long, default_value = "256", help = "How wide each picture is")]
+        width: u32,
+
+        #[structopt(long, default_value = "144", help = "How tall each picture is")]
+        height: u32,
+
+        #[structopt(
+            long,
+            default_value = "0",
+            help = "How many seconds into their animations to show the trees"
+        )]
+        time: f32,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "The directory of trees, saved as json or exported as PNGs"
+        )]
+        dir: PathBuf,
+
+        #[structopt(parse(from_os_str), help = "The PNG to write")]
+        out: PathBuf,
+    },
+
+    #[structopt(about = "Render every node of a tree on its own into one labeled grid")]
+    Nodes {
+        #[structopt(long, default_value = "8", help = "How many pictures to a row")]
+        columns: u32,
+
+        #[structopt(long, default_value = "160", help = "How wide each picture is")]
+        width: u32,
+
+        #[structopt(long, default_value = "90", help = "How tall each picture is")]
+        height: u32,
+
+        #[structopt(
+            long,
+            default_value = "0",
+            help = "How many seconds into its animation to show the tree"
+        )]
+        time: f32,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "The tree, saved with ctrl+c or exported as a PNG"
+        )]
+        tree: PathBuf,
+
+        #[structopt(parse(from_os_str), help = "The PNG to write")]
+        out: PathBuf,
+    },
+
+    #[structopt(about = "Pack a tree, with a thumbnail of it, into a compressed .stamp file")]
+    Stamp {
+        #[structopt(
+            long,
+            default_value = "160",
+            help = "How wide the thumbnail is; 0 for none"
+        )]
+        width: u32,
+
+        #[structopt(
+            long,
+            default_value = "90",
+            help = "How tall the thumbnail is; 0 for none"
+        )]
+        height: u32,
+
+        #[structopt(
+            long,
+            help = "Keep the tree's instructions as well, as the GPU sees them"
+        )]
+        bake: bool,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "The tree, saved with ctrl+c, exported as a PNG, or a stamp"
+        )]
+        tree: PathBuf,
+
+        #[structopt(parse(from_os_str), help = "The .stamp to write")]
+        out: PathBuf,
+    },
+
+    #[structopt(about = "Post a random tree to Mastodon every so often, as a bot")]
+    Post {
+        #[structopt(long, help = "Post just the one tree and stop")]
+        once: bool,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "The instance, access token and schedule, in json"
+        )]
+        config: PathBuf,
+    },
+
+    #[structopt(about = "Find saved trees by the ops in them, their size and their tags")]
+    Search {
+        #[structopt(long = "op", help = "Only trees with this op; may be repeated")]
+        ops: Vec<String>,
+
+        #[structopt(long, help = "Only trees with a layer at least this deep")]
+        min_depth: Option<usize>,
+
+        #[structopt(long, help = "Only trees with no layer deeper than this")]
+        max_depth: Option<usize>,
+
+        #[structopt(long, help = "Only trees with at least this many nodes")]
+        min_nodes: Option<usize>,
+
+        #[structopt(long, help = "Only trees with at most this many nodes")]
+        max_nodes: Option<usize>,
+
+        #[structopt(long = "tag", help = "Only favorites with this tag; may be repeated")]
+        tags: Vec<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Render the matches into this directory rather than list them"
+        )]
+        render: Option<PathBuf>,
+
+        #[structopt(parse(from_os_str), help = "The directory of saved trees to search")]
+        dir: PathBuf,
+    },
+
+    #[structopt(about = "List the favorites, saved with ctrl+s, with their tags and notes")]
+    Favorites {
+        #[structopt(long = "tag", help = "Only those with this tag; may be repeated")]
+        tags: Vec<String>,
+    },
+
+    #[structopt(about = "Tag a favorite or write notes on it")]
+    Tag {
+        #[structopt(help = "The favorite's name or canonical hash, or the start of it")]
+        tree: String,
+
+        #[structopt(long, help = "Tags to add; may be repeated")]
+        add: Vec<String>,
+
+        #[structopt(long, help = "Tags to remove; may be repeated")]
+        remove: Vec<String>,
+
+        #[structopt(long, help = "Replace the favorite's notes")]
+        notes: Option<String>,
+    },
+
+    #[structopt(about = "List the graphics adapters that --adapter can pick from")]
+    Adapters,
 }
 
-#[repr(C)]
-#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
-pub struct Vertex {
-    position: [f32; 2],
-    tex_coord: [f32; 2],
+fn parse_canvas_align(s: &str) -> Result<[f32; 2]> {
+    let parts = s.split(',').map(str::trim).collect::<Vec<_>>();
+    match parts[..] {
+        [x, y] => Ok([x.parse()?, y.parse()?]),
+        _ => Err(anyhow!("expected x,y for the canvas alignment, not {}", s)),
+    }
 }
 
-#[repr(C)]
-#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
-pub struct Configuration {
-    texture_size: [u32; 2],
-    texture_offsets: [u32; 2],
+fn parse_border_color(s: &str) -> Result<[f32; 3]> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(anyhow!("expected RRGGBB for the border color, not {}", s));
+    }
+    let channel = |i: usize| -> Result<f32> {
+        Ok(f32::from(u8::from_str_radix(&hex[i..i + 2], 16)?) / 255f32)
+    };
+    Ok([channel(0)?, channel(2)?, channel(4)?])
 }
 
-struct ComputeLayer {
-    instr_buffer: wgpu::Buffer,
-    pool_buffer: wgpu::Buffer,
-    texture_view: wgpu::TextureView,
-    bind_group: wgpu::BindGroup,
+fn parse_size(s: &str) -> Result<[u32; 2]> {
+    let parts = s.split('x').map(str::trim).collect::<Vec<_>>();
+    match parts[..] {
+        [width, height] => Ok([width.parse()?, height.parse()?]),
+        _ => Err(anyhow!("expected WIDTHxHEIGHT, not {}", s)),
+    }
 }
 
-fn main() -> Fallible<()> {
-    let opt = Opt::from_args();
+fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split);
+    let count = count.trim().parse::<f32>()?;
+    let seconds = match unit {
+        "ms" => count / 1000f32,
+        "" | "s" => count,
+        "m" => count * 60f32,
+        "h" => count * 3600f32,
+        _ => bail!("unknown unit {}; expected ms, s, m or h", unit),
+    };
+    // Duration::from_secs_f32 panics on anything that overflows its seconds.
+    if !seconds.is_finite() || seconds < 0f32 || seconds >= u64::MAX as f32 {
+        bail!("{} is not a duration", s);
+    }
+    Ok(Duration::from_secs_f32(seconds))
+}
 
-    let program_start = Instant::now();
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop)?;
-    let mut gpu = GPU::new(&window, Default::default())?;
+// One value for all three channels or layers, or one for each.
+fn parse_channels<T, F>(s: &str, what: &str, parse: F) -> Result<[T; 3]>
+where
+    T: Copy,
+    F: Fn(&str) -> Result<T>,
+{
+    let values = s
+        .split(',')
+        .map(|value| parse(value.trim()))
+        .collect::<Result<Vec<T>>>()?;
+    match values[..] {
+        [all] => Ok([all; 3]),
+        [r, g, b] => Ok([r, g, b]),
+        _ => Err(anyhow!("expected one {} or three, not {}", what, s)),
+    }
+}
+
+fn parse_layer_filters(s: &str) -> Result<[LayerFilter; 3]> {
+    parse_channels(s, "layer filter", str::parse)
+}
+
+fn parse_posterize_levels(s: &str) -> Result<[u32; 3]> {
+    parse_channels(s, "posterize level count", |v| Ok(v.parse()?))
+}
+
+fn parse_posterize_thresholds(s: &str) -> Result<[f32; 3]> {
+    parse_channels(s, "posterize threshold", |v| Ok(v.parse()?))
+}
+
+fn parse_crt(s: &str) -> Result<Crt> {
+    let values = s
+        .split(',')
+        .map(|value| value.trim().parse())
+        .collect::<Result<Vec<f32>, _>>()?;
+    match values[..] {
+        [all] => Ok(Crt {
+            curvature: all,
+            scanlines: all,
+            mask: all,
+            vignette: all,
+        }),
+        [curvature, scanlines, mask, vignette] => Ok(Crt {
+            curvature,
+            scanlines,
+            mask,
+            vignette,
+        }),
+        _ => bail!("expected one CRT intensity or four, not {}", s),
+    }
+}
+
+// How many trees Previous can go back through.
+const HISTORY_LENGTH: usize = 100;
+
+// How far Mutate moves each constant, as a fraction of its range, to begin with;
+// its bounds; and the factor that WeakerMutations and StrongerMutations change it
+// by.
+const MUTATION_STRENGTH: f32 = 0.1;
+const MUTATION_STRENGTH_BOUNDS: [f32; 2] = [0.01, 1f32];
+const MUTATION_STRENGTH_STEP: f32 = 1.5;
+
+fn rng_from_seed(seed: &str) -> StdRng {
+    if let Ok(u) = seed.parse::<u64>() {
+        StdRng::seed_from_u64(u)
+    } else {
+        let mut hasher = Sha3_256::new();
+        hasher.input(seed);
+        let mut sized_result = [0u8; 32];
+        sized_result.copy_from_slice(&hasher.result());
+        StdRng::from_seed(sized_result)
+    }
+}
+
+// A seed's tree, narrowed to lightness and blue-yellow if red_green_safe.
+fn tree_from_seed(seed: &str, red_green_safe: bool) -> Result<Tree> {
+    let mut tree = Tree::new(&mut rng_from_seed(seed))?;
+    if red_green_safe {
+        if let Err(e) = tree.narrow_red_green() {
+            println!("leaving seed {} in full color: {}", seed, e);
+        }
+    }
+    Ok(tree)
+}
+
+// Save to the favorites, with the trees that this one was made from, if it was.
+fn save_favorite(tree: &Tree, lineage: &Option<(String, Vec<String>)>) {
+    let hash = tree.canonical_hash();
+    let parents = match lineage {
+        Some((child, parents)) if *child == hash => &parents[..],
+        _ => &[],
+    };
+    match library::save_favorite(tree, parents) {
+        Ok(path) => println!("saved favorite to {}", path.display()),
+        Err(e) => println!("failed to save favorite: {}", e),
+    }
+}
+
+// Rolling for a tree within budget gives up after this many, to take the cheapest.
+const MAX_REROLLS: usize = 32;
+
+// A tree from a random seed. Seeds are rolled again for trees that are the same
+// as one already shown, by canonical hash, and, with a budget, for trees that
+// cannot be computed in time, so that slideshows never stall on a monster.
+fn random_tree(
+    red_green_safe: bool,
+    budget: Option<&CostBudget>,
+    shown: &HashSet<String>,
+) -> Result<(String, Tree)> {
+    let mut cheapest: Option<(Duration, String, Tree)> = None;
+    for _ in 0..MAX_REROLLS {
+        let seed = random::<u64>().to_string();
+        // Growth keeps well inside the interpreter, but never show one that is not.
+        let tree = match tree_from_seed(&seed, red_green_safe) {
+            Ok(tree) if tree.stats().check().is_ok() => tree,
+            _ => continue,
+        };
+        let cost = budget.map(|b| b.estimate(&tree)).unwrap_or_default();
+        let fits = budget.map(|b| b.allows(&tree)).unwrap_or(true);
+        if fits && !shown.contains(&tree.canonical_hash()) {
+            return Ok((seed, tree));
+        }
+        if cheapest.as_ref().map(|(c, _, _)| cost < *c).unwrap_or(true) {
+            cheapest = Some((cost, seed, tree));
+        }
+    }
+    let (cost, seed, tree) =
+        cheapest.ok_or_else(|| anyhow!("no tree could be grown in {} rolls", MAX_REROLLS))?;
+    println!(
+        "no new tree in {} rolls fit the budget; taking one of {:0.1}ms",
+        MAX_REROLLS,
+        cost.as_secs_f64() * 1000.0
+    );
+    Ok((seed, tree))
+}
+
+// The bindings, shown over the picture in the HUD's font, so no more than HUD_COLS
+// wide or HUD_ROWS tall. The mouse and the nudging keys cannot be rebound.
+fn help_text(keymap: &Keymap) -> String {
+    let mut lines = vec![
+        "KEYS".to_owned(),
+        format!("{:<14}{}", "drag wheel", "pan, zoom; right click centers"),
+    ];
+    lines.extend(keymap.describe());
+    lines.join("\n")
+}
+
+// How far each press of a color or layer action moves its setting.
+const NUDGE_STEP: f32 = 0.05;
+
+// Brightness, contrast and saturation moved a step by one of their actions, or
+// all put back.
+fn nudge_colors(colors: ColorAdjustment, action: Action) -> ColorAdjustment {
+    let mut next = colors;
+    match action {
+        Action::BrightnessDown => next.brightness -= NUDGE_STEP,
+        Action::BrightnessUp => next.brightness += NUDGE_STEP,
+        Action::ContrastDown => next.contrast -= NUDGE_STEP,
+        Action::ContrastUp => next.contrast += NUDGE_STEP,
+        Action::SaturationDown => next.saturation -= NUDGE_STEP,
+        Action::SaturationUp => next.saturation += NUDGE_STEP,
+        Action::ResetColors => next = ColorAdjustment::default(),
+        _ => {}
+    }
+    next.clamped()
+}
+
+// A layer's gain or bias moved a step by one of their actions, or all put back.
+fn nudge_balance(balance: LayerBalance, action: Action) -> LayerBalance {
+    let (layer, gain, direction) = match action {
+        Action::Layer1GainDown => (0, true, -1f32),
+        Action::Layer1GainUp => (0, true, 1f32),
+        Action::Layer2GainDown => (1, true, -1f32),
+        Action::Layer2GainUp => (1, true, 1f32),
+        Action::Layer3GainDown => (2, true, -1f32),
+        Action::Layer3GainUp => (2, true, 1f32),
+        Action::Layer1BiasDown => (0, false, -1f32),
+        Action::Layer1BiasUp => (0, false, 1f32),
+        Action::Layer2BiasDown => (1, false, -1f32),
+        Action::Layer2BiasUp => (1, false, 1f32),
+        Action::Layer3BiasDown => (2, false, -1f32),
+        Action::Layer3BiasUp => (2, false, 1f32),
+        Action::ResetLayers => return LayerBalance::default(),
+        _ => return balance,
+    };
+    let mut next = balance;
+    if gain {
+        next.gain[layer] += direction * NUDGE_STEP;
+    } else {
+        next.bias[layer] += direction * NUDGE_STEP;
+    }
+    next.clamped()
+}
+
+// Prints what each layer came to on the GPU under the cursor, and what every node
+// of it comes to there on the CPU, for tracing a region that looks wrong back to
+// the node that makes it so. Returns where the pixel is on the plane, for the
+// stepper to run the instructions there.
+fn inspect_pixel(
+    gpu: &mut GPU,
+    display: &Display,
+    view: &View,
+    tree: &Tree,
+) -> Result<EvalContext> {
+    let config = display.config();
+    let uv = config.mouse_position;
+    if uv.iter().any(|&v| v < 0f32 || v > 1f32) {
+        bail!("not on the canvas");
+    }
+    let layers = display.read_layers_at(gpu, uv)?;
+    let position = view.position(uv, config.aspect_ratio);
+    let ctx = EvalContext {
+        position,
+        mouse: position,
+        time: tree.time(),
+        draft: config.draft != 0,
+    };
+    let extent = display.extent();
+    println!(
+        "texel ({}, {}) of {}x{}, at ({:0.4}, {:0.4}) on the plane, t={:0.2}",
+        ((uv[0] * extent.width as f32) as u32).min(extent.width - 1),
+        ((uv[1] * extent.height as f32) as u32).min(extent.height - 1),
+        extent.width,
+        extent.height,
+        position[0],
+        position[1],
+        ctx.time
+    );
+    for (offset, layer) in layers.iter().enumerate() {
+        let values = tree.node_values(offset, &ctx);
+        // The interpreter maps [-1,1] onto the layer's [0,1].
+        println!(
+            "{}: {:0.6} on the GPU, {:0.6} on the CPU",
+            values[0].path,
+            layer,
+            (values[0].value + 1f32) / 2f32
+        );
+        for node in &values {
+            println!(
+                "  {:indent$}{:<16} {:>10.6}  {}",
+                "",
+                node.op,
+                node.value,
+                node.path,
+                indent = node.depth * 2
+            );
+        }
+    }
+    Ok(ctx)
+}
+
+// What the seed is shown as for an opened tree that does not say where it came
+// from.
+const OPENED: &str = "opened";
+
+// A tree saved with ctrl+c, or the tree in a PNG that stampede exported, along
+// with the seed that it came from if the PNG says.
+fn open_tree(path: &Path) -> Result<(Option<String>, Tree)> {
+    let is_png = path
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("png"))
+        .unwrap_or(false);
+    if stamp::is_stamp(path) {
+        let stamp = Stamp::read(path)?;
+        if let Err(e) = stamp.check_instructions() {
+            println!("{}: {}", path.display(), e);
+        }
+        Ok((stamp.seed, stamp.tree))
+    } else if is_png {
+        let recipe = Recipe::read(path)?;
+        Ok((recipe.seed.clone(), recipe.tree()?))
+    } else {
+        Ok((None, Tree::from_json(&fs::read_to_string(path)?)?))
+    }
+}
+
+// Subcommands only compute offscreen, but wgpu still needs a surface, so a hidden
+// window, and an event loop for it, are kept for as long as the GPU. Those that
+// need no GPU, like listing favorites, never make one, and so run without a
+// display.
+struct Offscreen {
+    gpu: GPU,
+    _window: Window,
+    _event_loop: EventLoop<()>,
+}
+
+impl Offscreen {
+    fn new(adapter: Option<usize>) -> Result<Self> {
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_visible(false)
+            .build(&event_loop)?;
+        let gpu = GPU::new(&window, GPUConfig::default().with_adapter(adapter))?;
+        Ok(Self {
+            gpu,
+            _window: window,
+            _event_loop: event_loop,
+        })
+    }
+}
+
+impl Deref for Offscreen {
+    type Target = GPU;
+
+    fn deref(&self) -> &GPU {
+        &self.gpu
+    }
+}
+
+impl DerefMut for Offscreen {
+    fn deref_mut(&mut self) -> &mut GPU {
+        &mut self.gpu
+    }
+}
+
+// Exports the tree as it is now, at the size that it is computed at. The renderer
+// is only made the first time, as most sessions never need one.
+fn screenshot(
+    gpu: &mut GPU,
+    renderer: &mut Option<OffscreenRenderer>,
+    seed: &str,
+    tree: &Tree,
+    extent: wgpu::Extent3d,
+    path: &Path,
+) -> Result<()> {
+    if renderer.is_none() {
+        *renderer = Some(OffscreenRenderer::new(gpu)?);
+    }
+    let renderer = renderer.as_ref().expect("a renderer");
+    let scene = Scene::single(tree.clone());
+    export::write_frame(gpu, renderer, &scene, Some(seed), extent, path)
+}
+
+// Rebuilding the display starts its simulations over, but keeps how it is drawn.
+fn rebuild_display(
+    gpu: &mut GPU,
+    display: &mut Display,
+    extent: wgpu::Extent3d,
+    layer_format: wgpu::TextureFormat,
+    display_config: &DisplayConfig,
+) {
+    match Display::new(gpu, extent, layer_format, display_config.clone()) {
+        Ok(mut next) => {
+            *next.draw_config_mut() = *display.draw_config();
+            next.set_exposure_control(display.exposure_control());
+            next.histogram_mut()
+                .set_visible(display.histogram().is_visible());
+            next.config_mut().mouse_position = display.config().mouse_position;
+            *display = next;
+        }
+        Err(e) => println!("failed to rebuild the display: {}", e),
+    }
+}
+
+// The clipboard crate reports errors as a non-Send boxed Error, so stringify them.
+fn copy_tree(tree: &Tree) -> Result<()> {
+    let mut ctx: ClipboardContext =
+        ClipboardProvider::new().map_err(|e| anyhow!("clipboard: {}", e))?;
+    ctx.set_contents(tree.to_json()?)
+        .map_err(|e| anyhow!("clipboard: {}", e))
+}
+
+fn paste_tree() -> Result<Tree> {
+    let mut ctx: ClipboardContext =
+        ClipboardProvider::new().map_err(|e| anyhow!("clipboard: {}", e))?;
+    let contents = ctx
+        .get_contents()
+        .map_err(|e| anyhow!("clipboard: {}", e))?;
+    Ok(Tree::from_json(&contents)?)
+}
+
+// Returns true if the tree needs to be uploaded again.
+fn apply_script_commands(
+    commands: Result<Vec<ScriptCommand>>,
+    seed: &mut String,
+    tree: &mut Tree,
+    regenerate_at: &mut Option<Instant>,
+    red_green_safe: bool,
+) -> bool {
+    let commands = match commands {
+        Ok(commands) => commands,
+        Err(e) => {
+            println!("script failed: {}", e);
+            return false;
+        }
+    };
+    let mut changed = false;
+    for command in commands {
+        match command {
+            ScriptCommand::UseSeed(next) => match tree_from_seed(&next, red_green_safe) {
+                Ok(next_tree) => {
+                    *tree = next_tree;
+                    *seed = next;
+                    changed = true;
+                }
+                Err(e) => println!("script: no tree for seed {}: {}", next, e),
+            },
+            ScriptCommand::UseJson(json) => match Tree::from_json(&json) {
+                Ok(next) => {
+                    *tree = next;
+                    *seed = "scripted".to_owned();
+                    changed = true;
+                }
+                Err(e) => println!("script gave a bad tree: {}", e),
+            },
+            ScriptCommand::Regenerate(delay) => {
+                *regenerate_at = Some(Instant::now() + Duration::from_secs_f32(delay.max(0f32)))
+            }
+            ScriptCommand::SetConstant(index, value) => match tree.set_constant(index, value) {
+                Ok(()) => changed = true,
+                Err(e) => println!("script: {}", e),
+            },
+            ScriptCommand::FreezeConstant(index, frozen) => {
+                match tree.freeze_constant(index, frozen) {
+                    Ok(()) => changed = true,
+                    Err(e) => println!("script: {}", e),
+                }
+            }
+            ScriptCommand::ScrubConstant(index, phase) => match tree.scrub_constant(index, phase) {
+                Ok(()) => changed = true,
+                Err(e) => println!("script: {}", e),
+            },
+        }
+    }
+    changed
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    tree::set_growth(opt.growth);
+    tree::set_channels(opt.channels);
+    tree::set_simplify(opt.simplify);
+    if let Some(dir) = &opt.grammar {
+        let grammar = Grammar::from_dir(dir)?;
+        tree::set_growth_strategy(move || Box::new(grammar.clone()));
+    }
 
     let dimensions = match opt.dimensions.as_str() {
         "1080p" => [1920, 1080],
@@ -90,327 +1269,967 @@ fn main() -> Fallible<()> {
         depth: 1,
     };
 
-    // Compute Resources
-    let uni_shader = gpu.create_shader_module(include_bytes!("../target/uni_shader.comp.spirv"))?;
-    let uni_shader_layout =
-        gpu.device()
-            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                bindings: &[
-                    wgpu::BindGroupLayoutBinding {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
-                    },
-                    wgpu::BindGroupLayoutBinding {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            dimension: wgpu::TextureViewDimension::D2,
-                        },
-                    },
-                    wgpu::BindGroupLayoutBinding {
-                        binding: 2,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
-                    },
-                    wgpu::BindGroupLayoutBinding {
-                        binding: 3,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
-                    },
-                ],
-            });
-    let uni_shader_pipeline =
-        gpu.device()
-            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                layout: &gpu
-                    .device()
-                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        bind_group_layouts: &[&uni_shader_layout],
-                    }),
-                compute_stage: wgpu::ProgrammableStageDescriptor {
-                    module: &uni_shader,
-                    entry_point: "main",
-                },
-            });
-    let config_buffer_size = mem::size_of::<Configuration>() as wgpu::BufferAddress;
-    let config_buffer = gpu
-        .device()
-        .create_buffer_mapped(1, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::MAP_READ)
-        .fill_from_slice(&[Configuration {
-            texture_size: [texture_extent.width, texture_extent.height],
-            texture_offsets: [0, (texture_extent.width - texture_extent.height) / 2],
-        }]);
-    let instr_buffer_size = InstructionEncoder::instruction_buffer_size();
-    let pool_buffer_size = InstructionEncoder::pool_buffer_size();
-    let texture_sampler = gpu.device().create_sampler(&wgpu::SamplerDescriptor {
-        address_mode_u: wgpu::AddressMode::ClampToEdge,
-        address_mode_v: wgpu::AddressMode::ClampToEdge,
-        address_mode_w: wgpu::AddressMode::ClampToEdge,
-        mag_filter: wgpu::FilterMode::Linear,
-        min_filter: wgpu::FilterMode::Linear,
-        mipmap_filter: wgpu::FilterMode::Linear,
-        lod_min_clamp: 0f32,
-        lod_max_clamp: 9_999_999f32,
-        compare_function: wgpu::CompareFunction::Never,
-    });
-    let compute_buffers = (0..3)
-        .map(|_| {
-            let instr_buffer = gpu.device().create_buffer(&wgpu::BufferDescriptor {
-                size: instr_buffer_size,
-                usage: wgpu::BufferUsage::UNIFORM
-                    | wgpu::BufferUsage::MAP_READ
-                    | wgpu::BufferUsage::COPY_DST,
-            });
-            let pool_buffer = gpu.device().create_buffer(&wgpu::BufferDescriptor {
-                size: pool_buffer_size,
-                usage: wgpu::BufferUsage::UNIFORM
-                    | wgpu::BufferUsage::MAP_READ
-                    | wgpu::BufferUsage::COPY_DST,
-            });
-            let texture = gpu.device().create_texture(&wgpu::TextureDescriptor {
-                size: texture_extent,
-                array_layer_count: 1,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::R32Float,
-                usage: wgpu::TextureUsage::all(),
-            });
-            let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
-                format: wgpu::TextureFormat::R32Float,
-                dimension: wgpu::TextureViewDimension::D2,
-                aspect: wgpu::TextureAspect::All,
-                base_mip_level: 0,
-                level_count: 1, // mip level
-                base_array_layer: 0,
-                array_layer_count: 1,
-            });
-            let bind_group = gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &uni_shader_layout,
-                bindings: &[
-                    wgpu::Binding {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer {
-                            buffer: &config_buffer,
-                            range: 0..config_buffer_size,
-                        },
-                    },
-                    wgpu::Binding {
-                        binding: 1,
-                        resource: wgpu::BindingResource::TextureView(&texture_view),
-                    },
-                    wgpu::Binding {
-                        binding: 2,
-                        resource: wgpu::BindingResource::Buffer {
-                            buffer: &instr_buffer,
-                            range: 0..instr_buffer_size,
-                        },
-                    },
-                    wgpu::Binding {
-                        binding: 3,
-                        resource: wgpu::BindingResource::Buffer {
-                            buffer: &pool_buffer,
-                            range: 0..pool_buffer_size,
-                        },
-                    },
-                ],
-            });
-            ComputeLayer {
-                instr_buffer,
-                pool_buffer,
-                texture_view,
-                bind_group,
+    // Exports should always use f32; half precision is only for interactive display.
+    let half = opt.precision == "f16";
+    let layer_format = if half {
+        wgpu::TextureFormat::R16Float
+    } else {
+        wgpu::TextureFormat::R32Float
+    };
+    // The favorites library and searches that only list need no GPU.
+    let query_for = |command: &Command| match command {
+        Command::Search {
+            ops,
+            min_depth,
+            max_depth,
+            min_nodes,
+            max_nodes,
+            tags,
+            ..
+        } => Query {
+            ops: ops.clone(),
+            min_depth: *min_depth,
+            max_depth: *max_depth,
+            min_nodes: *min_nodes,
+            max_nodes: *max_nodes,
+            tags: tags.clone(),
+        },
+        _ => Query::default(),
+    };
+    if let Some(command) = &opt.command {
+        return match command {
+            Command::Search {
+                render: None, dir, ..
+            } => search::list(dir, &query_for(command)),
+            Command::Favorites { tags } => library::list(tags),
+            Command::Tag {
+                tree,
+                add,
+                remove,
+                notes,
+            } => library::tag(tree, add, remove, notes.as_deref()),
+            Command::Adapters => {
+                for (i, name) in GPU::adapter_names().iter().enumerate() {
+                    println!("{}: {}", i, name);
+                }
+                Ok(())
             }
-        })
-        .collect::<Vec<_>>();
-
-    // Screen Resources
-    let graphics_layout = gpu
-        .device()
-        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            bindings: &[
-                wgpu::BindGroupLayoutBinding {
-                    binding: 0,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::SampledTexture {
-                        multisampled: true,
-                        dimension: wgpu::TextureViewDimension::D2,
-                    },
-                },
-                wgpu::BindGroupLayoutBinding {
-                    binding: 1,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler,
-                },
-                wgpu::BindGroupLayoutBinding {
-                    binding: 2,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::SampledTexture {
-                        multisampled: true,
-                        dimension: wgpu::TextureViewDimension::D2,
-                    },
-                },
-                wgpu::BindGroupLayoutBinding {
-                    binding: 3,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler,
-                },
-                wgpu::BindGroupLayoutBinding {
-                    binding: 4,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::SampledTexture {
-                        multisampled: true,
-                        dimension: wgpu::TextureViewDimension::D2,
-                    },
-                },
-                wgpu::BindGroupLayoutBinding {
-                    binding: 5,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler,
+            Command::Bench { trees, frames } => bench::run(
+                &mut Offscreen::new(opt.adapter)?,
+                *trees,
+                *frames,
+                layer_format,
+                texture_extent,
+                half,
+                opt.workgroup_size.as_deref(),
+            ),
+            Command::Golden { trees, step, time } => golden::run(
+                &mut Offscreen::new(opt.adapter)?,
+                *trees,
+                *step,
+                *time,
+                texture_extent,
+            ),
+            Command::Export {
+                frames,
+                fps,
+                json,
+                scene,
+                out,
+            } => {
+                let (scene, seed) = match (json, scene) {
+                    (_, Some(path)) => (Scene::load(path)?, None),
+                    (Some(path), None) => (
+                        Scene::single(Tree::from_json(&fs::read_to_string(path)?)?),
+                        None,
+                    ),
+                    (None, None) => {
+                        let seed = opt
+                            .seed
+                            .clone()
+                            .unwrap_or_else(|| random::<u64>().to_string());
+                        println!("seed: {}", seed);
+                        let tree = tree_from_seed(&seed, opt.red_green_safe)?;
+                        (Scene::single(tree), Some(seed))
+                    }
+                };
+                export::run(
+                    &mut Offscreen::new(opt.adapter)?,
+                    scene,
+                    seed,
+                    *frames,
+                    *fps,
+                    texture_extent,
+                    Quality::Full,
+                    out,
+                )
+            }
+            Command::Tiled {
+                width,
+                height,
+                tile_size,
+                adapters,
+                time,
+                json,
+                out,
+            } => {
+                let (mut tree, seed) = match json {
+                    Some(path) => (Tree::from_json(&fs::read_to_string(path)?)?, None),
+                    None => {
+                        let seed = opt
+                            .seed
+                            .clone()
+                            .unwrap_or_else(|| random::<u64>().to_string());
+                        println!("seed: {}", seed);
+                        (tree_from_seed(&seed, opt.red_green_safe)?, Some(seed))
+                    }
+                };
+                tree.animate(*time);
+                if adapters.is_empty() {
+                    let mut offscreen = Offscreen::new(opt.adapter)?;
+                    let gpus = slice::from_mut(&mut *offscreen);
+                    return tiled::run(gpus, &tree, seed, *width, *height, *tile_size, out);
+                }
+                // Every GPU needs a surface of its own.
+                let event_loop = EventLoop::new();
+                let mut windows = Vec::with_capacity(adapters.len());
+                let mut gpus = Vec::with_capacity(adapters.len());
+                for &adapter in adapters {
+                    let window = WindowBuilder::new()
+                        .with_visible(false)
+                        .build(&event_loop)?;
+                    let gpu = GPU::new(&window, GPUConfig::default().with_adapter(Some(adapter)))?;
+                    println!("adapter {}: {}", adapter, gpu.adapter_name());
+                    gpus.push(gpu);
+                    windows.push(window);
+                }
+                tiled::run(&mut gpus, &tree, seed, *width, *height, *tile_size, out)
+            }
+            Command::Watch {
+                frames,
+                fps,
+                video,
+                dir,
+                out,
+            } => watch::run(
+                &mut Offscreen::new(opt.adapter)?,
+                dir,
+                out,
+                *frames,
+                *fps,
+                *video,
+                texture_extent,
+            ),
+            Command::Contact {
+                columns,
+                width,
+                height,
+                time,
+                dir,
+                out,
+            } => contact::run(
+                &mut Offscreen::new(opt.adapter)?,
+                dir,
+                out,
+                *columns,
+                [*width, *height],
+                *time,
+            ),
+            Command::Nodes {
+                columns,
+                width,
+                height,
+                time,
+                tree,
+                out,
+            } => contact::run_nodes(
+                &mut Offscreen::new(opt.adapter)?,
+                tree,
+                out,
+                *columns,
+                [*width, *height],
+                *time,
+            ),
+            Command::Stamp {
+                width,
+                height,
+                bake,
+                tree,
+                out,
+            } => stamp::run(
+                &mut Offscreen::new(opt.adapter)?,
+                tree,
+                out,
+                [*width, *height],
+                *bake,
+                GenerationConfig {
+                    growth: opt.growth,
+                    channels: opt.channels,
+                    simplify: opt.simplify,
+                    red_green_safe: opt.red_green_safe,
                 },
-            ],
-        });
-    let vert_shader = gpu.create_shader_module(include_bytes!("../target/draw.vert.spirv"))?;
-    let frag_shader = gpu.create_shader_module(include_bytes!("../target/draw.frag.spirv"))?;
-    let graphics_pipeline = gpu
-        .device()
-        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            layout: &gpu
-                .device()
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    bind_group_layouts: &[&graphics_layout],
-                }),
-            vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vert_shader,
-                entry_point: "main",
-            },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &frag_shader,
-                entry_point: "main",
-            }),
-            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::Back,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-            }),
-            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: GPU::texture_format(),
-                alpha_blend: wgpu::BlendDescriptor::REPLACE,
-                color_blend: wgpu::BlendDescriptor::REPLACE,
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: GPU::DEPTH_FORMAT,
-                depth_write_enabled: false,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_read_mask: 0,
-                stencil_write_mask: 0,
-            }),
-            index_format: wgpu::IndexFormat::Uint32,
-            vertex_buffers: &[wgpu::VertexBufferDescriptor {
-                stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
-                step_mode: wgpu::InputStepMode::Vertex,
-                attributes: &[
-                    wgpu::VertexAttributeDescriptor {
-                        format: wgpu::VertexFormat::Float2,
-                        offset: 0,
-                        shader_location: 0,
-                    },
-                    wgpu::VertexAttributeDescriptor {
-                        format: wgpu::VertexFormat::Float2,
-                        offset: 8,
-                        shader_location: 1,
-                    },
-                ],
-            }],
-            sample_count: 1,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        });
-    let verts = [
-        Vertex {
-            position: [-1f32, -1f32],
-            tex_coord: [0f32, 0f32],
-        },
-        Vertex {
-            position: [-1f32, 1f32],
-            tex_coord: [0f32, 1f32],
-        },
-        Vertex {
-            position: [1f32, -1f32],
-            tex_coord: [1f32, 0f32],
-        },
-        Vertex {
-            position: [1f32, 1f32],
-            tex_coord: [1f32, 1f32],
-        },
-    ];
-    let vertex_buffer = gpu
-        .device()
-        .create_buffer_mapped(verts.len(), wgpu::BufferUsage::all())
-        .fill_from_slice(&verts);
-    let graphics_bind_group = gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &graphics_layout,
-        bindings: &[
-            wgpu::Binding {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&compute_buffers[0].texture_view),
-            },
-            wgpu::Binding {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&texture_sampler),
-            },
-            wgpu::Binding {
-                binding: 2,
-                resource: wgpu::BindingResource::TextureView(&compute_buffers[1].texture_view),
-            },
-            wgpu::Binding {
-                binding: 3,
-                resource: wgpu::BindingResource::Sampler(&texture_sampler),
-            },
-            wgpu::Binding {
-                binding: 4,
-                resource: wgpu::BindingResource::TextureView(&compute_buffers[2].texture_view),
-            },
-            wgpu::Binding {
-                binding: 5,
-                resource: wgpu::BindingResource::Sampler(&texture_sampler),
-            },
-        ],
-    });
+            ),
+            Command::Post { once, config } => mastodon::run(
+                &mut Offscreen::new(opt.adapter)?,
+                &PostConfig::load(config)?,
+                *once,
+                opt.red_green_safe,
+            ),
+            Command::Search {
+                render: Some(out),
+                dir,
+                ..
+            } => search::render(
+                &mut Offscreen::new(opt.adapter)?,
+                dir,
+                &query_for(command),
+                texture_extent,
+                out,
+            ),
+        };
+    }
 
-    let mut rng = if let Some(seed) = opt.seed {
-        if let Ok(u) = seed.parse::<u64>() {
-            StdRng::seed_from_u64(u)
-        } else {
-            let mut hasher = Sha3_256::new();
-            hasher.input(seed);
-            let mut sized_result = [0u8; 32];
-            sized_result.copy_from_slice(&hasher.result());
-            StdRng::from_seed(sized_result)
+    let (mut recovery, crashed) = Recovery::start()?;
+    if crashed && !opt.recover {
+        println!("stampede did not exit cleanly last time; run with --recover to restore its tree");
+    }
+    let session_path = Session::default_path()?;
+    let session = if opt.recover {
+        let crashed_path = Session::crashed_path()?;
+        if !crashed_path.exists() {
+            bail!("there is no crashed session to recover");
+        }
+        Some(Session::load(&crashed_path)?)
+    } else if opt.resume {
+        Some(Session::load(&session_path)?)
+    } else {
+        None
+    };
+
+    let program_start = Instant::now();
+    let event_loop = EventLoop::new();
+    let mut window_builder = WindowBuilder::new();
+    if let Some(session) = &session {
+        window_builder = window_builder.with_inner_size(session.window.size());
+    }
+    if opt.overlay {
+        window_builder = window_builder
+            .with_decorations(false)
+            .with_transparent(true)
+            .with_always_on_top(true);
+    }
+    let window = window_builder.build(&event_loop)?;
+    // Clicks only go through once there is some other way to quit.
+    let exit_hotkey = if opt.overlay {
+        match ExitHotkey::listen() {
+            Ok(hotkey) => Some(hotkey),
+            Err(e) => {
+                println!(
+                    "not letting clicks through, since {} is not free: {}",
+                    ExitHotkey::NAME,
+                    e
+                );
+                None
+            }
         }
     } else {
-        StdRng::from_entropy()
+        None
     };
+    if opt.overlay {
+        // Still on top, just not see-through or clicked through.
+        if let Err(e) = overlay::float(&window, opt.overlay_opacity, exit_hotkey.is_some()) {
+            println!("overlaying as well as this platform allows: {}", e);
+        }
+    }
+    if let Some(position) = session.as_ref().and_then(|s| s.window.position()) {
+        window.set_outer_position(position);
+    }
+    let mut gpu = GPU::new(
+        &window,
+        GPUConfig::default()
+            .with_sample_count(opt.msaa)
+            .with_adapter(opt.adapter),
+    )?;
 
-    let mut tree = Tree::new(&mut rng);
+    // Some ops only make sense with something to read, a simulation or a depth, so
+    // they are only rolled when there is one.
+    if opt.reaction_diffusion {
+        ops::set_rate("reaction", 2f32)?;
+    }
+    if opt.mode == DisplayMode::Volume {
+        ops::set_rate("sphere", 3f32)?;
+    }
+    // Ratings are always kept, but only steer generation when asked, as they change
+    // which tree a seed grows.
+    let ratings_path = Ratings::default_path()?;
+    if opt.reset_ratings {
+        Ratings::default().save(&ratings_path)?;
+    }
+    let mut ratings = Ratings::load(&ratings_path)?;
+    if opt.adapt_rates {
+        ratings.apply()?;
+    }
+
+    let colors = session
+        .as_ref()
+        .map(|session| session.colors)
+        .unwrap_or_default();
+    // An explicit --crt wins over the one the session was left with.
+    let initial_crt = opt
+        .crt
+        .or_else(|| session.as_ref().and_then(|session| session.crt));
+    let budget = match opt.max_tree_ms {
+        Some(ms) => {
+            let model = CostModel::measure(&mut gpu, layer_format, half)?;
+            let limit = Duration::from_secs_f32(ms / 1000f32);
+            Some(CostBudget::new(model, texture_extent, limit))
+        }
+        None => None,
+    };
+    // Always run from a known seed so that a session can be reported and recreated.
+    let (mut seed, mut tree, mut view_path) = if let Some(session) = session {
+        (session.seed, session.tree, session.view_path)
+    } else if let Some(path) = &opt.open {
+        let (seed, tree) = open_tree(path)?;
+        (
+            seed.unwrap_or_else(|| OPENED.to_owned()),
+            tree,
+            ViewPath::default(),
+        )
+    } else {
+        let (seed, tree) = match opt.seed {
+            Some(seed) => {
+                let tree = tree_from_seed(&seed, opt.red_green_safe)?;
+                (seed, tree)
+            }
+            None => random_tree(opt.red_green_safe, budget.as_ref(), &HashSet::new())?,
+        };
+        (seed, tree, ViewPath::default())
+    };
+    // Pixel art is the live view computed small, so a screenshot or export of it is
+    // still full size.
+    let view_extent = match opt.pixel_art {
+        Some([width, height]) => wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth: 1,
+        },
+        None => texture_extent,
+    };
+    let mut display_config = DisplayConfig::default()
+        .with_mode(opt.mode)
+        .with_layer_filters(if opt.pixel_art.is_some() {
+            [LayerFilter::Nearest; 3]
+        } else {
+            opt.layer_filter
+        })
+        .with_quality(if opt.draft {
+            Quality::Draft
+        } else {
+            Quality::Full
+        })
+        .with_canvas(Canvas {
+            fit: opt.canvas,
+            align: opt.canvas_align,
+            border_color: opt.border_color,
+        })
+        .with_half_precision(half)
+        .with_workgroup_size(opt.workgroup_size.as_deref());
+    if opt.reaction_diffusion {
+        let control = Tree::new(&mut rng_from_seed(&format!("{}/reaction", seed)))?;
+        display_config = display_config.with_reaction_diffusion(control);
+    }
+    if !opt.no_preview {
+        display_config = display_config.with_progressive_refinement();
+    }
+    if let Some(layer) = opt.particles {
+        display_config = display_config.with_particles(layer);
+    }
+    if let Some(path) = &opt.projection {
+        display_config = display_config.with_projection(Calibration::load(path)?);
+    }
+    let mut power = if opt.ignore_battery {
+        None
+    } else {
+        Some(PowerMonitor::new())
+    };
+    let power_state = power
+        .as_ref()
+        .map(PowerMonitor::state)
+        .unwrap_or(PowerState::Mains);
+    let mut display = Display::new(
+        &mut gpu,
+        power_state.scale_extent(view_extent),
+        layer_format,
+        display_config.clone(),
+    )?;
+    display.draw_config_mut().depth_layer = opt.depth_layer;
+    display.draw_config_mut().set_color_vision(opt.color_vision);
+    display.draw_config_mut().set_palette(opt.palette.as_ref());
+    let posterize = Posterize {
+        levels: opt.posterize.unwrap_or(Posterize::default().levels),
+        thresholds: opt.posterize_thresholds,
+    };
+    if opt.posterize.is_some() {
+        display.draw_config_mut().set_posterize(Some(posterize));
+    }
+    let mut posterized = opt.posterize.is_some();
+    let mut view = View::new();
+    let mut script = opt
+        .script
+        .as_ref()
+        .map(|path| Script::load(path))
+        .transpose()?;
+    let mut regenerate_at = None;
+    let mut recorder = opt
+        .record
+        .as_ref()
+        .map(|path| Recorder::create(path))
+        .transpose()?;
+    let sonifier = opt
+        .sonify
+        .as_ref()
+        .map(|path| Sonifier::start(&tree, path))
+        .transpose()?;
+    let mut playback = opt
+        .replay
+        .as_ref()
+        .map(|path| Playback::load(path))
+        .transpose()?;
+    if let Some(script) = &mut script {
+        apply_script_commands(
+            Ok(script.on_start()),
+            &mut seed,
+            &mut tree,
+            &mut regenerate_at,
+            opt.red_green_safe,
+        );
+    }
     if opt.show_tree {
         println!("tree: {}", tree.show());
     }
 
+    let daemon = opt
+        .daemon
+        .as_ref()
+        .map(|path| Daemon::listen(path))
+        .transpose()?;
+    let mut offscreen = None;
+    // Held for as long as the event loop runs; failing to get it is no reason not
+    // to show anything.
+    let _inhibitor = if opt.allow_screensaver {
+        None
+    } else {
+        ScreensaverInhibitor::inhibit()
+            .map_err(|e| println!("the screensaver may blank the display: {}", e))
+            .ok()
+    };
+    let media = if opt.dbus {
+        Some(MediaService::start(&tree.name())?)
+    } else {
+        None
+    };
+    // Pads are optional for the same reason the screensaver is.
+    let mut gamepad = if opt.gamepad {
+        Gamepad::new()
+            .map_err(|e| println!("no gamepads: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    // Where Previous goes back to, oldest first.
+    let mut history = VecDeque::new();
+    let mut mutation_strength = MUTATION_STRENGTH;
+    // Whether frames have been skipped for want of a surface, which is only said once.
+    let mut skipped_frames = false;
+    let mut gallery = match &opt.gallery {
+        Some(path) => Some(Gallery::load(path)?),
+        None => None,
+    };
+    // Every tree shown, by canonical hash, so that random ones are not repeated.
+    let mut shown = HashSet::new();
+    // The tree that was last made from others, by hash, and those it came from.
+    let mut lineage: Option<(String, Vec<String>)> = None;
+    shown.insert(tree.canonical_hash());
+    let mut paused = false;
+    // Whether a click inspects the pixel under it rather than dragging the view.
+    let mut inspecting = false;
+    // The instructions of the last pixel inspected, run an opcode at a time.
+    let mut stepper: Option<Stepper> = None;
+    let mut interaction = Interaction::new();
+    let preview = !opt.no_preview;
+
+    let mut hud = Hud::new(&gpu, opt.show_hud)?;
+    let config = match &opt.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let keymap = Keymap::new(&config.keymap)?;
+    let mut help = Hud::new(&gpu, false)?;
+    help.set_text(&help_text(&keymap));
+    let outline = Outline {
+        thickness: opt.outline.unwrap_or(Outline::default().thickness),
+        blend: opt.outline_blend,
+        ..Outline::default()
+    };
+    let pixel_sort = PixelSort {
+        vertical: opt.pixel_sort_vertical,
+        threshold: opt.pixel_sort.unwrap_or(PixelSort::default().threshold),
+        swing: opt.pixel_sort_swing,
+        period: opt.pixel_sort_period,
+    };
+    let crt = initial_crt.unwrap_or_default();
+    let mut post = PostProcess::new(&gpu)?;
+    post.set_chain(config.post);
+    if opt.pixel_sort.is_some() {
+        post.set_enabled(PostPass::PixelSort(pixel_sort), true);
+    }
+    if opt.outline.is_some() {
+        post.set_enabled(PostPass::Outline(outline), true);
+    }
+    if initial_crt.is_some() {
+        post.set_enabled(PostPass::Crt(crt), true);
+    }
+    let mut stats_start = Instant::now();
+    let mut stats_frames = 0u32;
+
+    let show_tree = opt.show_tree;
     let show_long_frames = opt.show_long_frames;
+    let red_green_safe = opt.red_green_safe;
+    let (mut exit_after, mut exit_frames) = (opt.duration, opt.frames);
+    let (final_png, final_tree) = (opt.final_png.clone(), opt.final_tree.clone());
+    let mut frames_drawn = 0u64;
+    let mut clock = Clock::new(opt.fixed_fps);
     let mut last_redraw = Instant::now();
+    let run_start = last_redraw;
     event_loop.run(move |event, _, control_flow| {
+        if let Some(script) = &mut script {
+            if let Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } = &event
+            {
+                let commands = script.on_key(&format!("{:?}", key), &seed, &tree);
+                if apply_script_commands(
+                    commands,
+                    &mut seed,
+                    &mut tree,
+                    &mut regenerate_at,
+                    red_green_safe,
+                ) {
+                    display.note_tree_changed();
+                }
+            }
+        }
+        // Every action, whether from a key, a media key over D-Bus or a gamepad
+        // button, is carried out in the one match below.
+        let mut actions = Vec::new();
+        if let Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            modifiers,
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } = &event
+        {
+            actions.extend(keymap.action(*key, *modifiers));
+        }
+        let from_key = !actions.is_empty();
+        if let Event::EventsCleared = &event {
+            if exit_hotkey.as_ref().map_or(false, ExitHotkey::pressed) {
+                actions.push(Action::Quit);
+            }
+            if let Some(media) = &media {
+                actions.extend(media.pending());
+            }
+            if let Some(gamepad) = &mut gamepad {
+                actions.extend(gamepad.pending());
+                if let Some([brightness, contrast, saturation]) = gamepad.stick_colors() {
+                    let mut colors = display.draw_config().color_adjustment();
+                    colors.brightness += brightness;
+                    colors.contrast += contrast;
+                    colors.saturation += saturation;
+                    display
+                        .draw_config_mut()
+                        .set_color_adjustment(colors.clamped());
+                    display.set_exposure_control(opt.exposure);
+                }
+            }
+        }
+        for action in actions {
+            match action {
+                Action::Quit => *control_flow = ControlFlow::Exit,
+                Action::Help => help.toggle(),
+                Action::Status => hud.toggle(),
+                Action::ResetView => view.reset(),
+                Action::AddWaypoint => {
+                    let count = view_path.push(view.waypoint());
+                    println!("added waypoint {}", count);
+                }
+                Action::ClearPath => view_path.clear(),
+                Action::PlayTour => view_path.toggle_playback(),
+                Action::Next | Action::Skip => {
+                    history.push_back((seed.clone(), tree.clone()));
+                    if history.len() > HISTORY_LENGTH {
+                        history.pop_front();
+                    }
+                    match gallery.as_mut() {
+                        Some(gallery) if action == Action::Skip => gallery.skip(),
+                        _ => regenerate_at = Some(Instant::now()),
+                    }
+                }
+                Action::Previous => {
+                    if let Some((previous_seed, previous)) = history.pop_back() {
+                        seed = previous_seed;
+                        tree = previous;
+                        display.note_tree_changed();
+                    }
+                }
+                Action::Pause => paused = !paused,
+                Action::Reroll | Action::Mutate => {
+                    // As with a pasted tree, the result did not come from any seed.
+                    seed = "rerolled".to_owned();
+                    let parent = tree.canonical_hash();
+                    let mut rng = StdRng::from_entropy();
+                    if action == Action::Mutate {
+                        tree.perturb_constants(&mut rng, mutation_strength);
+                    } else {
+                        tree.reroll_constants(&mut rng);
+                    }
+                    lineage = Some((tree.canonical_hash(), vec![parent]));
+                    display.note_tree_changed();
+                    if show_tree {
+                        println!("tree: {}", tree.show());
+                    }
+                }
+                Action::WeakerMutations | Action::StrongerMutations => {
+                    let [low, high] = MUTATION_STRENGTH_BOUNDS;
+                    mutation_strength = if action == Action::StrongerMutations {
+                        mutation_strength * MUTATION_STRENGTH_STEP
+                    } else {
+                        mutation_strength / MUTATION_STRENGTH_STEP
+                    }
+                    .max(low)
+                    .min(high);
+                    println!("mutation strength {:0.2}", mutation_strength);
+                }
+                Action::RateGood | Action::RateBad => {
+                    let rating = if action == Action::RateGood {
+                        Rating::Good
+                    } else {
+                        Rating::Bad
+                    };
+                    ratings.rate(&tree, rating);
+                    match ratings.save(&ratings_path) {
+                        Ok(()) => {
+                            println!("rated {} {:?}; {} ratings", seed, rating, ratings.count())
+                        }
+                        Err(e) => println!("failed to save ratings: {}", e),
+                    }
+                }
+                Action::CopyTree => {
+                    if let Err(e) = copy_tree(&tree) {
+                        println!("failed to copy tree: {}", e);
+                    }
+                }
+                Action::PasteTree => match paste_tree() {
+                    Ok(pasted) => {
+                        // A pasted tree did not come from any seed we know about.
+                        seed = "pasted".to_owned();
+                        tree = pasted;
+                        display.note_tree_changed();
+                        if show_tree {
+                            println!("tree: {}", tree.show());
+                        }
+                    }
+                    Err(e) => println!("failed to paste tree: {}", e),
+                },
+                Action::SaveFavorite => save_favorite(&tree, &lineage),
+                Action::Exposure => {
+                    // Holding keeps the exposure that auto had reached, to look around
+                    // without it chasing the picture.
+                    let control = match display.exposure_control() {
+                        ExposureControl::Auto => ExposureControl::Hold,
+                        _ => ExposureControl::Auto,
+                    };
+                    display.set_exposure_control(control);
+                    println!("exposure: {:?}", control);
+                }
+                Action::Posterize => {
+                    posterized = !posterized;
+                    display.draw_config_mut().set_posterize(if posterized {
+                        Some(posterize)
+                    } else {
+                        None
+                    });
+                }
+                Action::Outline => post.toggle(PostPass::Outline(outline)),
+                Action::PixelSort => post.toggle(PostPass::PixelSort(pixel_sort)),
+                Action::Crt => post.toggle(PostPass::Crt(crt)),
+                Action::Histogram => {
+                    let visible = display.histogram().is_visible();
+                    display.histogram_mut().set_visible(!visible);
+                }
+                Action::Inspect => {
+                    inspecting = !inspecting;
+                    stepper = None;
+                    println!(
+                        "{}",
+                        if inspecting {
+                            "click to inspect a pixel; inspect again to stop"
+                        } else {
+                            "no longer inspecting"
+                        }
+                    );
+                }
+                Action::Step | Action::StepLayer => {
+                    if let Some(s) = stepper.as_mut() {
+                        let more = if action == Action::StepLayer {
+                            s.run_layer()
+                        } else {
+                            s.step()
+                        };
+                        if !more {
+                            println!("done stepping");
+                            stepper = None;
+                        }
+                    }
+                }
+                Action::Quality => {
+                    let quality = display_config.quality().toggled();
+                    display_config = display_config.clone().with_quality(quality);
+                    let power_state = power
+                        .as_ref()
+                        .map(PowerMonitor::state)
+                        .unwrap_or(PowerState::Mains);
+                    rebuild_display(
+                        &mut gpu,
+                        &mut display,
+                        power_state.scale_extent(view_extent),
+                        layer_format,
+                        &display_config,
+                    );
+                    println!("quality: {:?}", quality);
+                }
+                Action::ColorVision => {
+                    let vision = display.draw_config().color_vision().next();
+                    display.draw_config_mut().set_color_vision(vision);
+                    println!("color vision: {:?}", vision);
+                }
+                Action::BrightnessDown
+                | Action::BrightnessUp
+                | Action::ContrastDown
+                | Action::ContrastUp
+                | Action::SaturationDown
+                | Action::SaturationUp
+                | Action::ResetColors => {
+                    let colors = nudge_colors(display.draw_config().color_adjustment(), action);
+                    display.draw_config_mut().set_color_adjustment(colors);
+                    display.set_exposure_control(opt.exposure);
+                    println!(
+                        "brightness {:0.2}, contrast {:0.2}, saturation {:0.2}",
+                        colors.brightness, colors.contrast, colors.saturation
+                    );
+                }
+                Action::Layer1GainDown
+                | Action::Layer1GainUp
+                | Action::Layer2GainDown
+                | Action::Layer2GainUp
+                | Action::Layer3GainDown
+                | Action::Layer3GainUp
+                | Action::Layer1BiasDown
+                | Action::Layer1BiasUp
+                | Action::Layer2BiasDown
+                | Action::Layer2BiasUp
+                | Action::Layer3BiasDown
+                | Action::Layer3BiasUp
+                | Action::ResetLayers => {
+                    let balance = nudge_balance(display.draw_config().layer_balance(), action);
+                    display.draw_config_mut().set_layer_balance(balance);
+                    display.set_exposure_control(opt.exposure);
+                    println!(
+                        "layer gain {:0.2?}, bias {:0.2?}",
+                        balance.gain, balance.bias
+                    );
+                }
+            }
+        }
+        if from_key {
+            return;
+        }
         match event {
             Event::EventsCleared => {
+                if let Some(fps) = power.as_ref().and_then(|p| p.state().frame_cap()) {
+                    let next_frame = last_redraw + Duration::from_secs_f32(1f32 / fps);
+                    if Instant::now() < next_frame {
+                        *control_flow = ControlFlow::WaitUntil(next_frame);
+                        return;
+                    }
+                }
+                // Rebuilding the display starts its simulations over, but the power
+                // does not change often.
+                if let Some(state) = power.as_mut().and_then(PowerMonitor::poll) {
+                    println!(
+                        "on {:?}: computing at 1/{} size",
+                        state,
+                        state.resolution_divisor()
+                    );
+                    rebuild_display(
+                        &mut gpu,
+                        &mut display,
+                        state.scale_extent(view_extent),
+                        layer_format,
+                        &display_config,
+                    );
+                }
+
                 // Application update code.
-                tree.animate();
+                let now = Instant::now();
+                let mut dt = clock.tick();
+
+                if let Some(daemon) = &daemon {
+                    for (command, reply) in daemon.pending() {
+                        let result = match command {
+                            DaemonCommand::Load { path } => {
+                                open_tree(&path).map(|(opened_seed, opened)| {
+                                    seed = opened_seed.unwrap_or_else(|| OPENED.to_owned());
+                                    tree = opened;
+                                    display.note_tree_changed();
+                                })
+                            }
+                            DaemonCommand::Seed { seed: next } => {
+                                tree_from_seed(&next, red_green_safe).map(|next_tree| {
+                                    tree = next_tree;
+                                    seed = next;
+                                    display.note_tree_changed();
+                                })
+                            }
+                            DaemonCommand::Regenerate => {
+                                regenerate_at = Some(now);
+                                Ok(())
+                            }
+                            DaemonCommand::Screenshot { path } => screenshot(
+                                &mut gpu,
+                                &mut offscreen,
+                                &seed,
+                                &tree,
+                                texture_extent,
+                                &path,
+                            ),
+                            DaemonCommand::Quit => {
+                                *control_flow = ControlFlow::Exit;
+                                Ok(())
+                            }
+                        };
+                        // The client may have hung up without waiting.
+                        reply.send(result.map_err(|e| e.to_string())).ok();
+                    }
+                }
+                if paused {
+                    dt = 0f32;
+                }
+
+                match playback.as_mut().and_then(|playback| playback.next_frame()) {
+                    Some(frame) => {
+                        dt = frame.dt;
+                        match frame.tree {
+                            Some(recorded) => {
+                                seed = "replayed".to_owned();
+                                tree = recorded;
+                                display.note_tree_changed();
+                            }
+                            None => tree.animate(dt),
+                        }
+                        view.go_to(&frame.view);
+                        display.config_mut().mouse_position = frame.mouse_position;
+                    }
+                    None => {
+                        if playback.take().is_some() {
+                            println!("replay finished");
+                        }
+                        tree.animate(dt);
+                        view_path.advance(dt, &mut view);
+
+                        if let Some(script) = &mut script {
+                            let commands = script.on_frame(dt, &seed, &tree);
+                            if apply_script_commands(
+                                commands,
+                                &mut seed,
+                                &mut tree,
+                                &mut regenerate_at,
+                                red_green_safe,
+                            ) {
+                                display.note_tree_changed();
+                            }
+                        }
+                        if regenerate_at.map(|at| now >= at).unwrap_or(false) {
+                            regenerate_at = None;
+                            match random_tree(red_green_safe, budget.as_ref(), &shown) {
+                                Ok((next_seed, next)) => {
+                                    shown.insert(next.canonical_hash());
+                                    seed = next_seed;
+                                    tree = next;
+                                    display.note_tree_changed();
+                                    if show_tree {
+                                        println!("tree: {}", tree.show());
+                                    }
+                                }
+                                Err(e) => println!("keeping this tree: {}", e),
+                            }
+                        }
+                        if let Some((name, next)) = gallery.as_mut().and_then(|g| g.poll(now)) {
+                            // Favorites are known by name rather than seed.
+                            seed = name;
+                            tree = next;
+                            display.note_tree_changed();
+                            if show_tree {
+                                println!("tree: {}", tree.show());
+                            }
+                        }
+                    }
+                }
+
+                // A new tree is previewed too, in case another is right behind it.
+                if display.is_upload_pending() {
+                    interaction.note();
+                }
+                display.set_preview(preview && interaction.is_active());
+                if let Some(sonifier) = &sonifier {
+                    if display.is_upload_pending() {
+                        sonifier.set_tree(&tree);
+                    }
+                }
+                if let Some(recorder) = &mut recorder {
+                    let frame = RecordedFrame {
+                        dt,
+                        tree: if display.is_upload_pending() {
+                            Some(tree.clone())
+                        } else {
+                            None
+                        },
+                        view: view.waypoint(),
+                        mouse_position: display.config().mouse_position,
+                    };
+                    if let Err(e) = recorder.record(&frame) {
+                        println!("failed to record frame: {}", e);
+                    }
+                }
 
                 // Queue a RedrawRequested event.
                 window.request_redraw();
@@ -424,81 +2243,71 @@ fn main() -> Fallible<()> {
                 // It's preferable to render in this event rather than in EventsCleared, since
                 // rendering in here allows the program to gracefully handle redraws requested
                 // by the OS.
-                let (instr_upload_buffer_r, const_upload_buffer_r) =
-                    tree.encode_upload_buffer(0, gpu.device());
-                let (instr_upload_buffer_g, const_upload_buffer_g) =
-                    tree.encode_upload_buffer(1, gpu.device());
-                let (instr_upload_buffer_b, const_upload_buffer_b) =
-                    tree.encode_upload_buffer(2, gpu.device());
-                let mut frame = gpu.begin_frame().unwrap();
-                frame.copy_buffer_to_buffer(
-                    &instr_upload_buffer_r,
-                    0,
-                    &compute_buffers[0].instr_buffer,
-                    0,
-                    InstructionEncoder::instruction_buffer_size(),
-                );
-                frame.copy_buffer_to_buffer(
-                    &const_upload_buffer_r,
-                    0,
-                    &compute_buffers[0].pool_buffer,
-                    0,
-                    InstructionEncoder::pool_buffer_size(),
-                );
-                frame.copy_buffer_to_buffer(
-                    &instr_upload_buffer_g,
-                    0,
-                    &compute_buffers[1].instr_buffer,
-                    0,
-                    InstructionEncoder::instruction_buffer_size(),
-                );
-                frame.copy_buffer_to_buffer(
-                    &const_upload_buffer_g,
-                    0,
-                    &compute_buffers[1].pool_buffer,
-                    0,
-                    InstructionEncoder::pool_buffer_size(),
-                );
-                frame.copy_buffer_to_buffer(
-                    &instr_upload_buffer_b,
-                    0,
-                    &compute_buffers[2].instr_buffer,
-                    0,
-                    InstructionEncoder::instruction_buffer_size(),
-                );
-                frame.copy_buffer_to_buffer(
-                    &const_upload_buffer_b,
-                    0,
-                    &compute_buffers[2].pool_buffer,
-                    0,
-                    InstructionEncoder::pool_buffer_size(),
-                );
-                {
-                    let mut cpass = frame.begin_compute_pass();
-                    cpass.set_pipeline(&uni_shader_pipeline);
-                    cpass.set_bind_group(0, &compute_buffers[0].bind_group, &[]);
-                    cpass.dispatch(texture_extent.width / 8, texture_extent.height / 8, 1);
+
+                let config = display.config_mut();
+                config.view_center = view.center();
+                config.view_scale = view.scale();
+                let display_upload = match display.encode_upload_buffers(&gpu, &tree) {
+                    Ok(upload) => upload,
+                    Err(e) => {
+                        println!("keeping the last tree up: {}", e);
+                        return;
+                    }
+                };
+                // Help takes the HUD's corner while it is up.
+                let text = if help.is_visible() { &help } else { &hud };
+                let hud_upload = if text.is_visible() {
+                    Some(text.encode_upload_buffers(&gpu))
+                } else {
+                    None
+                };
+                let post_upload = if post.is_active() {
+                    Some(post.encode_upload_buffers(&gpu, tree.time()))
+                } else {
+                    None
+                };
+                // The window may have changed size or display under us without the
+                // event having come yet; the surface cannot be drawn into until we
+                // catch up.
+                if gpu.is_outdated(&window) {
+                    gpu.note_resize(&window);
+                    display.note_resize(&gpu);
+                    post.note_resize(&gpu);
                 }
-                {
-                    let mut cpass = frame.begin_compute_pass();
-                    cpass.set_pipeline(&uni_shader_pipeline);
-                    cpass.set_bind_group(0, &compute_buffers[1].bind_group, &[]);
-                    cpass.dispatch(texture_extent.width / 8, texture_extent.height / 8, 1);
+                let mut frame = match gpu.begin_frame() {
+                    Ok(frame) => frame,
+                    // Nothing to see while minimized; we will be resized on the way back.
+                    Err(GpuError::EmptyWindow) => return,
+                    Err(e) => {
+                        if !skipped_frames {
+                            println!("skipping frames: {}", e);
+                            skipped_frames = true;
+                        }
+                        return;
+                    }
+                };
+                display.upload(&display_upload, &mut frame);
+                if let Some(upload) = &hud_upload {
+                    text.upload(upload, &mut frame);
                 }
-                {
-                    let mut cpass = frame.begin_compute_pass();
-                    cpass.set_pipeline(&uni_shader_pipeline);
-                    cpass.set_bind_group(0, &compute_buffers[2].bind_group, &[]);
-                    cpass.dispatch(texture_extent.width / 8, texture_extent.height / 8, 1);
+                if let Some(upload) = &post_upload {
+                    post.upload(upload, &mut frame);
+                    display.draw(&mut frame.begin_render_pass_into(post.composite_view()));
+                    post.compute(&mut frame);
                 }
                 {
+                    // The HUD goes over the post passes, so that they leave it be.
                     let mut rpass = frame.begin_render_pass();
-                    rpass.set_pipeline(&graphics_pipeline);
-                    rpass.set_bind_group(0, &graphics_bind_group, &[]);
-                    rpass.set_vertex_buffers(0, &[(&vertex_buffer, 0)]);
-                    rpass.draw(0..4, 0..1);
+                    if post.is_active() {
+                        post.draw(&mut rpass);
+                    } else {
+                        display.draw(&mut rpass);
+                    }
+                    display.histogram().draw(&mut rpass);
+                    text.draw(&mut rpass);
                 }
                 frame.finish();
+                display.compute(&mut gpu, display_upload);
 
                 let frame_time = last_redraw.elapsed();
                 if show_long_frames && frame_time >= Duration::from_millis(17) {
@@ -509,7 +2318,112 @@ fn main() -> Fallible<()> {
                     );
                 }
                 last_redraw = Instant::now();
+
+                frames_drawn += 1;
+                let out_of_time = exit_after
+                    .map(|d| run_start.elapsed() >= d)
+                    .unwrap_or(false);
+                let out_of_frames = exit_frames.map(|n| frames_drawn >= n).unwrap_or(false);
+                if out_of_time || out_of_frames {
+                    println!(
+                        "drew {} frames in {:?}; stopping",
+                        frames_drawn,
+                        run_start.elapsed()
+                    );
+                    if let Some(path) = &final_png {
+                        if let Err(e) =
+                            screenshot(&mut gpu, &mut offscreen, &seed, &tree, texture_extent, path)
+                        {
+                            println!("failed to export {}: {}", path.display(), e);
+                        }
+                    }
+                    if let Some(path) = &final_tree {
+                        if let Err(e) = tree.to_json().and_then(|json| Ok(fs::write(path, json)?)) {
+                            println!("failed to save {}: {}", path.display(), e);
+                        }
+                    }
+                    // Another frame may come before the loop stops; it is not the last.
+                    exit_after = None;
+                    exit_frames = None;
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+
+                let colors = display.draw_config().color_adjustment();
+                if let Err(e) = recovery.tick(|| {
+                    Session::to_json(&seed, &tree, &view_path, colors, post.crt(), &window)
+                }) {
+                    println!("failed to autosave: {}", e);
+                }
+
+                // Refresh the stats a couple times a second so that they are readable.
+                stats_frames += 1;
+                let stats_elapsed = stats_start.elapsed();
+                if stats_elapsed >= Duration::from_millis(500) {
+                    let avg_frame_time = stats_elapsed / stats_frames;
+                    let fps = f64::from(stats_frames) / stats_elapsed.as_secs_f64();
+                    let name = tree.name();
+                    if let Some(media) = &media {
+                        media.set_tree_name(&name);
+                    }
+                    window.set_title(&format!(
+                        "stampede - {} (seed {}) - {:0.1}ms ({:0.0} fps) - {} nodes",
+                        name,
+                        seed,
+                        avg_frame_time.as_secs_f64() * 1000.0,
+                        fps,
+                        tree.node_count()
+                    ));
+                    hud.set_text(&format!(
+                        "name: {}\nseed: {}\nframe: {:0.1}ms ({:0.0} fps)\nnodes: {}\nstack: {}",
+                        name,
+                        seed,
+                        avg_frame_time.as_secs_f64() * 1000.0,
+                        fps,
+                        tree.node_count(),
+                        tree.stats().stack_depth()
+                    ));
+                    stats_start = Instant::now();
+                    stats_frames = 0;
+                }
             }
+            Event::LoopDestroyed => {
+                if let Some(daemon) = &daemon {
+                    daemon.close();
+                }
+                if let Some(recorder) = &mut recorder {
+                    if let Err(e) = recorder.finish() {
+                        println!("failed to finish recording: {}", e);
+                    }
+                }
+                let colors = display.draw_config().color_adjustment();
+                if let Err(e) = Session::save(
+                    &session_path,
+                    &seed,
+                    &tree,
+                    &view_path,
+                    colors,
+                    post.crt(),
+                    &window,
+                ) {
+                    println!("failed to save session: {}", e);
+                }
+                recovery.finish();
+            }
+            Event::WindowEvent {
+                event: WindowEvent::DroppedFile(path),
+                ..
+            } => match open_tree(&path) {
+                Ok((opened_seed, opened)) => {
+                    seed = opened_seed.unwrap_or_else(|| OPENED.to_owned());
+                    tree = opened;
+                    display.note_tree_changed();
+                    if show_tree {
+                        println!("tree: {}", tree.show());
+                    }
+                }
+                Err(e) => println!("failed to open {}: {}", path.display(), e),
+            },
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
@@ -527,33 +2441,86 @@ fn main() -> Fallible<()> {
             Event::WindowEvent {
                 event: WindowEvent::Resized(_),
                 ..
+            }
+            | Event::WindowEvent {
+                // Dragged onto a display with another scale; the physical size changes.
+                event: WindowEvent::HiDpiFactorChanged(_),
+                ..
             } => {
                 gpu.note_resize(&window);
+                display.note_resize(&gpu);
+                post.note_resize(&gpu);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => {
+                // The quad puts the first texture row at the bottom of the screen.
+                let size = window.inner_size();
+                let mouse_position = display.window_to_canvas([
+                    (position.x / size.width) as f32,
+                    1f32 - (position.y / size.height) as f32,
+                ]);
+                if view.is_dragging() {
+                    interaction.note();
+                }
+                let config = display.config_mut();
+                config.mouse_position = mouse_position;
+                view.drag_to(config.mouse_position, config.aspect_ratio);
             }
             Event::WindowEvent {
                 event:
-                    WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
+                    WindowEvent::MouseInput {
+                        state,
+                        button: MouseButton::Left,
                         ..
                     },
                 ..
-            } => *control_flow = ControlFlow::Exit,
+            } => match state {
+                ElementState::Pressed if inspecting => {
+                    stepper = None;
+                    match inspect_pixel(&mut gpu, &display, &view, &tree)
+                        .and_then(|ctx| Stepper::new(&tree, &ctx))
+                    {
+                        Ok(s) => {
+                            println!("space steps through the instructions; shift+space a layer");
+                            stepper = Some(s);
+                        }
+                        Err(e) => println!("failed to inspect: {}", e),
+                    }
+                }
+                // Let a tour play out without fighting over the camera.
+                ElementState::Pressed if !view_path.is_playing() => {
+                    view.begin_drag(display.config().mouse_position)
+                }
+                _ => view.end_drag(),
+            },
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel { delta, .. },
+                ..
+            } => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(p) => (p.y / 100f64) as f32,
+                };
+                if !view_path.is_playing() {
+                    interaction.note();
+                    let config = display.config();
+                    view.zoom(steps, config.mouse_position, config.aspect_ratio);
+                }
+            }
             Event::WindowEvent {
                 event:
-                    WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                virtual_keycode: Some(VirtualKeyCode::Q),
-                                ..
-                            },
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Right,
                         ..
                     },
                 ..
-            } => *control_flow = ControlFlow::Exit,
+            } => {
+                let config = display.config();
+                view.recenter(config.mouse_position, config.aspect_ratio)
+            }
             // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't
             // dispatched any events. This is ideal for games and similar applications.
             _ => *control_flow = ControlFlow::Poll,
@@ -564,3 +2531,24 @@ fn main() -> Fallible<()> {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_parse_with_or_without_units() -> Result<()> {
+        assert_eq!(parse_duration("250ms")?, Duration::from_millis(250));
+        assert_eq!(parse_duration("3")?, Duration::from_secs(3));
+        assert_eq!(parse_duration("1.5m")?, Duration::from_secs(90));
+        assert_eq!(parse_duration(" 2h ")?, Duration::from_secs(7200));
+        Ok(())
+    }
+
+    #[test]
+    fn durations_out_of_range_are_errors() {
+        for s in &["1e20", "1e20h", "-1s", "inf", "NaN", "3 fortnights"] {
+            assert!(parse_duration(s).is_err(), "{}", s);
+        }
+    }
+}
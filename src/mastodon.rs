@@ -0,0 +1,224 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{export, scene::Scene, tree_from_seed};
+use anyhow::{bail, Result};
+use gpu::GPU;
+use rand::prelude::*;
+use serde::Deserialize;
+use stampede::{compute::Quality, render::OffscreenRenderer, tree::Tree};
+use std::{fmt, fs, path::Path, thread, time::Duration};
+
+fn default_interval_hours() -> f32 {
+    24f32
+}
+
+fn default_visibility() -> String {
+    "unlisted".to_owned()
+}
+
+fn default_size() -> [u32; 2] {
+    [1920, 1080]
+}
+
+// Where and how to post, e.g.:
+//
+//   {"instance": "https://botsin.space", "token": "...", "interval_hours": 12,
+//    "visibility": "public", "size": [1920, 1080], "hashtags": ["generativeart"]}
+//
+// The token is an access token for an application with write:media and
+// write:statuses, made under the account's development settings. It is only ever
+// sent over https, and is left out when the config is printed.
+#[derive(Deserialize)]
+pub struct PostConfig {
+    instance: String,
+    token: String,
+    #[serde(default = "default_interval_hours")]
+    interval_hours: f32,
+    #[serde(default = "default_visibility")]
+    visibility: String,
+    #[serde(default = "default_size")]
+    size: [u32; 2],
+    #[serde(default)]
+    hashtags: Vec<String>,
+}
+
+impl PostConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let config: Self = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if config.size[0] == 0 || config.size[1] == 0 {
+            bail!("posts must have some size");
+        }
+        if !config.instance.starts_with("https://") {
+            bail!(
+                "{} is not an https address; the token would be sent in the clear",
+                config.instance
+            );
+        }
+        Ok(config)
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}{}", self.instance.trim_end_matches('/'), endpoint)
+    }
+
+    fn authorization(&self) -> String {
+        format!("Bearer {}", self.token)
+    }
+}
+
+impl fmt::Debug for PostConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PostConfig")
+            .field("instance", &self.instance)
+            .field("token", &"<redacted>")
+            .field("interval_hours", &self.interval_hours)
+            .field("visibility", &self.visibility)
+            .field("size", &self.size)
+            .field("hashtags", &self.hashtags)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct Attachment {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    url: Option<String>,
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(response: ureq::Response) -> Result<T> {
+    if let Some(error) = response.synthetic_error() {
+        bail!("{}", error);
+    }
+    if !response.ok() {
+        bail!(
+            "{} {}: {}",
+            response.status(),
+            response.status_text().to_owned(),
+            response.into_string().unwrap_or_default()
+        );
+    }
+    Ok(serde_json::from_str(&response.into_string()?)?)
+}
+
+// Upload the picture, described for those who cannot see it, then post a status
+// with it attached. Returns the status's address.
+fn post(config: &PostConfig, png: &[u8], description: &str, text: &str) -> Result<String> {
+    let boundary = format!("stampede-{:016x}", random::<u64>());
+    let mut body = Vec::new();
+    body.extend(
+        format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"description\"\r\n\r\n{d}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"stampede.png\"\r\n\
+             Content-Type: image/png\r\n\r\n",
+            b = boundary,
+            d = description
+        )
+        .bytes(),
+    );
+    body.extend_from_slice(png);
+    body.extend(format!("\r\n--{}--\r\n", boundary).bytes());
+    let attachment: Attachment = read_json(
+        ureq::post(&config.url("/api/v1/media"))
+            .set("Authorization", &config.authorization())
+            .set(
+                "Content-Type",
+                &format!("multipart/form-data; boundary={}", boundary),
+            )
+            .send_bytes(&body),
+    )?;
+    let status: Status = read_json(
+        ureq::post(&config.url("/api/v1/statuses"))
+            .set("Authorization", &config.authorization())
+            .send_form(&[
+                ("status", text),
+                ("media_ids[]", &attachment.id),
+                ("visibility", &config.visibility),
+            ]),
+    )?;
+    Ok(status.url.unwrap_or_default())
+}
+
+fn describe(seed: &str, tree: &Tree, hashtags: &[String]) -> String {
+    let mut text = format!(
+        "{}\nseed {}, {} nodes, {} deep",
+        tree.name(),
+        seed,
+        tree.node_count(),
+        tree.depth()
+    );
+    if !hashtags.is_empty() {
+        text.push('\n');
+        text.push_str(
+            &hashtags
+                .iter()
+                .map(|tag| format!("#{}", tag.trim_start_matches('#')))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    }
+    text
+}
+
+// Posts a fresh random tree every interval, until interrupted, or just the one.
+// A failed post is reported and tried again with a new tree at the next interval,
+// so that a network outage does not stop the bot.
+pub fn run(gpu: &mut GPU, config: &PostConfig, once: bool, red_green_safe: bool) -> Result<()> {
+    // What is posted is the finished picture, never a draft.
+    let renderer = OffscreenRenderer::new(gpu)?.with_quality(Quality::Full);
+    let extent = wgpu::Extent3d {
+        width: config.size[0],
+        height: config.size[1],
+        depth: 1,
+    };
+    let dir = std::env::temp_dir();
+    loop {
+        let seed = random::<u64>().to_string();
+        let tree = tree_from_seed(&seed, red_green_safe)?;
+        let text = describe(&seed, &tree, &config.hashtags);
+        let path = dir.join(format!("stampede-post-{}.png", seed));
+        let result = export::write_frame(
+            gpu,
+            &renderer,
+            &Scene::single(tree.clone()),
+            Some(seed.as_str()),
+            extent,
+            &path,
+        )
+        .and_then(|()| Ok(fs::read(&path)?))
+        .and_then(|png| {
+            post(
+                config,
+                &png,
+                &format!("Generated art: {}", tree.name()),
+                &text,
+            )
+        });
+        fs::remove_file(&path).ok();
+        match result {
+            Ok(url) => println!("posted {} at {}", tree.name(), url),
+            Err(e) if once => return Err(e),
+            Err(e) => println!("failed to post {}: {}", tree.name(), e),
+        }
+        if once {
+            return Ok(());
+        }
+        let interval = Duration::from_secs_f32(config.interval_hours.max(0.01) * 3600f32);
+        thread::sleep(interval);
+    }
+}
@@ -0,0 +1,136 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::keymap::Action;
+use anyhow::{anyhow, Error, Result};
+use std::sync::{
+    mpsc::{self, Receiver},
+    Arc, Mutex,
+};
+
+pub const BUS_NAME: &str = "org.stampede.Stampede";
+const OBJECT_PATH: &str = "/org/stampede/Stampede";
+
+// A small D-Bus service on the session bus, for running stampede as a wallpaper
+// or ambient display with nothing to type into. It has the methods Next,
+// Previous, Pause and SaveFavorite and a read-only TreeName property, e.g.:
+//
+//   dbus-send --session --dest=org.stampede.Stampede /org/stampede/Stampede \
+//       org.stampede.Stampede.Next
+//
+// Calls are queued for the event loop, which applies them between frames.
+pub struct MediaService {
+    commands: Receiver<Action>,
+    tree_name: Arc<Mutex<String>>,
+}
+
+impl MediaService {
+    #[cfg(target_os = "linux")]
+    pub fn start(tree_name: &str) -> Result<Self> {
+        use std::thread;
+
+        let (sender, commands) = mpsc::channel();
+        let tree_name = Arc::new(Mutex::new(tree_name.to_owned()));
+        let name = tree_name.clone();
+        // Find out whether the name could be had before going on without it.
+        let (started, result) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(e) = serve(sender, name, &started) {
+                // Nobody to tell if we started fine and then lost the bus.
+                started
+                    .send(Err(e.to_string()))
+                    .unwrap_or_else(|_| println!("dbus: service stopped: {}", e));
+            }
+        });
+        result
+            .recv()
+            .map_err(|_| anyhow!("dbus: the service thread exited"))?
+            .map_err(Error::msg)?;
+        println!("dbus: serving {}", BUS_NAME);
+        Ok(Self {
+            commands,
+            tree_name,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn start(_tree_name: &str) -> Result<Self> {
+        Err(anyhow!("the dbus service is only available on linux"))
+    }
+
+    // Everything that has come in since the last look.
+    pub fn pending(&self) -> Vec<Action> {
+        self.commands.try_iter().collect()
+    }
+
+    // What TreeName reports, as the tree's name.
+    pub fn set_tree_name(&self, name: &str) {
+        let mut tree_name = self.tree_name.lock().expect("tree name lock");
+        if *tree_name != name {
+            *tree_name = name.to_owned();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn serve(
+    commands: mpsc::Sender<Action>,
+    tree_name: Arc<Mutex<String>>,
+    started: &mpsc::Sender<Result<(), String>>,
+) -> Result<()> {
+    use dbus::{
+        blocking::LocalConnection,
+        tree::{Access, EmitsChangedSignal, Factory},
+    };
+    use std::time::Duration;
+
+    let connection = LocalConnection::new_session()?;
+    connection.request_name(BUS_NAME, false, true, true)?;
+    let factory = Factory::new_fn::<()>();
+    let method = |name: &'static str, command: Action| {
+        let commands = commands.clone();
+        factory.method(name, (), move |m| {
+            commands
+                .send(command)
+                .map_err(|_| dbus::tree::MethodErr::failed(&"stampede is shutting down"))?;
+            Ok(vec![m.msg.method_return()])
+        })
+    };
+    let tree_name_property = factory
+        .property::<&str, _>("TreeName", ())
+        .access(Access::Read)
+        .emits_changed(EmitsChangedSignal::False)
+        .on_get(move |iter, _| {
+            iter.append(tree_name.lock().expect("tree name lock").clone());
+            Ok(())
+        });
+    let interface = factory
+        .interface(BUS_NAME, ())
+        .add_m(method("Next", Action::Next))
+        .add_m(method("Previous", Action::Previous))
+        .add_m(method("Pause", Action::Pause))
+        .add_m(method("SaveFavorite", Action::SaveFavorite))
+        .add_p(tree_name_property);
+    let tree = factory.tree(()).add(
+        factory
+            .object_path(OBJECT_PATH, ())
+            .introspectable()
+            .add(interface),
+    );
+    tree.start_receive(&connection);
+    started.send(Ok(())).ok();
+    loop {
+        connection.process(Duration::from_millis(1000))?;
+    }
+}
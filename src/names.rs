@@ -0,0 +1,52 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+
+// Words for naming trees, so that a tree can be talked about as "crimson-meadow"
+// rather than by seed. There are a power of two of each, so that a hash picks
+// among them evenly.
+const ADJECTIVES: [&str; 64] = [
+    "amber", "ancient", "ashen", "autumn", "azure", "bitter", "blazing", "bold", "bright",
+    "broken", "calm", "cobalt", "coral", "crimson", "crystal", "dappled", "dark", "dawning",
+    "deep", "distant", "dusky", "electric", "emerald", "faded", "feral", "fierce", "frosted",
+    "gentle", "gilded", "glassy", "golden", "hidden", "hollow", "hushed", "icy", "indigo", "ivory",
+    "jade", "lunar", "misty", "molten", "mossy", "muted", "nimble", "obsidian", "pale", "quiet",
+    "restless", "rosy", "rusty", "scarlet", "shadowed", "silent", "silver", "smoky", "solar",
+    "spiral", "stormy", "sunken", "swift", "tangled", "velvet", "violet", "wild",
+];
+
+const NOUNS: [&str; 64] = [
+    "aurora", "bay", "bloom", "brook", "canyon", "cascade", "cavern", "cinder", "cloud", "comet",
+    "coral", "cove", "crater", "creek", "delta", "dune", "ember", "fern", "field", "fjord",
+    "flame", "forest", "garden", "geyser", "glacier", "grove", "harbor", "haze", "heath",
+    "horizon", "island", "lagoon", "lantern", "marsh", "meadow", "mesa", "mirage", "moon",
+    "nebula", "oasis", "orchard", "peak", "petal", "prairie", "prism", "quartz", "reef", "ridge",
+    "river", "shore", "sky", "spring", "star", "storm", "summit", "thicket", "tide", "tundra",
+    "valley", "vapor", "wave", "willow", "wind", "zenith",
+];
+
+// Two words, adjective-noun, from the first two bytes of a hex hash, such as
+// Tree::canonical_hash. The same hash always gets the same name.
+pub fn name_for_hash(hash: &str) -> String {
+    let byte = |i: usize| {
+        hash.get(i * 2..i * 2 + 2)
+            .and_then(|b| u8::from_str_radix(b, 16).ok())
+            .unwrap_or(0) as usize
+    };
+    format!(
+        "{}-{}",
+        ADJECTIVES[byte(0) % ADJECTIVES.len()],
+        NOUNS[byte(1) % NOUNS.len()]
+    )
+}
@@ -0,0 +1,473 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    error::Fallible,
+    tree::{EvalContext, WrapMode},
+};
+use lazy_static::lazy_static;
+use std::{f32::consts::PI, sync::RwLock};
+
+// The range a constant is generated in and how it behaves at its limits: "m"
+// mirrors, "r" repeats and "f" is fixed.
+pub struct ConstantSpec {
+    pub name: &'static str,
+    pub bounds: [f32; 2],
+    pub wrap_mode: &'static str,
+}
+
+impl ConstantSpec {
+    pub const fn new(name: &'static str, min: f32, max: f32, wrap_mode: &'static str) -> Self {
+        Self {
+            name,
+            bounds: [min, max],
+            wrap_mode,
+        }
+    }
+}
+
+const fn c(name: &'static str, min: f32, max: f32, wrap_mode: &'static str) -> ConstantSpec {
+    ConstantSpec::new(name, min, max, wrap_mode)
+}
+
+// Given the current values of the constants and the values of the children, in
+// order. This must match the op's case in include/interpreter.glsl.
+pub type Evaluate = fn(&[f32], &[f32], &EvalContext) -> f32;
+
+// Where an op's children are evaluated, given its constants and where and how it
+// is evaluated. This must match the op's case in include/interpreter.glsl.
+pub type Remap = fn(&[f32], &EvalContext) -> [f32; 2];
+
+// Everything there is to know about an op. Generation, encoding, display and the
+// CPU evaluator all work from this table, so a new op only needs a row here and a
+// case in the interpreter.
+pub struct OpDescriptor {
+    pub opcode: usize,
+    pub name: &'static str,
+    // The relative chance of picking this op, among the leaves or among the rest.
+    pub rate: f32,
+    pub constants: &'static [ConstantSpec],
+    pub children: &'static [&'static str],
+    pub evaluate: Evaluate,
+    // The body of the op's case in the interpreter, for ops registered from outside
+    // stampede; the built in ops are written out in include/interpreter.glsl.
+    pub shader: Option<&'static str>,
+    // For ops that change the plane under their child rather than its value. Such
+    // an op is encoded as two instructions: its own, ahead of its child, which
+    // saves the position and moves it, and RESTORE_OPCODE after the child, which
+    // puts it back. Its own case must save the position with save_position and
+    // end in continue, since it leaves nothing on the stack; see polar in
+    // include/interpreter.glsl.
+    pub remap: Option<Remap>,
+}
+
+impl OpDescriptor {
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    pub fn find(opcode: usize) -> Option<&'static OpDescriptor> {
+        REGISTRY
+            .read()
+            .expect("registry lock")
+            .get(opcode)
+            .cloned()
+            .flatten()
+    }
+}
+
+// The opcode is the low byte of an instruction and zero is padding.
+const OPCODE_LIMIT: usize = 256;
+// Ends the child of an op with a remap, restoring the position from before it;
+// see OpDescriptor::remap. No op may take it.
+pub const RESTORE_OPCODE: usize = OPCODE_LIMIT - 1;
+// The interpreter takes an op's wrap modes from a single byte.
+const MAX_CONSTANTS: usize = 8;
+// The opcodes with a case in include/plugin_ops.glsl, as the interpreter was
+// compiled; build.rs collects them.
+const COMPILED_PLUGIN_OPCODES: &[usize] = &include!("../target/plugin_ops.cases");
+
+lazy_static! {
+    // Indexed by opcode.
+    static ref REGISTRY: RwLock<Vec<Option<&'static OpDescriptor>>> = {
+        let mut ops = vec![None; OPCODE_LIMIT];
+        for op in &BUILTIN_OPS {
+            ops[op.opcode] = Some(op);
+        }
+        RwLock::new(ops)
+    };
+}
+
+// Add an op from outside stampede. This must happen at startup, before any trees
+// are built, so that every tree is made from the same set of ops.
+//
+// The interpreter only knows the ops it was compiled with, so a registered op's
+// shader snippet has to be built into it first, and an op without its case is
+// turned away; see plugin_cases. The snippet is a GLSL block in the same style
+// as the built in cases: it takes its constants in order with pop_const(coff),
+// finds its children at stack[stack_offset - N] for N children, and leaves its
+// result in the first child's slot, or at stack[stack_offset] for a leaf.
+pub fn register(op: OpDescriptor) -> Fallible<()> {
+    if op.opcode == 0 || op.opcode >= RESTORE_OPCODE {
+        bail!(
+            Invalid,
+            "opcode {} for {} is out of range",
+            op.opcode,
+            op.name
+        );
+    }
+    if op.constants.len() > MAX_CONSTANTS {
+        bail!(
+            Invalid,
+            "{} has {} constants; at most {} are allowed",
+            op.name,
+            op.constants.len(),
+            MAX_CONSTANTS
+        );
+    }
+    for spec in op.constants {
+        WrapMode::from_name(spec.wrap_mode)?;
+        if !(spec.bounds[0] <= spec.bounds[1]) {
+            bail!(
+                Invalid,
+                "{}'s {} has bounds {:?}",
+                op.name,
+                spec.name,
+                spec.bounds
+            );
+        }
+    }
+    if !(op.rate >= 0f32) {
+        bail!(
+            Invalid,
+            "{} has rate {}; rates cannot be negative",
+            op.name,
+            op.rate
+        );
+    }
+    if op.shader.is_none() {
+        bail!(Invalid, "{} has no shader", op.name);
+    }
+    if !COMPILED_PLUGIN_OPCODES.contains(&op.opcode) {
+        bail!(
+            Invalid,
+            "the interpreter has no case for {}'s opcode {}; write plugin_cases \
+             to include/plugin_ops.glsl and rebuild",
+            op.name,
+            op.opcode
+        );
+    }
+    if op.remap.is_some() && op.children.len() != 1 {
+        bail!(
+            Invalid,
+            "{} remaps the plane, so it must have one child",
+            op.name
+        );
+    }
+    let mut registry = REGISTRY.write().expect("registry lock");
+    if let Some(existing) = registry[op.opcode] {
+        bail!(
+            Invalid,
+            "opcode {} for {} is already taken by {}",
+            op.opcode,
+            op.name,
+            existing.name
+        );
+    }
+    registry[op.opcode] = Some(Box::leak(Box::new(op)));
+    Ok(())
+}
+
+// Change how often generation picks an op; e.g. to bring in an op that depends on
+// something optional only while that is running. Like register, this must happen
+// before any trees are built.
+pub fn set_rate(name: &str, rate: f32) -> Fallible<()> {
+    if !(rate >= 0f32) {
+        bail!(
+            Invalid,
+            "{} cannot have rate {}; rates cannot be negative",
+            name,
+            rate
+        );
+    }
+    let mut registry = REGISTRY.write().expect("registry lock");
+    let slot = match registry
+        .iter_mut()
+        .find(|op| op.map(|op| op.name == name).unwrap_or(false))
+    {
+        Some(slot) => slot,
+        None => bail!(Invalid, "no op named {}", name),
+    };
+    let op = slot.expect("a registered op");
+    *slot = Some(Box::leak(Box::new(OpDescriptor { rate, ..*op })));
+    Ok(())
+}
+
+// Every op, built in or registered, in opcode order.
+pub fn registered() -> Vec<&'static OpDescriptor> {
+    REGISTRY
+        .read()
+        .expect("registry lock")
+        .iter()
+        .filter_map(|op| *op)
+        .collect()
+}
+
+// The interpreter cases for ops to be registered, to be written to
+// include/plugin_ops.glsl and built into the interpreter before registering them.
+pub fn plugin_cases(ops: &[OpDescriptor]) -> String {
+    ops.iter()
+        .filter_map(|op| {
+            op.shader.map(|shader| {
+                format!(
+                    "case {}: // {}\n{{\n{}\n}}\nbreak;\n",
+                    op.opcode, op.name, shader
+                )
+            })
+        })
+        .collect()
+}
+
+pub fn opcode_name(opcode: usize) -> &'static str {
+    OpDescriptor::find(opcode)
+        .map(|op| op.name)
+        .unwrap_or("unknown")
+}
+
+#[rustfmt::skip]
+static BUILTIN_OPS: [OpDescriptor; 23] = [
+    // Leaves
+    OpDescriptor { opcode: 1, name: "const", rate: 0.01, children: &[], evaluate: eval_const,
+        constants: &[c("value", -1., 1., "m")], shader: None, remap: None },
+    OpDescriptor { opcode: 2, name: "ellipse", rate: 2.0, children: &[], evaluate: eval_ellipse,
+        constants: &[c("p0x", -1., 1., "m"), c("p0y", -0.8, 0.8, "m"), c("p1x", -1., 1., "m"), c("p1y", -0.8, 0.8, "m"), c("size", 0.1, 1., "m"), c("sharp", 1., 100., "m")], shader: None, remap: None },
+    OpDescriptor { opcode: 3, name: "flower", rate: 4.0, children: &[], evaluate: eval_flower,
+        constants: &[c("x", -1., 1., "m"), c("y", -0.8, 0.8, "m"), c("angle", 0., 2. * PI, "r"), c("size", 0., 2.5, "m"), c("ratio", 0., 1., "m"), c("n_points", 3., 25., "f"), c("sharpness", 2., 10., "m")], shader: None, remap: None },
+    OpDescriptor { opcode: 4, name: "linear gradient", rate: 1.0, children: &[], evaluate: eval_linear_gradient,
+        constants: &[c("p0x", -1., 1., "m"), c("p0y", -0.8, 0.8, "m"), c("p1x", -1., 1., "m"), c("p1y", -0.8, 0.8, "m"), c("sharp", 2., 20., "m")], shader: None, remap: None },
+    OpDescriptor { opcode: 5, name: "radial gradient", rate: 2.0, children: &[], evaluate: eval_radial_gradient,
+        constants: &[c("p0x", -1., 1., "m"), c("p0y", -0.8, 0.8, "m"), c("p1x", -1., 1., "m"), c("p1y", -0.8, 0.8, "m"), c("angle", 0., 2. * PI, "r")], shader: None, remap: None },
+    OpDescriptor { opcode: 6, name: "polar theta", rate: 2.0, children: &[], evaluate: eval_polar_theta,
+        constants: &[c("x", -1., 1., "m"), c("y", -0.8, 0.8, "m"), c("angle", 0., 2. * PI, "r")], shader: None, remap: None },
+    OpDescriptor { opcode: 7, name: "mouse", rate: 0.5, children: &[], evaluate: eval_mouse,
+        constants: &[c("size", 0.1, 1.5, "m"), c("sharp", 1., 10., "m")], shader: None, remap: None },
+    // Only generated while a reaction-diffusion simulation is running; see set_rate.
+    // The CPU has no simulation, so it sees an empty one.
+    OpDescriptor { opcode: 20, name: "reaction", rate: 0.0, children: &[], evaluate: |_, _, _| -1.,
+        constants: &[c("scale", 0.25, 4., "m")], shader: None, remap: None },
+    // A ball in (x, y, depth). Flat pictures are the slice at zero depth, so this is
+    // only generated for volumes; see set_rate.
+    OpDescriptor { opcode: 21, name: "sphere", rate: 0.0, children: &[], evaluate: eval_sphere,
+        constants: &[c("x", -1., 1., "m"), c("y", -0.8, 0.8, "m"), c("z", -1., 1., "m"), c("size", 0.1, 1., "m"),
+                     c("sharp", 1., 10., "m")], shader: None, remap: None },
+
+    // Operations
+    OpDescriptor { opcode: 8, name: "absolute", rate: 0.2, children: &["value"], evaluate: |_, v, _| v[0].abs(),
+        constants: &[], shader: None, remap: None },
+    OpDescriptor { opcode: 9, name: "invert", rate: 0.1, children: &["value"], evaluate: |_, v, _| -v[0],
+        constants: &[], shader: None, remap: None },
+    OpDescriptor { opcode: 10, name: "add", rate: 0.3, children: &["lhs", "rhs"], evaluate: |_, v, _| v[0] + v[1],
+        constants: &[], shader: None, remap: None },
+    OpDescriptor { opcode: 11, name: "subtract", rate: 0.3, children: &["lhs", "rhs"], evaluate: |_, v, _| v[0] - v[1],
+        constants: &[], shader: None, remap: None },
+    OpDescriptor { opcode: 12, name: "multiply", rate: 0.3, children: &["lhs", "rhs"], evaluate: |_, v, _| v[0] * v[1],
+        constants: &[], shader: None, remap: None },
+    OpDescriptor { opcode: 13, name: "divide", rate: 0.3, children: &["lhs", "rhs"], evaluate: |_, v, _| v[0] / v[1],
+        constants: &[], shader: None, remap: None },
+    OpDescriptor { opcode: 14, name: "modulus", rate: 0.5, children: &["lhs", "rhs"], evaluate: eval_modulus,
+        constants: &[], shader: None, remap: None },
+    OpDescriptor { opcode: 15, name: "exponentiate", rate: 0.5, children: &["lhs", "rhs"], evaluate: |_, v, _| v[0].powf(v[1]),
+        constants: &[], shader: None, remap: None },
+    OpDescriptor { opcode: 16, name: "sinc", rate: 0.0, children: &["input"], evaluate: eval_sinc,
+        constants: &[c("freq", -PI, PI, "r"), c("phase", -PI, PI, "r")], shader: None, remap: None },
+    OpDescriptor { opcode: 17, name: "sine", rate: 0.0, children: &["input"], evaluate: |c, v, _| (v[0] * c[0] + c[1]).sin(),
+        constants: &[c("freq", -PI, PI, "r"), c("phase", -PI, PI, "r")], shader: None, remap: None },
+    OpDescriptor { opcode: 18, name: "spiral", rate: 0.2, children: &["V"], evaluate: eval_spiral,
+        constants: &[c("x", -1., 1., "m"), c("y", -0.8, 0.8, "m"), c("n", 0., 10., "m"), c("b", -1., 1., "m")], shader: None, remap: None },
+    OpDescriptor { opcode: 19, name: "squircle", rate: 2.0, children: &["a", "b"], evaluate: eval_squircle,
+        constants: &[c("x", -1., 1., "m"), c("y", -0.8, 0.8, "m"), c("r", 0., 2., "m"), c("n", 0., 4., "m")], shader: None, remap: None },
+    // The child drawn with radius across and angle up, so that stripes and gradients
+    // come out as rings, rays and spirals around the center.
+    OpDescriptor { opcode: 22, name: "polar", rate: 0.3, children: &["input"], evaluate: |_, v, _| v[0],
+        constants: &[c("x", -1., 1., "m"), c("y", -0.8, 0.8, "m"), c("angle", 0., 2. * PI, "r"), c("scale", 0.5, 4., "m")], shader: None, remap: Some(remap_polar) },
+    // The child pushed about by a smooth noise field, so that hard edges waver like
+    // brush strokes. Moving the offset drifts the field through the picture.
+    OpDescriptor { opcode: 23, name: "warp", rate: 0.3, children: &["input"], evaluate: |_, v, _| v[0],
+        constants: &[c("amplitude", 0., 0.5, "m"), c("frequency", 0.5, 6., "m"), c("offset", -16., 16., "m")], shader: None, remap: Some(remap_warp) },
+];
+
+fn clamp(v: f32) -> f32 {
+    v.max(-1f32).min(1f32)
+}
+
+fn rotate(v: [f32; 2], angle: f32) -> [f32; 2] {
+    [
+        v[0] * angle.cos() - v[1] * angle.sin(),
+        v[0] * angle.sin() + v[1] * angle.cos(),
+    ]
+}
+
+fn eval_const(c: &[f32], _: &[f32], _: &EvalContext) -> f32 {
+    c[0]
+}
+
+fn eval_ellipse(c: &[f32], _: &[f32], ctx: &EvalContext) -> f32 {
+    let [px, py] = ctx.position;
+    let dist = (px - c[0]).hypot(py - c[1]) + (px - c[2]).hypot(py - c[3]);
+    clamp(c[4] - dist) * c[5]
+}
+
+fn eval_flower(c: &[f32], _: &[f32], ctx: &EvalContext) -> f32 {
+    let [px, py] = ctx.position;
+    let v0 = [px - c[0], py - c[1]];
+    let d = v0[0].hypot(v0[1]);
+    let v1 = rotate(v0, c[2]);
+    let theta = (v1[1].atan2(v1[0]) / PI + 1f32) / 2f32;
+    let expanded = theta * c[5].floor();
+    let offset = (expanded - expanded.floor()) * 2f32 - 1f32;
+    let inner = c[3] * c[4];
+    let r = (d - inner) * (1f32 / (c[3] - inner));
+    clamp(-(r - offset.abs())) * c[6]
+}
+
+fn eval_linear_gradient(c: &[f32], _: &[f32], ctx: &EvalContext) -> f32 {
+    let [px, py] = ctx.position;
+    let cross = (c[2] - c[0]) * (py - c[1]) - (c[3] - c[1]) * (px - c[0]);
+    let t = ((cross * c[4] + 1f32) / 2f32).max(0f32).min(1f32);
+    t * t * (3f32 - 2f32 * t) * 2f32 - 1f32
+}
+
+fn eval_radial_gradient(c: &[f32], _: &[f32], ctx: &EvalContext) -> f32 {
+    let [px, py] = ctx.position;
+    let v1 = rotate([px - c[0], py - c[1]], c[4]);
+    let len = (v1[0] / c[2]).hypot(v1[1] / c[3]);
+    clamp(-len * 2f32 / 2f32.sqrt() + 1f32)
+}
+
+fn eval_polar_theta(c: &[f32], _: &[f32], ctx: &EvalContext) -> f32 {
+    let [px, py] = ctx.position;
+    let v1 = rotate([px - c[0], py - c[1]], c[2]);
+    v1[1].atan2(v1[0]) / PI
+}
+
+fn eval_mouse(c: &[f32], _: &[f32], ctx: &EvalContext) -> f32 {
+    let [px, py] = ctx.position;
+    let dist = (px - ctx.mouse[0]).hypot(py - ctx.mouse[1]);
+    clamp((c[0] - dist) * c[1])
+}
+
+fn eval_sphere(c: &[f32], _: &[f32], ctx: &EvalContext) -> f32 {
+    let [px, py] = ctx.position;
+    let dist = ((px - c[0]).powi(2) + (py - c[1]).powi(2) + c[2].powi(2)).sqrt();
+    clamp((c[3] - dist) * c[4])
+}
+
+fn eval_modulus(_: &[f32], v: &[f32], _: &EvalContext) -> f32 {
+    // GLSL's mod takes the sign of the divisor.
+    v[0] - v[1] * (v[0] / v[1]).floor()
+}
+
+fn eval_sinc(c: &[f32], v: &[f32], _: &EvalContext) -> f32 {
+    let denom = v[0] * c[0] + c[1];
+    clamp(denom.sin() / denom)
+}
+
+fn eval_spiral(_: &[f32], v: &[f32], _: &EvalContext) -> f32 {
+    4f32 * (v[0].abs() - 0.5f32).abs() - 1f32
+}
+
+fn eval_squircle(c: &[f32], v: &[f32], ctx: &EvalContext) -> f32 {
+    let [px, py] = ctx.position;
+    let a = (px - c[0] - v[0]).abs();
+    let b = (py - c[1] - v[1]).abs();
+    clamp(-(a.powf(c[3]) + b.powf(c[3])) / c[2].powf(c[3]))
+}
+
+fn remap_polar(c: &[f32], ctx: &EvalContext) -> [f32; 2] {
+    let [px, py] = ctx.position;
+    let v1 = rotate([px - c[0], py - c[1]], c[2]);
+    [v1[0].hypot(v1[1]) * c[3] - 1f32, v1[1].atan2(v1[0]) / PI]
+}
+
+// How many octaves of noise warp sums, and how many for a draft. Must match
+// WARP_OCTAVES and DRAFT_WARP_OCTAVES in include/interpreter.glsl.
+const WARP_OCTAVES: u32 = 3;
+const DRAFT_WARP_OCTAVES: u32 = 1;
+
+// Integer hashing, so that the CPU and the interpreter see the same noise.
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+// In [-1,1], at the corners of the unit grid.
+fn lattice(cell: [i32; 2], seed: u32) -> f32 {
+    let h = hash(cell[0] as u32 ^ hash(cell[1] as u32 ^ hash(seed)));
+    (h >> 8) as f32 / 16_777_215f32 * 2f32 - 1f32
+}
+
+fn value_noise(p: [f32; 2], seed: u32) -> f32 {
+    let cell = [p[0].floor(), p[1].floor()];
+    let f = [p[0] - cell[0], p[1] - cell[1]];
+    let u = [
+        f[0] * f[0] * (3f32 - 2f32 * f[0]),
+        f[1] * f[1] * (3f32 - 2f32 * f[1]),
+    ];
+    let (x, y) = (cell[0] as i32, cell[1] as i32);
+    let mix = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    mix(
+        mix(lattice([x, y], seed), lattice([x + 1, y], seed), u[0]),
+        mix(
+            lattice([x, y + 1], seed),
+            lattice([x + 1, y + 1], seed),
+            u[0],
+        ),
+        u[1],
+    )
+}
+
+// Octaves of value noise, each at twice the frequency and half the weight of the
+// last, kept in [-1,1].
+fn fractal_noise(p: [f32; 2], seed: u32, octaves: u32) -> f32 {
+    let (mut sum, mut weight, mut total, mut scale) = (0f32, 1f32, 0f32, 1f32);
+    for _ in 0..octaves {
+        sum += weight * value_noise([p[0] * scale, p[1] * scale], seed);
+        total += weight;
+        weight *= 0.5f32;
+        scale *= 2f32;
+    }
+    sum / total
+}
+
+fn warp_octaves(draft: bool) -> u32 {
+    if draft {
+        DRAFT_WARP_OCTAVES
+    } else {
+        WARP_OCTAVES
+    }
+}
+
+fn remap_warp(c: &[f32], ctx: &EvalContext) -> [f32; 2] {
+    let [px, py] = ctx.position;
+    let q = [px * c[1] + c[2], py * c[1] + c[2]];
+    let octaves = warp_octaves(ctx.draft);
+    [
+        px + c[0] * fractal_noise(q, 0, octaves),
+        py + c[0] * fractal_noise(q, 1, octaves),
+    ]
+}
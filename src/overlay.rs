@@ -0,0 +1,279 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::Result;
+use std::sync::mpsc::Receiver;
+use winit::window::Window;
+
+// Lets the picture float over the desktop: see-through at opacity, in [0,1], and,
+// if click_through, with clicks going straight through to whatever is under it.
+// The window should already be undecorated and on top; this is the part that
+// winit cannot do.
+#[cfg(target_os = "linux")]
+pub fn float(window: &Window, opacity: f32, click_through: bool) -> Result<()> {
+    linux::float(window, opacity, click_through)
+}
+
+#[cfg(windows)]
+pub fn float(window: &Window, opacity: f32, click_through: bool) -> Result<()> {
+    windows::float(window, opacity, click_through)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn float(_window: &Window, _opacity: f32, _click_through: bool) -> Result<()> {
+    anyhow::bail!("don't know how to let clicks through a window on this platform")
+}
+
+// A window that clicks go through can never be focused, so none of its keys
+// reach it. Ctrl+alt+Q is taken for the whole desktop instead, to quit with.
+pub struct ExitHotkey {
+    pressed: Receiver<()>,
+}
+
+impl ExitHotkey {
+    pub const NAME: &'static str = "ctrl+alt+q";
+
+    #[cfg(target_os = "linux")]
+    pub fn listen() -> Result<Self> {
+        Ok(Self {
+            pressed: linux::listen_for_exit()?,
+        })
+    }
+
+    #[cfg(windows)]
+    pub fn listen() -> Result<Self> {
+        Ok(Self {
+            pressed: windows::listen_for_exit()?,
+        })
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    pub fn listen() -> Result<Self> {
+        anyhow::bail!("don't know how to take a key for the whole desktop on this platform")
+    }
+
+    // Whether it has been pressed since the last look.
+    pub fn pressed(&self) -> bool {
+        self.pressed.try_iter().count() > 0
+    }
+}
+
+// The compositor fades the window by _NET_WM_WINDOW_OPACITY, and an empty input
+// shape, through XFixes 2, leaves nothing of it to click on. Only X11 has either.
+#[cfg(target_os = "linux")]
+mod linux {
+    use anyhow::{anyhow, bail, Result};
+    use std::{
+        os::raw::{c_int, c_ulong},
+        ptr,
+        sync::mpsc::{self, Receiver},
+        thread,
+    };
+    use winit::{platform::unix::WindowExtUnix, window::Window};
+    use x11_dl::{
+        keysym::XK_q,
+        xfixes::XFixes,
+        xlib::{self, Xlib},
+    };
+
+    // From X11/extensions/shape.h.
+    const SHAPE_INPUT: i32 = 2;
+
+    pub fn float(window: &Window, opacity: f32, click_through: bool) -> Result<()> {
+        let (display, id) = match (window.xlib_display(), window.xlib_window()) {
+            (Some(display), Some(id)) => (display as *mut xlib::Display, id),
+            _ => bail!("the window is not on X11"),
+        };
+        let xlib = Xlib::open()?;
+        let xfixes = if click_through {
+            let xfixes = XFixes::open()?;
+            // Input shapes came with XFixes 2; without them, the calls below would
+            // be errors from the server rather than from us.
+            let (mut event_base, mut error_base) = (0, 0);
+            let (mut major, mut minor) = (2, 0);
+            unsafe {
+                if (xfixes.XFixesQueryExtension)(display, &mut event_base, &mut error_base) == 0 {
+                    bail!("the X server has no XFixes extension");
+                }
+                if (xfixes.XFixesQueryVersion)(display, &mut major, &mut minor) == 0 || major < 2 {
+                    bail!(
+                        "the X server has XFixes {}.{}, but input shapes need 2.0",
+                        major,
+                        minor
+                    );
+                }
+            }
+            Some(xfixes)
+        } else {
+            None
+        };
+        let opacity = (f64::from(opacity.max(0f32).min(1f32)) * f64::from(u32::MAX)) as c_ulong;
+        unsafe {
+            let atom = (xlib.XInternAtom)(
+                display,
+                b"_NET_WM_WINDOW_OPACITY\0".as_ptr() as *const _,
+                xlib::False,
+            );
+            (xlib.XChangeProperty)(
+                display,
+                id,
+                atom,
+                xlib::XA_CARDINAL,
+                32,
+                xlib::PropModeReplace,
+                &opacity as *const c_ulong as *const u8,
+                1,
+            );
+            if let Some(xfixes) = &xfixes {
+                let region = (xfixes.XFixesCreateRegion)(display, ptr::null_mut(), 0);
+                (xfixes.XFixesSetWindowShapeRegion)(display, id, SHAPE_INPUT, 0, 0, region);
+                (xfixes.XFixesDestroyRegion)(display, region);
+            }
+            (xlib.XFlush)(display);
+        }
+        Ok(())
+    }
+
+    // Grabs ctrl+alt+Q on the root window, over a connection of our own so that
+    // winit never sees the presses, and sends one for each.
+    pub fn listen_for_exit() -> Result<Receiver<()>> {
+        let (sender, pressed) = mpsc::channel();
+        let (ready, grabbed) = mpsc::channel();
+        thread::spawn(move || {
+            let grab = || -> Result<(Xlib, *mut xlib::Display)> {
+                let xlib = Xlib::open()?;
+                let display = unsafe { (xlib.XOpenDisplay)(ptr::null()) };
+                if display.is_null() {
+                    bail!("cannot open the X display");
+                }
+                unsafe {
+                    let root = (xlib.XDefaultRootWindow)(display);
+                    let keycode = (xlib.XKeysymToKeycode)(display, c_ulong::from(XK_q));
+                    // The grab is for exact modifiers, so also take it with caps
+                    // lock and num lock on.
+                    let modifiers = xlib::ControlMask | xlib::Mod1Mask;
+                    for &locks in &[
+                        0,
+                        xlib::LockMask,
+                        xlib::Mod2Mask,
+                        xlib::LockMask | xlib::Mod2Mask,
+                    ] {
+                        (xlib.XGrabKey)(
+                            display,
+                            c_int::from(keycode),
+                            modifiers | locks,
+                            root,
+                            xlib::False,
+                            xlib::GrabModeAsync,
+                            xlib::GrabModeAsync,
+                        );
+                    }
+                    (xlib.XSync)(display, xlib::False);
+                }
+                Ok((xlib, display))
+            };
+            let (xlib, display) = match grab() {
+                Ok(grabbed) => {
+                    ready.send(Ok(())).ok();
+                    grabbed
+                }
+                Err(e) => {
+                    ready.send(Err(e)).ok();
+                    return;
+                }
+            };
+            let mut event = xlib::XEvent { pad: [0; 24] };
+            loop {
+                unsafe { (xlib.XNextEvent)(display, &mut event) };
+                if event.get_type() == xlib::KeyPress && sender.send(()).is_err() {
+                    return;
+                }
+            }
+        });
+        grabbed
+            .recv()
+            .map_err(|_| anyhow!("the hotkey thread stopped"))??;
+        Ok(pressed)
+    }
+}
+
+// A layered window is faded as a whole, and a transparent one is skipped over when
+// finding what was clicked.
+#[cfg(windows)]
+mod windows {
+    use anyhow::{anyhow, bail, Result};
+    use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+    use std::{
+        mem, ptr,
+        sync::mpsc::{self, Receiver},
+        thread,
+    };
+    use winapi::{
+        shared::windef::HWND,
+        um::winuser::{
+            GetMessageW, GetWindowLongW, RegisterHotKey, SetLayeredWindowAttributes,
+            SetWindowLongW, GWL_EXSTYLE, LWA_ALPHA, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MSG,
+            WM_HOTKEY, WS_EX_LAYERED, WS_EX_TRANSPARENT,
+        },
+    };
+    use winit::window::Window;
+
+    pub fn float(window: &Window, opacity: f32, click_through: bool) -> Result<()> {
+        let hwnd = match window.raw_window_handle() {
+            RawWindowHandle::Windows(handle) => handle.hwnd as HWND,
+            _ => bail!("the window has no HWND"),
+        };
+        let alpha = (opacity.max(0f32).min(1f32) * 255f32) as u8;
+        let mut add = WS_EX_LAYERED;
+        if click_through {
+            add |= WS_EX_TRANSPARENT;
+        }
+        unsafe {
+            let style = GetWindowLongW(hwnd, GWL_EXSTYLE) as u32;
+            SetWindowLongW(hwnd, GWL_EXSTYLE, (style | add) as i32);
+            if SetLayeredWindowAttributes(hwnd, 0, alpha, LWA_ALPHA) == 0 {
+                bail!("SetLayeredWindowAttributes failed");
+            }
+        }
+        Ok(())
+    }
+
+    // A hotkey belongs to the thread that registers it, which then has to wait on
+    // its messages.
+    pub fn listen_for_exit() -> Result<Receiver<()>> {
+        let (sender, pressed) = mpsc::channel();
+        let (ready, registered) = mpsc::channel();
+        thread::spawn(move || {
+            let id = 1;
+            let modifiers = (MOD_CONTROL | MOD_ALT | MOD_NOREPEAT) as u32;
+            if unsafe { RegisterHotKey(ptr::null_mut(), id, modifiers, u32::from(b'Q')) } == 0 {
+                ready
+                    .send(Err(anyhow!("ctrl+alt+Q is taken by something else")))
+                    .ok();
+                return;
+            }
+            ready.send(Ok(())).ok();
+            let mut message: MSG = unsafe { mem::zeroed() };
+            while unsafe { GetMessageW(&mut message, ptr::null_mut(), 0, 0) } > 0 {
+                if message.message == WM_HOTKEY && sender.send(()).is_err() {
+                    return;
+                }
+            }
+        });
+        registered
+            .recv()
+            .map_err(|_| anyhow!("the hotkey thread stopped"))??;
+        Ok(pressed)
+    }
+}
@@ -0,0 +1,333 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::Fallible;
+use gpu::GPU;
+use rand::prelude::*;
+use std::mem;
+use wgpu;
+use zerocopy::{AsBytes, FromBytes};
+
+const PARTICLE_COUNT: u32 = 16384;
+
+// Must match the workgroup size in particles.comp.glsl.
+const WORKGROUP_SIZE: u32 = 64;
+
+// Must match the Configuration block in the particle shaders.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
+struct ParticleConfiguration {
+    dt: f32,
+    // In screens per second, at the steepest.
+    speed: f32,
+    lifetime: f32,
+    time: f32,
+}
+
+impl ParticleConfiguration {
+    fn buffer_size() -> wgpu::BufferAddress {
+        mem::size_of::<Self>() as wgpu::BufferAddress
+    }
+}
+
+// Must match Particle in particles.comp.glsl. Positions are in texture
+// coordinates; the seed is where the particle goes when it respawns.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
+struct Particle {
+    position: [f32; 2],
+    age: f32,
+    seed: f32,
+}
+
+// Points that drift uphill on one of the layers and are drawn over the picture,
+// to show the shape of a field that may otherwise sit still.
+pub struct Particles {
+    config: ParticleConfiguration,
+    config_buffer: wgpu::Buffer,
+    particle_buffer: wgpu::Buffer,
+    update_pipeline: wgpu::ComputePipeline,
+    // One for each slot of the layer that steers the particles.
+    update_bind_groups: Vec<wgpu::BindGroup>,
+    draw_pipeline: wgpu::RenderPipeline,
+    draw_bind_group: wgpu::BindGroup,
+    last_time: Option<f32>,
+}
+
+impl Particles {
+    pub fn new(gpu: &GPU, field_views: &[&wgpu::TextureView]) -> Fallible<Self> {
+        let config = ParticleConfiguration {
+            dt: 0f32,
+            speed: 0.1,
+            lifetime: 4f32,
+            time: 0f32,
+        };
+        let config_buffer = gpu
+            .device()
+            .create_buffer_mapped(1, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST)
+            .fill_from_slice(&[config]);
+
+        // Start everywhere at once, but at different ages so that they do not all
+        // respawn together.
+        let mut rng = StdRng::seed_from_u64(0);
+        let particles = (0..PARTICLE_COUNT)
+            .map(|_| Particle {
+                position: [rng.gen(), rng.gen()],
+                age: rng.gen_range(0f32, config.lifetime),
+                seed: rng.gen(),
+            })
+            .collect::<Vec<_>>();
+        let particle_buffer = gpu
+            .device()
+            .create_buffer_mapped(
+                particles.len(),
+                wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::VERTEX,
+            )
+            .fill_from_slice(&particles);
+
+        let field_sampler = gpu.device().create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0f32,
+            lod_max_clamp: 0f32,
+            compare_function: wgpu::CompareFunction::Never,
+        });
+        let update_layout =
+            gpu.device()
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    bindings: &[
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 0,
+                            visibility: wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 1,
+                            visibility: wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::StorageBuffer {
+                                dynamic: false,
+                                readonly: false,
+                            },
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 2,
+                            visibility: wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::SampledTexture {
+                                multisampled: false,
+                                dimension: wgpu::TextureViewDimension::D2,
+                            },
+                        },
+                        wgpu::BindGroupLayoutBinding {
+                            binding: 3,
+                            visibility: wgpu::ShaderStage::COMPUTE,
+                            ty: wgpu::BindingType::Sampler,
+                        },
+                    ],
+                });
+        let update_shader =
+            gpu.create_shader_module(include_bytes!("../target/particles.comp.spirv"))?;
+        let update_pipeline =
+            gpu.device()
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    layout: &gpu
+                        .device()
+                        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            bind_group_layouts: &[&update_layout],
+                        }),
+                    compute_stage: wgpu::ProgrammableStageDescriptor {
+                        module: &update_shader,
+                        entry_point: "main",
+                    },
+                });
+        let update_bind_groups = field_views
+            .iter()
+            .map(|field_view| {
+                gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &update_layout,
+                    bindings: &[
+                        wgpu::Binding {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: &config_buffer,
+                                range: 0..ParticleConfiguration::buffer_size(),
+                            },
+                        },
+                        wgpu::Binding {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: &particle_buffer,
+                                range: 0..Self::particle_buffer_size(),
+                            },
+                        },
+                        wgpu::Binding {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(field_view),
+                        },
+                        wgpu::Binding {
+                            binding: 3,
+                            resource: wgpu::BindingResource::Sampler(&field_sampler),
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let draw_layout = gpu
+            .device()
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[wgpu::BindGroupLayoutBinding {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                }],
+            });
+        let draw_bind_group = gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &draw_layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &config_buffer,
+                    range: 0..ParticleConfiguration::buffer_size(),
+                },
+            }],
+        });
+        let vert_shader =
+            gpu.create_shader_module(include_bytes!("../target/particles.vert.spirv"))?;
+        let frag_shader =
+            gpu.create_shader_module(include_bytes!("../target/particles.frag.spirv"))?;
+        let draw_pipeline = gpu
+            .device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[&draw_layout],
+                    }),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &vert_shader,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &frag_shader,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::None,
+                    depth_bias: 0,
+                    depth_bias_slope_scale: 0.0,
+                    depth_bias_clamp: 0.0,
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::PointList,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: GPU::texture_format(),
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: GPU::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_read_mask: 0,
+                    stencil_write_mask: 0,
+                }),
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<Particle>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float,
+                            offset: 8,
+                            shader_location: 1,
+                        },
+                    ],
+                }],
+                sample_count: gpu.sample_count(),
+                sample_mask: !0,
+                alpha_to_coverage_enabled: false,
+            });
+
+        Ok(Self {
+            config,
+            config_buffer,
+            particle_buffer,
+            update_pipeline,
+            update_bind_groups,
+            draw_pipeline,
+            draw_bind_group,
+            last_time: None,
+        })
+    }
+
+    fn particle_buffer_size() -> wgpu::BufferAddress {
+        (mem::size_of::<Particle>() * PARTICLE_COUNT as usize) as wgpu::BufferAddress
+    }
+
+    // Move the particles along the field as it is in slot, by however much time
+    // has passed on the tree's clock. A new tree restarts its clock, which is not
+    // a reason to jump.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        slot: usize,
+        time: f32,
+    ) {
+        let dt = self
+            .last_time
+            .map(|last| (time - last).max(0f32).min(0.1))
+            .unwrap_or(0f32);
+        self.last_time = Some(time);
+        self.config.dt = dt;
+        self.config.time = time;
+        let upload_buffer = device
+            .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&[self.config]);
+        encoder.copy_buffer_to_buffer(
+            &upload_buffer,
+            0,
+            &self.config_buffer,
+            0,
+            ParticleConfiguration::buffer_size(),
+        );
+        let mut cpass = encoder.begin_compute_pass();
+        cpass.set_pipeline(&self.update_pipeline);
+        cpass.set_bind_group(0, &self.update_bind_groups[slot], &[]);
+        cpass.dispatch(PARTICLE_COUNT / WORKGROUP_SIZE, 1, 1);
+    }
+
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
+        rpass.set_pipeline(&self.draw_pipeline);
+        rpass.set_bind_group(0, &self.draw_bind_group, &[]);
+        rpass.set_vertex_buffers(0, &[(&self.particle_buffer, 0)]);
+        rpass.draw(0..PARTICLE_COUNT, 0..1);
+    }
+}
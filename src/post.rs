@@ -0,0 +1,863 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::Fallible;
+use gpu::{Frame, GPU};
+use serde::{Deserialize, Serialize};
+use std::{f32::consts::PI, mem};
+use wgpu;
+use zerocopy::{AsBytes, FromBytes};
+
+// Must match the PassConfiguration block in include/post.glsl. What the params
+// mean is up to each pass.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
+struct PassConfiguration {
+    screen_size: [f32; 2],
+    time: f32,
+    _pad: f32,
+    params: [[f32; 4]; 2],
+}
+
+// Must match local_size_x in shaders/pixel_sort.comp.glsl.
+const PIXEL_SORT_GROUP: u32 = 64;
+
+// Maps the light of the finished picture onto a curve that rolls off toward
+// white instead of clipping, after brightening or darkening it by some stops.
+// First in a chain, it sets the exposure that the rest of the passes see.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMap {
+    pub exposure: f32,
+    // How bright the light has to be, after the exposure, to come out white.
+    pub white: f32,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        Self {
+            exposure: 0f32,
+            white: 2f32,
+        }
+    }
+}
+
+// Inks the edges of the finished picture, where its brightness changes sharply.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Outline {
+    // How far apart, in pixels, the edge detector looks.
+    pub thickness: f32,
+    // How much of the ink to lay over an edge, in [0,1].
+    pub blend: f32,
+    // sRGB.
+    pub ink: [f32; 3],
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            thickness: 1f32,
+            blend: 1f32,
+            ink: [0f32; 3],
+        }
+    }
+}
+
+// Sorts each run of bright pixels along the rows, or down the columns, of the
+// finished picture by brightness, for a glitched look. The threshold for a bright
+// pixel can swing up and down over time, so that the runs grow and shrink.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PixelSort {
+    pub vertical: bool,
+    // In [0,1].
+    pub threshold: f32,
+    // How far the threshold swings either way, and how many seconds a full swing
+    // takes.
+    pub swing: f32,
+    pub period: f32,
+}
+
+impl Default for PixelSort {
+    fn default() -> Self {
+        Self {
+            vertical: false,
+            threshold: 0.5,
+            swing: 0f32,
+            period: 10f32,
+        }
+    }
+}
+
+impl PixelSort {
+    pub fn threshold_at(&self, time: f32) -> f32 {
+        let phase = if self.period > 0f32 {
+            (time / self.period * 2f32 * PI).sin()
+        } else {
+            0f32
+        };
+        (self.threshold + self.swing * phase).max(0f32).min(1f32)
+    }
+}
+
+// A glow that spills out of the brightest parts of the picture.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bloom {
+    // How bright, in [0,1], a pixel has to be to glow.
+    pub threshold: f32,
+    // How far, in pixels, the glow spreads.
+    pub radius: f32,
+    // How much of the glow to add.
+    pub intensity: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 0.7,
+            radius: 8f32,
+            intensity: 0.5,
+        }
+    }
+}
+
+// Film grain: noise over the picture that changes every frame.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Grain {
+    // How far the noise moves each pixel, in [0,1].
+    pub amount: f32,
+    // How big, in pixels, each grain is.
+    pub size: f32,
+}
+
+impl Default for Grain {
+    fn default() -> Self {
+        Self {
+            amount: 0.1,
+            size: 1f32,
+        }
+    }
+}
+
+// Makes the finished picture look as if it were on an old tube: bulged glass,
+// dark lines between the rows, columns of colored phosphor, and dim corners. Each
+// is in [0,1], where 0 leaves the picture be.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Crt {
+    pub curvature: f32,
+    pub scanlines: f32,
+    pub mask: f32,
+    pub vignette: f32,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Self {
+            curvature: 0.5,
+            scanlines: 0.5,
+            mask: 0.5,
+            vignette: 0.5,
+        }
+    }
+}
+
+impl Crt {
+    pub fn clamped(self) -> Self {
+        let clamp = |v: f32| v.max(0f32).min(1f32);
+        Self {
+            curvature: clamp(self.curvature),
+            scanlines: clamp(self.scanlines),
+            mask: clamp(self.mask),
+            vignette: clamp(self.vignette),
+        }
+    }
+}
+
+// One pass over the finished picture, and how it is set up. Passes of the same
+// kind can appear more than once in a chain.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "pass", rename_all = "snake_case")]
+pub enum PostPass {
+    ToneMap(ToneMap),
+    PixelSort(PixelSort),
+    Outline(Outline),
+    Bloom(Bloom),
+    Grain(Grain),
+    Crt(Crt),
+}
+
+impl PostPass {
+    fn is_same_kind(&self, other: &PostPass) -> bool {
+        mem::discriminant(self) == mem::discriminant(other)
+    }
+
+    // Where a pass goes when it is turned on and the chain has none of its kind: the
+    // tone map first, so that the rest work from the exposed picture, then the
+    // glitch, so that the rest see it, and the tube last, so that it shows it all.
+    fn rank(&self) -> usize {
+        match self {
+            PostPass::ToneMap(_) => 0,
+            PostPass::PixelSort(_) => 1,
+            PostPass::Outline(_) => 2,
+            PostPass::Bloom(_) => 3,
+            PostPass::Grain(_) => 4,
+            PostPass::Crt(_) => 5,
+        }
+    }
+
+    // Whether the pass sorts the picture in a compute pass rather than drawing it.
+    fn is_compute(&self) -> bool {
+        match self {
+            PostPass::PixelSort(_) => true,
+            _ => false,
+        }
+    }
+
+    fn params(&self, time: f32) -> [[f32; 4]; 2] {
+        match self {
+            PostPass::ToneMap(tone_map) => [
+                [tone_map.exposure, tone_map.white.max(1e-3), 0f32, 0f32],
+                [0f32; 4],
+            ],
+            PostPass::PixelSort(pixel_sort) => [
+                [
+                    pixel_sort.threshold_at(time),
+                    pixel_sort.vertical as u32 as f32,
+                    0f32,
+                    0f32,
+                ],
+                [0f32; 4],
+            ],
+            PostPass::Outline(outline) => {
+                let [r, g, b] = outline.ink;
+                [
+                    [
+                        outline.thickness.max(0f32),
+                        outline.blend.max(0f32).min(1f32),
+                        0f32,
+                        0f32,
+                    ],
+                    [r, g, b, 1f32],
+                ]
+            }
+            PostPass::Bloom(bloom) => [
+                [
+                    bloom.threshold.max(0f32).min(1f32),
+                    bloom.radius.max(0f32),
+                    bloom.intensity.max(0f32),
+                    0f32,
+                ],
+                [0f32; 4],
+            ],
+            PostPass::Grain(grain) => [
+                [
+                    grain.amount.max(0f32).min(1f32),
+                    grain.size.max(1f32),
+                    0f32,
+                    0f32,
+                ],
+                [0f32; 4],
+            ],
+            PostPass::Crt(crt) => {
+                let crt = crt.clamped();
+                [
+                    [crt.curvature, crt.scanlines, crt.mask, crt.vignette],
+                    [0f32; 4],
+                ]
+            }
+        }
+    }
+}
+
+// What each step of the chain does, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Pass(PostPass),
+    // Only to get the last compute pass onto the screen.
+    Copy,
+}
+
+impl Step {
+    fn params(&self, time: f32) -> [[f32; 4]; 2] {
+        match self {
+            Step::Pass(pass) => pass.params(time),
+            Step::Copy => [[0f32; 4]; 2],
+        }
+    }
+}
+
+// A pass in the chain, which can be turned off without losing its place.
+#[derive(Clone, Copy, Debug)]
+struct Link {
+    pass: PostPass,
+    enabled: bool,
+}
+
+pub struct PostUpload {
+    passes: Vec<wgpu::Buffer>,
+}
+
+// A render pass draws either into the next texture of the chain or, last, onto
+// the screen, which needs a pipeline for each.
+struct DrawPipelines {
+    to_texture: wgpu::RenderPipeline,
+    to_screen: wgpu::RenderPipeline,
+}
+
+// Passes over the whole finished picture, after tone mapping, for looks that need
+// to see more than one pixel of it at a time. While any pass is on, the display
+// draws into a composite texture instead of the screen. Each pass then works from
+// the picture so far into one of a pair of textures, turn and turn about, and the
+// last draws onto the screen:
+//
+// The passes come from a chain, which can be described in json as a list of
+// PostPass.
+//
+//   let upload = post.encode_upload_buffers(&gpu, tree.time());
+//   let mut frame = gpu.begin_frame()?;
+//   post.upload(&upload, &mut frame);
+//   display.draw(&mut frame.begin_render_pass_into(post.composite_view()));
+//   post.compute(&mut frame);
+//   post.draw(&mut frame.begin_render_pass());
+//   frame.finish();
+pub struct PostProcess {
+    draw_layout: wgpu::BindGroupLayout,
+    sort_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // One for each step, since every step may be configured differently.
+    buffers: Vec<wgpu::Buffer>,
+    tone_map_pipelines: DrawPipelines,
+    outline_pipelines: DrawPipelines,
+    bloom_pipelines: DrawPipelines,
+    grain_pipelines: DrawPipelines,
+    crt_pipelines: DrawPipelines,
+    copy_pipelines: DrawPipelines,
+    sort_pipeline: wgpu::ComputePipeline,
+    targets: Targets,
+
+    chain: Vec<Link>,
+}
+
+impl PostProcess {
+    fn buffer_size() -> wgpu::BufferAddress {
+        mem::size_of::<PassConfiguration>() as wgpu::BufferAddress
+    }
+
+    pub fn new(gpu: &GPU) -> Fallible<Self> {
+        let vert_shader = gpu.create_shader_module(include_bytes!("../target/post.vert.spirv"))?;
+        let tone_map_shader =
+            gpu.create_shader_module(include_bytes!("../target/tone_map.frag.spirv"))?;
+        let outline_shader =
+            gpu.create_shader_module(include_bytes!("../target/outline.frag.spirv"))?;
+        let bloom_shader =
+            gpu.create_shader_module(include_bytes!("../target/bloom.frag.spirv"))?;
+        let grain_shader =
+            gpu.create_shader_module(include_bytes!("../target/grain.frag.spirv"))?;
+        let crt_shader = gpu.create_shader_module(include_bytes!("../target/crt.frag.spirv"))?;
+        let copy_shader =
+            gpu.create_shader_module(include_bytes!("../target/post_copy.frag.spirv"))?;
+        let sort_shader =
+            gpu.create_shader_module(include_bytes!("../target/pixel_sort.comp.spirv"))?;
+        let source_bindings = |visibility| {
+            vec![
+                wgpu::BindGroupLayoutBinding {
+                    binding: 0,
+                    visibility,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 2,
+                    visibility,
+                    ty: wgpu::BindingType::Sampler,
+                },
+            ]
+        };
+        let draw_layout = gpu
+            .device()
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &source_bindings(wgpu::ShaderStage::FRAGMENT),
+            });
+        let mut sort_bindings = source_bindings(wgpu::ShaderStage::COMPUTE);
+        sort_bindings.push(wgpu::BindGroupLayoutBinding {
+            binding: 3,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        });
+        let sort_layout = gpu
+            .device()
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &sort_bindings,
+            });
+        let sampler = gpu.device().create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0f32,
+            lod_max_clamp: 0f32,
+            compare_function: wgpu::CompareFunction::Never,
+        });
+        let draw_pipelines = |frag_shader| DrawPipelines {
+            to_texture: Self::create_draw_pipeline(
+                gpu,
+                &draw_layout,
+                &vert_shader,
+                frag_shader,
+                false,
+            ),
+            to_screen: Self::create_draw_pipeline(
+                gpu,
+                &draw_layout,
+                &vert_shader,
+                frag_shader,
+                true,
+            ),
+        };
+        let tone_map_pipelines = draw_pipelines(&tone_map_shader);
+        let outline_pipelines = draw_pipelines(&outline_shader);
+        let bloom_pipelines = draw_pipelines(&bloom_shader);
+        let grain_pipelines = draw_pipelines(&grain_shader);
+        let crt_pipelines = draw_pipelines(&crt_shader);
+        let copy_pipelines = draw_pipelines(&copy_shader);
+        let sort_pipeline =
+            gpu.device()
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    layout: &gpu
+                        .device()
+                        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            bind_group_layouts: &[&sort_layout],
+                        }),
+                    compute_stage: wgpu::ProgrammableStageDescriptor {
+                        module: &sort_shader,
+                        entry_point: "main",
+                    },
+                });
+        let buffers = Vec::new();
+        let targets = Targets::new(gpu, &draw_layout, &sort_layout, &buffers, &sampler);
+        Ok(Self {
+            draw_layout,
+            sort_layout,
+            sampler,
+            buffers,
+            tone_map_pipelines,
+            outline_pipelines,
+            bloom_pipelines,
+            grain_pipelines,
+            crt_pipelines,
+            copy_pipelines,
+            sort_pipeline,
+            targets,
+            chain: Vec::new(),
+        })
+    }
+
+    // Every render pass draws the whole window from a texture, either into the next
+    // texture in the chain or onto the screen, where it has to match the frame's
+    // attachments.
+    fn create_draw_pipeline(
+        gpu: &GPU,
+        layout: &wgpu::BindGroupLayout,
+        vert_shader: &wgpu::ShaderModule,
+        frag_shader: &wgpu::ShaderModule,
+        to_screen: bool,
+    ) -> wgpu::RenderPipeline {
+        gpu.device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[layout],
+                    }),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: vert_shader,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: frag_shader,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::None,
+                    depth_bias: 0,
+                    depth_bias_slope_scale: 0.0,
+                    depth_bias_clamp: 0.0,
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: if to_screen {
+                        GPU::texture_format()
+                    } else {
+                        Targets::FORMAT
+                    },
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                depth_stencil_state: if to_screen {
+                    Some(wgpu::DepthStencilStateDescriptor {
+                        format: GPU::DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::Always,
+                        stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                        stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                        stencil_read_mask: 0,
+                        stencil_write_mask: 0,
+                    })
+                } else {
+                    None
+                },
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[],
+                sample_count: if to_screen { gpu.sample_count() } else { 1 },
+                sample_mask: !0,
+                alpha_to_coverage_enabled: false,
+            })
+    }
+
+    // The textures have to match the window.
+    pub fn note_resize(&mut self, gpu: &GPU) {
+        self.targets = Targets::new(
+            gpu,
+            &self.draw_layout,
+            &self.sort_layout,
+            &self.buffers,
+            &self.sampler,
+        );
+    }
+
+    // Whether the display has to draw into the composite this frame.
+    pub fn is_active(&self) -> bool {
+        self.chain.iter().any(|link| link.enabled)
+    }
+
+    // Replaces every pass, all turned on.
+    pub fn set_chain(&mut self, chain: Vec<PostPass>) {
+        self.chain = chain
+            .into_iter()
+            .map(|pass| Link {
+                pass,
+                enabled: true,
+            })
+            .collect();
+    }
+
+    // Whether any pass of this kind is on.
+    pub fn is_enabled(&self, pass: &PostPass) -> bool {
+        self.chain
+            .iter()
+            .any(|link| link.enabled && link.pass.is_same_kind(pass))
+    }
+
+    // Turns every pass of this kind on or off. Those in the chain keep their
+    // settings; if there are none, this one is put in where it ranks.
+    pub fn set_enabled(&mut self, pass: PostPass, enabled: bool) {
+        let mut found = false;
+        for link in self.chain.iter_mut() {
+            if link.pass.is_same_kind(&pass) {
+                link.enabled = enabled;
+                found = true;
+            }
+        }
+        if !found && enabled {
+            let at = self
+                .chain
+                .iter()
+                .position(|link| link.pass.rank() > pass.rank())
+                .unwrap_or_else(|| self.chain.len());
+            self.chain.insert(at, Link { pass, enabled });
+        }
+    }
+
+    pub fn toggle(&mut self, pass: PostPass) {
+        let enabled = self.is_enabled(&pass);
+        self.set_enabled(pass, !enabled);
+    }
+
+    // The first CRT pass that is on, if any, to carry over between sessions.
+    pub fn crt(&self) -> Option<Crt> {
+        self.chain.iter().find_map(|link| match link.pass {
+            PostPass::Crt(crt) if link.enabled => Some(crt),
+            _ => None,
+        })
+    }
+
+    pub fn composite_view(&self) -> &wgpu::TextureView {
+        &self.targets.composite
+    }
+
+    // The passes that are on, in the order they run.
+    fn steps(&self) -> Vec<Step> {
+        let mut steps = self
+            .chain
+            .iter()
+            .filter(|link| link.enabled)
+            .map(|link| Step::Pass(link.pass))
+            .collect::<Vec<_>>();
+        match steps.last() {
+            Some(Step::Pass(pass)) if !pass.is_compute() => {}
+            _ => steps.push(Step::Copy),
+        }
+        steps
+    }
+
+    // Time is in seconds, for the passes that animate. Each step needs its own
+    // uniforms and bindings, so a longer chain than before makes more.
+    pub fn encode_upload_buffers(&mut self, gpu: &GPU, time: f32) -> PostUpload {
+        let steps = self.steps();
+        if steps.len() > self.buffers.len() {
+            while self.buffers.len() < steps.len() {
+                self.buffers
+                    .push(gpu.device().create_buffer(&wgpu::BufferDescriptor {
+                        size: Self::buffer_size(),
+                        usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                    }));
+            }
+            self.note_resize(gpu);
+        }
+        let screen_size = [self.targets.size[0] as f32, self.targets.size[1] as f32];
+        PostUpload {
+            passes: steps
+                .iter()
+                .map(|step| {
+                    let configuration = PassConfiguration {
+                        screen_size,
+                        time,
+                        _pad: 0f32,
+                        params: step.params(time),
+                    };
+                    gpu.device()
+                        .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
+                        .fill_from_slice(&[configuration])
+                })
+                .collect(),
+        }
+    }
+
+    pub fn upload(&self, upload: &PostUpload, frame: &mut Frame) {
+        for (source, buffer) in upload.passes.iter().zip(&self.buffers) {
+            frame.copy_buffer_to_buffer(source, 0, buffer, 0, Self::buffer_size());
+        }
+    }
+
+    // None for the compute passes, which have no pipelines to draw with.
+    fn draw_pipelines(&self, step: Step) -> Option<&DrawPipelines> {
+        match step {
+            Step::Pass(PostPass::ToneMap(_)) => Some(&self.tone_map_pipelines),
+            Step::Pass(PostPass::Outline(_)) => Some(&self.outline_pipelines),
+            Step::Pass(PostPass::Bloom(_)) => Some(&self.bloom_pipelines),
+            Step::Pass(PostPass::Grain(_)) => Some(&self.grain_pipelines),
+            Step::Pass(PostPass::Crt(_)) => Some(&self.crt_pipelines),
+            Step::Copy => Some(&self.copy_pipelines),
+            Step::Pass(PostPass::PixelSort(_)) => None,
+        }
+    }
+
+    // Every step but the last, after the display has drawn into the composite; each
+    // leaves its result in the texture that the next step reads.
+    pub fn compute(&self, frame: &mut Frame) {
+        let steps = self.steps();
+        for (i, step) in steps.iter().enumerate().take(steps.len() - 1) {
+            match step {
+                Step::Pass(PostPass::PixelSort(pixel_sort)) => {
+                    let lines = if pixel_sort.vertical {
+                        self.targets.size[0]
+                    } else {
+                        self.targets.size[1]
+                    };
+                    let mut cpass = frame.begin_compute_pass();
+                    cpass.set_pipeline(&self.sort_pipeline);
+                    cpass.set_bind_group(0, &self.targets.sort_bind_groups[i], &[]);
+                    cpass.dispatch((lines + PIXEL_SORT_GROUP - 1) / PIXEL_SORT_GROUP, 1, 1);
+                }
+                _ => {
+                    let pipelines = match self.draw_pipelines(*step) {
+                        Some(pipelines) => pipelines,
+                        None => continue,
+                    };
+                    let mut rpass =
+                        frame
+                            .encoder_mut()
+                            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                                    attachment: self.targets.target(i),
+                                    resolve_target: None,
+                                    load_op: wgpu::LoadOp::Clear,
+                                    store_op: wgpu::StoreOp::Store,
+                                    clear_color: wgpu::Color::BLACK,
+                                }],
+                                depth_stencil_attachment: None,
+                            });
+                    rpass.set_pipeline(&pipelines.to_texture);
+                    rpass.set_bind_group(0, &self.targets.draw_bind_groups[i], &[]);
+                    rpass.draw(0..4, 0..1);
+                }
+            }
+        }
+    }
+
+    // The last step, onto the screen.
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
+        if !self.is_active() {
+            return;
+        }
+        let steps = self.steps();
+        let last = steps.len() - 1;
+        // steps always ends with a step that draws.
+        if let Some(pipelines) = self.draw_pipelines(steps[last]) {
+            rpass.set_pipeline(&pipelines.to_screen);
+            rpass.set_bind_group(0, &self.targets.draw_bind_groups[last], &[]);
+            rpass.draw(0..4, 0..1);
+        }
+    }
+}
+
+// The window-sized textures that the passes work through, and the bindings for
+// each step: the first reads the composite, and each after it reads what the one
+// before wrote, into ping for even steps and pong for odd.
+struct Targets {
+    size: [u32; 2],
+    composite: wgpu::TextureView,
+    ping: wgpu::TextureView,
+    pong: wgpu::TextureView,
+    draw_bind_groups: Vec<wgpu::BindGroup>,
+    sort_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Targets {
+    // Storage textures cannot be bgra.
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    fn new(
+        gpu: &GPU,
+        draw_layout: &wgpu::BindGroupLayout,
+        sort_layout: &wgpu::BindGroupLayout,
+        buffers: &[wgpu::Buffer],
+        sampler: &wgpu::Sampler,
+    ) -> Self {
+        let physical = gpu.physical_size();
+        let size = [
+            (physical.width.floor() as u32).max(1),
+            (physical.height.floor() as u32).max(1),
+        ];
+        let create_texture = |format, usage| {
+            gpu.device()
+                .create_texture(&wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width: size[0],
+                        height: size[1],
+                        depth: 1,
+                    },
+                    array_layer_count: 1,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage,
+                })
+                .create_default_view()
+        };
+        let composite = create_texture(
+            GPU::texture_format(),
+            wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        );
+        let create_target = || {
+            create_texture(
+                Self::FORMAT,
+                wgpu::TextureUsage::OUTPUT_ATTACHMENT
+                    | wgpu::TextureUsage::STORAGE
+                    | wgpu::TextureUsage::SAMPLED,
+            )
+        };
+        let ping = create_target();
+        let pong = create_target();
+        let mut targets = Self {
+            size,
+            composite,
+            ping,
+            pong,
+            draw_bind_groups: Vec::with_capacity(buffers.len()),
+            sort_bind_groups: Vec::with_capacity(buffers.len()),
+        };
+        for (i, buffer) in buffers.iter().enumerate() {
+            let source_bindings = || {
+                vec![
+                    wgpu::Binding {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer {
+                            buffer,
+                            range: 0..PostProcess::buffer_size(),
+                        },
+                    },
+                    wgpu::Binding {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(targets.source(i)),
+                    },
+                    wgpu::Binding {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ]
+            };
+            let draw_bind_group = gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: draw_layout,
+                bindings: &source_bindings(),
+            });
+            let mut sort_bindings = source_bindings();
+            sort_bindings.push(wgpu::Binding {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(targets.target(i)),
+            });
+            let sort_bind_group = gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: sort_layout,
+                bindings: &sort_bindings,
+            });
+            targets.draw_bind_groups.push(draw_bind_group);
+            targets.sort_bind_groups.push(sort_bind_group);
+        }
+        targets
+    }
+
+    fn source(&self, step: usize) -> &wgpu::TextureView {
+        if step == 0 {
+            &self.composite
+        } else {
+            self.target(step - 1)
+        }
+    }
+
+    fn target(&self, step: usize) -> &wgpu::TextureView {
+        if step % 2 == 0 {
+            &self.ping
+        } else {
+            &self.pong
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use std::time::{Duration, Instant};
+
+// Plugging in or unplugging does not need noticing within the frame.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// At or below this percentage, the battery is low.
+const LOW_BATTERY: u32 = 20;
+
+// Where the machine is drawing its power from, as far as throttling cares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerState {
+    Mains,
+    Battery,
+    LowBattery,
+}
+
+impl PowerState {
+    // Anything we cannot tell the power of is assumed to be plugged in, which on
+    // platforms other than Linux and Windows is everything.
+    #[cfg(target_os = "linux")]
+    pub fn current() -> Self {
+        linux::current().unwrap_or(PowerState::Mains)
+    }
+
+    #[cfg(windows)]
+    pub fn current() -> Self {
+        windows::current().unwrap_or(PowerState::Mains)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    pub fn current() -> Self {
+        PowerState::Mains
+    }
+
+    // How many times smaller, on each side, to compute the layers.
+    pub fn resolution_divisor(self) -> u32 {
+        match self {
+            PowerState::Mains => 1,
+            PowerState::Battery => 2,
+            PowerState::LowBattery => 4,
+        }
+    }
+
+    // The most frames a second to draw, if there is a limit.
+    pub fn frame_cap(self) -> Option<f32> {
+        match self {
+            PowerState::Mains => None,
+            PowerState::Battery => Some(30f32),
+            PowerState::LowBattery => Some(15f32),
+        }
+    }
+
+    // The extent to compute at.
+    pub fn scale_extent(self, extent: wgpu::Extent3d) -> wgpu::Extent3d {
+        let scale = |side: u32| (side / self.resolution_divisor()).max(1);
+        wgpu::Extent3d {
+            width: scale(extent.width),
+            height: scale(extent.height),
+            depth: extent.depth,
+        }
+    }
+}
+
+// Checks on the power every so often, so that quality can drop when the laptop is
+// unplugged and come back when it is plugged in again.
+pub struct PowerMonitor {
+    state: PowerState,
+    checked: Instant,
+}
+
+impl PowerMonitor {
+    pub fn new() -> Self {
+        Self {
+            state: PowerState::current(),
+            checked: Instant::now(),
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    // The new state, if it has changed since the last look.
+    pub fn poll(&mut self) -> Option<PowerState> {
+        if self.checked.elapsed() < CHECK_INTERVAL {
+            return None;
+        }
+        self.checked = Instant::now();
+        let state = PowerState::current();
+        if state == self.state {
+            return None;
+        }
+        self.state = state;
+        Some(state)
+    }
+}
+
+// Every supply is listed under /sys/class/power_supply. Any online mains adapter
+// means we are plugged in, whatever the batteries are doing.
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{PowerState, LOW_BATTERY};
+    use std::{fs, path::Path};
+
+    fn read(supply: &Path, name: &str) -> Option<String> {
+        fs::read_to_string(supply.join(name))
+            .ok()
+            .map(|value| value.trim().to_owned())
+    }
+
+    pub fn current() -> Option<PowerState> {
+        let mut state = None;
+        for entry in fs::read_dir("/sys/class/power_supply").ok()? {
+            let supply = entry.ok()?.path();
+            match read(&supply, "type").as_deref() {
+                Some("Mains") if read(&supply, "online").as_deref() == Some("1") => {
+                    return Some(PowerState::Mains);
+                }
+                Some("Battery") if read(&supply, "status").as_deref() == Some("Discharging") => {
+                    let capacity = read(&supply, "capacity").and_then(|c| c.parse::<u32>().ok());
+                    let low = capacity.map(|c| c <= LOW_BATTERY).unwrap_or(false);
+                    if low || state.is_none() {
+                        state = Some(if low {
+                            PowerState::LowBattery
+                        } else {
+                            PowerState::Battery
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        state
+    }
+}
+
+// The AC line status is 1 when plugged in, 0 when not and 255 when Windows cannot
+// tell; the battery percentage is 255 when it is unknown.
+#[cfg(windows)]
+mod windows {
+    use super::{PowerState, LOW_BATTERY};
+    use std::mem;
+    use winapi::um::winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    pub fn current() -> Option<PowerState> {
+        let mut status: SYSTEM_POWER_STATUS = unsafe { mem::zeroed() };
+        if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+            return None;
+        }
+        match status.ACLineStatus {
+            1 => Some(PowerState::Mains),
+            0 if u32::from(status.BatteryLifePercent) <= LOW_BATTERY => {
+                Some(PowerState::LowBattery)
+            }
+            0 => Some(PowerState::Battery),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,315 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::error::Fallible;
+use gpu::GPU;
+use serde::Deserialize;
+use std::{fs, mem, path::Path};
+use wgpu;
+use zerocopy::{AsBytes, FromBytes};
+
+// How finely each output's warp is tessellated, whatever its number of points.
+const MESH_SIDE: usize = 32;
+
+// Fades an output out toward each edge where it overlaps its neighbours, in
+// fractions of its own width or height. The ramp is made linear in light by
+// raising it to 1/gamma, the gamma of the projector.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct EdgeBlend {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+    pub gamma: f32,
+}
+
+impl Default for EdgeBlend {
+    fn default() -> Self {
+        Self {
+            left: 0f32,
+            right: 0f32,
+            top: 0f32,
+            bottom: 0f32,
+            gamma: 2.2,
+        }
+    }
+}
+
+// Where the output's grid of control points lands in its viewport: columns by
+// rows of points, in rows from the top, with each from [0,0] at the top left to
+// [1,1] at the bottom right. Between the points the image is stretched bilinearly.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Warp {
+    pub columns: usize,
+    pub rows: usize,
+    pub points: Vec<[f32; 2]>,
+}
+
+impl Warp {
+    fn identity() -> Self {
+        Self {
+            columns: 2,
+            rows: 2,
+            points: vec![[0f32, 0f32], [1f32, 0f32], [0f32, 1f32], [1f32, 1f32]],
+        }
+    }
+
+    fn check(&self) -> Fallible<()> {
+        if self.columns < 2 || self.rows < 2 || self.points.len() != self.columns * self.rows {
+            bail!(
+                Invalid,
+                "a warp needs at least 2x2 points and exactly columns x rows of them; {}x{} has {}",
+                self.columns,
+                self.rows,
+                self.points.len()
+            );
+        }
+        Ok(())
+    }
+
+    // The warped place of [u, v] in the viewport.
+    fn at(&self, u: f32, v: f32) -> [f32; 2] {
+        let x = u * (self.columns - 1) as f32;
+        let y = v * (self.rows - 1) as f32;
+        let x0 = (x.floor() as usize).min(self.columns - 2);
+        let y0 = (y.floor() as usize).min(self.rows - 2);
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let p = |cx: usize, cy: usize| self.points[cy * self.columns + cx];
+        let lerp =
+            |a: [f32; 2], b: [f32; 2], t: f32| [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+        lerp(
+            lerp(p(x0, y0), p(x0 + 1, y0), fx),
+            lerp(p(x0, y0 + 1), p(x0 + 1, y0 + 1), fx),
+            fy,
+        )
+    }
+}
+
+// One projector: the part of the window it is plugged into and the part of the
+// picture it shows, both as [x, y, width, height] from the top left in [0,1].
+#[derive(Clone, Debug, Deserialize)]
+pub struct Output {
+    pub viewport: [f32; 4],
+    pub source: [f32; 4],
+    #[serde(default)]
+    pub blend: EdgeBlend,
+    #[serde(default)]
+    pub warp: Option<Warp>,
+}
+
+// Describes how one window, stretched over several projectors, is cut up so that
+// their pictures meet on the surface, e.g. for two side by side:
+//
+//   {"outputs": [
+//     {"viewport": [0, 0, 0.5, 1], "source": [0, 0, 0.55, 1], "blend": {"right": 0.18}},
+//     {"viewport": [0.5, 0, 0.5, 1], "source": [0.45, 0, 0.55, 1], "blend": {"left": 0.18}}
+//   ]}
+#[derive(Clone, Debug, Deserialize)]
+pub struct Calibration {
+    pub outputs: Vec<Output>,
+}
+
+impl Calibration {
+    pub fn load(path: &Path) -> Fallible<Self> {
+        let calibration: Self = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if calibration.outputs.is_empty() {
+            bail!(Invalid, "a calibration needs at least one output");
+        }
+        for output in &calibration.outputs {
+            if let Some(warp) = &output.warp {
+                warp.check()?;
+            }
+        }
+        Ok(calibration)
+    }
+}
+
+// Must match the inputs of projector.vert.glsl.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, Copy, Clone, Debug, Default)]
+struct ProjectorVertex {
+    position: [f32; 2],
+    tex_coord: [f32; 2],
+    output_coord: [f32; 2],
+    blend_edges: [f32; 4],
+    gamma: f32,
+}
+
+fn output_mesh(output: &Output) -> Vec<ProjectorVertex> {
+    let identity = Warp::identity();
+    let warp = output.warp.as_ref().unwrap_or(&identity);
+    let [vx, vy, vw, vh] = output.viewport;
+    let [sx, sy, sw, sh] = output.source;
+    let blend = output.blend;
+    let vertex = |i: usize, j: usize| {
+        let (u, v) = (i as f32 / MESH_SIDE as f32, j as f32 / MESH_SIDE as f32);
+        let [wx, wy] = warp.at(u, v);
+        ProjectorVertex {
+            // Window space has y up; everything in the calibration has it down.
+            position: [(vx + wx * vw) * 2f32 - 1f32, 1f32 - (vy + wy * vh) * 2f32],
+            tex_coord: [sx + u * sw, 1f32 - (sy + v * sh)],
+            output_coord: [u, v],
+            blend_edges: [blend.left, blend.right, blend.top, blend.bottom],
+            gamma: blend.gamma,
+        }
+    };
+    let mut vertices = Vec::with_capacity(MESH_SIDE * MESH_SIDE * 6);
+    for j in 0..MESH_SIDE {
+        for i in 0..MESH_SIDE {
+            vertices.extend_from_slice(&[
+                vertex(i, j),
+                vertex(i, j + 1),
+                vertex(i + 1, j),
+                vertex(i + 1, j),
+                vertex(i, j + 1),
+                vertex(i + 1, j + 1),
+            ]);
+        }
+    }
+    vertices
+}
+
+// Draws the layers once for each output of a calibration, warped and blended, in
+// place of the single full screen quad.
+pub struct Projection {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+}
+
+impl Projection {
+    // The layout is that of the display's bind groups; the projector shaders read
+    // the layers in the same way as draw.frag.glsl.
+    pub fn new(
+        gpu: &GPU,
+        layout: &wgpu::BindGroupLayout,
+        calibration: &Calibration,
+    ) -> Fallible<Self> {
+        let vertices = calibration
+            .outputs
+            .iter()
+            .flat_map(output_mesh)
+            .collect::<Vec<_>>();
+        let vertex_buffer = gpu
+            .device()
+            .create_buffer_mapped(vertices.len(), wgpu::BufferUsage::VERTEX)
+            .fill_from_slice(&vertices);
+
+        let vert_shader =
+            gpu.create_shader_module(include_bytes!("../target/projector.vert.spirv"))?;
+        let frag_shader =
+            gpu.create_shader_module(include_bytes!("../target/projector.frag.spirv"))?;
+        let attribute = |shader_location, format, offset| wgpu::VertexAttributeDescriptor {
+            format,
+            offset,
+            shader_location,
+        };
+        let pipeline = gpu
+            .device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[layout],
+                    }),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &vert_shader,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &frag_shader,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::None,
+                    depth_bias: 0,
+                    depth_bias_slope_scale: 0.0,
+                    depth_bias_clamp: 0.0,
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: GPU::texture_format(),
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: GPU::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_read_mask: 0,
+                    stencil_write_mask: 0,
+                }),
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<ProjectorVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        attribute(0, wgpu::VertexFormat::Float2, 0),
+                        attribute(1, wgpu::VertexFormat::Float2, 8),
+                        attribute(2, wgpu::VertexFormat::Float2, 16),
+                        attribute(3, wgpu::VertexFormat::Float4, 24),
+                        attribute(4, wgpu::VertexFormat::Float, 40),
+                    ],
+                }],
+                sample_count: gpu.sample_count(),
+                sample_mask: !0,
+                alpha_to_coverage_enabled: false,
+            });
+        Ok(Self {
+            pipeline,
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+        })
+    }
+
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass, bind_group: &wgpu::BindGroup) {
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.set_vertex_buffers(0, &[(&self.vertex_buffer, 0)]);
+        rpass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warps_pass_through_their_points() {
+        let warp = Warp {
+            columns: 3,
+            rows: 2,
+            points: vec![
+                [0.0, 0.1],
+                [0.5, 0.0],
+                [1.0, 0.1],
+                [0.1, 1.0],
+                [0.5, 0.9],
+                [0.9, 1.0],
+            ],
+        };
+        assert!(warp.check().is_ok());
+        for (k, point) in warp.points.iter().enumerate() {
+            let (i, j) = (k % 3, k / 3);
+            let at = warp.at(i as f32 / 2.0, j as f32);
+            assert!((at[0] - point[0]).abs() < 1e-6 && (at[1] - point[1]).abs() < 1e-6);
+        }
+        assert!(Warp { rows: 3, ..warp }.check().is_err());
+    }
+}
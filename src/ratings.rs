@@ -0,0 +1,128 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use stampede::{ops, tree::Tree};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+// How much of every op's score survives each new rating, so that old ratings fade
+// and taste can drift.
+const DECAY: f32 = 0.95;
+
+// How far a score moves an op's rate: by a factor of e per unit of score, within
+// the bounds below.
+const LEARNING_RATE: f32 = 2f32;
+const MIN_FACTOR: f32 = 0.25;
+const MAX_FACTOR: f32 = 4f32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rating {
+    Good,
+    Bad,
+}
+
+impl Rating {
+    fn sign(self) -> f32 {
+        match self {
+            Rating::Good => 1f32,
+            Rating::Bad => -1f32,
+        }
+    }
+}
+
+// What ratings have taught about each op, by name: positive for ops found more
+// often than their rate would have them in trees that were liked, negative for
+// those over-represented in trees that were not.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ratings {
+    scores: HashMap<String, f32>,
+    count: usize,
+}
+
+impl Ratings {
+    pub fn default_path() -> Result<PathBuf> {
+        let dir = dirs::data_dir()
+            .ok_or_else(|| anyhow!("no data directory on this platform"))?
+            .join("stampede");
+        fs::create_dir_all(&dir)?;
+        Ok(dir.join("ratings.json"))
+    }
+
+    // Nothing rated yet is no different from no file.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // Leaves and branches are picked separately, so an op's share of a tree is
+    // measured against the others of its kind.
+    pub fn rate(&mut self, tree: &Tree, rating: Rating) {
+        for score in self.scores.values_mut() {
+            *score *= DECAY;
+        }
+        let registered = ops::registered();
+        let found = tree.ops();
+        for leaf in &[true, false] {
+            let kind_rate = registered
+                .iter()
+                .filter(|op| op.is_leaf() == *leaf)
+                .map(|op| op.rate)
+                .sum::<f32>();
+            let kind_count = found.iter().filter(|op| op.is_leaf() == *leaf).count();
+            if kind_rate <= 0f32 || kind_count == 0 {
+                continue;
+            }
+            for op in registered.iter().filter(|op| op.is_leaf() == *leaf) {
+                let count = found.iter().filter(|o| o.opcode == op.opcode).count();
+                let excess = count as f32 / kind_count as f32 - op.rate / kind_rate;
+                *self.scores.entry(op.name.to_owned()).or_default() += rating.sign() * excess;
+            }
+        }
+        self.count += 1;
+    }
+
+    // Scale every op's rate by what has been learned. Like ops::set_rate, this has
+    // to happen before any trees are built. Ops that are left out, with a rate of
+    // zero, stay left out.
+    pub fn apply(&self) -> Result<()> {
+        for op in ops::registered() {
+            let score = match self.scores.get(op.name) {
+                Some(&score) if op.rate > 0f32 => score,
+                _ => continue,
+            };
+            let factor = (score * LEARNING_RATE)
+                .exp()
+                .max(MIN_FACTOR)
+                .min(MAX_FACTOR);
+            ops::set_rate(op.name, op.rate * factor)?;
+        }
+        Ok(())
+    }
+}
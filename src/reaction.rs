@@ -0,0 +1,243 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    compute::{self, Configuration, OffscreenLayer},
+    error::Fallible,
+    tree::{EncodedLayer, Tree},
+    workgroup::Interpreter,
+};
+use gpu::GPU;
+use rand::prelude::*;
+use std::mem;
+use wgpu;
+
+// The simulation covers the plane from -1 to 1 and repeats beyond.
+const STATE_EXTENT: wgpu::Extent3d = wgpu::Extent3d {
+    width: 512,
+    height: 512,
+    depth: 1,
+};
+
+// Even, so that the newest state always ends up in the first texture.
+const STEPS_PER_FRAME: usize = 8;
+
+// How many spots of v the simulation starts from.
+const SEED_SPOTS: usize = 40;
+
+// A Gray-Scott reaction-diffusion simulation that runs alongside the tree. Its
+// feed and kill rates come from the red and green layers of a tree of its own,
+// and the tree being shown can read it back through the reaction op.
+pub struct ReactionDiffusion {
+    // The control tree's red and green layers, encoded once; only the time moves.
+    control: Vec<EncodedLayer>,
+    control_config: Configuration,
+    control_config_buffer: wgpu::Buffer,
+    control_layers: Vec<OffscreenLayer>,
+    interpreter: Interpreter,
+    states: Vec<wgpu::Texture>,
+    state_view: wgpu::TextureView,
+    pipeline: wgpu::ComputePipeline,
+    // Reading from the first state into the second, and back.
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl ReactionDiffusion {
+    pub fn new(gpu: &mut GPU, control: Tree) -> Fallible<Self> {
+        let control = (0..2)
+            .map(|i| control.encode_layer(i))
+            .collect::<Fallible<Vec<_>>>()?;
+        let interpreter_layout = compute::create_layout(gpu);
+        let control_config = Configuration::new(STATE_EXTENT, 1f32);
+        let control_config_buffer = control_config.create_buffer(gpu.device());
+        let control_layers = (0..2)
+            .map(|_| {
+                OffscreenLayer::new(
+                    gpu,
+                    &interpreter_layout,
+                    &control_config_buffer,
+                    wgpu::TextureFormat::R32Float,
+                    STATE_EXTENT,
+                )
+            })
+            .collect::<Vec<_>>();
+        let interpreter = Interpreter::new(gpu, &interpreter_layout, [8, 8], false)?;
+
+        let states = (0..2)
+            .map(|_| {
+                gpu.device().create_texture(&wgpu::TextureDescriptor {
+                    size: STATE_EXTENT,
+                    array_layer_count: 1,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    usage: wgpu::TextureUsage::STORAGE
+                        | wgpu::TextureUsage::SAMPLED
+                        | wgpu::TextureUsage::COPY_DST,
+                })
+            })
+            .collect::<Vec<_>>();
+        let state_views = states
+            .iter()
+            .map(|state| state.create_default_view())
+            .collect::<Vec<_>>();
+        let control_views = control_layers
+            .iter()
+            .map(|layer| layer.texture().create_default_view())
+            .collect::<Vec<_>>();
+
+        let storage_binding = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        let layout = gpu
+            .device()
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    storage_binding(0),
+                    storage_binding(1),
+                    storage_binding(2),
+                    storage_binding(3),
+                ],
+            });
+        let module =
+            gpu.create_shader_module(include_bytes!("../target/reaction_diffusion.comp.spirv"))?;
+        let pipeline = gpu
+            .device()
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[&layout],
+                    }),
+                compute_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &module,
+                    entry_point: "main",
+                },
+            });
+        let bind_groups = (0..2)
+            .map(|from| {
+                gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &layout,
+                    bindings: &[
+                        wgpu::Binding {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&state_views[from]),
+                        },
+                        wgpu::Binding {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&state_views[1 - from]),
+                        },
+                        wgpu::Binding {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&control_views[0]),
+                        },
+                        wgpu::Binding {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(&control_views[1]),
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let simulation = Self {
+            control,
+            control_config,
+            control_config_buffer,
+            control_layers,
+            interpreter,
+            states,
+            state_view: state_views.into_iter().next().expect("a state"),
+            pipeline,
+            bind_groups,
+        };
+        simulation.seed(gpu);
+        Ok(simulation)
+    }
+
+    // Start from u everywhere, with a scattering of small squares of v to react.
+    fn seed(&self, gpu: &mut GPU) {
+        let (width, height) = (STATE_EXTENT.width as usize, STATE_EXTENT.height as usize);
+        let mut texels = vec![[1f32, 0f32, 0f32, 0f32]; width * height];
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..SEED_SPOTS {
+            let (x0, y0) = (rng.gen_range(0, width), rng.gen_range(0, height));
+            for y in y0..(y0 + 8).min(height) {
+                for x in x0..(x0 + 8).min(width) {
+                    texels[y * width + x] = [0.5, 0.25, 0f32, 0f32];
+                }
+            }
+        }
+        let upload = gpu
+            .device()
+            .create_buffer_mapped(texels.len(), wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&texels);
+        let mut encoder = gpu
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &upload,
+                offset: 0,
+                row_pitch: (width * mem::size_of::<[f32; 4]>()) as u32,
+                image_height: height as u32,
+            },
+            wgpu::TextureCopyView {
+                texture: &self.states[0],
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d {
+                    x: 0f32,
+                    y: 0f32,
+                    z: 0f32,
+                },
+            },
+            STATE_EXTENT,
+        );
+        gpu.queue_mut().submit(&[encoder.finish()]);
+    }
+
+    // The newest state, for the interpreter's reaction op.
+    pub fn state_view(&self) -> &wgpu::TextureView {
+        &self.state_view
+    }
+
+    // Advance the simulation by a frame's worth of steps, with feed and kill as
+    // the control tree has them at time.
+    pub fn step(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, time: f32) {
+        self.control_config.time = time;
+        self.control_config
+            .upload(device, encoder, &self.control_config_buffer);
+        for (layer, encoded) in self.control_layers.iter_mut().zip(&self.control) {
+            layer.update(encoded.clone(), device, encoder);
+        }
+        for layer in &self.control_layers {
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_pipeline(self.interpreter.pipeline());
+            cpass.set_bind_group(0, layer.bind_group(), &[]);
+            self.interpreter.dispatch(&mut cpass, STATE_EXTENT);
+        }
+        for step in 0..STEPS_PER_FRAME {
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &self.bind_groups[step % 2], &[]);
+            cpass.dispatch(STATE_EXTENT.width / 8, STATE_EXTENT.height / 8, 1);
+        }
+    }
+}
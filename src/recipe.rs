@@ -0,0 +1,166 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{bail, Result};
+use stampede::tree::Tree;
+use std::{convert::TryInto, fs, io::Write, path::Path};
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// Keywords of the text chunks that a recipe is written to.
+const SEED_KEYWORD: &str = "stampede:seed";
+const TREE_KEYWORD: &str = "stampede:tree";
+const SCENE_KEYWORD: &str = "stampede:scene";
+const TIME_KEYWORD: &str = "stampede:time";
+const VERSION_KEYWORD: &str = "stampede:version";
+
+// What a picture was made from. It is written into every PNG that stampede
+// exports, so that any picture carries its own recipe.
+#[derive(Debug, Default)]
+pub struct Recipe {
+    pub seed: Option<String>,
+    // As saved with ctrl+c, carrying the animation time of the picture.
+    pub tree: Option<String>,
+    // The description of a scene of several trees, with its animation time.
+    pub scene: Option<(String, f32)>,
+}
+
+impl Recipe {
+    // Must be called before any image data is written.
+    pub fn write_chunks<W: Write>(&self, writer: &mut png::Writer<W>) -> Result<()> {
+        let version = env!("CARGO_PKG_VERSION");
+        writer.write_chunk(
+            *b"tEXt",
+            &text("Software", &format!("stampede {}", version)),
+        )?;
+        writer.write_chunk(*b"tEXt", &text(VERSION_KEYWORD, version))?;
+        if let Some(seed) = &self.seed {
+            writer.write_chunk(*b"iTXt", &international_text(SEED_KEYWORD, seed))?;
+        }
+        if let Some(tree) = &self.tree {
+            writer.write_chunk(*b"iTXt", &international_text(TREE_KEYWORD, tree))?;
+        }
+        if let Some((scene, time)) = &self.scene {
+            writer.write_chunk(*b"iTXt", &international_text(SCENE_KEYWORD, scene))?;
+            writer.write_chunk(*b"tEXt", &text(TIME_KEYWORD, &time.to_string()))?;
+        }
+        Ok(())
+    }
+
+    // The recipe written into a PNG by write_chunks. Text that stampede did not
+    // write is ignored.
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(&PNG_SIGNATURE) {
+            bail!("not a PNG");
+        }
+        let mut recipe = Recipe::default();
+        let mut time = 0f32;
+        let mut rest = &bytes[PNG_SIGNATURE.len()..];
+        // Each chunk is a length, a type, the data and a CRC.
+        while rest.len() >= 12 {
+            let length = u32::from_be_bytes(rest[0..4].try_into()?) as usize;
+            if rest.len() < 12 + length {
+                bail!("the PNG is cut short");
+            }
+            let kind = &rest[4..8];
+            let data = &rest[8..8 + length];
+            let entry = match kind {
+                b"tEXt" => parse_text(data),
+                b"iTXt" => parse_international_text(data),
+                b"IEND" => break,
+                _ => None,
+            };
+            match entry {
+                Some((SEED_KEYWORD, value)) => recipe.seed = Some(value),
+                Some((TREE_KEYWORD, value)) => recipe.tree = Some(value),
+                Some((SCENE_KEYWORD, value)) => recipe.scene = Some((value, 0f32)),
+                Some((TIME_KEYWORD, value)) => time = value.parse().unwrap_or(0f32),
+                _ => {}
+            }
+            rest = &rest[12 + length..];
+        }
+        if let Some((_, scene_time)) = &mut recipe.scene {
+            *scene_time = time;
+        }
+        Ok(recipe)
+    }
+
+    // The tree that was pictured, as it was at the moment pictured.
+    pub fn tree(&self) -> Result<Tree> {
+        match (&self.tree, &self.scene) {
+            (Some(tree), _) => Ok(Tree::from_json(tree)?),
+            (None, Some(_)) => bail!("the picture is of a scene of several trees"),
+            (None, None) => bail!("the picture has no stampede tree in it"),
+        }
+    }
+}
+
+// The keywords we look for, so that a parsed chunk can be matched on.
+fn known_keyword(keyword: &[u8]) -> Option<&'static str> {
+    [
+        SEED_KEYWORD,
+        TREE_KEYWORD,
+        SCENE_KEYWORD,
+        TIME_KEYWORD,
+        VERSION_KEYWORD,
+    ]
+    .iter()
+    .find(|known| known.as_bytes() == keyword)
+    .cloned()
+}
+
+fn parse_text(data: &[u8]) -> Option<(&'static str, String)> {
+    let split = data.iter().position(|&b| b == 0)?;
+    let keyword = known_keyword(&data[..split])?;
+    // Latin-1 maps straight onto the first 256 code points.
+    let value = data[split + 1..].iter().map(|&b| b as char).collect();
+    Some((keyword, value))
+}
+
+// Only uncompressed text is read, which is all that write_chunks writes.
+fn parse_international_text(data: &[u8]) -> Option<(&'static str, String)> {
+    let split = data.iter().position(|&b| b == 0)?;
+    let keyword = known_keyword(&data[..split])?;
+    let rest = data.get(split + 1..)?;
+    if rest.len() < 2 || rest[0] != 0 {
+        return None;
+    }
+    // Skip the language tag and the translated keyword.
+    let mut text = &rest[2..];
+    for _ in 0..2 {
+        let end = text.iter().position(|&b| b == 0)?;
+        text = &text[end + 1..];
+    }
+    Some((keyword, String::from_utf8(text.to_vec()).ok()?))
+}
+
+// A tEXt chunk holds Latin-1, so it is only used for text that we know is ASCII.
+fn text(keyword: &str, value: &str) -> Vec<u8> {
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    data.extend_from_slice(value.as_bytes());
+    data
+}
+
+// An uncompressed iTXt chunk, with no language, for UTF-8.
+fn international_text(keyword: &str, value: &str) -> Vec<u8> {
+    let mut data = keyword.as_bytes().to_vec();
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(value.as_bytes());
+    data
+}
@@ -0,0 +1,80 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::view::Waypoint;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use stampede::tree::Tree;
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+// Everything that moved in one frame. Constants animate in closed form from the
+// tree's clock, so the tree itself is only stored when it was replaced or edited;
+// otherwise replay just advances it by dt.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub dt: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<Tree>,
+    pub view: Waypoint,
+    pub mouse_position: [f32; 2],
+}
+
+// Logs a run frame by frame, one json object per line, so that it can be played
+// back exactly; at another size or quality, if need be.
+pub struct Recorder {
+    file: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record(&mut self, frame: &RecordedFrame) -> Result<()> {
+        serde_json::to_writer(&mut self.file, frame)?;
+        self.file.write_all(b"\n")?;
+        Ok(())
+    }
+
+    // The event loop never returns, so this has to be called on the way out.
+    pub fn finish(&mut self) -> Result<()> {
+        Ok(self.file.flush()?)
+    }
+}
+
+pub struct Playback {
+    frames: VecDeque<RecordedFrame>,
+}
+
+impl Playback {
+    pub fn load(path: &Path) -> Result<Self> {
+        let frames = fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<VecDeque<_>, _>>()?;
+        Ok(Self { frames })
+    }
+
+    pub fn next_frame(&mut self) -> Option<RecordedFrame> {
+        self.frames.pop_front()
+    }
+}
@@ -0,0 +1,253 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    compute::{self, Configuration, OffscreenLayer, Quality},
+    error::Fallible,
+    tree::Tree,
+    workgroup::Interpreter,
+};
+use gpu::{ReadbackQueue, GPU};
+use wgpu;
+
+// A picture or a region of one with no texels has no aspect ratio, and the GPU
+// has no textures that small, so both are turned away before any work is done.
+pub fn check_size(size: [u32; 2]) -> Fallible<()> {
+    if size[0] == 0 || size[1] == 0 {
+        bail!(Invalid, "cannot render {}x{} texels", size[0], size[1]);
+    }
+    Ok(())
+}
+
+// Renders trees into memory rather than onto the screen, for callers that want
+// the pixels themselves.
+pub struct OffscreenRenderer {
+    layout: wgpu::BindGroupLayout,
+    interpreter: Interpreter,
+    quality: Quality,
+}
+
+impl OffscreenRenderer {
+    pub fn new(gpu: &GPU) -> Fallible<Self> {
+        let layout = compute::create_layout(gpu);
+        let interpreter = Interpreter::new(gpu, &layout, [8, 8], false)?;
+        Ok(Self {
+            layout,
+            interpreter,
+            quality: Quality::Full,
+        })
+    }
+
+    // Callers pick the size to render at, so a draft only changes what the ops do,
+    // which for now means warps sum fewer octaves of noise.
+    pub fn with_quality(mut self, quality: Quality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    // The tree at its current time, as rows from the top of the image with red,
+    // green and blue for each texel, all in [0,1].
+    pub fn render(
+        &self,
+        gpu: &mut GPU,
+        tree: &Tree,
+        width: u32,
+        height: u32,
+    ) -> Fallible<Vec<f32>> {
+        self.render_region(gpu, tree, [width, height], [0, 0], [width, height])
+    }
+
+    // A rectangle of a picture that is size in all, starting at origin from the
+    // top left, in the same form as render. Pictures too big for the GPU can be
+    // put together from these.
+    pub fn render_region(
+        &self,
+        gpu: &mut GPU,
+        tree: &Tree,
+        size: [u32; 2],
+        origin: [u32; 2],
+        region: [u32; 2],
+    ) -> Fallible<Vec<f32>> {
+        self.begin_region(gpu, tree, size, origin, region)?
+            .finish(gpu)
+    }
+
+    // As render_region, but only submits the work; the pixels come from finish.
+    // With a renderer per GPU, this lets several GPUs work at once.
+    pub fn begin_region(
+        &self,
+        gpu: &mut GPU,
+        tree: &Tree,
+        size: [u32; 2],
+        origin: [u32; 2],
+        region: [u32; 2],
+    ) -> Fallible<PendingRegion> {
+        check_size(size)?;
+        check_size(region)?;
+        let [width, height] = region;
+        let extent = wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        };
+        let whole = wgpu::Extent3d {
+            width: size[0],
+            height: size[1],
+            depth: 1,
+        };
+        let mut config = Configuration::new(whole, size[0] as f32 / size[1] as f32);
+        config.time = tree.time();
+        config.set_quality(self.quality);
+        // Texture rows run from the bottom of the picture.
+        config.texture_offsets = [
+            origin[0] as i32,
+            size[1] as i32 - origin[1] as i32 - height as i32,
+        ];
+        let config_buffer = config.create_buffer(gpu.device());
+        let mut readback = ReadbackQueue::new(gpu.device(), extent, 4, 3);
+        let mut layers = Vec::with_capacity(3);
+        for offset in 0..3 {
+            let mut layer = OffscreenLayer::new(
+                gpu,
+                &self.layout,
+                &config_buffer,
+                wgpu::TextureFormat::R32Float,
+                extent,
+            );
+            let mut encoder = gpu
+                .device()
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+            layer.update(tree.encode_layer(offset)?, gpu.device(), &mut encoder);
+            {
+                let mut cpass = encoder.begin_compute_pass();
+                cpass.set_pipeline(self.interpreter.pipeline());
+                cpass.set_bind_group(0, layer.bind_group(), &[]);
+                self.interpreter.dispatch(&mut cpass, extent);
+            }
+            if !readback.request(&mut encoder, layer.texture(), offset as u64) {
+                bail!(Gpu, "readback queue is busy");
+            }
+            gpu.queue_mut().submit(&[encoder.finish()]);
+            readback.submitted();
+            layers.push(layer);
+        }
+        Ok(PendingRegion {
+            _config_buffer: config_buffer,
+            _layers: layers,
+            readback,
+            width: width as usize,
+            height: height as usize,
+        })
+    }
+}
+
+// A region that is being rendered; it must be finished on the GPU that began it.
+pub struct PendingRegion {
+    // The GPU may still be using these.
+    _config_buffer: wgpu::Buffer,
+    _layers: Vec<OffscreenLayer>,
+    readback: ReadbackQueue,
+    width: usize,
+    height: usize,
+}
+
+impl PendingRegion {
+    // Waits for the GPU, then returns the pixels as render_region does.
+    pub fn finish(mut self, gpu: &mut GPU) -> Fallible<Vec<f32>> {
+        let (width, height) = (self.width, self.height);
+        let mut pixels = vec![0f32; width * height * 3];
+        let mut remaining = 3;
+        while remaining > 0 {
+            gpu.device().poll(true);
+            let results = self.readback.poll(gpu.device());
+            if results.is_empty() {
+                bail!(Gpu, "readback failed");
+            }
+            for result in results {
+                remaining -= 1;
+                let offset = result.tag as usize;
+                // The first texture row is the bottom of the picture.
+                for (i, b) in result.data.chunks(4).enumerate() {
+                    let row = height - 1 - i / width;
+                    pixels[(row * width + i % width) * 3 + offset] =
+                        f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                }
+            }
+        }
+        Ok(pixels)
+    }
+}
+
+// Lab, as the layers are read by include/color.glsl, to XYZ.
+fn lab_to_xyz(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let fy = (l + 16f32) / 116f32;
+    let fx = a / 500f32 + fy;
+    let fz = fy - b / 200f32;
+    let f = |t: f32| {
+        if t > 0.206_897 {
+            t * t * t
+        } else {
+            (t - 16f32 / 116f32) / 7.787
+        }
+    };
+    [95.047 * f(fx), 100.000 * f(fy), 108.883 * f(fz)]
+}
+
+fn xyz_to_srgb(c: [f32; 3]) -> [f32; 3] {
+    let [x, y, z] = [c[0] / 100f32, c[1] / 100f32, c[2] / 100f32];
+    let linear = [
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    ];
+    let gamma = |v: f32| {
+        if v > 0.003_130_8 {
+            1.055 * v.powf(1f32 / 2.4) - 0.055
+        } else {
+            12.92 * v
+        }
+    };
+    [gamma(linear[0]), gamma(linear[1]), gamma(linear[2])]
+}
+
+// The colors that the screen would show for rendered layers, as sRGB in [0,1] in
+// the same order. This must match include/color.glsl.
+pub fn layers_to_srgb(pixels: &[f32]) -> Vec<f32> {
+    pixels
+        .chunks(3)
+        .flat_map(|p| {
+            let l = 100f32 * p[0];
+            let a = 255f32 * p[1] - 128f32;
+            let b = 255f32 * p[2] - 128f32;
+            // lab2rgb scales its input once more; odd, but it is what is on screen.
+            let xyz = lab_to_xyz(
+                100f32 * l,
+                2f32 * 127f32 * (a - 0.5),
+                2f32 * 127f32 * (b - 0.5),
+            );
+            let rgb = xyz_to_srgb(xyz);
+            vec![
+                rgb[0].max(0f32).min(1f32),
+                rgb[1].max(0f32).min(1f32),
+                rgb[2].max(0f32).min(1f32),
+            ]
+        })
+        .collect()
+}
+
+pub fn srgb_to_8bit(rgb: &[f32]) -> Vec<u8> {
+    rgb.iter()
+        .map(|v| (v.max(0f32).min(1f32) * 255f32).round() as u8)
+        .collect()
+}
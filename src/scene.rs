@@ -0,0 +1,209 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::rng_from_seed;
+use anyhow::Result;
+use gpu::GPU;
+use serde::Deserialize;
+use stampede::{
+    render::{layers_to_srgb, OffscreenRenderer},
+    tree::Tree,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Where a tree in a scene comes from: a seed, or a file saved with ctrl+c. Files
+// are found relative to the scene.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TreeSource {
+    Seed(String),
+    File(PathBuf),
+}
+
+impl TreeSource {
+    fn load(&self, dir: &Path) -> Result<Tree> {
+        Ok(match self {
+            TreeSource::Seed(seed) => Tree::new(&mut rng_from_seed(seed))?,
+            TreeSource::File(path) => Tree::from_json(&fs::read_to_string(dir.join(path))?)?,
+        })
+    }
+}
+
+// How a layer's colors combine with everything below it, per channel in sRGB.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    Normal,
+    Add,
+    Multiply,
+    Screen,
+    Difference,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::Normal
+    }
+}
+
+impl BlendMode {
+    fn apply(self, below: f32, above: f32) -> f32 {
+        match self {
+            BlendMode::Normal => above,
+            BlendMode::Add => (below + above).min(1f32),
+            BlendMode::Multiply => below * above,
+            BlendMode::Screen => 1f32 - (1f32 - below) * (1f32 - above),
+            BlendMode::Difference => (below - above).abs(),
+        }
+    }
+}
+
+fn full_opacity() -> f32 {
+    1f32
+}
+
+#[derive(Debug, Deserialize)]
+struct LayerDescription {
+    tree: TreeSource,
+    #[serde(default)]
+    blend: BlendMode,
+    #[serde(default = "full_opacity")]
+    opacity: f32,
+    // Where the layer shows: the mask tree's red layer, in [0,1], times opacity.
+    #[serde(default)]
+    mask: Option<TreeSource>,
+}
+
+// Several trees drawn over one another, bottom first, e.g.:
+//
+//   {"layers": [
+//     {"tree": {"file": "sunset.json"}},
+//     {"tree": {"seed": "42"}, "blend": "screen", "mask": {"seed": "mask"}},
+//     {"tree": {"file": "petals.json"}, "blend": "multiply", "opacity": 0.5}
+//   ]}
+#[derive(Debug, Deserialize)]
+struct SceneDescription {
+    layers: Vec<LayerDescription>,
+}
+
+struct SceneLayer {
+    tree: Tree,
+    mask: Option<Tree>,
+    blend: BlendMode,
+    opacity: f32,
+}
+
+pub struct Scene {
+    layers: Vec<SceneLayer>,
+    // The description that the scene was loaded from, if any.
+    source: Option<String>,
+    time: f32,
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)?;
+        let description: SceneDescription = serde_json::from_str(&source)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let layers = description
+            .layers
+            .iter()
+            .map(|layer| -> Result<SceneLayer> {
+                Ok(SceneLayer {
+                    tree: layer.tree.load(dir)?,
+                    mask: layer.mask.as_ref().map(|mask| mask.load(dir)).transpose()?,
+                    blend: layer.blend,
+                    opacity: layer.opacity.max(0f32).min(1f32),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            layers,
+            source: Some(source),
+            time: 0f32,
+        })
+    }
+
+    // Just the one tree, as it would be drawn on its own.
+    pub fn single(tree: Tree) -> Self {
+        Self {
+            layers: vec![SceneLayer {
+                tree,
+                mask: None,
+                blend: BlendMode::Normal,
+                opacity: 1f32,
+            }],
+            source: None,
+            time: 0f32,
+        }
+    }
+
+    // The tree, if the scene is just the one.
+    pub fn tree(&self) -> Option<&Tree> {
+        match &self.layers[..] {
+            [layer] if layer.mask.is_none() => Some(&layer.tree),
+            _ => None,
+        }
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    // Seconds of animation since the scene was loaded.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    // Every tree, masks included, shares the same clock.
+    pub fn animate(&mut self, dt: f32) {
+        self.time += dt;
+        for layer in &mut self.layers {
+            layer.tree.animate(dt);
+            if let Some(mask) = &mut layer.mask {
+                mask.animate(dt);
+            }
+        }
+    }
+
+    // The composited picture as sRGB in [0,1], rows from the top.
+    pub fn render(
+        &self,
+        renderer: &OffscreenRenderer,
+        gpu: &mut GPU,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<f32>> {
+        let mut out = vec![0f32; (width * height * 3) as usize];
+        for layer in &self.layers {
+            let colors = layers_to_srgb(&renderer.render(gpu, &layer.tree, width, height)?);
+            let mask = match &layer.mask {
+                Some(mask) => Some(renderer.render(gpu, mask, width, height)?),
+                None => None,
+            };
+            for (i, (below, above)) in out.iter_mut().zip(&colors).enumerate() {
+                let alpha = layer.opacity
+                    * mask
+                        .as_ref()
+                        .map(|m| m[i - i % 3].max(0f32).min(1f32))
+                        .unwrap_or(1f32);
+                *below += (layer.blend.apply(*below, *above) - *below) * alpha;
+            }
+        }
+        Ok(out)
+    }
+}
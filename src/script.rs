@@ -0,0 +1,168 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::Result;
+use rlua::{Function, Lua, Table, ToLuaMulti};
+use stampede::tree::Tree;
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// What a script has asked for. Scripts never touch the tree directly; requests
+// are queued and applied by the event loop between frames.
+pub enum ScriptCommand {
+    UseSeed(String),
+    UseJson(String),
+    // Replace the tree with a new random one after this many seconds.
+    Regenerate(f32),
+    SetConstant(usize, f32),
+    FreezeConstant(usize, bool),
+    ScrubConstant(usize, f32),
+}
+
+// A Lua script driving the session. Everything the script can do is in the
+// global stampede table:
+//
+//   stampede.use_seed(seed)         build the tree from a seed
+//   stampede.use_json(json)         load a tree, as copied with ctrl+c
+//   stampede.regenerate(delay)      switch to a random tree in delay seconds
+//   stampede.set_constant(i, value) set the ith constant, from 0, as it stands now
+//   stampede.freeze(i, frozen)      hold the ith constant still, or let it go
+//   stampede.scrub(i, phase)        move the ith constant to a phase of its cycle, in [0,1)
+//   stampede.seed, stampede.time, stampede.constant_count
+//
+// The script body runs once at startup. If it defines on_frame(dt) or
+// on_key(name), they are called every frame and on every key press, where the
+// name is the winit key code, e.g. "Space" or "Key1".
+pub struct Script {
+    lua: Lua,
+    commands: Arc<Mutex<Vec<ScriptCommand>>>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)?;
+        let lua = Lua::new();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        lua.context(|ctx| -> rlua::Result<()> {
+            let stampede = ctx.create_table()?;
+            let queue = commands.clone();
+            stampede.set(
+                "use_seed",
+                ctx.create_function(move |_, seed: String| {
+                    Self::push(&queue, ScriptCommand::UseSeed(seed));
+                    Ok(())
+                })?,
+            )?;
+            let queue = commands.clone();
+            stampede.set(
+                "use_json",
+                ctx.create_function(move |_, json: String| {
+                    Self::push(&queue, ScriptCommand::UseJson(json));
+                    Ok(())
+                })?,
+            )?;
+            let queue = commands.clone();
+            stampede.set(
+                "regenerate",
+                ctx.create_function(move |_, delay: Option<f32>| {
+                    Self::push(&queue, ScriptCommand::Regenerate(delay.unwrap_or(0f32)));
+                    Ok(())
+                })?,
+            )?;
+            let queue = commands.clone();
+            stampede.set(
+                "set_constant",
+                ctx.create_function(move |_, (index, value): (usize, f32)| {
+                    Self::push(&queue, ScriptCommand::SetConstant(index, value));
+                    Ok(())
+                })?,
+            )?;
+            let queue = commands.clone();
+            stampede.set(
+                "freeze",
+                ctx.create_function(move |_, (index, frozen): (usize, Option<bool>)| {
+                    Self::push(
+                        &queue,
+                        ScriptCommand::FreezeConstant(index, frozen.unwrap_or(true)),
+                    );
+                    Ok(())
+                })?,
+            )?;
+            let queue = commands.clone();
+            stampede.set(
+                "scrub",
+                ctx.create_function(move |_, (index, phase): (usize, f32)| {
+                    Self::push(&queue, ScriptCommand::ScrubConstant(index, phase));
+                    Ok(())
+                })?,
+            )?;
+            ctx.globals().set("stampede", stampede)?;
+            ctx.load(&source)
+                .set_name(path.to_string_lossy().as_bytes())?
+                .exec()
+        })?;
+        Ok(Self { lua, commands })
+    }
+
+    fn push(queue: &Mutex<Vec<ScriptCommand>>, command: ScriptCommand) {
+        queue.lock().expect("script commands").push(command);
+    }
+
+    // Anything queued while the script body ran.
+    pub fn on_start(&mut self) -> Vec<ScriptCommand> {
+        self.drain()
+    }
+
+    pub fn on_frame(&mut self, dt: f32, seed: &str, tree: &Tree) -> Result<Vec<ScriptCommand>> {
+        self.call("on_frame", dt, seed, tree)
+    }
+
+    pub fn on_key(&mut self, key: &str, seed: &str, tree: &Tree) -> Result<Vec<ScriptCommand>> {
+        self.call("on_key", key.to_owned(), seed, tree)
+    }
+
+    fn call<A>(
+        &mut self,
+        name: &str,
+        args: A,
+        seed: &str,
+        tree: &Tree,
+    ) -> Result<Vec<ScriptCommand>>
+    where
+        A: for<'lua> ToLuaMulti<'lua>,
+    {
+        self.lua.context(|ctx| -> rlua::Result<()> {
+            let stampede: Table = ctx.globals().get("stampede")?;
+            stampede.set("seed", seed)?;
+            stampede.set("time", tree.time())?;
+            stampede.set("constant_count", tree.constant_count())?;
+            if let Some(callback) = ctx.globals().get::<_, Option<Function>>(name)? {
+                callback.call::<_, ()>(args)?;
+            }
+            Ok(())
+        })?;
+        Ok(self.drain())
+    }
+
+    fn drain(&mut self) -> Vec<ScriptCommand> {
+        self.commands
+            .lock()
+            .expect("script commands")
+            .drain(..)
+            .collect()
+    }
+}
@@ -0,0 +1,139 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{export, library::Library, open_tree, scene::Scene};
+use anyhow::{bail, Result};
+use gpu::GPU;
+use stampede::{compute::Quality, ops, render::OffscreenRenderer, tree::Tree};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// What a saved tree has to have to be found. Every condition that is given must
+// hold.
+#[derive(Debug, Default)]
+pub struct Query {
+    // Names of ops that must all be in the tree somewhere.
+    pub ops: Vec<String>,
+    pub min_depth: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub min_nodes: Option<usize>,
+    pub max_nodes: Option<usize>,
+    // Tags that the tree must have in the favorites library.
+    pub tags: Vec<String>,
+}
+
+impl Query {
+    // A misspelled op would otherwise quietly match nothing.
+    fn check(&self) -> Result<()> {
+        let known = ops::registered();
+        for name in &self.ops {
+            if !known.iter().any(|op| op.name == name) {
+                bail!(
+                    "there is no op named {}; the ops are: {}",
+                    name,
+                    known
+                        .iter()
+                        .map(|op| op.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, tree: &Tree, library: &Library) -> bool {
+        let ops = tree.ops();
+        let within = |value: usize, min: Option<usize>, max: Option<usize>| {
+            min.map(|min| value >= min).unwrap_or(true)
+                && max.map(|max| value <= max).unwrap_or(true)
+        };
+        self.ops
+            .iter()
+            .all(|name| ops.iter().any(|op| op.name == name))
+            && within(tree.depth(), self.min_depth, self.max_depth)
+            && within(tree.node_count(), self.min_nodes, self.max_nodes)
+            && (self.tags.is_empty()
+                || library
+                    .entry(&tree.canonical_hash())
+                    .map(|entry| self.tags.iter().all(|tag| entry.tags.contains(tag)))
+                    .unwrap_or(false))
+    }
+}
+
+// Every tree saved in dir, as json or an exported PNG, that matches query, by
+// file name. Files that are not trees, such as scenes, are passed over.
+pub fn find(dir: &Path, query: &Query) -> Result<Vec<(PathBuf, Tree)>> {
+    query.check()?;
+    let library = Library::open()?;
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.sort();
+    let mut found = Vec::new();
+    for path in paths {
+        let is_tree = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| {
+                ["json", "png", "stamp"].contains(&extension.to_ascii_lowercase().as_str())
+            })
+            .unwrap_or(false);
+        if !is_tree {
+            continue;
+        }
+        if let Ok((_, tree)) = open_tree(&path) {
+            if query.matches(&tree, &library) {
+                found.push((path, tree));
+            }
+        }
+    }
+    Ok(found)
+}
+
+// Lists the matches, one a line, with their names and sizes.
+pub fn list(dir: &Path, query: &Query) -> Result<()> {
+    for (path, tree) in find(dir, query)? {
+        println!(
+            "{}\t{}\tdepth {}\t{} nodes",
+            path.display(),
+            tree.name(),
+            tree.depth(),
+            tree.node_count()
+        );
+    }
+    Ok(())
+}
+
+// Renders each match to out, as a PNG named for its file, in draft quality to
+// browse through.
+pub fn render(
+    gpu: &mut GPU,
+    dir: &Path,
+    query: &Query,
+    extent: wgpu::Extent3d,
+    out: &Path,
+) -> Result<()> {
+    fs::create_dir_all(out)?;
+    let renderer = OffscreenRenderer::new(gpu)?.with_quality(Quality::Draft);
+    for (path, tree) in find(dir, query)? {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let target = out.join(format!("{}.png", stem));
+        export::write_frame(gpu, &renderer, &Scene::single(tree), None, extent, &target)?;
+        println!("{} -> {}", path.display(), target.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,292 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::view::ViewPath;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use stampede::{display::ColorAdjustment, post::Crt, tree::Tree};
+use std::{
+    fs, panic,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use winit::{
+    dpi::{LogicalPosition, LogicalSize},
+    window::Window,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    position: Option<[f64; 2]>,
+    size: [f64; 2],
+}
+
+impl WindowGeometry {
+    pub fn from_window(window: &Window) -> Self {
+        let size = window.inner_size();
+        Self {
+            // Not every platform can report where a window is; wayland, for one.
+            position: window.outer_position().ok().map(|p| [p.x, p.y]),
+            size: [size.width, size.height],
+        }
+    }
+
+    pub fn size(&self) -> LogicalSize {
+        LogicalSize::new(self.size[0], self.size[1])
+    }
+
+    pub fn position(&self) -> Option<LogicalPosition> {
+        self.position.map(|p| LogicalPosition::new(p[0], p[1]))
+    }
+}
+
+// Everything needed to pick up where we left off. The animation phase is captured
+// by the tree itself, since it carries the animation clock.
+#[derive(Debug, Deserialize)]
+pub struct Session {
+    pub seed: String,
+    pub tree: Tree,
+    pub window: WindowGeometry,
+    #[serde(default)]
+    pub view_path: ViewPath,
+    #[serde(default)]
+    pub colors: ColorAdjustment,
+    // Whether the picture was shown as if on an old tube, and how.
+    #[serde(default)]
+    pub crt: Option<Crt>,
+}
+
+// The live tree belongs to the event loop, so we save by reference.
+#[derive(Serialize)]
+struct SessionRef<'a> {
+    seed: &'a str,
+    tree: &'a Tree,
+    window: WindowGeometry,
+    view_path: &'a ViewPath,
+    colors: ColorAdjustment,
+    crt: Option<Crt>,
+}
+
+// How often the live session is snapshotted in memory, for the panic hook, and
+// how often that snapshot is written out, for crashes that take the process down
+// with no chance to run a hook, such as in the GPU driver.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+fn data_dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| anyhow!("no data directory on this platform"))?
+        .join("stampede");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// Write next to the target and rename so that a crash mid-write cannot clobber
+// the previous good file.
+fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+impl Session {
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(data_dir()?.join("session.json"))
+    }
+
+    // Where a run that did not exit cleanly left its session; see Recovery.
+    pub fn crashed_path() -> Result<PathBuf> {
+        Ok(data_dir()?.join("crashed.json"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(
+        path: &Path,
+        seed: &str,
+        tree: &Tree,
+        view_path: &ViewPath,
+        colors: ColorAdjustment,
+        crt: Option<Crt>,
+        window: &Window,
+    ) -> Result<()> {
+        write_atomically(
+            path,
+            &Self::to_json(seed, tree, view_path, colors, crt, window)?,
+        )
+    }
+
+    pub fn to_json(
+        seed: &str,
+        tree: &Tree,
+        view_path: &ViewPath,
+        colors: ColorAdjustment,
+        crt: Option<Crt>,
+        window: &Window,
+    ) -> Result<String> {
+        let session = SessionRef {
+            seed,
+            tree,
+            window: WindowGeometry::from_window(window),
+            view_path,
+            colors,
+            crt,
+        };
+        Ok(serde_json::to_string(&session)?)
+    }
+}
+
+// Keeps the live session somewhere that survives a crash: written out every so
+// often, and again from a panic hook, and removed on a clean exit. A recovery file
+// that is still there at startup was left by a crash, unless the run that owns it,
+// by the pid next to it, is still going; it is moved to Session::crashed_path,
+// from where it can be restored. While another run owns it, this one keeps none.
+pub struct Recovery {
+    path: Option<PathBuf>,
+    snapshot: Arc<Mutex<Option<String>>>,
+    snapshot_at: Instant,
+    saved_at: Instant,
+}
+
+impl Recovery {
+    // Returns whether the last run crashed, along with the recovery.
+    pub fn start() -> Result<(Self, bool)> {
+        let dir = data_dir()?;
+        let pid_path = dir.join("recovery.pid");
+        let owner = fs::read_to_string(&pid_path)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<u32>().ok());
+        let now = Instant::now();
+        let snapshot: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        if let Some(pid) = owner.filter(|&pid| is_running(pid)) {
+            println!(
+                "stampede is already running as process {}; this one will not be recoverable",
+                pid
+            );
+            return Ok((
+                Self {
+                    path: None,
+                    snapshot,
+                    snapshot_at: now,
+                    saved_at: now,
+                },
+                false,
+            ));
+        }
+        let path = dir.join("recovery.json");
+        let crashed = path.exists();
+        if crashed {
+            fs::rename(&path, Session::crashed_path()?)?;
+        }
+        fs::write(&pid_path, process::id().to_string())?;
+        let hook_snapshot = snapshot.clone();
+        let hook_path = path.clone();
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // The lock may be poisoned by the very panic we are in; take it anyway.
+            let snapshot = hook_snapshot.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(json) = snapshot.as_ref() {
+                write_atomically(&hook_path, json).ok();
+            }
+            previous_hook(info);
+        }));
+        Ok((
+            Self {
+                path: Some(path),
+                snapshot,
+                snapshot_at: now,
+                saved_at: now,
+            },
+            crashed,
+        ))
+    }
+
+    // Call every frame; session is only serialized when a snapshot is due.
+    pub fn tick<F>(&mut self, session: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<String>,
+    {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if self.snapshot_at.elapsed() < SNAPSHOT_INTERVAL {
+            return Ok(());
+        }
+        self.snapshot_at = Instant::now();
+        let json = session()?;
+        if self.saved_at.elapsed() >= AUTOSAVE_INTERVAL {
+            self.saved_at = Instant::now();
+            write_atomically(path, &json)?;
+        }
+        *self.snapshot.lock().expect("snapshot lock") = Some(json);
+        Ok(())
+    }
+
+    // After a clean exit there is nothing to recover.
+    pub fn finish(&self) {
+        *self.snapshot.lock().expect("snapshot lock") = None;
+        if let Some(path) = &self.path {
+            fs::remove_file(path).ok();
+            fs::remove_file(path.with_extension("pid")).ok();
+        }
+    }
+}
+
+// Signal 0 only checks that the process is there. One that belongs to someone
+// else cannot be signaled, but is there all the same.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    extern "C" {
+        fn kill(pid: i32, signal: i32) -> i32;
+    }
+    const EPERM: i32 = 1;
+    pid != process::id()
+        && (unsafe { kill(pid as i32, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(EPERM))
+}
+
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    use winapi::um::{
+        handleapi::CloseHandle,
+        minwinbase::STILL_ACTIVE,
+        processthreadsapi::{GetExitCodeProcess, OpenProcess},
+        winnt::PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    if pid == process::id() {
+        return false;
+    }
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return false;
+        }
+        let mut code = 0;
+        let running = GetExitCodeProcess(handle, &mut code) != 0 && code == STILL_ACTIVE;
+        CloseHandle(handle);
+        running
+    }
+}
+
+// With no way to tell, every recovery file left behind was from a crash.
+#[cfg(not(any(unix, windows)))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
@@ -0,0 +1,270 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::{anyhow, Result};
+use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
+use stampede::tree::{EvalContext, NodeId, Tree};
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+// How often the loop that the subtree is read along goes around, and so the pitch.
+const BASE_FREQUENCY: f32 = 110.0;
+
+// The loop is a circle of this radius about the middle of the plane.
+const ORBIT_RADIUS: f32 = 0.5;
+
+// Trees are not written with ears in mind, so never let one past this.
+const CEILING: f32 = 0.25;
+
+// How far ahead of the speaker the tree is evaluated, in seconds. Walking the
+// tree is slow and uneven, so it happens on a thread of its own and the audio
+// callback only copies samples out.
+const AHEAD: f32 = 0.1;
+
+// A queue of samples between one writer and one reader, neither of which ever
+// waits on the other: the writer stops when it is full, and the reader plays
+// silence when it is empty.
+struct SampleRing {
+    samples: Vec<AtomicU32>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+impl SampleRing {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, value: f32) -> bool {
+        let write = self.write.load(Ordering::Relaxed);
+        if write.wrapping_sub(self.read.load(Ordering::Acquire)) >= self.samples.len() {
+            return false;
+        }
+        self.samples[write % self.samples.len()].store(value.to_bits(), Ordering::Relaxed);
+        self.write.store(write.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    fn pop(&self) -> f32 {
+        let read = self.read.load(Ordering::Relaxed);
+        if read == self.write.load(Ordering::Acquire) {
+            return 0f32;
+        }
+        let value = self.samples[read % self.samples.len()].load(Ordering::Relaxed);
+        self.read.store(read.wrapping_add(1), Ordering::Release);
+        f32::from_bits(value)
+    }
+}
+
+// Keeps a tree's output at a comfortable level: a DC blocker, so that a constant
+// offset cannot hold the speaker out, then a peak limiter that clamps down at once
+// and recovers over a quarter of a second.
+struct Limiter {
+    ceiling: f32,
+    gain: f32,
+    recovery: f32,
+    last_in: f32,
+    last_out: f32,
+}
+
+impl Limiter {
+    fn new(sample_rate: f32, ceiling: f32) -> Self {
+        Self {
+            ceiling,
+            gain: 1f32,
+            recovery: 1f32 - (-1f32 / (0.25 * sample_rate)).exp(),
+            last_in: 0f32,
+            last_out: 0f32,
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        // Divides can make infinities and NaNs; those are silence.
+        let x = if x.is_finite() { x } else { 0f32 };
+        let y = x - self.last_in + 0.995 * self.last_out;
+        self.last_in = x;
+        self.last_out = y;
+        if y.abs() * self.gain > self.ceiling {
+            self.gain = self.ceiling / y.abs();
+        } else {
+            self.gain += (1f32 - self.gain) * self.recovery;
+        }
+        (y * self.gain).max(-self.ceiling).min(self.ceiling)
+    }
+}
+
+struct Voice {
+    tree: Tree,
+    path: String,
+    node: Option<NodeId>,
+    sample_rate: f32,
+    time: f32,
+    phase: f32,
+    limiter: Limiter,
+}
+
+impl Voice {
+    fn set_tree(&mut self, tree: Tree) {
+        self.node = tree.node_at(&self.path);
+        self.time = tree.time();
+        self.tree = tree;
+    }
+
+    // The subtree along one step of the loop, with the tree's constants moving on
+    // in real time underneath.
+    fn next_sample(&mut self) -> f32 {
+        let value = match self.node {
+            Some(node) => {
+                let angle = self.phase * 2f32 * PI;
+                let ctx = EvalContext {
+                    position: [ORBIT_RADIUS * angle.cos(), ORBIT_RADIUS * angle.sin()],
+                    mouse: [0f32, 0f32],
+                    time: self.time,
+                    draft: false,
+                };
+                self.tree.evaluate_node(node, &ctx)
+            }
+            None => 0f32,
+        };
+        self.phase = (self.phase + BASE_FREQUENCY / self.sample_rate).fract();
+        self.time += 1f32 / self.sample_rate;
+        self.limiter.process(value)
+    }
+
+    // Keep the ring topped up, following new trees as they come, until the
+    // Sonifier is dropped.
+    fn run(mut self, ring: &SampleRing, trees: Receiver<Tree>) {
+        let mut pending = None;
+        loop {
+            loop {
+                match trees.try_recv() {
+                    Ok(tree) => self.set_tree(tree),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            loop {
+                let value = pending.take().unwrap_or_else(|| self.next_sample());
+                if !ring.push(value) {
+                    pending = Some(value);
+                    break;
+                }
+            }
+            thread::sleep(Duration::from_secs_f32(AHEAD / 4f32));
+        }
+    }
+}
+
+// Plays a subtree as sound, on the default output, for as long as it lives. The
+// subtree is named by a path like "r" or "g/1/0"; see Tree::node_at. If a new tree
+// has nothing at that path, it is silent.
+pub struct Sonifier {
+    trees: Sender<Tree>,
+}
+
+impl Sonifier {
+    pub fn start(tree: &Tree, path: &str) -> Result<Self> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("no audio output device"))?;
+        let format = device.default_output_format()?;
+        let event_loop = host.event_loop();
+        let stream_id = event_loop.build_output_stream(&device, &format)?;
+        event_loop.play_stream(stream_id)?;
+
+        let sample_rate = format.sample_rate.0 as f32;
+        let voice = Voice {
+            tree: tree.clone(),
+            path: path.to_owned(),
+            node: tree.node_at(path),
+            sample_rate,
+            time: tree.time(),
+            phase: 0f32,
+            limiter: Limiter::new(sample_rate, CEILING),
+        };
+        if voice.node.is_none() {
+            println!("sonify: nothing at {} in this tree", path);
+        }
+        let ring = Arc::new(SampleRing::new((AHEAD * sample_rate) as usize));
+        let (trees, receiver) = mpsc::channel();
+        let voice_ring = ring.clone();
+        thread::spawn(move || voice.run(&voice_ring, receiver));
+
+        let channels = format.channels as usize;
+        thread::spawn(move || {
+            event_loop.run(move |_, result| {
+                let data = match result {
+                    Ok(data) => data,
+                    Err(e) => {
+                        println!("sonify: {}", e);
+                        return;
+                    }
+                };
+                match data {
+                    cpal::StreamData::Output {
+                        buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer),
+                    } => {
+                        for frame in buffer.chunks_mut(channels) {
+                            let value = ring.pop();
+                            for sample in frame {
+                                *sample = value;
+                            }
+                        }
+                    }
+                    cpal::StreamData::Output {
+                        buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer),
+                    } => {
+                        for frame in buffer.chunks_mut(channels) {
+                            let value = (ring.pop() * i16::max_value() as f32) as i16;
+                            for sample in frame {
+                                *sample = value;
+                            }
+                        }
+                    }
+                    cpal::StreamData::Output {
+                        buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer),
+                    } => {
+                        for frame in buffer.chunks_mut(channels) {
+                            let value = ((ring.pop() + 1f32) * 32767.5) as u16;
+                            for sample in frame {
+                                *sample = value;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            });
+        });
+        Ok(Self { trees })
+    }
+
+    // Follow a tree that was replaced or edited. The voice picks it up between
+    // samples, so the audio callback never waits on it.
+    pub fn set_tree(&self, tree: &Tree) {
+        let _ = self.trees.send(tree.clone());
+    }
+}
@@ -0,0 +1,327 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{open_tree, scene::Scene};
+use anyhow::{bail, Result};
+use gpu::GPU;
+use serde::{Deserialize, Serialize};
+use stampede::{
+    compute::Quality,
+    render::{srgb_to_8bit, OffscreenRenderer},
+    tree::{Channels, EncodedTree, Growth, InstructionEncoder, Tree},
+};
+use std::{convert::TryInto, fs, io::Read, path::Path, str};
+
+// A .stamp file is MAGIC and a version byte, then zstd compressed sections, each
+// a tag, a little endian length and that many bytes. Only the tree is required.
+// Tags that a reader does not know are skipped, so sections can be added without
+// a new version; a new version is for changing the ones that are there.
+const MAGIC: &[u8; 4] = b"STMP";
+const VERSION: u8 = 1;
+const COMPRESSION_LEVEL: i32 = 9;
+// The most that the sections may come to once decompressed, so that a small file
+// cannot ask for all of memory.
+const MAX_SECTIONS_LENGTH: u64 = 64 << 20;
+
+const TREE_TAG: &[u8; 4] = b"TREE";
+const CONFIG_TAG: &[u8; 4] = b"CONF";
+const SEED_TAG: &[u8; 4] = b"SEED";
+const THUMBNAIL_TAG: &[u8; 4] = b"THMB";
+const INSTRUCTIONS_TAG: &[u8; 4] = b"INST";
+
+// How Tree::new was set up when the tree was grown, so that its seed can be grown
+// again into the same tree.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationConfig {
+    pub growth: Growth,
+    pub channels: Channels,
+    pub simplify: bool,
+    pub red_green_safe: bool,
+}
+
+// A tree with what it came from, for galleries and archives that have outgrown
+// a directory of json.
+pub struct Stamp {
+    // As saved with ctrl+c.
+    pub tree: Tree,
+    pub config: Option<GenerationConfig>,
+    pub seed: Option<String>,
+    // An 8 bit RGB PNG.
+    pub thumbnail: Option<Vec<u8>>,
+    // The tree as the interpreter sees it, baked when the stamp was written.
+    pub instructions: Option<EncodedTree>,
+}
+
+impl Stamp {
+    pub fn new(tree: Tree) -> Self {
+        Self {
+            tree,
+            config: None,
+            seed: None,
+            thumbnail: None,
+            instructions: None,
+        }
+    }
+
+    pub fn bake(&mut self) -> Result<()> {
+        self.instructions = Some(InstructionEncoder::encode(&self.tree)?);
+        Ok(())
+    }
+
+    // Whether the baked instructions still say what the tree does. They stop when
+    // the instruction format or an opcode changes under them.
+    pub fn check_instructions(&self) -> Result<()> {
+        if let Some(instructions) = &self.instructions {
+            let baked = InstructionEncoder::decode(instructions)?;
+            if baked.to_json()? != self.tree.to_json()? {
+                bail!("the baked instructions are of some other tree");
+            }
+        }
+        Ok(())
+    }
+
+    // Packs an 8 bit RGB picture as the thumbnail.
+    pub fn set_thumbnail(&mut self, width: u32, height: u32, rgb: &[u8]) -> Result<()> {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, width, height);
+            encoder.set_color(png::ColorType::RGB);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(rgb)?;
+        }
+        self.thumbnail = Some(png);
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut sections = Vec::new();
+        section(&mut sections, TREE_TAG, self.tree.to_json()?.as_bytes());
+        if let Some(config) = &self.config {
+            section(&mut sections, CONFIG_TAG, &serde_json::to_vec(config)?);
+        }
+        if let Some(seed) = &self.seed {
+            section(&mut sections, SEED_TAG, seed.as_bytes());
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            section(&mut sections, THUMBNAIL_TAG, thumbnail);
+        }
+        if let Some(instructions) = &self.instructions {
+            section(&mut sections, INSTRUCTIONS_TAG, &instructions.to_bytes());
+        }
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend(zstd::encode_all(&sections[..], COMPRESSION_LEVEL)?);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < MAGIC.len() + 1 || &bytes[..MAGIC.len()] != MAGIC {
+            bail!("not a stamp");
+        }
+        match bytes[MAGIC.len()] {
+            1 => Self::read_v1(&decompress(&bytes[MAGIC.len() + 1..])?),
+            version => bail!(
+                "the stamp is version {}, but this stampede reads up to {}",
+                version,
+                VERSION
+            ),
+        }
+    }
+
+    fn read_v1(mut rest: &[u8]) -> Result<Self> {
+        let mut tree = None;
+        let (mut config, mut seed, mut thumbnail, mut instructions) = (None, None, None, None);
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                bail!("the stamp is cut short");
+            }
+            let length = u32::from_le_bytes(rest[4..8].try_into()?) as usize;
+            if rest.len() < 8 + length {
+                bail!("the stamp is cut short");
+            }
+            let data = &rest[8..8 + length];
+            match &rest[..4] {
+                tag if tag == TREE_TAG => tree = Some(Tree::from_json(str::from_utf8(data)?)?),
+                tag if tag == CONFIG_TAG => config = Some(serde_json::from_slice(data)?),
+                tag if tag == SEED_TAG => seed = Some(String::from_utf8(data.to_vec())?),
+                tag if tag == THUMBNAIL_TAG => thumbnail = Some(data.to_vec()),
+                tag if tag == INSTRUCTIONS_TAG => {
+                    instructions = Some(EncodedTree::from_bytes(data)?)
+                }
+                _ => {}
+            }
+            rest = &rest[8 + length..];
+        }
+        let tree = match tree {
+            Some(tree) => tree,
+            None => bail!("the stamp has no tree in it"),
+        };
+        Ok(Self {
+            tree,
+            config,
+            seed,
+            thumbnail,
+            instructions,
+        })
+    }
+}
+
+// Packs the tree at path, saved as json, exported as a PNG or already a stamp,
+// with a thumbnail of it and, if bake, its instructions. The config is only kept
+// along with a seed, which is all that it is any use for.
+pub fn run(
+    gpu: &mut GPU,
+    path: &Path,
+    out: &Path,
+    thumbnail: [u32; 2],
+    bake: bool,
+    config: GenerationConfig,
+) -> Result<()> {
+    let (seed, tree) = open_tree(path)?;
+    let mut stamp = Stamp::new(tree);
+    if seed.is_some() {
+        stamp.seed = seed;
+        stamp.config = Some(config);
+    }
+    let [width, height] = thumbnail;
+    if width > 0 && height > 0 {
+        let renderer = OffscreenRenderer::new(gpu)?.with_quality(Quality::Draft);
+        let rgb = Scene::single(stamp.tree.clone()).render(&renderer, gpu, width, height)?;
+        stamp.set_thumbnail(width, height, &srgb_to_8bit(&rgb))?;
+    }
+    if bake {
+        stamp.bake()?;
+    }
+    stamp.write(out)?;
+    println!(
+        "wrote {} ({} bytes)",
+        out.display(),
+        fs::metadata(out)?.len()
+    );
+    Ok(())
+}
+
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>> {
+    let mut sections = Vec::new();
+    zstd::stream::read::Decoder::new(compressed)?
+        .take(MAX_SECTIONS_LENGTH + 1)
+        .read_to_end(&mut sections)?;
+    if sections.len() as u64 > MAX_SECTIONS_LENGTH {
+        bail!(
+            "the stamp unpacks to more than {} bytes",
+            MAX_SECTIONS_LENGTH
+        );
+    }
+    Ok(sections)
+}
+
+fn section(out: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(tag);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+pub fn is_stamp(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.eq_ignore_ascii_case("stamp"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stampede::builder::NodeBuilder;
+
+    fn sample_tree() -> Tree {
+        Tree::build([
+            NodeBuilder::flower([0.1, 0.2], 1.0, 7),
+            NodeBuilder::value(0.5),
+            NodeBuilder::value(0.0),
+        ])
+        .expect("a tree")
+    }
+
+    fn packed(sections: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend(zstd::encode_all(sections, COMPRESSION_LEVEL).expect("compressed"));
+        bytes
+    }
+
+    #[test]
+    fn stamps_round_trip() -> Result<()> {
+        let mut stamp = Stamp::new(sample_tree());
+        stamp.seed = Some("quiet-heron".to_owned());
+        stamp.config = Some(GenerationConfig::default());
+        stamp.set_thumbnail(2, 1, &[0, 0, 0, 255, 255, 255])?;
+        stamp.bake()?;
+        let read = Stamp::from_bytes(&stamp.to_bytes()?)?;
+        assert_eq!(read.tree.to_json()?, stamp.tree.to_json()?);
+        assert_eq!(read.seed, stamp.seed);
+        assert!(read.config.is_some());
+        assert_eq!(read.thumbnail, stamp.thumbnail);
+        assert_eq!(
+            read.instructions
+                .map(|instructions| instructions.to_bytes()),
+            stamp
+                .instructions
+                .map(|instructions| instructions.to_bytes())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_stamps_are_errors() -> Result<()> {
+        let bytes = Stamp::new(sample_tree()).to_bytes()?;
+        assert!(Stamp::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+        assert!(Stamp::from_bytes(&bytes[..MAGIC.len()]).is_err());
+
+        // A section that says it is longer than what is left.
+        let mut sections = Vec::new();
+        section(&mut sections, TREE_TAG, sample_tree().to_json()?.as_bytes());
+        sections.truncate(sections.len() - 1);
+        assert!(Stamp::from_bytes(&packed(&sections)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_sections_are_skipped() -> Result<()> {
+        let mut sections = Vec::new();
+        section(&mut sections, b"XTRA", b"from a later stampede");
+        section(&mut sections, TREE_TAG, sample_tree().to_json()?.as_bytes());
+        let read = Stamp::from_bytes(&packed(&sections))?;
+        assert_eq!(read.tree.to_json()?, sample_tree().to_json()?);
+        assert!(read.seed.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn later_versions_are_refused() -> Result<()> {
+        let mut bytes = Stamp::new(sample_tree()).to_bytes()?;
+        bytes[MAGIC.len()] = VERSION + 1;
+        let error = Stamp::from_bytes(&bytes).err().expect("an error");
+        assert!(error.to_string().contains("version 2"));
+        Ok(())
+    }
+}
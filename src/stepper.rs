@@ -0,0 +1,103 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use anyhow::Result;
+use stampede::tree::{EvalContext, InstructionEncoder, TraceStep, Tree};
+
+const LAYER_NAMES: [&str; 3] = ["r", "g", "b"];
+
+// Walks the encoded instruction stream of each layer at one point, an opcode at a
+// time, printing the value stack as it goes. When a layer finishes, its last value
+// is set beside what evaluating the tree itself gives, so that a new opcode the
+// encoder or interpreter gets wrong shows up as a disagreement.
+pub struct Stepper {
+    traces: Vec<Vec<TraceStep>>,
+    walked: [f32; 3],
+    layer: usize,
+    step: usize,
+}
+
+impl Stepper {
+    pub fn new(tree: &Tree, ctx: &EvalContext) -> Result<Self> {
+        let encoded = InstructionEncoder::encode(tree)?;
+        let mut traces = Vec::new();
+        let mut walked = [0f32; 3];
+        for (offset, value) in walked.iter_mut().enumerate() {
+            traces.push(encoded.layer(offset).trace(ctx)?);
+            *value = tree.evaluate(offset, ctx);
+        }
+        Ok(Self {
+            traces,
+            walked,
+            layer: 0,
+            step: 0,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.layer >= self.traces.len()
+    }
+
+    // Print the next opcode, and return whether there is anything left after it.
+    pub fn step(&mut self) -> bool {
+        if self.is_done() {
+            return false;
+        }
+        let trace = &self.traces[self.layer];
+        let step = &trace[self.step];
+        let constants = step
+            .constants
+            .iter()
+            .map(|c| format!("{:0.4}", c))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let stack = step
+            .stack
+            .iter()
+            .map(|v| format!("{:0.6}", v))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{} {:>3}  {:<12} [{}]  -> {}",
+            LAYER_NAMES[self.layer], step.index, step.op, constants, stack
+        );
+        self.step += 1;
+        if self.step == trace.len() {
+            self.finish_layer();
+        }
+        !self.is_done()
+    }
+
+    // Run out the rest of the layer being stepped through.
+    pub fn run_layer(&mut self) -> bool {
+        let layer = self.layer;
+        while self.layer == layer && self.step() {}
+        !self.is_done()
+    }
+
+    fn finish_layer(&mut self) {
+        let traced = self.traces[self.layer][self.step - 1].stack[0];
+        let walked = self.walked[self.layer];
+        let agrees = (traced.is_nan() && walked.is_nan()) || (traced - walked).abs() < 1e-5;
+        println!(
+            "{}: {:0.6} from the instructions, {:0.6} from the tree{}",
+            LAYER_NAMES[self.layer],
+            traced,
+            walked,
+            if agrees { "" } else { "  DISAGREE" }
+        );
+        self.layer += 1;
+        self.step = 0;
+    }
+}
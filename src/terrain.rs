@@ -0,0 +1,223 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{compute::Configuration, error::Fallible};
+use gpu::GPU;
+use std::mem;
+use wgpu;
+
+// Vertices along each side of the grid.
+const GRID_SIDE: u32 = 256;
+
+// The red layer as the height of a landscape, lit by the sun and colored by the
+// other two layers, seen from a camera that circles it.
+pub struct Terrain {
+    pipeline: wgpu::RenderPipeline,
+    // One for each slot of the display's layers.
+    bind_groups: Vec<wgpu::BindGroup>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+}
+
+impl Terrain {
+    // The display's configuration, which the camera takes the time and aspect
+    // ratio from, and its red, green and blue layers for each slot.
+    pub fn new(
+        gpu: &GPU,
+        display_config_buffer: &wgpu::Buffer,
+        slot_views: &[[&wgpu::TextureView; 3]],
+    ) -> Fallible<Self> {
+        let tex_coords = (0..GRID_SIDE)
+            .flat_map(|y| {
+                (0..GRID_SIDE).map(move |x| {
+                    [
+                        x as f32 / (GRID_SIDE - 1) as f32,
+                        y as f32 / (GRID_SIDE - 1) as f32,
+                    ]
+                })
+            })
+            .collect::<Vec<[f32; 2]>>();
+        let vertex_buffer = gpu
+            .device()
+            .create_buffer_mapped(tex_coords.len(), wgpu::BufferUsage::VERTEX)
+            .fill_from_slice(&tex_coords);
+        let indices = (0..GRID_SIDE - 1)
+            .flat_map(|y| {
+                (0..GRID_SIDE - 1).flat_map(move |x| {
+                    let i = y * GRID_SIDE + x;
+                    vec![
+                        i,
+                        i + 1,
+                        i + GRID_SIDE,
+                        i + 1,
+                        i + GRID_SIDE + 1,
+                        i + GRID_SIDE,
+                    ]
+                })
+            })
+            .collect::<Vec<u32>>();
+        let index_buffer = gpu
+            .device()
+            .create_buffer_mapped(indices.len(), wgpu::BufferUsage::INDEX)
+            .fill_from_slice(&indices);
+
+        let sampler = gpu.device().create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_min_clamp: 0f32,
+            lod_max_clamp: 9_999_999f32,
+            compare_function: wgpu::CompareFunction::Never,
+        });
+        let visibility = wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT;
+        let texture_binding = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        let layout = gpu
+            .device()
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 0,
+                        visibility,
+                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                    },
+                    texture_binding(1),
+                    texture_binding(2),
+                    texture_binding(3),
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 4,
+                        visibility,
+                        ty: wgpu::BindingType::Sampler,
+                    },
+                ],
+            });
+        let bind_groups = slot_views
+            .iter()
+            .map(|views| {
+                gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &layout,
+                    bindings: &[
+                        wgpu::Binding {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: display_config_buffer,
+                                range: 0..Configuration::buffer_size(),
+                            },
+                        },
+                        wgpu::Binding {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(views[0]),
+                        },
+                        wgpu::Binding {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(views[1]),
+                        },
+                        wgpu::Binding {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(views[2]),
+                        },
+                        wgpu::Binding {
+                            binding: 4,
+                            resource: wgpu::BindingResource::Sampler(&sampler),
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let vert_shader =
+            gpu.create_shader_module(include_bytes!("../target/terrain.vert.spirv"))?;
+        let frag_shader =
+            gpu.create_shader_module(include_bytes!("../target/terrain.frag.spirv"))?;
+        let pipeline = gpu
+            .device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[&layout],
+                    }),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &vert_shader,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &frag_shader,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::None,
+                    depth_bias: 0,
+                    depth_bias_slope_scale: 0.0,
+                    depth_bias_clamp: 0.0,
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: GPU::texture_format(),
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: GPU::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_read_mask: 0,
+                    stencil_write_mask: 0,
+                }),
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float2,
+                        offset: 0,
+                        shader_location: 0,
+                    }],
+                }],
+                sample_count: gpu.sample_count(),
+                sample_mask: !0,
+                alpha_to_coverage_enabled: false,
+            });
+
+        Ok(Self {
+            pipeline,
+            bind_groups,
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+        })
+    }
+
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass, slot: usize) {
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_groups[slot], &[]);
+        rpass.set_index_buffer(&self.index_buffer, 0);
+        rpass.set_vertex_buffers(0, &[(&self.vertex_buffer, 0)]);
+        rpass.draw_indexed(0..self.index_count, 0, 0..1);
+    }
+}
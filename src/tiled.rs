@@ -0,0 +1,286 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{export::create_png, recipe::Recipe};
+use anyhow::{bail, Result};
+use gpu::GPU;
+use serde::{Deserialize, Serialize};
+use stampede::{
+    render::{layers_to_srgb, srgb_to_8bit, OffscreenRenderer},
+    tree::Tree,
+};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+// What the tiles in a tile directory were rendered from. If it does not match,
+// the tiles are from some other picture and are thrown away.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TileManifest {
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    tree: String,
+}
+
+// Renders one frame of a tree at any size, however large, for print. The picture
+// is rendered a tile at a time and each tile is kept in a directory next to out
+// until the whole picture is done, so an interrupted render picks up where it
+// stopped when run again. The tiles are then stitched into out a row of tiles at
+// a time, so the whole picture never has to fit in memory. Out is a PNG or, if it
+// ends in .tif or .tiff, a BigTIFF, for pictures past the 4GiB that plain TIFF
+// and many PNG readers can manage.
+//
+// With more than one GPU, each renders its own tiles at the same time; the tiles
+// come back to the CPU to be stitched, so the GPUs need not share anything.
+pub fn run(
+    gpus: &mut [GPU],
+    tree: &Tree,
+    seed: Option<String>,
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    out: &Path,
+) -> Result<()> {
+    if width == 0 || height == 0 {
+        bail!("the picture must not be empty");
+    }
+    if gpus.is_empty() {
+        bail!("there is no GPU to render with");
+    }
+    if tile_size == 0 {
+        bail!("tiles must not be empty");
+    }
+    let tile_dir = tile_dir(out);
+    let manifest = TileManifest {
+        width,
+        height,
+        tile_size,
+        tree: tree.to_json()?,
+    };
+    prepare_tile_dir(&tile_dir, &manifest)?;
+
+    let columns = (width + tile_size - 1) / tile_size;
+    let rows = (height + tile_size - 1) / tile_size;
+    let total = columns * rows;
+    let renderers = gpus
+        .iter()
+        .map(OffscreenRenderer::new)
+        .collect::<Result<Vec<_>>>()?;
+    let missing = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (row, column)))
+        .filter(|&(row, column)| !tile_path(&tile_dir, row, column).exists())
+        .collect::<Vec<_>>();
+    let mut done = total - missing.len() as u32;
+    for batch in missing.chunks(gpus.len()) {
+        // Start a tile on every GPU before waiting on any of them.
+        let mut pending = Vec::with_capacity(batch.len());
+        for ((gpu, renderer), &(row, column)) in gpus.iter_mut().zip(&renderers).zip(batch) {
+            // Edge tiles are cut short by the edge of the picture.
+            let origin = [column * tile_size, row * tile_size];
+            let region = [
+                (width - origin[0]).min(tile_size),
+                (height - origin[1]).min(tile_size),
+            ];
+            let region = renderer.begin_region(gpu, tree, [width, height], origin, region)?;
+            pending.push((region, tile_path(&tile_dir, row, column)));
+        }
+        for (gpu, (region, path)) in gpus.iter_mut().zip(pending) {
+            let rgb = srgb_to_8bit(&layers_to_srgb(&region.finish(gpu)?));
+            // Write and rename, so a tile that is there is a tile that is complete.
+            let tmp_path = path.with_extension("rgb.tmp");
+            fs::write(&tmp_path, &rgb)?;
+            fs::rename(&tmp_path, &path)?;
+            done += 1;
+            println!(
+                "tile {} of {} ({:.1}%)",
+                done,
+                total,
+                done as f32 * 100f32 / total as f32
+            );
+        }
+    }
+
+    println!("stitching {}x{} into {}", width, height, out.display());
+    // The rows of pixels across a row of tiles.
+    let stitch = |row: u32| -> Result<Vec<u8>> {
+        let strip_height = (height - row * tile_size).min(tile_size) as usize;
+        let tiles = (0..columns)
+            .map(|column| fs::read(tile_path(&tile_dir, row, column)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut strip = Vec::with_capacity(width as usize * 3 * strip_height);
+        for y in 0..strip_height {
+            for (column, tile) in tiles.iter().enumerate() {
+                let tile_width = (width - column as u32 * tile_size).min(tile_size) as usize * 3;
+                strip.extend_from_slice(&tile[y * tile_width..(y + 1) * tile_width]);
+            }
+        }
+        Ok(strip)
+    };
+    if is_tiff(out) {
+        write_big_tiff(out, width, height, tile_size, rows, stitch)?;
+    } else {
+        let recipe = Recipe {
+            seed,
+            tree: Some(manifest.tree),
+            scene: None,
+        };
+        let mut writer = create_png(out, width, height, &recipe)?;
+        {
+            let mut stream = writer.stream_writer();
+            for row in 0..rows {
+                stream.write_all(&stitch(row)?)?;
+            }
+            stream.flush()?;
+        }
+        drop(writer);
+    }
+    fs::remove_dir_all(&tile_dir)?;
+    println!("wrote {}", out.display());
+    Ok(())
+}
+
+fn is_tiff(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| {
+            extension.eq_ignore_ascii_case("tif") || extension.eq_ignore_ascii_case("tiff")
+        })
+        .unwrap_or(false)
+}
+
+// Writes a little endian BigTIFF of 8 bit RGB, uncompressed, in strips of
+// rows_per_strip rows, as strip gives them first to last. The directory goes
+// after the pixels, so that they can be written as they come.
+fn write_big_tiff<F>(
+    out: &Path,
+    width: u32,
+    height: u32,
+    rows_per_strip: u32,
+    strips: u32,
+    mut strip: F,
+) -> Result<()>
+where
+    F: FnMut(u32) -> Result<Vec<u8>>,
+{
+    const HEADER_LENGTH: u64 = 16;
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    const LONG8: u16 = 16;
+    const ENTRY_COUNT: u64 = 10;
+
+    let pixels_length = u64::from(width) * u64::from(height) * 3;
+    // Offsets into the file have to be to an even byte.
+    let padding = pixels_length % 2;
+    let directory_offset = HEADER_LENGTH + pixels_length + padding;
+    let mut file = BufWriter::new(File::create(out)?);
+    file.write_all(b"II")?;
+    file.write_all(&43u16.to_le_bytes())?;
+    file.write_all(&8u16.to_le_bytes())?;
+    file.write_all(&0u16.to_le_bytes())?;
+    file.write_all(&directory_offset.to_le_bytes())?;
+
+    let mut offsets = Vec::with_capacity(strips as usize);
+    let mut lengths = Vec::with_capacity(strips as usize);
+    let mut offset = HEADER_LENGTH;
+    for i in 0..strips {
+        let pixels = strip(i)?;
+        offsets.push(offset);
+        lengths.push(pixels.len() as u64);
+        offset += pixels.len() as u64;
+        file.write_all(&pixels)?;
+    }
+    if offset != HEADER_LENGTH + pixels_length {
+        bail!("the strips do not add up to a {}x{} picture", width, height);
+    }
+    file.write_all(&vec![0u8; padding as usize])?;
+
+    // With one strip its offset and length fit in their entries; with more, the
+    // lists follow the directory.
+    let lists_offset = directory_offset + 8 + ENTRY_COUNT * 20 + 8;
+    let (offsets_value, lengths_value) = if strips == 1 {
+        (offsets[0], lengths[0])
+    } else {
+        (lists_offset, lists_offset + 8 * u64::from(strips))
+    };
+    let strips = u64::from(strips);
+    // By tag, in order: the tag, its type, how many values and the value, or where
+    // the values are if they do not fit in 8 bytes.
+    let entries: [(u16, u16, u64, u64); ENTRY_COUNT as usize] = [
+        (256, LONG, 1, u64::from(width)),
+        (257, LONG, 1, u64::from(height)),
+        // BitsPerSample: 8, 8 and 8, packed.
+        (258, SHORT, 3, 8 | 8 << 16 | 8 << 32),
+        // Compression: none.
+        (259, SHORT, 1, 1),
+        // PhotometricInterpretation: RGB.
+        (262, SHORT, 1, 2),
+        (273, LONG8, strips, offsets_value),
+        // SamplesPerPixel.
+        (277, SHORT, 1, 3),
+        (278, LONG, 1, u64::from(rows_per_strip.min(height))),
+        (279, LONG8, strips, lengths_value),
+        // PlanarConfiguration: the samples of each pixel together.
+        (284, SHORT, 1, 1),
+    ];
+    file.write_all(&ENTRY_COUNT.to_le_bytes())?;
+    for &(tag, kind, count, value) in entries.iter() {
+        file.write_all(&tag.to_le_bytes())?;
+        file.write_all(&kind.to_le_bytes())?;
+        file.write_all(&count.to_le_bytes())?;
+        file.write_all(&value.to_le_bytes())?;
+    }
+    // There is no next directory.
+    file.write_all(&0u64.to_le_bytes())?;
+    if strips > 1 {
+        for value in offsets.iter().chain(&lengths) {
+            file.write_all(&value.to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+fn tile_dir(out: &Path) -> PathBuf {
+    let mut name = out.file_name().unwrap_or_default().to_os_string();
+    name.push(".tiles");
+    out.with_file_name(name)
+}
+
+fn tile_path(tile_dir: &Path, row: u32, column: u32) -> PathBuf {
+    tile_dir.join(format!("tile_{:04}_{:04}.rgb", row, column))
+}
+
+// Keep any tiles from an earlier run of this same render, and only those.
+fn prepare_tile_dir(tile_dir: &Path, manifest: &TileManifest) -> Result<()> {
+    let manifest_path = tile_dir.join("manifest.json");
+    if tile_dir.exists() {
+        let previous = fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<TileManifest>(&json).ok());
+        if previous.as_ref() == Some(manifest) {
+            println!("resuming from the tiles in {}", tile_dir.display());
+            return Ok(());
+        }
+        println!(
+            "starting over; {} is from another render",
+            tile_dir.display()
+        );
+        fs::remove_dir_all(tile_dir)?;
+    }
+    fs::create_dir_all(tile_dir)?;
+    fs::write(&manifest_path, serde_json::to_string(manifest)?)?;
+    Ok(())
+}
@@ -12,70 +12,751 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    error::{Fallible, StampedeError},
+    names,
+    ops::{self, OpDescriptor, RESTORE_OPCODE},
+};
 use lazy_static::lazy_static;
 use rand::prelude::*;
-use std::{f32::consts::PI, mem};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    mem,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
 use wgpu;
 
+// Both of these are bound as storage buffers, so they may be raised freely; the
+// instruction count must match the interpreter's in include/interpreter.glsl.
 pub const INSTRUCTION_COUNT: usize = 128;
 pub const CONSTANT_POOL_SIZE: usize = 1024;
+// The interpreter's value stack is a fixed array, so this must match its
+// STACK_DEPTH. Every child but the last leaves a value waiting, so a layer that
+// fits in INSTRUCTION_COUNT never goes deeper than this; the encoder still
+// measures how deep each layer goes.
+pub const STACK_DEPTH: usize = INSTRUCTION_COUNT / 2 + 1;
+// Likewise for the positions saved by ops that remap the plane under their child,
+// which must match the interpreter's REMAP_DEPTH.
+pub const REMAP_DEPTH: usize = 8;
+
+// Every instruction stream starts with a header: the format version, then how many
+// instructions and constants follow, then a word kept for later. A stream of
+// another version is refused, so that old data is never misread by new opcodes.
+// These must match the interpreter's.
+pub const ENCODING_VERSION: u32 = 1;
+pub const HEADER_SIZE: usize = 4;
+const INSTRUCTION_BUFFER_LEN: usize = HEADER_SIZE + INSTRUCTION_COUNT;
+
+// How much of the red-green range is left to a tree by narrow_red_green.
+const RED_GREEN_SAFE_SCALE: f32 = 0.25;
 
 pub struct InstructionEncoder {
-    instrs: [u32; INSTRUCTION_COUNT],
+    instrs: [u32; INSTRUCTION_BUFFER_LEN],
     instr_offset: usize,
 
-    constant_pool: [f32; CONSTANT_POOL_SIZE],
+    constant_pool: [[f32; 4]; CONSTANT_POOL_SIZE],
     pool_offset: usize,
+
+    // Values on the interpreter's stack after the last instruction, and the most
+    // there have been at once.
+    stack_depth: usize,
+    max_stack_depth: usize,
+    // Likewise for the positions saved by remaps.
+    remap_depth: usize,
+    max_remap_depth: usize,
 }
 
 impl InstructionEncoder {
     pub fn instruction_buffer_size() -> wgpu::BufferAddress {
-        mem::size_of::<[u64; INSTRUCTION_COUNT]>() as wgpu::BufferAddress
+        mem::size_of::<[u32; INSTRUCTION_BUFFER_LEN]>() as wgpu::BufferAddress
     }
 
     pub fn pool_buffer_size() -> wgpu::BufferAddress {
-        mem::size_of::<[f32; CONSTANT_POOL_SIZE]>() as wgpu::BufferAddress
+        mem::size_of::<[[f32; 4]; CONSTANT_POOL_SIZE]>() as wgpu::BufferAddress
+    }
+
+    // Layers share one instruction buffer and one constant pool buffer, each at a
+    // multiple of these strides, since a storage binding has to start on an
+    // aligned offset; see compute::create_bind_group.
+    pub fn instruction_layer_stride() -> wgpu::BufferAddress {
+        align_binding(Self::instruction_buffer_size())
+    }
+
+    pub fn pool_layer_stride() -> wgpu::BufferAddress {
+        align_binding(Self::pool_buffer_size())
     }
 
     pub fn new() -> Self {
         Self {
-            instrs: [0u32; INSTRUCTION_COUNT],
+            instrs: [0u32; INSTRUCTION_BUFFER_LEN],
             instr_offset: 0,
-            constant_pool: [0f32; CONSTANT_POOL_SIZE],
+            constant_pool: [[0f32; 4]; CONSTANT_POOL_SIZE],
             pool_offset: 0,
+            stack_depth: 0,
+            max_stack_depth: 0,
+            remap_depth: 0,
+            max_remap_depth: 0,
+        }
+    }
+
+    // Counts keep going past the end of the buffers, so that a layer that does not
+    // fit can still be measured; see Tree::stats.
+    pub fn stats(&self) -> LayerStats {
+        LayerStats {
+            node_count: self.instr_offset,
+            constant_count: self.pool_offset,
+            stack_depth: self.max_stack_depth,
+            remap_depth: self.max_remap_depth,
         }
     }
 
-    pub fn finish(self) -> ([u32; INSTRUCTION_COUNT], [f32; CONSTANT_POOL_SIZE]) {
+    pub fn finish(
+        mut self,
+    ) -> (
+        [u32; INSTRUCTION_BUFFER_LEN],
+        [[f32; 4]; CONSTANT_POOL_SIZE],
+    ) {
+        self.instrs[0] = ENCODING_VERSION;
+        self.instrs[1] = self.instr_offset.min(INSTRUCTION_COUNT) as u32;
+        self.instrs[2] = self.pool_offset.min(CONSTANT_POOL_SIZE) as u32;
         (self.instrs, self.constant_pool)
     }
 
-    pub fn push<Op: Opcode>(&mut self, op: &Op) {
-        let children = op.get_children();
-        let consts = op.get_constants();
-        for child in children {
-            child.encode(self);
+    // A whole tree, in the form that the interpreter sees it, or an error if any
+    // layer would overflow the interpreter's buffers.
+    pub fn encode(tree: &Tree) -> Fallible<EncodedTree> {
+        Ok(EncodedTree {
+            layers: [
+                tree.encode_layer(0)?,
+                tree.encode_layer(1)?,
+                tree.encode_layer(2)?,
+            ],
+            sidecar: Sidecar { time: tree.time },
+        })
+    }
+
+    // Rebuild a tree from its encoding. Malformed input is an error rather than a
+    // panic, and the result is encoded again and compared, so a tree that decodes
+    // is exactly the tree that was encoded.
+    pub fn decode(encoded: &EncodedTree) -> Fallible<Tree> {
+        let mut arena = TreeArena::new();
+        let mut layers = [NodeId(0); 3];
+        for (root, layer) in layers.iter_mut().zip(&encoded.layers) {
+            *root = Self::decode_layer(&layer.instrs, &layer.constant_pool, &mut arena)?;
+        }
+        let tree = Tree {
+            arena,
+            layers,
+            time: encoded.sidecar.time,
+        };
+        let reencoded = Self::encode(&tree)?;
+        for (i, (a, b)) in encoded.layers.iter().zip(&reencoded.layers).enumerate() {
+            if !a.is_identical(b) {
+                bail!(Encoding, "layer {} does not survive a round trip", i);
+            }
+        }
+        Ok(tree)
+    }
+
+    pub fn decode_layer(
+        instrs: &[u32],
+        pool: &[[f32; 4]],
+        arena: &mut TreeArena,
+    ) -> Fallible<NodeId> {
+        Self::run_layer(instrs, pool, |_, op, consts, children, _, _| {
+            arena.push(op, consts, &children)
+        })
+    }
+
+    // Walk a layer the way the interpreter does, handing each instruction its
+    // decoded constants, the values it pops, what is left on the stack under them,
+    // and the remaps it is under, outermost first. Whatever `apply` returns is
+    // pushed in their place. An op with a remap is applied at its restore.
+    fn run_layer<T>(
+        instrs: &[u32],
+        pool: &[[f32; 4]],
+        mut apply: impl FnMut(
+            usize,
+            &'static OpDescriptor,
+            Vec<Constant>,
+            Vec<T>,
+            &[T],
+            &[(&'static OpDescriptor, Vec<Constant>)],
+        ) -> T,
+    ) -> Fallible<T> {
+        if instrs.len() < HEADER_SIZE {
+            bail!(
+                Encoding,
+                "the instruction stream is too short to have a header"
+            );
+        }
+        if instrs[0] != ENCODING_VERSION {
+            bail!(
+                Encoding,
+                "the instructions are in format version {}, not {}",
+                instrs[0],
+                ENCODING_VERSION
+            );
+        }
+        let (instr_count, pool_count) = (instrs[1] as usize, instrs[2] as usize);
+        let body = &instrs[HEADER_SIZE..];
+        if instr_count > body.len().min(INSTRUCTION_COUNT) || pool_count > pool.len() {
+            bail!(
+                Encoding,
+                "the header claims {} instructions and {} constants, more than the stream holds",
+                instr_count,
+                pool_count
+            );
+        }
+        let mut stack: Vec<T> = Vec::new();
+        let mut remaps = Vec::new();
+        let mut pool_offset = 0;
+        for (i, &instr) in body[..instr_count].iter().enumerate() {
+            let opcode = (instr & 0xFF) as usize;
+            let child_count = ((instr >> 8) & 0xFF) as usize;
+            let const_count = ((instr >> 16) & 0xFF) as usize;
+            let wrap_mask = instr >> 24;
+            // The interpreter skips anything it does not know, but only zero is
+            // ever written as padding.
+            if opcode == 0 {
+                continue;
+            }
+            if opcode == RESTORE_OPCODE {
+                if child_count != 1 || const_count != 0 {
+                    bail!(Encoding, "instruction {} is a malformed restore", i);
+                }
+                let (op, consts) = match remaps.pop() {
+                    Some(remap) => remap,
+                    None => bail!(Encoding, "instruction {} restores outside of a remap", i),
+                };
+                let children = match stack.pop() {
+                    Some(child) => vec![child],
+                    None => bail!(Encoding, "instruction {} underflows the stack", i),
+                };
+                let value = apply(i, op, consts, children, &stack, &remaps);
+                stack.push(value);
+                continue;
+            }
+            let op = match OpDescriptor::find(opcode) {
+                Some(op) => op,
+                None => bail!(Encoding, "instruction {} has unknown opcode {}", i, opcode),
+            };
+            // A remap's own instruction comes before its child, so takes none.
+            let expected_children = if op.remap.is_some() {
+                0
+            } else {
+                op.children.len()
+            };
+            if const_count != op.constants.len() || child_count != expected_children {
+                bail!(
+                    Encoding,
+                    "instruction {}: {} takes {} constants and {} children, not {} and {}",
+                    i,
+                    op.name,
+                    op.constants.len(),
+                    expected_children,
+                    const_count,
+                    child_count
+                );
+            }
+            if child_count > stack.len() {
+                bail!(Encoding, "instruction {} underflows the stack", i);
+            }
+            if pool_offset + const_count > pool_count {
+                bail!(Encoding, "instruction {} overruns the constant pool", i);
+            }
+            let children = stack.split_off(stack.len() - child_count);
+            let consts = (0..const_count)
+                .map(|j| Constant::decode(pool[pool_offset + j], wrap_mask & (1 << j) != 0))
+                .collect();
+            pool_offset += const_count;
+            if op.remap.is_some() {
+                remaps.push((op, consts));
+                continue;
+            }
+            let value = apply(i, op, consts, children, &stack, &remaps);
+            stack.push(value);
+        }
+        if !remaps.is_empty() {
+            bail!(Encoding, "{} remaps are never restored", remaps.len());
+        }
+        if stack.len() != 1 {
+            bail!(
+                Encoding,
+                "expected one value left on the stack, found {}",
+                stack.len()
+            );
+        }
+        if pool_offset != pool_count {
+            bail!(
+                Encoding,
+                "the header claims {} constants, but the instructions use {}",
+                pool_count,
+                pool_offset
+            );
+        }
+        Ok(stack.pop().expect("one value"))
+    }
+
+    pub fn push(&mut self, arena: &TreeArena, id: NodeId) {
+        let children = arena.children(id);
+        let consts = arena.constants(id);
+        // A remap moves the position ahead of its child and restores it after; see
+        // OpDescriptor::remap.
+        if arena.op(id).remap.is_some() {
+            self.push_instr(arena.op(id).opcode, consts, 0);
+            self.remap_depth += 1;
+            self.max_remap_depth = self.max_remap_depth.max(self.remap_depth);
+            for &child in children {
+                self.push(arena, child);
+            }
+            self.push_instr(RESTORE_OPCODE, &[], children.len());
+            self.remap_depth -= 1;
+            return;
+        }
+        for &child in children {
+            self.push(arena, child);
+        }
+        self.push_instr(arena.op(id).opcode, consts, children.len());
+        // The children have left their values on the stack; this takes them off
+        // and leaves its own.
+        self.stack_depth = self.stack_depth + 1 - children.len();
+        self.max_stack_depth = self.max_stack_depth.max(self.stack_depth);
+    }
+
+    fn push_instr(&mut self, opcode: usize, consts: &[Constant], child_count: usize) {
+        // The top byte tells the interpreter which of this op's constants mirror at
+        // their limits rather than repeating.
+        debug_assert!(consts.len() <= 8);
+        let mut wrap_mask = 0u32;
+        for (i, v) in consts.iter().enumerate() {
+            if v.wrap_mode == WrapMode::Mirror {
+                wrap_mask |= 1 << i;
+            }
+            self.push_constant(v.encode());
         }
-        for v in consts {
-            self.push_constant(v.value());
+        let op_bits = wrap_mask << 24
+            | ((consts.len() & 0xFF) as u32) << 16
+            | ((child_count & 0xFF) as u32) << 8
+            | (opcode as u32);
+        if let Some(slot) = self.instrs[HEADER_SIZE..].get_mut(self.instr_offset) {
+            *slot = op_bits;
         }
-        let op_bits = ((consts.len() & 0xFF) as u32) << 16
-            | ((children.len() & 0xFF) as u32) << 8
-            | (Op::opcode() as u32);
-        self.instrs[self.instr_offset] = op_bits;
         self.instr_offset += 1;
     }
 
-    pub fn push_constant(&mut self, value: f32) {
-        self.constant_pool[self.pool_offset] = value;
+    pub fn push_constant(&mut self, value: [f32; 4]) {
+        if let Some(slot) = self.constant_pool.get_mut(self.pool_offset) {
+            *slot = value;
+        }
         self.pool_offset += 1;
     }
 }
 
-pub trait Opcode {
-    fn opcode() -> usize;
-    fn get_constants(&self) -> &[Constant];
-    fn get_children(&self) -> &[Box<Node>];
+// One layer as the interpreter sees it.
+#[derive(Clone)]
+pub struct EncodedLayer {
+    instrs: [u32; INSTRUCTION_BUFFER_LEN],
+    constant_pool: [[f32; 4]; CONSTANT_POOL_SIZE],
+}
+
+impl EncodedLayer {
+    // Bitwise, so that NaN constants compare equal to themselves.
+    fn is_identical(&self, other: &Self) -> bool {
+        self.instrs[..] == other.instrs[..]
+            && self
+                .constant_pool
+                .iter()
+                .flatten()
+                .zip(other.constant_pool.iter().flatten())
+                .all(|(a, b)| a.to_bits() == b.to_bits())
+    }
+}
+
+// How much of the interpreter's room one layer takes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LayerStats {
+    pub node_count: usize,
+    pub constant_count: usize,
+    pub stack_depth: usize,
+    pub remap_depth: usize,
+}
+
+impl LayerStats {
+    pub fn check(&self) -> Fallible<()> {
+        if self.node_count > INSTRUCTION_COUNT {
+            bail!(
+                Encoding,
+                "{} nodes, but the interpreter runs at most {}",
+                self.node_count,
+                INSTRUCTION_COUNT
+            );
+        }
+        if self.constant_count > CONSTANT_POOL_SIZE {
+            bail!(
+                Encoding,
+                "{} constants, but the pool holds at most {}",
+                self.constant_count,
+                CONSTANT_POOL_SIZE
+            );
+        }
+        if self.stack_depth > STACK_DEPTH {
+            bail!(
+                Encoding,
+                "a stack {} deep, but the interpreter's holds {}",
+                self.stack_depth,
+                STACK_DEPTH
+            );
+        }
+        if self.remap_depth > REMAP_DEPTH {
+            bail!(
+                Encoding,
+                "remaps {} deep, but the interpreter saves at most {} positions",
+                self.remap_depth,
+                REMAP_DEPTH
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TreeStats {
+    pub layers: [LayerStats; 3],
+}
+
+impl TreeStats {
+    pub fn stack_depth(&self) -> usize {
+        self.layers.iter().map(|l| l.stack_depth).max().unwrap_or(0)
+    }
+
+    // A tree that overflows any of the interpreter's buffers would come out as
+    // garbage, or worse, so it is turned away rather than shown.
+    pub fn check(&self) -> Fallible<()> {
+        for (name, layer) in ["r", "g", "b"].iter().zip(&self.layers) {
+            if let Err(StampedeError::Encoding(e)) = layer.check() {
+                bail!(Encoding, "layer {} does not fit: {}", name, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+// One instruction of a layer run on the CPU: the constants it saw at the
+// context's time and the whole value stack once it had run, top last.
+#[derive(Clone, Debug)]
+pub struct TraceStep {
+    pub index: usize,
+    pub op: &'static str,
+    pub constants: Vec<f32>,
+    pub stack: Vec<f32>,
+}
+
+impl EncodedLayer {
+    // Evaluate the instruction stream itself, rather than the tree it came from,
+    // so that a disagreement between the two points at the encoder or at the
+    // interpreter's reading of it.
+    pub fn trace(&self, ctx: &EvalContext) -> Fallible<Vec<TraceStep>> {
+        let mut steps = Vec::new();
+        InstructionEncoder::run_layer(
+            &self.instrs,
+            &self.constant_pool,
+            |index, op, consts, children: Vec<f32>, below, remaps| {
+                let constants = consts
+                    .iter()
+                    .map(|c| c.value_at(ctx.time))
+                    .collect::<Vec<_>>();
+                let ctx = remaps
+                    .iter()
+                    .fold(ctx.clone(), |ctx, (op, consts)| ctx.remapped(op, consts));
+                let value = (op.evaluate)(&constants, &children, &ctx);
+                let mut stack = below.to_vec();
+                stack.push(value);
+                steps.push(TraceStep {
+                    index,
+                    op: op.name,
+                    constants,
+                    stack,
+                });
+                value
+            },
+        )?;
+        Ok(steps)
+    }
+}
+
+// The parts of a tree that the interpreter has no use for.
+pub struct Sidecar {
+    time: f32,
+}
+
+pub struct EncodedTree {
+    layers: [EncodedLayer; 3],
+    sidecar: Sidecar,
+}
+
+impl EncodedTree {
+    pub fn layer(&self, offset: usize) -> &EncodedLayer {
+        &self.layers[offset]
+    }
+
+    // Each layer's instructions then its constants, then the time, all little
+    // endian. The instruction header carries the version, so there is none here.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for layer in &self.layers {
+            for instr in layer.instrs.iter() {
+                bytes.extend_from_slice(&instr.to_le_bytes());
+            }
+            for v in layer.constant_pool.iter().flatten() {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.sidecar.time.to_le_bytes());
+        bytes
+    }
+
+    // Only the size is checked here; InstructionEncoder::decode checks the rest.
+    pub fn from_bytes(bytes: &[u8]) -> Fallible<Self> {
+        let layer_len = 4 * (INSTRUCTION_BUFFER_LEN + 4 * CONSTANT_POOL_SIZE);
+        if bytes.len() != 3 * layer_len + 4 {
+            bail!(
+                Encoding,
+                "an encoded tree is {} bytes, not {}",
+                bytes.len(),
+                3 * layer_len + 4
+            );
+        }
+        let word = |at: usize| {
+            let mut b = [0u8; 4];
+            b.copy_from_slice(&bytes[at..at + 4]);
+            b
+        };
+        let layer = |l: usize| {
+            let base = l * layer_len;
+            let mut layer = EncodedLayer {
+                instrs: [0u32; INSTRUCTION_BUFFER_LEN],
+                constant_pool: [[0f32; 4]; CONSTANT_POOL_SIZE],
+            };
+            for (i, instr) in layer.instrs.iter_mut().enumerate() {
+                *instr = u32::from_le_bytes(word(base + 4 * i));
+            }
+            let pool_base = base + 4 * INSTRUCTION_BUFFER_LEN;
+            for (i, v) in layer.constant_pool.iter_mut().flatten().enumerate() {
+                *v = f32::from_le_bytes(word(pool_base + 4 * i));
+            }
+            layer
+        };
+        Ok(Self {
+            layers: [layer(0), layer(1), layer(2)],
+            sidecar: Sidecar {
+                time: f32::from_le_bytes(word(3 * layer_len)),
+            },
+        })
+    }
+}
+
+// The alignment of a buffer binding's offset that every backend accepts.
+const BINDING_ALIGNMENT: wgpu::BufferAddress = 256;
+
+fn align_binding(size: wgpu::BufferAddress) -> wgpu::BufferAddress {
+    (size + BINDING_ALIGNMENT - 1) / BINDING_ALIGNMENT * BINDING_ALIGNMENT
+}
+
+// Extends the last run of indices if index follows on from it, or starts another.
+fn extend_runs(runs: &mut Vec<(usize, usize)>, index: usize) {
+    match runs.last_mut() {
+        Some((start, count)) if *start + *count == index => *count += 1,
+        _ => runs.push((index, 1)),
+    }
+}
+
+// The copies needed to bring a set of layers' shared buffers up to date. Changed
+// instructions and constants are packed together in one staging buffer each and
+// copied out in runs, so that neighboring layers that both changed take one copy.
+pub struct LayerUpload {
+    instr_buffer: Option<wgpu::Buffer>,
+    pool_buffer: Option<wgpu::Buffer>,
+    // (first layer, count) of each run of layers whose instructions changed.
+    instr_runs: Vec<(usize, usize)>,
+    // (first constant, count) of each changed run, with each layer's constants
+    // starting at its pool stride.
+    pool_runs: Vec<(usize, usize)>,
+}
+
+impl LayerUpload {
+    pub fn byte_count(&self) -> wgpu::BufferAddress {
+        let instr_count = self
+            .instr_runs
+            .iter()
+            .map(|&(_, count)| count)
+            .sum::<usize>();
+        let pool_count = self
+            .pool_runs
+            .iter()
+            .map(|&(_, count)| count)
+            .sum::<usize>();
+        instr_count as wgpu::BufferAddress * InstructionEncoder::instruction_layer_stride()
+            + (pool_count * mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress
+    }
+
+    pub fn copy_to(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        instr_buffer: &wgpu::Buffer,
+        pool_buffer: &wgpu::Buffer,
+    ) {
+        if let Some(upload) = &self.instr_buffer {
+            let stride = InstructionEncoder::instruction_layer_stride();
+            let mut src_offset = 0;
+            for &(first, count) in &self.instr_runs {
+                let size = count as wgpu::BufferAddress * stride;
+                encoder.copy_buffer_to_buffer(
+                    upload,
+                    src_offset,
+                    instr_buffer,
+                    first as wgpu::BufferAddress * stride,
+                    size,
+                );
+                src_offset += size;
+            }
+        }
+        if let Some(upload) = &self.pool_buffer {
+            let stride = mem::size_of::<[f32; 4]>() as wgpu::BufferAddress;
+            let mut src_offset = 0;
+            for &(start, count) in &self.pool_runs {
+                let size = count as wgpu::BufferAddress * stride;
+                encoder.copy_buffer_to_buffer(
+                    upload,
+                    src_offset,
+                    pool_buffer,
+                    start as wgpu::BufferAddress * stride,
+                    size,
+                );
+                src_offset += size;
+            }
+        }
+    }
+}
+
+// Remembers what was last uploaded for a set of layers that share buffers, so that
+// an update only has to copy the instructions and constants that actually changed.
+pub struct LayerMirror {
+    uploaded: Vec<Option<EncodedLayer>>,
+}
+
+impl LayerMirror {
+    pub fn new(layer_count: usize) -> Self {
+        Self {
+            uploaded: vec![None; layer_count],
+        }
+    }
+
+    // The slots of the pool buffer that each layer's constants take.
+    fn pool_slots() -> usize {
+        (InstructionEncoder::pool_layer_stride()
+            / mem::size_of::<[f32; 4]>() as wgpu::BufferAddress) as usize
+    }
+
+    // The (first layer, count) runs of changed instructions and the
+    // (first slot, count) runs of changed constants between what was last
+    // uploaded and layers.
+    fn changes(&self, layers: &[EncodedLayer]) -> (Vec<(usize, usize)>, Vec<(usize, usize)>) {
+        assert_eq!(layers.len(), self.uploaded.len());
+        let pool_slots = Self::pool_slots();
+        let mut instr_runs = Vec::new();
+        let mut pool_runs = Vec::new();
+        for (i, (prior, layer)) in self.uploaded.iter().zip(layers).enumerate() {
+            let base = i * pool_slots;
+            match prior {
+                Some(prior) => {
+                    if prior.instrs[..] != layer.instrs[..] {
+                        extend_runs(&mut instr_runs, i);
+                    }
+                    for (j, (a, b)) in prior
+                        .constant_pool
+                        .iter()
+                        .zip(&layer.constant_pool[..])
+                        .enumerate()
+                    {
+                        if a != b {
+                            extend_runs(&mut pool_runs, base + j);
+                        }
+                    }
+                }
+                None => {
+                    extend_runs(&mut instr_runs, i);
+                    for j in 0..CONSTANT_POOL_SIZE {
+                        extend_runs(&mut pool_runs, base + j);
+                    }
+                }
+            }
+        }
+        (instr_runs, pool_runs)
+    }
+
+    // Takes one layer for each that the mirror was made with. Returns None when
+    // the buffers already hold these layers.
+    pub fn update(
+        &mut self,
+        layers: Vec<EncodedLayer>,
+        device: &wgpu::Device,
+    ) -> Option<LayerUpload> {
+        let instr_words = (InstructionEncoder::instruction_layer_stride()
+            / mem::size_of::<u32>() as wgpu::BufferAddress) as usize;
+        let pool_slots = Self::pool_slots();
+        let (instr_runs, pool_runs) = self.changes(&layers);
+        if instr_runs.is_empty() && pool_runs.is_empty() {
+            return None;
+        }
+        let instr_buffer = if instr_runs.is_empty() {
+            None
+        } else {
+            // Each layer padded out to its stride, so that a run copies as one.
+            let mut packed = Vec::new();
+            for &(first, count) in &instr_runs {
+                for layer in &layers[first..first + count] {
+                    packed.extend_from_slice(&layer.instrs);
+                    packed.resize(packed.len() + instr_words - layer.instrs.len(), 0u32);
+                }
+            }
+            Some(
+                device
+                    .create_buffer_mapped(packed.len(), wgpu::BufferUsage::COPY_SRC)
+                    .fill_from_slice(&packed),
+            )
+        };
+        let pool_buffer = if pool_runs.is_empty() {
+            None
+        } else {
+            let packed = pool_runs
+                .iter()
+                .flat_map(|&(start, count)| start..start + count)
+                .map(|k| layers[k / pool_slots].constant_pool[k % pool_slots])
+                .collect::<Vec<_>>();
+            Some(
+                device
+                    .create_buffer_mapped(packed.len(), wgpu::BufferUsage::COPY_SRC)
+                    .fill_from_slice(&packed),
+            )
+        };
+        for (uploaded, layer) in self.uploaded.iter_mut().zip(layers) {
+            *uploaded = Some(layer);
+        }
+        Some(LayerUpload {
+            instr_buffer,
+            pool_buffer,
+            instr_runs,
+            pool_runs,
+        })
+    }
 }
 
 fn prefix(level: usize) -> String {
@@ -86,414 +767,2002 @@ fn prefix(level: usize) -> String {
     s
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum WrapMode {
     Repeat,
     Mirror,
 }
 
 impl WrapMode {
-    pub fn from_name(name: &'static str) -> Self {
-        match name {
+    pub fn from_name(name: &str) -> Fallible<Self> {
+        Ok(match name {
             "m" => Self::Mirror,
             "r" => Self::Repeat,
             "f" => Self::Repeat, // "fixed" does not wrap, so we can pick anything
-            _ => panic!("Unknown wrap mode name"),
-        }
+            _ => bail!(Invalid, "unknown wrap mode {}; expected m, r or f", name),
+        })
+    }
+}
+
+// Uniform in [low, high), or low for an empty range, which gen_range panics on.
+fn uniform(rng: &mut StdRng, low: f32, high: f32) -> f32 {
+    if low < high {
+        rng.gen_range(low, high)
+    } else {
+        low
     }
 }
 
-pub const RATE_SCALE: f32 = 500f32;
+// In a coherent tree, how far green and blue's constants stray from red's, as a
+// share of each constant's range, and how often one of their leaves is a different
+// leaf altogether.
+const COHERENT_SPREAD: f32 = 0.1;
+const COHERENT_LEAF_CHANCE: f32 = 0.1;
+
+// Ops that compute only from their children and constants, never from where or
+// when they are evaluated, so that they can be folded away when those are fixed.
+const PURE_OPS: [&str; 10] = [
+    "absolute",
+    "invert",
+    "add",
+    "subtract",
+    "multiply",
+    "divide",
+    "modulus",
+    "exponentiate",
+    "sinc",
+    "sine",
+];
+
+// Ops whose children can be swapped without changing what they make.
+const COMMUTATIVE_OPS: [&str; 2] = ["add", "multiply"];
+
+// Constants that land in the same of this many slices of their range, in value
+// and in speed, are taken as the same by the canonical form.
+const CONSTANT_BUCKETS: f32 = 32f32;
+
+// A multiply by less than this is taken as a multiply by zero.
+const NEGLIGIBLE: f32 = 1e-6;
+
+// Rates are in units per second: at most, a constant will sweep its full range
+// over 500 frames at 60fps.
+pub const RATE_SCALE: f32 = 500f32 / 60f32;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Constant {
     limits: [f32; 2],
     value: f32,
     rate: f32,
     wrap_mode: WrapMode,
+
+    // The rate to go back to when a frozen constant is let go; frozen constants
+    // hold still with no rate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    frozen_rate: Option<f32>,
 }
 
 impl Constant {
-    pub fn new(rng: &mut StdRng, min_bound: f32, max_bound: f32, mode_name: &'static str) -> Self {
+    pub fn new(
+        rng: &mut StdRng,
+        min_bound: f32,
+        max_bound: f32,
+        mode_name: &'static str,
+    ) -> Fallible<Self> {
         let rate = if mode_name != "f" {
-            rng.gen_range(min_bound / RATE_SCALE, max_bound / RATE_SCALE)
+            uniform(rng, min_bound / RATE_SCALE, max_bound / RATE_SCALE)
         } else {
             0f32
         };
-        Self {
+        Ok(Self {
             limits: [min_bound, max_bound],
-            value: rng.gen_range(min_bound, max_bound),
+            value: uniform(rng, min_bound, max_bound),
             rate,
-            wrap_mode: WrapMode::from_name(mode_name),
-        }
+            wrap_mode: WrapMode::from_name(mode_name)?,
+            frozen_rate: None,
+        })
     }
 
-    pub fn value(&self) -> f32 {
-        self.value
+    // A constant with a chosen value, clamped to its limits, and rate, for trees
+    // that are written out by hand.
+    pub fn with_value(
+        min_bound: f32,
+        max_bound: f32,
+        mode_name: &'static str,
+        value: f32,
+        rate: f32,
+    ) -> Fallible<Self> {
+        Ok(Self {
+            limits: [min_bound, max_bound],
+            value: value.max(min_bound).min(max_bound),
+            rate,
+            wrap_mode: WrapMode::from_name(mode_name)?,
+            frozen_rate: None,
+        })
     }
 
-    pub fn animate(&mut self) {
-        self.value += self.rate;
-        if self.value < self.limits[0] {
-            match self.wrap_mode {
-                WrapMode::Repeat => self.value += (self.limits[1] - self.limits[0]),
-                WrapMode::Mirror => {
-                    self.value = self.limits[0] + (self.limits[0] - self.value);
-                    self.rate *= -1f32;
-                }
-            }
+    // Animation is evaluated in closed form from the value at time zero so that the
+    // shader can do the same with nothing more than the current time. This must
+    // match animate_constant in include/interpreter.glsl. A constant with an empty
+    // range has nowhere to go, so it stays at its low limit.
+    pub fn value_at(&self, time: f32) -> f32 {
+        let range = self.limits[1] - self.limits[0];
+        if !(range > 0f32) {
+            return self.limits[0];
         }
-        if self.value > self.limits[1] {
-            match self.wrap_mode {
-                WrapMode::Repeat => self.value -= (self.limits[1] - self.limits[0]),
-                WrapMode::Mirror => {
-                    self.value = self.limits[1] - (self.value - self.limits[1]);
-                    self.rate *= -1f32;
-                }
+        let offset = self.value - self.limits[0] + self.rate * time;
+        match self.wrap_mode {
+            WrapMode::Repeat => self.limits[0] + offset.rem_euclid(range),
+            WrapMode::Mirror => {
+                self.limits[0] + range - (offset.rem_euclid(2f32 * range) - range).abs()
             }
         }
     }
-}
 
-macro_rules! make_op {
-    ($op_name:ident [$opcode:literal] {
-        constants($const_count:literal) => [$($const_name:ident[$min_bound:expr,$max_bound:expr,$wrap_mode:ident]),*],
-        children($child_count:literal) => [$($child_name:ident),*]
-    }) => {
-        #[derive(Debug)]
-        pub struct $op_name {
-            consts: [Constant; $const_count],
-            children: [Box<Node>; $child_count]
+    // Move the base value so that the constant reads value at time, clamped to its
+    // limits. It carries on animating from there.
+    pub fn set_value_at(&mut self, time: f32, value: f32) {
+        let value = value.max(self.limits[0]).min(self.limits[1]);
+        self.value = value - self.rate * time;
+    }
+
+    // Draw the value and rate again within the limits, as a new constant would
+    // have them. Fixed and frozen constants have been chosen, so they are kept.
+    pub fn reroll(&mut self, rng: &mut StdRng) {
+        if self.rate == 0f32 {
+            return;
         }
+        self.value = uniform(rng, self.limits[0], self.limits[1]);
+        self.rate = uniform(
+            rng,
+            self.limits[0] / RATE_SCALE,
+            self.limits[1] / RATE_SCALE,
+        );
+    }
 
-        impl $op_name {
-            pub fn new(rng: &mut StdRng, _count: &mut usize) -> Self {
-                Self {
-                    consts: [
-                        $(
-                            Constant::new(rng, ($min_bound) as f32, ($max_bound) as f32, stringify!($wrap_mode))
-                            //rng.gen_range(($min_bound) as f32, ($max_bound) as f32)
-                        ),*
-                    ],
-                    children: [
-                        $(
-                            Box::new(Node::new(rng, _count, stringify!($child_name)))
-                        ),*
-                    ],
-                }
-            }
+    // Nudge the base value by up to spread of the range either way, clamped to the
+    // limits.
+    pub fn perturb(&mut self, rng: &mut StdRng, spread: f32) {
+        let range = self.limits[1] - self.limits[0];
+        let value = self.value + uniform(rng, -spread, spread) * range;
+        self.value = value.max(self.limits[0]).min(self.limits[1]);
+    }
 
-            pub fn animate(&mut self) {
-                for child in self.children.iter_mut() {
-                    child.animate();
-                }
-                for c in self.consts.iter_mut() {
-                    c.animate();
-                }
-            }
-            /*
-            #[allow(dead_code)]
-            pub fn with_constants($($const_name: f32),*) -> Self {
-                let _rng = &mut thread_rng();
-                let _count = &mut 0;
-                Self {
-                    consts: [
-                        Constant::new(_rng, -1f32, 1f32, "m"),
-                    ],
-                    children: [
-                        $(
-                            Box::new(Node::new(_rng, _count, stringify!($child_name)))
-                        ),*
-                    ],
-                }
-            }
-            */
+    pub fn limits(&self) -> [f32; 2] {
+        self.limits
+    }
 
-            pub fn show(&self, level: usize) -> String {
-                let cc = self.consts.iter().map(|v| format!("{:0.2}", v.value())).collect::<Vec<String>>().join(", ");
-                if $child_count == 0 {
-                    format!("{}{}({})", prefix(level), stringify!($op_name), cc)
-                } else {
-                    let ch = self.children.iter().map(|c| c.show(level + 1)).collect::<Vec<String>>().join("\n");
-                    format!("{}{}({})-\n{}", prefix(level), stringify!($op_name), cc, ch)
-                }
-            }
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    pub fn wrap_mode(&self) -> WrapMode {
+        self.wrap_mode
+    }
+
+    // The setters below change how the constant moves from now on, without making
+    // it jump: it reads the same at time as it did before, clamped to its limits.
+    pub fn set_limits(&mut self, time: f32, limits: [f32; 2]) {
+        let value = self.value_at(time);
+        self.limits = [limits[0].min(limits[1]), limits[0].max(limits[1])];
+        self.set_value_at(time, value);
+    }
+
+    pub fn set_rate(&mut self, time: f32, rate: f32) {
+        let value = self.value_at(time);
+        self.rate = rate;
+        self.set_value_at(time, value);
+    }
+
+    pub fn set_wrap_mode(&mut self, time: f32, wrap_mode: WrapMode) {
+        let value = self.value_at(time);
+        self.wrap_mode = wrap_mode;
+        self.set_value_at(time, value);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen_rate.is_some()
+    }
+
+    // Stop the constant where it is at time while the rest of the tree carries on.
+    pub fn freeze(&mut self, time: f32) {
+        if !self.is_frozen() {
+            let rate = self.rate;
+            self.set_rate(time, 0f32);
+            self.frozen_rate = Some(rate);
+        }
+    }
+
+    // Carry on from wherever the constant was left.
+    pub fn thaw(&mut self, time: f32) {
+        if let Some(rate) = self.frozen_rate.take() {
+            self.set_rate(time, rate);
         }
+    }
 
-        impl Opcode for $op_name {
-            fn opcode() -> usize {
-                $opcode
-            }
+    // How far through its cycle the constant is at time, in [0,1). Repeating
+    // constants run from low to high; mirrored ones run up over the first half and
+    // back down over the second.
+    pub fn phase_at(&self, time: f32) -> f32 {
+        if !(self.period() > 0f32) {
+            return 0f32;
+        }
+        let offset = self.value - self.limits[0] + self.rate * time;
+        offset.rem_euclid(self.period()) / self.period()
+    }
 
-            fn get_constants(&self) -> &[Constant] {
-                &self.consts
-            }
+    // Move the constant to a phase of its cycle at time. It carries on from there
+    // unless it is frozen.
+    pub fn scrub(&mut self, time: f32, phase: f32) {
+        self.value = self.limits[0] + phase.rem_euclid(1f32) * self.period() - self.rate * time;
+    }
 
-            fn get_children(&self) -> &[Box<Node>] {
-                &self.children
+    fn period(&self) -> f32 {
+        let range = self.limits[1] - self.limits[0];
+        match self.wrap_mode {
+            WrapMode::Repeat => range,
+            WrapMode::Mirror => 2f32 * range,
+        }
+    }
+
+    // Where the constant falls among CONSTANT_BUCKETS, for the canonical form.
+    fn canonical(&self) -> String {
+        let range = self.limits[1] - self.limits[0];
+        let bucket = |v: f32| {
+            if range > 0f32 {
+                (v / range * CONSTANT_BUCKETS).round() as i64
+            } else {
+                0
             }
+        };
+        format!(
+            "{}{}{}{}",
+            match self.wrap_mode {
+                WrapMode::Repeat => "r",
+                WrapMode::Mirror => "m",
+            },
+            bucket(self.value - self.limits[0]),
+            if self.rate < 0f32 { "-" } else { "+" },
+            bucket((self.rate * RATE_SCALE).abs()),
+        )
+    }
+
+    // The layout of a constant in the pool: (base, rate, low, high).
+    pub fn encode(&self) -> [f32; 4] {
+        [self.value, self.rate, self.limits[0], self.limits[1]]
+    }
+
+    // Fixed constants encode as repeating with no rate, so that is what they decode as.
+    pub fn decode(v: [f32; 4], mirror: bool) -> Self {
+        Self {
+            limits: [v[2], v[3]],
+            value: v[0],
+            rate: v[1],
+            wrap_mode: if mirror {
+                WrapMode::Mirror
+            } else {
+                WrapMode::Repeat
+            },
+            frozen_rate: None,
         }
     }
 }
 
-make_op!(ConstOp          [1] { constants(1) => [value[-1,1,m]], children(0) => [] });
-make_op!(EllipseOp        [2] { constants(6) => [p0x[-1,1,m], p0y[-0.8,0.8,m], p1x[-1,1,m], p1y[-0.8,0.8,m], size[0.1,1,m], sharp[1,100,m]], children(0) => [] });
-make_op!(FlowerOp         [3] { constants(7) => [x[-1,1,m], y[-0.8,0.8,m], angle[0,2.0*PI,r], size[0,2.5,m], ratio[0,1,m], n_points[3,25,f], sharpness[2,10,m]], children(0) => [] });
-make_op!(LinearGradientOp [4] { constants(5) => [p0x[-1,1,m], p0y[-0.8,0.8,m], p1x[-1,1,m], p1y[-0.8,0.8,m], sharp[2,20,m]], children(0) => [] });
-make_op!(RadialGradientOp [5] { constants(5) => [p0x[-1,1,m], p0y[-0.8,0.8,m], p1x[-1,1,m], p1y[-0.8,0.8,m], angle[0,2.0*PI,r]], children(0) => [] });
-make_op!(PolarThetaOp     [6] { constants(3) => [x[-1,1,m], y[-0.8,0.8,m], angle[0,2.0*PI,r]], children(0) => [] });
-//
-make_op!(AbsoluteOp       [8] { constants(0) => [], children(1) => [value] });
-make_op!(InvertOp         [9] { constants(0) => [], children(1) => [value] });
-make_op!(AddOp           [10] { constants(0) => [], children(2) => [lhs, rhs] });
-make_op!(SubtractOp      [11] { constants(0) => [], children(2) => [lhs, rhs] });
-make_op!(MultiplyOp      [12] { constants(0) => [], children(2) => [lhs, rhs] });
-make_op!(DivideOp        [13] { constants(0) => [], children(2) => [lhs, rhs] });
-make_op!(ModulusOp       [14] { constants(0) => [], children(2) => [lhs, rhs] });
-make_op!(ExponentOp      [15] { constants(0) => [], children(2) => [lhs, rhs] });
-make_op!(SincOp          [16] { constants(2) => [freq[-PI,PI,r], phase[-PI,PI,r]], children(1) => [input] });
-make_op!(SineOp          [17] { constants(2) => [freq[-PI,PI,r], phase[-PI,PI,r]], children(1) => [input] });
-make_op!(SpiralOp        [18] { constants(4) => [x[-1,1,m], y[-0.8,0.8,m], n[0,10,m], b[-1,1,m]], children(1) => [V] });
-make_op!(SquircleOp      [19] { constants(4) => [x[-1,1,m], y[-0.8,0.8,m], r[0,2,m], n[0,4,m]], children(2) => [a, b] });
-
-#[derive(Debug)]
-pub enum Node {
-    // Leaves
-    Const(ConstOp),
-    Ellipse(EllipseOp),
-    Flower(FlowerOp),
-    LinearGradient(LinearGradientOp),
-    RadialGradient(RadialGradientOp),
-    PolarTheta(PolarThetaOp),
-
-    // Operations
-    Absolute(AbsoluteOp),
-    Invert(InvertOp),
-    Add(AddOp),
-    Subtract(SubtractOp),
-    Multiply(MultiplyOp),
-    Divide(DivideOp),
-    Modulus(ModulusOp),
-    Exponent(ExponentOp),
-    Sinc(SincOp),
-    Sine(SineOp),
-    Spiral(SpiralOp),
-    Squircle(SquircleOp),
+// Nodes refer to their children by their index in the tree's arena.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct NodeId(u32);
+
+// An op and where to find its constants and children in the arena, as (first,
+// count) ranges.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct Node {
+    opcode: usize,
+    constants: (u32, u32),
+    children: (u32, u32),
 }
 
-lazy_static! {
-    static ref LEAF_RATE_TOTAL: f32 = {
-        let mut total = 0.0;
-        for (rate, _, _) in &LEAF_RATES {
-            total += rate;
-        }
-        total
-    };
-    static ref OP_RATE_TOTAL: f32 = {
-        let mut total = 0.0;
-        for (rate, _, _) in &OP_RATES {
-            total += rate;
-        }
-        total
-    };
-}
-
-const LEAF_RATES: [(f32, usize, &'static str); 6] = [
-    (0.01, 1, "const"),
-    (2.00, 2, "ellipse"),
-    (4.00, 3, "flower"),
-    (1.00, 4, "linear gradient"),
-    (2.00, 5, "radial gradient"),
-    (2.00, 6, "polar theta"),
-];
+// Every node of a tree, stored flat, so that building, walking and cloning a tree
+// does not chase a separate heap allocation for every node. Children are always
+// pushed before their parents.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TreeArena {
+    nodes: Vec<Node>,
+    constants: Vec<Constant>,
+    links: Vec<NodeId>,
+}
 
-const OP_RATES: [(f32, usize, &'static str); 12] = [
-    (0.2, 8, "absolute"),
-    (0.1, 9, "invert"),
-    (0.3, 10, "add"),
-    (0.3, 11, "subtract"),
-    (0.3, 12, "multiply"),
-    (0.3, 13, "divide"),
-    (0.5, 14, "modulus"),
-    (0.5, 15, "exponentiate"),
-    (0.0, 16, "sinc"),
-    (0.0, 17, "sine"),
-    (0.2, 18, "spiral"),
-    (2.0, 19, "squircle"),
-];
+// Where generation is about to grow a node: how far below its layer's root, how
+// many nodes the layer has grown before it, and which child of which op it will be,
+// as (opcode, child index), or None for the root.
+pub struct GrowthSite {
+    pub depth: usize,
+    pub count: usize,
+    pub parent: Option<(usize, usize)>,
+}
 
-fn guided_random_walk(rng: &mut StdRng, rates: &[(f32, usize, &'static str)], total: f32) -> usize {
-    let f = rng.gen_range(0f32, total);
-    let mut i = 0;
-    let mut acc = 0f32;
-    while acc <= f {
-        // Note that the interval is half open, so this will always be true.
-        acc += rates[i].0;
-        i += 1;
+// Shapes generated trees by deciding, node by node, where a branch stops with a
+// leaf. Whatever a strategy decides, a layer stops branching once it has grown
+// half of INSTRUCTION_COUNT nodes, so that it always fits.
+pub trait GrowthStrategy {
+    // Before each layer is grown, for strategies that vary from layer to layer.
+    fn begin_layer(&mut self, _rng: &mut StdRng) {}
+
+    fn is_leaf(&mut self, rng: &mut StdRng, site: &GrowthSite) -> bool;
+
+    // The op for a node, once it is known whether it is a leaf; by rate, unless a
+    // strategy knows better.
+    fn choose_op(
+        &mut self,
+        rng: &mut StdRng,
+        _site: &GrowthSite,
+        leaf: bool,
+    ) -> Fallible<&'static OpDescriptor> {
+        guided_random_walk(rng, leaf)
     }
-    i -= 1; // Hence we can subtract safely here.
-    rates[i].1
 }
 
-impl Node {
-    fn new(rng: &mut StdRng, count: &mut usize, _link_name: &str) -> Self {
-        // FIXME: pick a better walk for this
-        let fullness = (*count * 2) as f32 / INSTRUCTION_COUNT as f32;
-        *count += 1;
-        if rng.gen_range(0f32, 1f32) < fullness {
-            let x = guided_random_walk(rng, &LEAF_RATES, *LEAF_RATE_TOTAL);
-            match x {
-                1 => Self::Const(ConstOp::new(rng, count)),
-                2 => Self::Ellipse(EllipseOp::new(rng, count)),
-                3 => Self::Flower(FlowerOp::new(rng, count)),
-                4 => Self::LinearGradient(LinearGradientOp::new(rng, count)),
-                5 => Self::RadialGradient(RadialGradientOp::new(rng, count)),
-                6 => Self::PolarTheta(PolarThetaOp::new(rng, count)),
-                _ => panic!("unknown const opcode"),
-            }
-        } else {
-            let x = guided_random_walk(rng, &OP_RATES, *OP_RATE_TOTAL);
-            match x {
-                8 => Self::Absolute(AbsoluteOp::new(rng, count)),
-                9 => Self::Invert(InvertOp::new(rng, count)),
-                10 => Self::Add(AddOp::new(rng, count)),
-                11 => Self::Subtract(SubtractOp::new(rng, count)),
-                12 => Self::Multiply(MultiplyOp::new(rng, count)),
-                13 => Self::Divide(DivideOp::new(rng, count)),
-                14 => Self::Modulus(ModulusOp::new(rng, count)),
-                15 => Self::Exponent(ExponentOp::new(rng, count)),
-                16 => Self::Sinc(SincOp::new(rng, count)),
-                17 => Self::Sine(SineOp::new(rng, count)),
-                18 => Self::Spiral(SpiralOp::new(rng, count)),
-                19 => Self::Squircle(SquircleOp::new(rng, count)),
-                _ => panic!("unknown opcode"),
-            }
-        }
-    }
-
-    fn show(&self, level: usize) -> String {
-        let l = level + 1;
-        match self {
-            Self::Const(ref op) => op.show(l),
-            Self::Ellipse(ref op) => op.show(l),
-            Self::Flower(ref op) => op.show(l),
-            Self::LinearGradient(ref op) => op.show(l),
-            Self::RadialGradient(ref op) => op.show(l),
-            Self::PolarTheta(ref op) => op.show(l),
-            Self::Absolute(ref op) => op.show(l),
-            Self::Invert(ref op) => op.show(l),
-            Self::Add(ref op) => op.show(l),
-            Self::Subtract(ref op) => op.show(l),
-            Self::Multiply(ref op) => op.show(l),
-            Self::Divide(ref op) => op.show(l),
-            Self::Modulus(ref op) => op.show(l),
-            Self::Exponent(ref op) => op.show(l),
-            Self::Sinc(ref op) => op.show(l),
-            Self::Sine(ref op) => op.show(l),
-            Self::Spiral(ref op) => op.show(l),
-            Self::Squircle(ref op) => op.show(l),
-        }
-    }
-
-    fn encode(&self, encoder: &mut InstructionEncoder) {
-        match self {
-            Self::Const(ref op) => encoder.push(op),
-            Self::Ellipse(ref op) => encoder.push(op),
-            Self::Flower(ref op) => encoder.push(op),
-            Self::LinearGradient(ref op) => encoder.push(op),
-            Self::RadialGradient(ref op) => encoder.push(op),
-            Self::PolarTheta(ref op) => encoder.push(op),
-            Self::Absolute(ref op) => encoder.push(op),
-            Self::Invert(ref op) => encoder.push(op),
-            Self::Add(ref op) => encoder.push(op),
-            Self::Subtract(ref op) => encoder.push(op),
-            Self::Multiply(ref op) => encoder.push(op),
-            Self::Divide(ref op) => encoder.push(op),
-            Self::Modulus(ref op) => encoder.push(op),
-            Self::Exponent(ref op) => encoder.push(op),
-            Self::Sinc(ref op) => encoder.push(op),
-            Self::Sine(ref op) => encoder.push(op),
-            Self::Spiral(ref op) => encoder.push(op),
-            Self::Squircle(ref op) => encoder.push(op),
-        }
-    }
-
-    fn animate(&mut self) {
-        match self {
-            Self::Const(ref mut op) => op.animate(),
-            Self::Ellipse(ref mut op) => op.animate(),
-            Self::Flower(ref mut op) => op.animate(),
-            Self::LinearGradient(ref mut op) => op.animate(),
-            Self::RadialGradient(ref mut op) => op.animate(),
-            Self::PolarTheta(ref mut op) => op.animate(),
-            Self::Absolute(ref mut op) => op.animate(),
-            Self::Invert(ref mut op) => op.animate(),
-            Self::Add(ref mut op) => op.animate(),
-            Self::Subtract(ref mut op) => op.animate(),
-            Self::Multiply(ref mut op) => op.animate(),
-            Self::Divide(ref mut op) => op.animate(),
-            Self::Modulus(ref mut op) => op.animate(),
-            Self::Exponent(ref mut op) => op.animate(),
-            Self::Sinc(ref mut op) => op.animate(),
-            Self::Sine(ref mut op) => op.animate(),
-            Self::Spiral(ref mut op) => op.animate(),
-            Self::Squircle(ref mut op) => op.animate(),
-        }
-    }
-}
-
-#[derive(Debug)]
-pub struct Tree {
-    layers: [Node; 3],
+// Leaves more likely as the layer fills, regardless of depth. This is the
+// default, so a seed grows the same tree under it as long as the registered ops
+// are the same; it makes lopsided trees, with one deep branch and stubs for the
+// rest.
+pub struct FillingGrowth;
+
+impl GrowthStrategy for FillingGrowth {
+    fn is_leaf(&mut self, rng: &mut StdRng, site: &GrowthSite) -> bool {
+        let fullness = (site.count * 2) as f32 / INSTRUCTION_COUNT as f32;
+        rng.gen_range(0f32, 1f32) < fullness
+    }
 }
 
-impl Tree {
-    pub fn new(rng: &mut StdRng) -> Self {
-        Self {
-            layers: [
-                Node::new(rng, &mut 0, "r"),
-                Node::new(rng, &mut 0, "g"),
-                Node::new(rng, &mut 0, "b"),
-            ],
+// Every branch as deep as depth: no leaves above it, and only leaves there.
+pub struct FullGrowth {
+    pub depth: usize,
+}
+
+impl GrowthStrategy for FullGrowth {
+    fn is_leaf(&mut self, _rng: &mut StdRng, site: &GrowthSite) -> bool {
+        site.depth >= self.depth
+    }
+}
+
+// Branches stop at random, as often as leaves come up among all the ops by rate,
+// and at depth at the latest. The root always branches.
+pub struct GrowGrowth {
+    pub depth: usize,
+}
+
+impl GrowthStrategy for GrowGrowth {
+    fn is_leaf(&mut self, rng: &mut StdRng, site: &GrowthSite) -> bool {
+        if site.depth == 0 {
+            return false;
+        }
+        if site.depth >= self.depth {
+            return true;
         }
+        let ops = ops::registered();
+        let total = ops.iter().map(|op| op.rate).sum::<f32>();
+        let leaves = ops
+            .iter()
+            .filter(|op| op.is_leaf())
+            .map(|op| op.rate)
+            .sum::<f32>();
+        uniform(rng, 0f32, total) < leaves
     }
+}
+
+// Ramped half-and-half: each layer gets a depth from min_depth to max_depth and
+// is grown full or grow, one or the other at even odds, for a spread of shapes
+// and sizes.
+pub struct RampedGrowth {
+    pub min_depth: usize,
+    pub max_depth: usize,
+    full: bool,
+    depth: usize,
+}
 
-    pub fn with_layers(r: Node, g: Node, b: Node) -> Self {
-        Self { layers: [r, g, b] }
+impl RampedGrowth {
+    pub fn new(min_depth: usize, max_depth: usize) -> Self {
+        Self {
+            min_depth,
+            max_depth,
+            full: false,
+            depth: max_depth,
+        }
     }
+}
 
-    pub fn show(&self) -> String {
-        format!(
-            "red:\n{}\ngreen:\n{}\nblue:\n{}\n",
-            self.layers[0].show(0),
-            self.layers[1].show(0),
-            self.layers[2].show(0)
-        )
+impl GrowthStrategy for RampedGrowth {
+    fn begin_layer(&mut self, rng: &mut StdRng) {
+        self.depth = rng.gen_range(self.min_depth, self.max_depth + 1);
+        self.full = rng.gen();
     }
 
-    pub fn animate(&mut self) {
-        for layer in self.layers.iter_mut() {
-            layer.animate();
+    fn is_leaf(&mut self, rng: &mut StdRng, site: &GrowthSite) -> bool {
+        if self.full {
+            FullGrowth { depth: self.depth }.is_leaf(rng, site)
+        } else {
+            GrowGrowth { depth: self.depth }.is_leaf(rng, site)
         }
     }
+}
 
-    pub fn encode_upload_buffer(
-        &self,
-        offset: usize,
-        device: &wgpu::Device,
-    ) -> (wgpu::Buffer, wgpu::Buffer) {
+// Which strategy Tree::new grows with, by name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Growth {
+    Filling,
+    Full,
+    Grow,
+    Ramped,
+}
+
+impl Default for Growth {
+    fn default() -> Self {
+        Growth::Filling
+    }
+}
+
+impl FromStr for Growth {
+    type Err = StampedeError;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "filling" => Growth::Filling,
+            "full" => Growth::Full,
+            "grow" => Growth::Grow,
+            "ramped" => Growth::Ramped,
+            _ => bail!(
+                Invalid,
+                "unknown growth {}; expected filling, full, grow or ramped",
+                s
+            ),
+        })
+    }
+}
+
+impl Growth {
+    // Depths are kept low enough that even a full layer of binary ops fits.
+    pub fn strategy(self) -> Box<dyn GrowthStrategy> {
+        match self {
+            Growth::Filling => Box::new(FillingGrowth),
+            Growth::Full => Box::new(FullGrowth { depth: 5 }),
+            Growth::Grow => Box::new(GrowGrowth { depth: 5 }),
+            Growth::Ramped => Box::new(RampedGrowth::new(2, 5)),
+        }
+    }
+}
+
+type MakeStrategy = Box<dyn Fn() -> Box<dyn GrowthStrategy> + Send + Sync>;
+
+lazy_static! {
+    static ref GROWTH: RwLock<MakeStrategy> =
+        RwLock::new(Box::new(|| Growth::default().strategy()));
+}
+
+// Change how Tree::new grows trees. Like ops::set_rate, this must happen at
+// startup, before any trees are built, or the same seed will grow different trees.
+pub fn set_growth(growth: Growth) {
+    set_growth_strategy(move || growth.strategy());
+}
+
+// As set_growth, for strategies that are not one of the named ones, e.g. a grammar.
+// A fresh strategy is made for every tree.
+pub fn set_growth_strategy<F>(make: F)
+where
+    F: Fn() -> Box<dyn GrowthStrategy> + Send + Sync + 'static,
+{
+    *GROWTH.write().expect("growth lock") = Box::new(make);
+}
+
+// How Tree::new relates red, green and blue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channels {
+    // Each grown on its own.
+    Independent,
+    // Green and blue varied from red; see Tree::grow_coherent.
+    Coherent,
+    // A branch of red in green and blue as well; see Tree::grow_shared.
+    Shared,
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Channels::Independent
+    }
+}
+
+impl FromStr for Channels {
+    type Err = StampedeError;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "independent" => Channels::Independent,
+            "coherent" => Channels::Coherent,
+            "shared" => Channels::Shared,
+            _ => bail!(
+                Invalid,
+                "unknown channels {}; expected independent, coherent or shared",
+                s
+            ),
+        })
+    }
+}
+
+lazy_static! {
+    static ref CHANNELS: RwLock<Channels> = RwLock::new(Channels::default());
+}
+
+// As set_growth, this must happen before any trees are built.
+pub fn set_channels(channels: Channels) {
+    *CHANNELS.write().expect("channels lock") = channels;
+}
+
+static SIMPLIFY: AtomicBool = AtomicBool::new(false);
+
+// Have Tree::new simplify every tree that it grows. It is left off by default as
+// it moves constants around, so their paths and indices change.
+pub fn set_simplify(simplify: bool) {
+    SIMPLIFY.store(simplify, Ordering::Relaxed);
+}
+
+pub(crate) fn guided_random_walk(rng: &mut StdRng, leaf: bool) -> Fallible<&'static OpDescriptor> {
+    let candidates = ops::registered()
+        .into_iter()
+        .filter(|op| op.is_leaf() == leaf)
+        .collect::<Vec<_>>();
+    let total = candidates.iter().map(|op| op.rate).sum::<f32>();
+    if !(total > 0f32) {
+        bail!(
+            Growth,
+            "no {} ops with a rate above zero to grow from",
+            if leaf { "leaf" } else { "branch" }
+        );
+    }
+    let f = rng.gen_range(0f32, total);
+    let mut i = 0;
+    let mut acc = 0f32;
+    while acc <= f {
+        // Note that the interval is half open, so this will always be true.
+        acc += candidates[i].rate;
+        i += 1;
+    }
+    i -= 1; // Hence we can subtract safely here.
+    Ok(candidates[i])
+}
+
+impl TreeArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(
+        &mut self,
+        op: &OpDescriptor,
+        constants: Vec<Constant>,
+        children: &[NodeId],
+    ) -> NodeId {
+        debug_assert_eq!(constants.len(), op.constants.len());
+        debug_assert_eq!(children.len(), op.children.len());
+        self.nodes.push(Node {
+            opcode: op.opcode,
+            constants: (self.constants.len() as u32, constants.len() as u32),
+            children: (self.links.len() as u32, children.len() as u32),
+        });
+        self.constants.extend(constants);
+        self.links.extend_from_slice(children);
+        NodeId((self.nodes.len() - 1) as u32)
+    }
+
+    // Everything that the accessors below take on trust: that every id is of a
+    // node, every range is inside its vector, every op is known and has what it
+    // takes, and that children come before their parents, so there are no cycles.
+    fn validate(&self, roots: &[NodeId]) -> Fallible<()> {
+        let in_range = |(first, count): (u32, u32), len: usize| {
+            (first as usize)
+                .checked_add(count as usize)
+                .map(|end| end <= len)
+                .unwrap_or(false)
+        };
+        for (i, node) in self.nodes.iter().enumerate() {
+            let op = match OpDescriptor::find(node.opcode) {
+                Some(op) => op,
+                None => bail!(Encoding, "node {} has unknown opcode {}", i, node.opcode),
+            };
+            if !in_range(node.constants, self.constants.len())
+                || !in_range(node.children, self.links.len())
+            {
+                bail!(Encoding, "node {} reaches past the end of the arena", i);
+            }
+            if node.constants.1 as usize != op.constants.len()
+                || node.children.1 as usize != op.children.len()
+            {
+                bail!(
+                    Encoding,
+                    "node {}: {} takes {} constants and {} children, not {} and {}",
+                    i,
+                    op.name,
+                    op.constants.len(),
+                    op.children.len(),
+                    node.constants.1,
+                    node.children.1
+                );
+            }
+            if let Some(child) = self
+                .children(NodeId(i as u32))
+                .iter()
+                .find(|c| c.0 as usize >= i)
+            {
+                bail!(
+                    Encoding,
+                    "node {} has child {}, which is not before it",
+                    i,
+                    child.0
+                );
+            }
+        }
+        if let Some(root) = roots.iter().find(|r| r.0 as usize >= self.nodes.len()) {
+            bail!(Encoding, "there is no node {} for a layer", root.0);
+        }
+        Ok(())
+    }
+
+    pub fn op(&self, id: NodeId) -> &'static OpDescriptor {
+        OpDescriptor::find(self.nodes[id.0 as usize].opcode).expect("only known ops are stored")
+    }
+
+    pub fn constants(&self, id: NodeId) -> &[Constant] {
+        let (first, count) = self.nodes[id.0 as usize].constants;
+        &self.constants[first as usize..(first + count) as usize]
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        let (first, count) = self.nodes[id.0 as usize].children;
+        &self.links[first as usize..(first + count) as usize]
+    }
+
+    fn generate(
+        &mut self,
+        rng: &mut StdRng,
+        strategy: &mut dyn GrowthStrategy,
+        depth: usize,
+        count: &mut usize,
+        parent: Option<(usize, usize)>,
+    ) -> Fallible<NodeId> {
+        let site = GrowthSite {
+            depth,
+            count: *count,
+            parent,
+        };
+        *count += 1;
+        let leaf = strategy.is_leaf(rng, &site) || site.count * 2 >= INSTRUCTION_COUNT;
+        let op = strategy.choose_op(rng, &site, leaf)?;
+        let constants = op
+            .constants
+            .iter()
+            .map(|spec| Constant::new(rng, spec.bounds[0], spec.bounds[1], spec.wrap_mode))
+            .collect::<Fallible<Vec<_>>>()?;
+        let children = (0..op.children.len())
+            .map(|i| self.generate(rng, strategy, depth + 1, count, Some((op.opcode, i))))
+            .collect::<Fallible<Vec<_>>>()?;
+        Ok(self.push(op, constants, &children))
+    }
+
+    // A copy of the nodes under id, with every constant perturbed, and now and then
+    // a leaf rolled again.
+    fn vary(&mut self, rng: &mut StdRng, id: NodeId) -> Fallible<NodeId> {
+        let op = self.op(id);
+        if op.is_leaf() && rng.gen_range(0f32, 1f32) < COHERENT_LEAF_CHANCE {
+            let leaf = guided_random_walk(rng, true)?;
+            let constants = leaf
+                .constants
+                .iter()
+                .map(|spec| Constant::new(rng, spec.bounds[0], spec.bounds[1], spec.wrap_mode))
+                .collect::<Fallible<Vec<_>>>()?;
+            return Ok(self.push(leaf, constants, &[]));
+        }
+        let mut constants = self.constants(id).to_vec();
+        for constant in &mut constants {
+            constant.perturb(rng, COHERENT_SPREAD);
+        }
+        let children = self
+            .children(id)
+            .to_vec()
+            .into_iter()
+            .map(|child| self.vary(rng, child))
+            .collect::<Fallible<Vec<_>>>()?;
+        Ok(self.push(op, constants, &children))
+    }
+
+    fn show(&self, id: NodeId, level: usize, time: f32) -> String {
+        let l = level + 1;
+        let op = self.op(id);
+        let cc = self
+            .constants(id)
+            .iter()
+            .zip(op.constants)
+            .map(|(v, spec)| format!("{}={:0.2}", spec.name, v.value_at(time)))
+            .collect::<Vec<String>>()
+            .join(", ");
+        if op.is_leaf() {
+            format!("{}{}({})", prefix(l), op.name, cc)
+        } else {
+            let ch = self
+                .children(id)
+                .iter()
+                .map(|&c| self.show(c, l + 1, time))
+                .collect::<Vec<String>>()
+                .join("\n");
+            format!("{}{}({})-\n{}", prefix(l), op.name, cc, ch)
+        }
+    }
+
+    // A copy of the nodes under id into out, once each, however many parents they
+    // have here.
+    fn compact(&self, id: NodeId, out: &mut Self, done: &mut HashMap<NodeId, NodeId>) -> NodeId {
+        if let Some(&copy) = done.get(&id) {
+            return copy;
+        }
+        let children = self
+            .children(id)
+            .iter()
+            .map(|&c| self.compact(c, out, done))
+            .collect::<Vec<_>>();
+        let copy = out.push(self.op(id), self.constants(id).to_vec(), &children);
+        done.insert(id, copy);
+        copy
+    }
+
+    // Names every constant under id by the way down to it from the layer: the
+    // index of each child taken, then the constant's name, e.g. "r/1/0/size".
+    fn constant_paths(&self, id: NodeId, path: &str, paths: &mut [String]) {
+        let (first, _) = self.nodes[id.0 as usize].constants;
+        for (i, spec) in self.op(id).constants.iter().enumerate() {
+            paths[first as usize + i] = format!("{}/{}", path, spec.name);
+        }
+        for (i, &child) in self.children(id).iter().enumerate() {
+            self.constant_paths(child, &format!("{}/{}", path, i), paths);
+        }
+    }
+
+    fn node_values(
+        &self,
+        id: NodeId,
+        path: String,
+        depth: usize,
+        ctx: &EvalContext,
+        out: &mut Vec<NodeValue>,
+    ) {
+        out.push(NodeValue {
+            path: path.clone(),
+            depth,
+            op: self.op(id).name,
+            value: self.evaluate(id, ctx),
+        });
+        let child_ctx = ctx.remapped(self.op(id), self.constants(id));
+        for (i, &child) in self.children(id).iter().enumerate() {
+            self.node_values(child, format!("{}/{}", path, i), depth + 1, &child_ctx, out);
+        }
+    }
+
+    fn node_paths(&self, id: NodeId, path: String, out: &mut Vec<String>) {
+        for (i, &child) in self.children(id).iter().enumerate() {
+            self.node_paths(child, format!("{}/{}", path, i), out);
+        }
+        out.push(path);
+    }
+
+    // The value of a const that never moves, if id is one. Frozen constants only
+    // hold still until they are thawed.
+    fn fixed_value(&self, id: NodeId) -> Option<f32> {
+        match (self.op(id).name, self.constants(id)) {
+            ("const", [c]) if c.rate() == 0f32 && !c.is_frozen() => Some(c.value_at(0f32)),
+            _ => None,
+        }
+    }
+
+    fn push_fixed(&mut self, value: f32) -> Option<NodeId> {
+        let op = ops::registered()
+            .into_iter()
+            .find(|op| op.name == "const")?;
+        let spec = &op.constants[0];
+        let constant = Constant::with_value(
+            spec.bounds[0].min(value),
+            spec.bounds[1].max(value),
+            spec.wrap_mode,
+            value,
+            0f32,
+        )
+        .ok()?;
+        Some(self.push(op, vec![constant], &[]))
+    }
+
+    // Push op over children, which are already simplified, or something simpler
+    // that evaluates the same.
+    fn push_simplified(
+        &mut self,
+        op: &'static OpDescriptor,
+        constants: Vec<Constant>,
+        children: Vec<NodeId>,
+    ) -> NodeId {
+        let child_op = |arena: &Self, i: usize| arena.op(children[i]).name;
+        match op.name {
+            "invert" if child_op(self, 0) == "invert" => return self.children(children[0])[0],
+            "absolute" if child_op(self, 0) == "absolute" => return children[0],
+            "absolute" if child_op(self, 0) == "invert" => {
+                let inner = self.children(children[0])[0];
+                return self.push(op, constants, &[inner]);
+            }
+            "multiply" => {
+                let negligible = children.iter().any(|&c| {
+                    self.fixed_value(c)
+                        .map(|v| v.abs() < NEGLIGIBLE)
+                        .unwrap_or(false)
+                });
+                if negligible {
+                    if let Some(id) = self.push_fixed(0f32) {
+                        return id;
+                    }
+                }
+            }
+            _ => {}
+        }
+        let fixed = children
+            .iter()
+            .map(|&c| self.fixed_value(c))
+            .collect::<Option<Vec<_>>>();
+        let still = constants.iter().all(|c| c.rate() == 0f32 && !c.is_frozen());
+        if let (true, Some(values), true) = (PURE_OPS.contains(&op.name), fixed, still) {
+            let ctx = EvalContext {
+                position: [0f32, 0f32],
+                mouse: [0f32, 0f32],
+                time: 0f32,
+                draft: false,
+            };
+            let constants = constants
+                .iter()
+                .map(|c| c.value_at(0f32))
+                .collect::<Vec<_>>();
+            let value = (op.evaluate)(&constants, &values, &ctx);
+            if value.is_finite() {
+                if let Some(id) = self.push_fixed(value) {
+                    return id;
+                }
+            }
+        }
+        self.push(op, constants, &children)
+    }
+
+    // Copy the nodes under id into out, simplified; done remembers what has been
+    // copied so that shared branches stay shared.
+    fn simplify(&self, id: NodeId, out: &mut Self, done: &mut HashMap<NodeId, NodeId>) -> NodeId {
+        if let Some(&copy) = done.get(&id) {
+            return copy;
+        }
+        let children = self
+            .children(id)
+            .iter()
+            .map(|&c| self.simplify(c, out, done))
+            .collect::<Vec<_>>();
+        let copy = out.push_simplified(self.op(id), self.constants(id).to_vec(), children);
+        done.insert(id, copy);
+        copy
+    }
+
+    // The most nodes on any way down from id to a leaf, id and the leaf included.
+    fn depth(&self, id: NodeId) -> usize {
+        1 + self
+            .children(id)
+            .iter()
+            .map(|&c| self.depth(c))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn node_count(&self, id: NodeId) -> usize {
+        1 + self
+            .children(id)
+            .iter()
+            .map(|&c| self.node_count(c))
+            .sum::<usize>()
+    }
+
+    // The nodes under id written out so that trees that are effectively the same
+    // write the same: children of commutative ops in order, and constants by bucket.
+    fn canonical(&self, id: NodeId) -> String {
+        let op = self.op(id);
+        let constants = self
+            .constants(id)
+            .iter()
+            .map(Constant::canonical)
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut children = self
+            .children(id)
+            .iter()
+            .map(|&c| self.canonical(c))
+            .collect::<Vec<_>>();
+        if COMMUTATIVE_OPS.contains(&op.name) {
+            children.sort();
+        }
+        format!("{}({})[{}]", op.opcode, constants, children.join(","))
+    }
+
+    fn collect_nodes(&self, id: NodeId, nodes: &mut Vec<NodeId>) {
+        nodes.push(id);
+        for &child in self.children(id) {
+            self.collect_nodes(child, nodes);
+        }
+    }
+
+    fn collect_opcodes(&self, id: NodeId, opcodes: &mut Vec<usize>) {
+        let opcode = self.op(id).opcode;
+        if !opcodes.contains(&opcode) {
+            opcodes.push(opcode);
+        }
+        for &child in self.children(id) {
+            self.collect_opcodes(child, opcodes);
+        }
+    }
+
+    // A CPU port of interpret in include/interpreter.glsl, working from the tree
+    // rather than from the encoding, so that it can catch mistakes in either.
+    fn evaluate(&self, id: NodeId, ctx: &EvalContext) -> f32 {
+        let constants = self
+            .constants(id)
+            .iter()
+            .map(|c| c.value_at(ctx.time))
+            .collect::<Vec<_>>();
+        let child_ctx = ctx.remapped(self.op(id), self.constants(id));
+        let children = self
+            .children(id)
+            .iter()
+            .map(|&c| self.evaluate(c, &child_ctx))
+            .collect::<Vec<_>>();
+        (self.op(id).evaluate)(&constants, &children, ctx)
+    }
+}
+
+// What one node of a layer comes to at a point, for working out where a layer's
+// value comes from. Depth counts down from the layer, which is 0.
+#[derive(Clone, Debug)]
+pub struct NodeValue {
+    pub path: String,
+    pub depth: usize,
+    pub op: &'static str,
+    pub value: f32,
+}
+
+// Where and when to evaluate a tree on the CPU. Positions are on the plane, after
+// the view has been applied.
+#[derive(Clone)]
+pub struct EvalContext {
+    pub position: [f32; 2],
+    pub mouse: [f32; 2],
+    pub time: f32,
+    // As the interpreter's draft; see Quality.
+    pub draft: bool,
+}
+
+impl EvalContext {
+    // Where op's children are evaluated, if it remaps the plane under them.
+    fn remapped(&self, op: &OpDescriptor, constants: &[Constant]) -> Self {
+        match op.remap {
+            Some(remap) => {
+                let constants = constants
+                    .iter()
+                    .map(|c| c.value_at(self.time))
+                    .collect::<Vec<_>>();
+                Self {
+                    position: remap(&constants, self),
+                    ..self.clone()
+                }
+            }
+            None => self.clone(),
+        }
+    }
+}
+
+// Trees are checked as they are read, wherever they come from, since the arena's
+// accessors trust its ids and ranges; see TreeArena::validate.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedTree")]
+pub struct Tree {
+    arena: TreeArena,
+    layers: [NodeId; 3],
+
+    // Seconds of animation since the constants' base values.
+    #[serde(default)]
+    time: f32,
+}
+
+// A tree as it was read, before anything has looked inside it.
+#[derive(Deserialize)]
+struct UncheckedTree {
+    arena: TreeArena,
+    layers: [NodeId; 3],
+    #[serde(default)]
+    time: f32,
+}
+
+impl TryFrom<UncheckedTree> for Tree {
+    type Error = StampedeError;
+
+    fn try_from(tree: UncheckedTree) -> Fallible<Self> {
+        tree.arena.validate(&tree.layers)?;
+        Ok(Self {
+            arena: tree.arena,
+            layers: tree.layers,
+            time: tree.time,
+        })
+    }
+}
+
+impl Tree {
+    pub fn new(rng: &mut StdRng) -> Fallible<Self> {
+        let mut strategy = (GROWTH.read().expect("growth lock"))();
+        let mut tree = match *CHANNELS.read().expect("channels lock") {
+            Channels::Independent => Self::grow(rng, strategy.as_mut())?,
+            Channels::Coherent => Self::grow_coherent(rng, strategy.as_mut())?,
+            Channels::Shared => Self::grow_shared(rng, strategy.as_mut())?,
+        };
+        if SIMPLIFY.load(Ordering::Relaxed) {
+            tree.simplify();
+        }
+        Ok(tree)
+    }
+
+    // Red grown as usual, and one of its branches taken into green and blue, by
+    // reference, under a new root of their own with the rest grown around it. The
+    // shared branch gives the channels a common structure while the rest of each
+    // gives it different colors.
+    pub fn grow_shared(rng: &mut StdRng, strategy: &mut dyn GrowthStrategy) -> Fallible<Self> {
+        let mut arena = TreeArena::new();
+        strategy.begin_layer(rng);
+        let red = arena.generate(rng, strategy, 0, &mut 0, None)?;
+        let mut below = Vec::new();
+        for &child in arena.children(red) {
+            arena.collect_nodes(child, &mut below);
+        }
+        let branches = below
+            .iter()
+            .cloned()
+            .filter(|&id| !arena.op(id).is_leaf())
+            .collect::<Vec<_>>();
+        let shared = branches
+            .choose(rng)
+            .or_else(|| below.choose(rng))
+            .cloned()
+            .unwrap_or(red);
+        let mut layer = |arena: &mut TreeArena| -> Fallible<NodeId> {
+            strategy.begin_layer(rng);
+            let op = guided_random_walk(rng, false)?;
+            let constants = op
+                .constants
+                .iter()
+                .map(|spec| Constant::new(rng, spec.bounds[0], spec.bounds[1], spec.wrap_mode))
+                .collect::<Fallible<Vec<_>>>()?;
+            let slot = rng.gen_range(0, op.children.len());
+            let mut count = 1 + arena.node_count(shared);
+            let children = (0..op.children.len())
+                .map(|i| {
+                    if i == slot {
+                        Ok(shared)
+                    } else {
+                        arena.generate(rng, strategy, 1, &mut count, Some((op.opcode, i)))
+                    }
+                })
+                .collect::<Fallible<Vec<_>>>()?;
+            Ok(arena.push(op, constants, &children))
+        };
+        let green = layer(&mut arena)?;
+        let blue = layer(&mut arena)?;
+        Ok(Self {
+            arena,
+            layers: [red, green, blue],
+            time: 0f32,
+        })
+    }
+
+    // Red grown as usual, and green and blue varied from it: the same shape with
+    // constants nudged and the odd leaf swapped, so that the channels move together.
+    pub fn grow_coherent(rng: &mut StdRng, strategy: &mut dyn GrowthStrategy) -> Fallible<Self> {
+        let mut arena = TreeArena::new();
+        strategy.begin_layer(rng);
+        let red = arena.generate(rng, strategy, 0, &mut 0, None)?;
+        let green = arena.vary(rng, red)?;
+        let blue = arena.vary(rng, red)?;
+        Ok(Self {
+            arena,
+            layers: [red, green, blue],
+            time: 0f32,
+        })
+    }
+
+    pub fn grow(rng: &mut StdRng, strategy: &mut dyn GrowthStrategy) -> Fallible<Self> {
+        let mut arena = TreeArena::new();
+        let mut layer = |arena: &mut TreeArena| {
+            strategy.begin_layer(rng);
+            arena.generate(rng, strategy, 0, &mut 0, None)
+        };
+        let layers = [layer(&mut arena)?, layer(&mut arena)?, layer(&mut arena)?];
+        Ok(Self {
+            arena,
+            layers,
+            time: 0f32,
+        })
+    }
+
+    // A tree from nodes that have already been pushed into an arena, one root for
+    // each of red, green and blue. Nothing is checked here: outside of this crate,
+    // trees are written with NodeBuilder and Tree::build.
+    pub(crate) fn from_arena(arena: TreeArena, layers: [NodeId; 3]) -> Self {
+        Self {
+            arena,
+            layers,
+            time: 0f32,
+        }
+    }
+
+    pub fn arena(&self) -> &TreeArena {
+        &self.arena
+    }
+
+    // The roots of red, green and blue.
+    pub fn layers(&self) -> &[NodeId; 3] {
+        &self.layers
+    }
+
+    pub fn to_json(&self) -> Fallible<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    // Read unchecked first, so that a tree that does not hold together is an
+    // Encoding error rather than one of serde's.
+    pub fn from_json(s: &str) -> Fallible<Self> {
+        let tree = Self::try_from(serde_json::from_str::<UncheckedTree>(s)?)?;
+        tree.stats().check()?;
+        Ok(tree)
+    }
+
+    // Red, green and blue in canonical form; see TreeArena::canonical. Animation
+    // is from the base values, so the form stays the same as the tree plays.
+    pub fn canonical_form(&self) -> String {
+        self.layers
+            .iter()
+            .map(|&id| self.arena.canonical(id))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    // A stable hash of the canonical form, in hex, to tell when two trees are
+    // effectively the same.
+    pub fn canonical_hash(&self) -> String {
+        let mut hasher = Sha3_256::new();
+        hasher.input(self.canonical_form());
+        hasher.result()[..8]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    // Two words to know the tree by, from its canonical hash; see names.
+    pub fn name(&self) -> String {
+        names::name_for_hash(&self.canonical_hash())
+    }
+
+    pub fn show(&self) -> String {
+        format!(
+            "red:\n{}\ngreen:\n{}\nblue:\n{}\n",
+            self.arena.show(self.layers[0], 0, self.time),
+            self.arena.show(self.layers[1], 0, self.time),
+            self.arena.show(self.layers[2], 0, self.time)
+        )
+    }
+
+    // The depth of the deepest layer.
+    pub fn depth(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|&id| self.arena.depth(id))
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.layers.iter().map(|&l| self.arena.node_count(l)).sum()
+    }
+
+    // The op of every node in every layer, in no particular order.
+    pub fn ops(&self) -> Vec<&'static OpDescriptor> {
+        let mut ops = Vec::new();
+        let mut pending = self.layers.to_vec();
+        while let Some(id) = pending.pop() {
+            ops.push(self.arena.op(id));
+            pending.extend_from_slice(self.arena.children(id));
+        }
+        ops
+    }
+
+    // The nodes in the largest layer, which is the one that takes longest to
+    // compute.
+    pub fn largest_layer_node_count(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|&l| self.arena.node_count(l))
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    // Constants are numbered in the order that they are stored, which is the order
+    // that they are encoded in.
+    pub fn constant_count(&self) -> usize {
+        self.arena.constants.len()
+    }
+
+    fn constant_at_mut(&mut self, index: usize) -> Fallible<&mut Constant> {
+        let count = self.constant_count();
+        match self.arena.constants.get_mut(index) {
+            Some(constant) => Ok(constant),
+            None => bail!(Invalid, "no constant {}; the tree has {}", index, count),
+        }
+    }
+
+    pub fn set_constant(&mut self, index: usize, value: f32) -> Fallible<()> {
+        let time = self.time;
+        self.constant_at_mut(index)?.set_value_at(time, value);
+        Ok(())
+    }
+
+    // Hold one constant still, or let it go again, to see what it moves.
+    pub fn freeze_constant(&mut self, index: usize, frozen: bool) -> Fallible<()> {
+        let time = self.time;
+        let constant = self.constant_at_mut(index)?;
+        if frozen {
+            constant.freeze(time);
+        } else {
+            constant.thaw(time);
+        }
+        Ok(())
+    }
+
+    pub fn scrub_constant(&mut self, index: usize, phase: f32) -> Fallible<()> {
+        let time = self.time;
+        self.constant_at_mut(index)?.scrub(time, phase);
+        Ok(())
+    }
+
+    // The same shape with new values and rates for every moving constant, for
+    // variations on a tree.
+    pub fn reroll_constants(&mut self, rng: &mut StdRng) {
+        for constant in &mut self.arena.constants {
+            constant.reroll(rng);
+        }
+    }
+
+    // Every constant moved by up to spread of its range: a variation that keeps to
+    // the look of the tree, where reroll_constants starts over.
+    pub fn perturb_constants(&mut self, rng: &mut StdRng, spread: f32) {
+        for constant in &mut self.arena.constants {
+            constant.perturb(rng, spread);
+        }
+    }
+
+    // The node at a path like "r/1/0", a layer and then the index of each child
+    // taken on the way down, grown again from there down the way Tree::new would
+    // grow it, with the rest of the tree kept.
+    pub fn regrow(&mut self, rng: &mut StdRng, path: &str) -> Fallible<()> {
+        let mut parts = path.split('/');
+        let layer = parts
+            .next()
+            .and_then(|name| ["r", "g", "b"].iter().position(|&n| n == name));
+        let indices = parts
+            .map(|part| part.parse::<usize>().ok())
+            .collect::<Option<Vec<_>>>();
+        let (layer, indices) = match (layer, indices) {
+            (Some(layer), Some(indices)) => (layer, indices),
+            _ => bail!(Invalid, "{} is not a node path", path),
+        };
+        let mut chain = vec![self.layers[layer]];
+        for &index in &indices {
+            let parent = *chain.last().expect("a root");
+            match self.arena.children(parent).get(index) {
+                Some(&child) => chain.push(child),
+                None => bail!(Invalid, "there is no node at {}", path),
+            }
+        }
+        let old = chain.pop().expect("a node");
+        let parent = chain
+            .last()
+            .map(|&id| (self.arena.op(id).opcode, indices[indices.len() - 1]));
+
+        let mut arena = self.arena.clone();
+        let mut strategy = (GROWTH.read().expect("growth lock"))();
+        strategy.begin_layer(rng);
+        let mut count = arena.node_count(self.layers[layer]) - arena.node_count(old);
+        let mut id = arena.generate(rng, strategy.as_mut(), indices.len(), &mut count, parent)?;
+        for (&ancestor, &index) in chain.iter().zip(&indices).rev() {
+            let op = arena.op(ancestor);
+            let constants = arena.constants(ancestor).to_vec();
+            let mut children = arena.children(ancestor).to_vec();
+            children[index] = id;
+            id = arena.push(op, constants, &children);
+        }
+        let mut layers = self.layers;
+        layers[layer] = id;
+
+        // The nodes that were replaced are left behind.
+        let mut compacted = TreeArena::new();
+        let mut done = HashMap::new();
+        for layer in &mut layers {
+            *layer = arena.compact(*layer, &mut compacted, &mut done);
+        }
+        let tree = Self {
+            arena: compacted,
+            layers,
+            time: self.time,
+        };
+        tree.stats().check()?;
+        *self = tree;
+        Ok(())
+    }
+
+    // Every constant along with its path, for binding controls to the live tree.
+    // Constants are yielded in index order, as for set_constant. Edits to a
+    // constant's value or animation only reach the GPU when the tree is uploaded
+    // again.
+    pub fn constants_mut(&mut self) -> impl Iterator<Item = (String, &mut Constant)> + '_ {
+        let mut paths = vec![String::new(); self.arena.constants.len()];
+        for (&root, name) in self.layers.iter().zip(&["r", "g", "b"]) {
+            self.arena.constant_paths(root, name, &mut paths);
+        }
+        paths.into_iter().zip(self.arena.constants.iter_mut())
+    }
+
+    pub fn constant_mut(&mut self, path: &str) -> Option<&mut Constant> {
+        self.constants_mut()
+            .find(|(p, _)| p == path)
+            .map(|(_, constant)| constant)
+    }
+
+    // The node at a path like "r/1/0": a layer, then the index of each child taken
+    // on the way down, as in the paths of constants_mut.
+    pub fn node_at(&self, path: &str) -> Option<NodeId> {
+        let mut parts = path.split('/');
+        let name = parts.next()?;
+        let layer = ["r", "g", "b"].iter().position(|&n| n == name)?;
+        let mut id = self.layers[layer];
+        for part in parts {
+            let index = part.parse::<usize>().ok()?;
+            id = *self.arena.children(id).get(index)?;
+        }
+        Some(id)
+    }
+
+    pub fn evaluate_node(&self, id: NodeId, ctx: &EvalContext) -> f32 {
+        self.arena.evaluate(id, ctx)
+    }
+
+    // Squeeze the green layer, which is CIELAB's red-green axis, in toward gray,
+    // so that the picture is carried by lightness and blue-yellow instead. Those
+    // hold up for viewers without red or green cones.
+    pub fn narrow_red_green(&mut self) -> Fallible<()> {
+        let op = |name| {
+            ops::registered()
+                .into_iter()
+                .find(|op| op.name == name)
+                .ok_or_else(|| StampedeError::Invalid(format!("no op named {}", name)))
+        };
+        let (constant, multiply) = (op("const")?, op("multiply")?);
+        if self.arena.node_count(self.layers[1]) + 2 > INSTRUCTION_COUNT {
+            bail!(Encoding, "the green layer is too full to narrow");
+        }
+        let spec = &constant.constants[0];
+        let scale = self.arena.push(
+            constant,
+            vec![Constant::with_value(
+                spec.bounds[0],
+                spec.bounds[1],
+                spec.wrap_mode,
+                RED_GREEN_SAFE_SCALE,
+                0f32,
+            )?],
+            &[],
+        );
+        self.layers[1] = self
+            .arena
+            .push(multiply, Vec::new(), &[self.layers[1], scale]);
+        Ok(())
+    }
+
+    // Fold branches that can only ever make one value into a const, drop pairs of
+    // inverts and repeated absolutes, and prune whatever is multiplied by zero; the
+    // picture is the same, from fewer instructions.
+    pub fn simplify(&mut self) {
+        let mut simplified = TreeArena::new();
+        let mut done = HashMap::new();
+        for layer in &mut self.layers {
+            *layer = self.arena.simplify(*layer, &mut simplified, &mut done);
+        }
+        let mut compacted = TreeArena::new();
+        let mut done = HashMap::new();
+        for layer in &mut self.layers {
+            *layer = simplified.compact(*layer, &mut compacted, &mut done);
+        }
+        self.arena = compacted;
+    }
+
+    // Constants are animated on the GPU; all we need to track is the clock.
+    pub fn animate(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    pub fn evaluate(&self, offset: usize, ctx: &EvalContext) -> f32 {
+        self.arena.evaluate(self.layers[offset], ctx)
+    }
+
+    // The path, as for node_at, of every node of every layer, children before
+    // their parents, so that a picture is built up in the order they are listed.
+    pub fn node_paths(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        for (&root, name) in self.layers.iter().zip(&["r", "g", "b"]) {
+            self.arena.node_paths(root, (*name).to_owned(), &mut out);
+        }
+        out
+    }
+
+    // The node at path on its own, as the lightness of an otherwise gray tree, for
+    // seeing what each part of a tree makes.
+    pub fn node_alone(&self, path: &str) -> Option<Self> {
+        let id = self.node_at(path)?;
+        let mut arena = TreeArena::new();
+        let root = self.arena.compact(id, &mut arena, &mut HashMap::new());
+        let gray = arena.push_fixed(0f32)?;
+        Some(Self {
+            arena,
+            layers: [root, gray, gray],
+            time: self.time,
+        })
+    }
+
+    pub fn node_op(&self, path: &str) -> Option<&'static str> {
+        self.node_at(path).map(|id| self.arena.op(id).name)
+    }
+
+    // Every node of a layer, parents before their children, with its path as for
+    // node_at and what it evaluates to at ctx.
+    pub fn node_values(&self, offset: usize, ctx: &EvalContext) -> Vec<NodeValue> {
+        let mut out = Vec::new();
+        let name = ["r", "g", "b"][offset];
+        self.arena
+            .node_values(self.layers[offset], name.to_owned(), 0, ctx, &mut out);
+        out
+    }
+
+    // Every opcode that appears in a layer, once each.
+    pub fn opcodes(&self, offset: usize) -> Vec<usize> {
+        let mut opcodes = Vec::new();
+        self.arena
+            .collect_opcodes(self.layers[offset], &mut opcodes);
+        opcodes
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        for (layer, &root) in stats.layers.iter_mut().zip(&self.layers) {
+            let mut encoder = InstructionEncoder::new();
+            encoder.push(&self.arena, root);
+            *layer = encoder.stats();
+        }
+        stats
+    }
+
+    pub fn encode_layer(&self, offset: usize) -> Fallible<EncodedLayer> {
         let mut encoder = InstructionEncoder::new();
-        self.layers[offset].encode(&mut encoder);
-        let (mut instrs, consts) = encoder.finish();
+        encoder.push(&self.arena, self.layers[offset]);
+        if let Err(StampedeError::Encoding(e)) = encoder.stats().check() {
+            bail!(
+                Encoding,
+                "layer {} does not fit: {}",
+                ["r", "g", "b"][offset],
+                e
+            );
+        }
+        let (instrs, constant_pool) = encoder.finish();
+        Ok(EncodedLayer {
+            instrs,
+            constant_pool,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_round_trips() -> Fallible<()> {
+        for seed in 0..100 {
+            let tree = Tree::new(&mut StdRng::seed_from_u64(seed))?;
+            let decoded = InstructionEncoder::decode(&InstructionEncoder::encode(&tree)?)?;
+            assert_eq!(tree.to_json()?, decoded.to_json()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_json_trees_are_errors_not_panics() -> Fallible<()> {
+        let json = Tree::new(&mut StdRng::seed_from_u64(3))?.to_json()?;
+        let corrupt = |change: &dyn Fn(&mut serde_json::Value)| -> String {
+            let mut value: serde_json::Value = serde_json::from_str(&json).expect("json");
+            change(&mut value);
+            value.to_string()
+        };
+        let node_count =
+            |v: &serde_json::Value| v["arena"]["nodes"].as_array().expect("nodes").len();
+        let malformed = [
+            // A layer that is not a node.
+            corrupt(&|v| v["layers"][0] = (node_count(v) + 5).into()),
+            // A child that is its own parent.
+            corrupt(&|v| {
+                let parent = (0..node_count(v))
+                    .find(|&i| v["arena"]["nodes"][i]["children"][1] != 0)
+                    .expect("a node with children");
+                let link = v["arena"]["nodes"][parent]["children"][0]
+                    .as_u64()
+                    .expect("a link") as usize;
+                v["arena"]["links"][link] = parent.into();
+            }),
+            // An op that was never registered.
+            corrupt(&|v| v["arena"]["nodes"][0]["opcode"] = 200.into()),
+            // Constants past the end of the pool.
+            corrupt(&|v| v["arena"]["nodes"][0]["constants"][0] = 100_000.into()),
+            // The wrong number of constants for the op.
+            corrupt(&|v| v["arena"]["nodes"][0]["constants"][1] = 0.into()),
+        ];
+        for json in &malformed {
+            match Tree::from_json(json) {
+                Err(StampedeError::Encoding(_)) => {}
+                other => panic!("expected an encoding error, got {:?}", other),
+            }
+            assert!(serde_json::from_str::<Tree>(json).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn regrowing_a_node_keeps_the_rest_of_the_tree() -> Fallible<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut changed = 0;
+        for seed in 0..20 {
+            let tree = Tree::new(&mut StdRng::seed_from_u64(seed))?;
+            let path = if tree.arena.children(tree.layers[0]).is_empty() {
+                "r"
+            } else {
+                "r/0"
+            };
+            let mut regrown = tree.clone();
+            regrown.regrow(&mut rng, path)?;
+            regrown.stats().check()?;
+            for offset in 1..3 {
+                assert_eq!(
+                    tree.arena.show(tree.layers[offset], 0, 0f32),
+                    regrown.arena.show(regrown.layers[offset], 0, 0f32)
+                );
+            }
+            if tree.show() != regrown.show() {
+                changed += 1;
+            }
+            assert!(regrown.regrow(&mut rng, "r/9/9/9/9/9/9").is_err());
+            assert!(regrown.regrow(&mut rng, "x").is_err());
+        }
+        assert!(changed > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn full_growth_reaches_its_depth_everywhere() -> Fallible<()> {
+        fn leaf_depths(tree: &Tree, id: NodeId, depth: usize, depths: &mut Vec<usize>) {
+            if tree.arena.op(id).is_leaf() {
+                depths.push(depth);
+            }
+            for &child in tree.arena.children(id) {
+                leaf_depths(tree, child, depth + 1, depths);
+            }
+        }
+        for seed in 0..20 {
+            let tree = Tree::grow(
+                &mut StdRng::seed_from_u64(seed),
+                &mut FullGrowth { depth: 4 },
+            )?;
+            let mut depths = Vec::new();
+            leaf_depths(&tree, tree.layers[0], 0, &mut depths);
+            assert!(depths.iter().all(|&depth| depth == 4));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn coherent_layers_share_a_shape() -> Fallible<()> {
+        for seed in 0..20 {
+            let tree = Tree::grow_coherent(&mut StdRng::seed_from_u64(seed), &mut FillingGrowth)?;
+            let counts = tree
+                .layers
+                .iter()
+                .map(|&id| tree.arena.node_count(id))
+                .collect::<Vec<_>>();
+            assert_eq!(counts[0], counts[1]);
+            assert_eq!(counts[0], counts[2]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shared_branches_are_shared() -> Fallible<()> {
+        for seed in 0..20 {
+            let tree = Tree::grow_shared(&mut StdRng::seed_from_u64(seed), &mut FillingGrowth)?;
+            let mut red = Vec::new();
+            tree.arena.collect_nodes(tree.layers[0], &mut red);
+            for &layer in &tree.layers[1..] {
+                assert!(tree.arena.children(layer).iter().any(|id| red.contains(id)));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn simplify_folds_and_prunes() {
+        let op = |name| {
+            ops::registered()
+                .into_iter()
+                .find(|op| op.name == name)
+                .expect("a builtin op")
+        };
+        let fixed =
+            |value| Constant::with_value(-1f32, 1f32, "m", value, 0f32).expect("a wrap mode");
+        let mut arena = TreeArena::new();
+        let a = arena.push(op("const"), vec![fixed(0.25)], &[]);
+        let b = arena.push(op("const"), vec![fixed(0.5)], &[]);
+        let sum = arena.push(op("add"), Vec::new(), &[a, b]);
+        let shape = arena.push(op("mouse"), vec![fixed(1f32), fixed(2f32)], &[]);
+        let once = arena.push(op("invert"), Vec::new(), &[shape]);
+        let twice = arena.push(op("invert"), Vec::new(), &[once]);
+        let zero = arena.push(op("const"), vec![fixed(0f32)], &[]);
+        let product = arena.push(op("multiply"), Vec::new(), &[twice, zero]);
+        let mut tree = Tree::from_arena(arena, [sum, twice, product]);
+        tree.simplify();
+        assert_eq!(tree.arena.nodes.len(), 3);
+        assert_eq!(tree.arena.fixed_value(tree.layers[0]), Some(0.75));
+        assert_eq!(tree.arena.op(tree.layers[1]).name, "mouse");
+        assert_eq!(tree.arena.fixed_value(tree.layers[2]), Some(0f32));
+    }
+
+    #[test]
+    fn canonical_hash_ignores_operand_order() {
+        let op = |name| {
+            ops::registered()
+                .into_iter()
+                .find(|op| op.name == name)
+                .expect("a builtin op")
+        };
+        let fixed =
+            |value| Constant::with_value(-1f32, 1f32, "m", value, 0f32).expect("a wrap mode");
+        let tree = |swap: bool, value: f32| {
+            let mut arena = TreeArena::new();
+            let a = arena.push(op("const"), vec![fixed(value)], &[]);
+            let b = arena.push(op("mouse"), vec![fixed(1f32), fixed(2f32)], &[]);
+            let children = if swap { [b, a] } else { [a, b] };
+            let sum = arena.push(op("add"), Vec::new(), &children);
+            Tree::from_arena(arena, [sum, sum, sum])
+        };
+        assert_eq!(
+            tree(false, 0.5).canonical_hash(),
+            tree(true, 0.501).canonical_hash()
+        );
+        assert_ne!(
+            tree(false, 0.5).canonical_hash(),
+            tree(false, -0.5).canonical_hash()
+        );
+    }
+
+    #[test]
+    fn constant_paths_are_unique() -> Fallible<()> {
+        let mut tree = Tree::new(&mut StdRng::seed_from_u64(0))?;
+        let count = tree.constant_count();
+        let mut paths = tree.constants_mut().map(|(p, _)| p).collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), count);
+        for path in paths {
+            assert!(tree.constant_mut(&path).is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn empty_ranges_hold_their_low_limit() -> Fallible<()> {
+        for &mode in &["r", "m"] {
+            let mut c = Constant::with_value(-1f32, 1f32, mode, 0.5, 0.25)?;
+            c.set_limits(2f32, [0.75, 0.75]);
+            for &time in &[0f32, 1f32, 3.5f32, -2f32] {
+                assert_eq!(c.value_at(time), 0.75);
+            }
+            assert_eq!(c.phase_at(1f32), 0f32);
+            c.scrub(1f32, 0.5);
+            assert_eq!(c.value_at(1f32), 0.75);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn nodes_are_found_by_path() -> Fallible<()> {
+        let tree = Tree::new(&mut StdRng::seed_from_u64(0))?;
+        assert_eq!(tree.node_at("g"), Some(tree.layers[1]));
+        assert!(tree.node_at("x").is_none());
+        assert!(tree.node_at("r/99").is_none());
+        let ctx = EvalContext {
+            position: [0.25, 0.5],
+            mouse: [0.5, 0.5],
+            time: 1f32,
+            draft: false,
+        };
+        let root = tree.node_at("b").expect("a layer");
+        // Bitwise, as a divide may have made a NaN.
+        assert_eq!(
+            tree.evaluate_node(root, &ctx).to_bits(),
+            tree.evaluate(2, &ctx).to_bits()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn node_values_follow_the_paths() -> Fallible<()> {
+        let tree = Tree::new(&mut StdRng::seed_from_u64(0))?;
+        let ctx = EvalContext {
+            position: [0.25, -0.5],
+            mouse: [0.5, 0.5],
+            time: 1f32,
+            draft: false,
+        };
+        for offset in 0..3 {
+            let values = tree.node_values(offset, &ctx);
+            assert_eq!(values[0].depth, 0);
+            assert_eq!(
+                values[0].value.to_bits(),
+                tree.evaluate(offset, &ctx).to_bits()
+            );
+            for value in &values {
+                let id = tree.node_at(&value.path).expect("a node");
+                assert_eq!(value.op, tree.arena.op(id).name);
+                assert_eq!(value.depth, value.path.matches('/').count());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn drafts_only_change_layers_that_warp() -> Fallible<()> {
+        let warp = ops::registered()
+            .iter()
+            .find(|op| op.name == "warp")
+            .expect("warp")
+            .opcode;
+        let mut differed = 0;
+        for seed in 0..50 {
+            let tree = Tree::new(&mut StdRng::seed_from_u64(seed))?;
+            for offset in 0..3 {
+                let mut opcodes = Vec::new();
+                tree.arena
+                    .collect_opcodes(tree.layers[offset], &mut opcodes);
+                for i in 0..16 {
+                    let full = EvalContext {
+                        position: [(i % 4) as f32 / 2.0 - 0.75, (i / 4) as f32 / 2.0 - 0.75],
+                        mouse: [0.5, 0.5],
+                        time: 1f32,
+                        draft: false,
+                    };
+                    let draft = EvalContext {
+                        draft: true,
+                        ..full.clone()
+                    };
+                    let (a, b) = (tree.evaluate(offset, &full), tree.evaluate(offset, &draft));
+                    if opcodes.contains(&warp) {
+                        if (a - b).abs() > 1e-5 {
+                            differed += 1;
+                        }
+                    } else {
+                        assert!((a.is_nan() && b.is_nan()) || a == b, "seed {}", seed);
+                    }
+                }
+            }
+        }
+        assert!(differed > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn traces_end_where_the_tree_does() -> Fallible<()> {
+        let ctx = EvalContext {
+            position: [0.5, 0.75],
+            mouse: [0.5, 0.5],
+            time: 3f32,
+            draft: false,
+        };
+        for seed in 0..20 {
+            let tree = Tree::new(&mut StdRng::seed_from_u64(seed))?;
+            let encoded = InstructionEncoder::encode(&tree)?;
+            for offset in 0..3 {
+                let steps = encoded.layer(offset).trace(&ctx)?;
+                let last = steps.last().expect("a step");
+                assert_eq!(last.stack.len(), 1);
+                let (traced, walked) = (last.stack[0], tree.evaluate(offset, &ctx));
+                assert!(
+                    (traced.is_nan() && walked.is_nan()) || (traced - walked).abs() < 1e-5,
+                    "seed {} layer {}: {} traced, {} walked",
+                    seed,
+                    offset,
+                    traced,
+                    walked
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn nodes_stand_alone() -> Fallible<()> {
+        let tree = Tree::new(&mut StdRng::seed_from_u64(0))?;
+        let ctx = EvalContext {
+            position: [-0.25, 0.5],
+            mouse: [0.5, 0.5],
+            time: 1f32,
+            draft: false,
+        };
+        let paths = tree.node_paths();
+        assert_eq!(paths.len(), tree.node_count());
+        assert_eq!(paths.last().map(String::as_str), Some("b"));
+        let alone = tree.node_alone("g").expect("a layer");
+        assert_eq!(
+            alone.evaluate(0, &ctx).to_bits(),
+            tree.evaluate(1, &ctx).to_bits()
+        );
+        assert_eq!(alone.evaluate(1, &ctx), 0f32);
+        assert!(InstructionEncoder::decode(&InstructionEncoder::encode(&alone)?).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn narrowing_scales_the_green_layer() -> Fallible<()> {
+        let mut tree = Tree::new(&mut StdRng::seed_from_u64(0))?;
+        let ctx = EvalContext {
+            position: [-0.5, 0.25],
+            mouse: [0.5, 0.5],
+            time: 2f32,
+            draft: false,
+        };
+        let before = tree.evaluate(1, &ctx);
+        tree.narrow_red_green()?;
+        let after = tree.evaluate(1, &ctx);
+        assert!(before.is_nan() || (after - before * RED_GREEN_SAFE_SCALE).abs() < 1e-6);
+        assert!(InstructionEncoder::decode(&InstructionEncoder::encode(&tree)?).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_constants_hold_still() -> Fallible<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut constant = Constant::new(&mut rng, -1f32, 1f32, "m")?;
+        constant.scrub(2f32, 0.25);
+        assert!((constant.phase_at(2f32) - 0.25).abs() < 1e-4);
+        let value = constant.value_at(2f32);
+        constant.freeze(2f32);
+        assert!((constant.value_at(7f32) - value).abs() < 1e-4);
+        constant.thaw(7f32);
+        assert!((constant.value_at(7f32) - value).abs() < 1e-4);
+        assert!(!constant.is_frozen());
+        Ok(())
+    }
+
+    #[test]
+    fn shared_layers_start_on_aligned_offsets() {
+        for stride in &[
+            InstructionEncoder::instruction_layer_stride(),
+            InstructionEncoder::pool_layer_stride(),
+        ] {
+            assert_eq!(stride % BINDING_ALIGNMENT, 0);
+        }
+        assert!(
+            InstructionEncoder::instruction_layer_stride()
+                >= InstructionEncoder::instruction_buffer_size()
+        );
+        let mut runs = Vec::new();
+        for &i in &[0, 1, 2, 5, 7, 8] {
+            extend_runs(&mut runs, i);
+        }
+        assert_eq!(runs, vec![(0, 3), (5, 1), (7, 2)]);
+    }
+
+    #[test]
+    fn bad_constants_are_errors_not_panics() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(Constant::new(&mut rng, -1f32, 1f32, "x").is_err());
+        let pinned = Constant::new(&mut rng, 1f32, 1f32, "m").expect("an empty range");
+        assert_eq!(pinned.value_at(0f32), 1f32);
+    }
+
+    #[test]
+    fn other_versions_are_refused() -> Fallible<()> {
+        let mut encoded = InstructionEncoder::encode(&Tree::new(&mut StdRng::seed_from_u64(0))?)?;
+        assert!(InstructionEncoder::decode(&encoded).is_ok());
+        encoded.layers[1].instrs[0] = ENCODING_VERSION + 1;
+        assert!(InstructionEncoder::decode(&encoded).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encoded_bytes_round_trip() -> Fallible<()> {
+        let tree = Tree::new(&mut StdRng::seed_from_u64(0))?;
+        let bytes = InstructionEncoder::encode(&tree)?.to_bytes();
+        let decoded = InstructionEncoder::decode(&EncodedTree::from_bytes(&bytes)?)?;
+        assert_eq!(tree.to_json()?, decoded.to_json()?);
+        assert!(EncodedTree::from_bytes(&bytes[1..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decoding_garbage_does_not_panic() -> Fallible<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let mut encoded = InstructionEncoder::encode(&Tree::new(&mut rng)?)?;
+            let i = rng.gen_range(0, INSTRUCTION_BUFFER_LEN);
+            encoded.layers[0].instrs[i] = rng.gen();
+            let _ = InstructionEncoder::decode(&encoded);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn layer_mirror_copies_only_what_changed() -> Fallible<()> {
+        let tree = Tree::new(&mut StdRng::seed_from_u64(0))?;
+        let layers = (0..3)
+            .map(|offset| tree.encode_layer(offset))
+            .collect::<Fallible<Vec<_>>>()?;
+        let pool_slots = LayerMirror::pool_slots();
+        let mut mirror = LayerMirror::new(3);
 
-        let instr_buffer = device
-            .create_buffer_mapped(instrs.len(), wgpu::BufferUsage::COPY_SRC)
-            .fill_from_slice(&instrs);
+        // Nothing is there yet, so every layer goes up, each in one run.
+        let (instr_runs, pool_runs) = mirror.changes(&layers);
+        assert_eq!(instr_runs, vec![(0, 3)]);
+        let uploaded = (0..3)
+            .flat_map(|i| i * pool_slots..i * pool_slots + CONSTANT_POOL_SIZE)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pool_runs
+                .iter()
+                .flat_map(|&(start, count)| start..start + count)
+                .collect::<Vec<_>>(),
+            uploaded
+        );
+        mirror.uploaded = layers.iter().cloned().map(Some).collect();
+        assert_eq!(mirror.changes(&layers), (vec![], vec![]));
 
-        let const_buffer = device
-            .create_buffer_mapped(consts.len(), wgpu::BufferUsage::all())
-            .fill_from_slice(&consts);
+        // One constant in layer 1 is one slot, at that layer's stride.
+        let j = 5;
+        let mut changed = layers.clone();
+        changed[1].constant_pool[j][0] += 1f32;
+        assert_eq!(
+            mirror.changes(&changed),
+            (vec![], vec![(pool_slots + j, 1)])
+        );
 
-        (instr_buffer, const_buffer)
+        // Neighboring layers whose instructions changed copy as one.
+        let mut changed = layers.clone();
+        changed[0].instrs[INSTRUCTION_BUFFER_LEN - 1] ^= 1;
+        changed[1].instrs[INSTRUCTION_BUFFER_LEN - 1] ^= 1;
+        assert_eq!(mirror.changes(&changed), (vec![(0, 2)], vec![]));
+        Ok(())
     }
 }
@@ -0,0 +1,189 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use serde::{Deserialize, Serialize};
+
+// How much a single notch of the scroll wheel zooms.
+const ZOOM_STEP: f32 = 0.9;
+
+// How long the camera takes to move between waypoints of a path.
+const SEGMENT_SECONDS: f32 = 4.0;
+
+// Must match texture_to_position in include/interpreter.glsl.
+fn extent(aspect_ratio: f32) -> [f32; 2] {
+    if aspect_ratio >= 1f32 {
+        [1f32, 1f32 / aspect_ratio]
+    } else {
+        [aspect_ratio, 1f32]
+    }
+}
+
+// Where on the plane we are looking. Positions are scaled and then offset before
+// the tree gets evaluated, so the default view shows the usual [-1,1] window.
+#[derive(Debug)]
+pub struct View {
+    center: [f32; 2],
+    scale: f32,
+    drag_from: Option<[f32; 2]>,
+}
+
+impl View {
+    pub fn new() -> Self {
+        Self {
+            center: [0f32, 0f32],
+            scale: 1f32,
+            drag_from: None,
+        }
+    }
+
+    pub fn center(&self) -> [f32; 2] {
+        self.center
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn reset(&mut self) {
+        self.center = [0f32, 0f32];
+        self.scale = 1f32;
+    }
+
+    pub fn waypoint(&self) -> Waypoint {
+        Waypoint {
+            center: self.center,
+            scale: self.scale,
+        }
+    }
+
+    pub fn go_to(&mut self, waypoint: &Waypoint) {
+        self.center = waypoint.center;
+        self.scale = waypoint.scale;
+    }
+
+    // Put the point under the cursor in the middle of the screen.
+    pub fn recenter(&mut self, uv: [f32; 2], aspect_ratio: f32) {
+        self.center = self.position(uv, aspect_ratio);
+    }
+
+    // Map a [0,1] texture coordinate onto the plane.
+    pub fn position(&self, uv: [f32; 2], aspect_ratio: f32) -> [f32; 2] {
+        let e = extent(aspect_ratio);
+        [
+            (uv[0] * 2f32 - 1f32) * e[0] * self.scale + self.center[0],
+            (uv[1] * 2f32 - 1f32) * e[1] * self.scale + self.center[1],
+        ]
+    }
+
+    pub fn begin_drag(&mut self, uv: [f32; 2]) {
+        self.drag_from = Some(uv);
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag_from.is_some()
+    }
+
+    pub fn end_drag(&mut self) {
+        self.drag_from = None;
+    }
+
+    // Keep the point under the cursor under the cursor.
+    pub fn drag_to(&mut self, uv: [f32; 2], aspect_ratio: f32) {
+        if let Some(from) = self.drag_from {
+            let e = extent(aspect_ratio);
+            self.center[0] -= (uv[0] - from[0]) * 2f32 * e[0] * self.scale;
+            self.center[1] -= (uv[1] - from[1]) * 2f32 * e[1] * self.scale;
+            self.drag_from = Some(uv);
+        }
+    }
+
+    // Zoom in for positive steps, around the point under the cursor.
+    pub fn zoom(&mut self, steps: f32, uv: [f32; 2], aspect_ratio: f32) {
+        let anchor = self.position(uv, aspect_ratio);
+        self.scale *= ZOOM_STEP.powf(steps);
+        let moved = self.position(uv, aspect_ratio);
+        self.center[0] += anchor[0] - moved[0];
+        self.center[1] += anchor[1] - moved[1];
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Waypoint {
+    center: [f32; 2],
+    scale: f32,
+}
+
+// A tour of bookmarked views that the camera can fly through.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ViewPath {
+    waypoints: Vec<Waypoint>,
+
+    // Seconds since the start of the tour, while playing.
+    #[serde(skip)]
+    playhead: Option<f32>,
+}
+
+impl ViewPath {
+    // Returns the number of waypoints now on the path.
+    pub fn push(&mut self, waypoint: Waypoint) -> usize {
+        self.waypoints.push(waypoint);
+        self.waypoints.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.waypoints.clear();
+        self.playhead = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playhead.is_some()
+    }
+
+    pub fn toggle_playback(&mut self) {
+        self.playhead = match self.playhead {
+            Some(_) => None,
+            None if self.waypoints.len() >= 2 => Some(0f32),
+            None => None,
+        };
+    }
+
+    pub fn advance(&mut self, dt: f32, view: &mut View) {
+        if let Some(playhead) = self.playhead {
+            let playhead = playhead + dt;
+            let duration = (self.waypoints.len() - 1) as f32 * SEGMENT_SECONDS;
+            if playhead >= duration {
+                view.go_to(&self.waypoints[self.waypoints.len() - 1]);
+                self.playhead = None;
+            } else {
+                view.go_to(&self.sample(playhead));
+                self.playhead = Some(playhead);
+            }
+        }
+    }
+
+    // Ease in and out of each waypoint. Scale is interpolated logarithmically so
+    // that deep zooms proceed at a constant apparent speed.
+    fn sample(&self, t: f32) -> Waypoint {
+        let segment = (t / SEGMENT_SECONDS) as usize;
+        let f = t / SEGMENT_SECONDS - segment as f32;
+        let f = f * f * (3f32 - 2f32 * f);
+        let a = &self.waypoints[segment];
+        let b = &self.waypoints[segment + 1];
+        let mix = |x: f32, y: f32| x + (y - x) * f;
+        Waypoint {
+            center: [mix(a.center[0], b.center[0]), mix(a.center[1], b.center[1])],
+            scale: mix(a.scale.ln(), b.scale.ln()).exp(),
+        }
+    }
+}
@@ -0,0 +1,243 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{
+    compute::{self, Configuration},
+    error::Fallible,
+    workgroup::Interpreter,
+};
+use gpu::GPU;
+use wgpu;
+
+// Must match volume.frag.glsl.
+const VOLUME_SIDE: u32 = 96;
+const VOLUME_SLICES: u32 = 64;
+
+// The slices are stacked from top to bottom in a single texture.
+const ATLAS_EXTENT: wgpu::Extent3d = wgpu::Extent3d {
+    width: VOLUME_SIDE,
+    height: VOLUME_SIDE * VOLUME_SLICES,
+    depth: 1,
+};
+
+// The tree computed over a cube rather than a plane, with depth as a third
+// coordinate, and drawn as a cloud; see the sphere op.
+pub struct Volume {
+    config_buffer: wgpu::Buffer,
+    // One for each layer, interpreting it into its own atlas.
+    layer_bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Volume {
+    // The interpreter's layout and format, and the display's configuration, which
+    // the raymarcher takes the time and aspect ratio from. The volume reads the
+    // tree from the display's instruction and pool buffers, which hold all three
+    // layers.
+    pub fn new(
+        gpu: &GPU,
+        interpreter_layout: &wgpu::BindGroupLayout,
+        layer_format: wgpu::TextureFormat,
+        display_config_buffer: &wgpu::Buffer,
+        instr_buffer: &wgpu::Buffer,
+        pool_buffer: &wgpu::Buffer,
+        reaction_view: &wgpu::TextureView,
+    ) -> Fallible<Self> {
+        let config_buffer = Configuration::default().create_buffer(gpu.device());
+        let mut atlas_views = Vec::new();
+        let layer_bind_groups = (0..3)
+            .map(|i| {
+                let texture = gpu.device().create_texture(&wgpu::TextureDescriptor {
+                    size: ATLAS_EXTENT,
+                    array_layer_count: 1,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: layer_format,
+                    usage: wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::SAMPLED,
+                });
+                let bind_group = compute::create_bind_group(
+                    gpu,
+                    interpreter_layout,
+                    &config_buffer,
+                    &texture.create_default_view(),
+                    instr_buffer,
+                    pool_buffer,
+                    i,
+                    reaction_view,
+                );
+                atlas_views.push(texture.create_default_view());
+                bind_group
+            })
+            .collect::<Vec<_>>();
+
+        let sampler = gpu.device().create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0f32,
+            lod_max_clamp: 0f32,
+            compare_function: wgpu::CompareFunction::Never,
+        });
+        let texture_binding = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        let layout = gpu
+            .device()
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                    },
+                    texture_binding(1),
+                    texture_binding(2),
+                    texture_binding(3),
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 4,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler,
+                    },
+                ],
+            });
+        let bind_group = gpu.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: display_config_buffer,
+                        range: 0..Configuration::buffer_size(),
+                    },
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_views[0]),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&atlas_views[1]),
+                },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&atlas_views[2]),
+                },
+                wgpu::Binding {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let vert_shader =
+            gpu.create_shader_module(include_bytes!("../target/volume.vert.spirv"))?;
+        let frag_shader =
+            gpu.create_shader_module(include_bytes!("../target/volume.frag.spirv"))?;
+        let pipeline = gpu
+            .device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[&layout],
+                    }),
+                vertex_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &vert_shader,
+                    entry_point: "main",
+                },
+                fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                    module: &frag_shader,
+                    entry_point: "main",
+                }),
+                rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::None,
+                    depth_bias: 0,
+                    depth_bias_slope_scale: 0.0,
+                    depth_bias_clamp: 0.0,
+                }),
+                primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+                color_states: &[wgpu::ColorStateDescriptor {
+                    format: GPU::texture_format(),
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                    format: GPU::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                    stencil_read_mask: 0,
+                    stencil_write_mask: 0,
+                }),
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[],
+                sample_count: gpu.sample_count(),
+                sample_mask: !0,
+                alpha_to_coverage_enabled: false,
+            });
+
+        Ok(Self {
+            config_buffer,
+            layer_bind_groups,
+            pipeline,
+            bind_group,
+        })
+    }
+
+    // Compute every slice of every layer, at the display's time and view.
+    pub fn compute(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        interpreter: &Interpreter,
+        display_config: &Configuration,
+    ) {
+        let config = Configuration {
+            texture_size: [ATLAS_EXTENT.width, ATLAS_EXTENT.height],
+            texture_offsets: [0, 0],
+            aspect_ratio: 1f32,
+            volume_slices: VOLUME_SLICES,
+            block_size: 1,
+            pass_stride: 1,
+            pass_offset: [0, 0],
+            ..*display_config
+        };
+        config.upload(device, encoder, &self.config_buffer);
+        for bind_group in &self.layer_bind_groups {
+            let mut cpass = encoder.begin_compute_pass();
+            cpass.set_pipeline(interpreter.pipeline());
+            cpass.set_bind_group(0, bind_group, &[]);
+            interpreter.dispatch(&mut cpass, ATLAS_EXTENT);
+        }
+    }
+
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
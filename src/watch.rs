@@ -0,0 +1,192 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{export, open_tree, scene::Scene};
+use anyhow::{bail, Result};
+use gpu::GPU;
+use stampede::{compute::Quality, render::OffscreenRenderer, tree::Tree};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// A file's size and modification time, as of a look at the directory.
+type Stamp = (u64, SystemTime);
+
+// Watches dir for trees and scenes saved as json, or PNGs exported with their
+// tree, and renders each to out: one frame to <name>.png, or more to numbered
+// frames in <name>/, or with video, encoded by ffmpeg into <name>.mp4. A file is
+// only picked up once it has stopped changing for a whole poll, so that it is not
+// read half-copied, and again whenever it changes.
+// Files whose results are newer than they are were rendered by an earlier run and
+// are left alone. Runs until interrupted.
+pub fn run(
+    gpu: &mut GPU,
+    dir: &Path,
+    out: &Path,
+    frame_count: usize,
+    fps: f32,
+    video: bool,
+    extent: wgpu::Extent3d,
+) -> Result<()> {
+    if frame_count == 0 {
+        bail!("there must be at least one frame to render");
+    }
+    if video && frame_count < 2 {
+        bail!("a video needs more than one frame");
+    }
+    fs::create_dir_all(out)?;
+    if fs::canonicalize(dir)? == fs::canonicalize(out)? {
+        bail!("the results must go somewhere other than the watched directory");
+    }
+    // The results are for a quick look while curating; what is kept can be exported
+    // at full quality.
+    let renderer = OffscreenRenderer::new(gpu)?.with_quality(Quality::Draft);
+    let mut last_seen = HashMap::new();
+    // The modification time that each file was last rendered, or failed, at.
+    let mut handled: HashMap<PathBuf, SystemTime> = HashMap::new();
+    println!("watching {} for trees", dir.display());
+    loop {
+        let seen = look(dir)?;
+        for (path, stamp) in &seen {
+            if last_seen.get(path) != Some(stamp) || handled.get(path) == Some(&stamp.1) {
+                continue;
+            }
+            handled.insert(path.clone(), stamp.1);
+            let result = result_path(path, out, frame_count, video);
+            if is_newer(&result, stamp.1) {
+                continue;
+            }
+            let rendered = load(path).and_then(|scene| {
+                if frame_count == 1 {
+                    export::write_frame(gpu, &renderer, &scene, None, extent, &result)
+                } else if video {
+                    let frames = result.with_extension("frames");
+                    export::run(
+                        gpu,
+                        scene,
+                        None,
+                        frame_count,
+                        fps,
+                        extent,
+                        Quality::Draft,
+                        &frames,
+                    )?;
+                    encode_video(&frames, fps, &result)?;
+                    Ok(fs::remove_dir_all(&frames)?)
+                } else {
+                    let frames = result.parent().expect("a frame directory");
+                    export::run(
+                        gpu,
+                        scene,
+                        None,
+                        frame_count,
+                        fps,
+                        extent,
+                        Quality::Draft,
+                        frames,
+                    )
+                }
+            });
+            match rendered {
+                Ok(()) => println!("rendered {} to {}", path.display(), result.display()),
+                Err(e) => println!("failed to render {}: {}", path.display(), e),
+            }
+        }
+        last_seen = seen;
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn look(dir: &Path) -> Result<HashMap<PathBuf, Stamp>> {
+    let mut seen = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if extension(&path).is_none() {
+            continue;
+        }
+        // Files can vanish between listing the directory and looking at them.
+        if let Ok(metadata) = fs::metadata(&path) {
+            if metadata.is_file() {
+                seen.insert(path, (metadata.len(), metadata.modified()?));
+            }
+        }
+    }
+    Ok(seen)
+}
+
+// The extension, if the file is one that we render.
+fn extension(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "json" | "png" => Some(extension),
+        _ => None,
+    }
+}
+
+// Json is either a single tree or a scene.
+fn load(path: &Path) -> Result<Scene> {
+    if extension(path).as_deref() == Some("png") {
+        return Ok(Scene::single(open_tree(path)?.1));
+    }
+    match Tree::from_json(&fs::read_to_string(path)?) {
+        Ok(tree) => Ok(Scene::single(tree)),
+        Err(_) => Scene::load(path),
+    }
+}
+
+// Encodes the numbered frames in frames into an H.264 video that most players
+// can show.
+fn encode_video(frames: &Path, fps: f32, out: &Path) -> Result<()> {
+    let status = Command::new("ffmpeg")
+        .arg("-y")
+        .args(&["-loglevel", "error"])
+        .args(&["-framerate", &fps.to_string()])
+        .arg("-i")
+        .arg(frames.join("frame_%05d.png"))
+        .args(&["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+        .arg(out)
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => bail!("ffmpeg failed: {}", status),
+        Err(e) => bail!("could not run ffmpeg: {}", e),
+    }
+}
+
+// The file written last for path.
+fn result_path(path: &Path, out: &Path, frame_count: usize, video: bool) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    if frame_count == 1 {
+        out.join(format!("{}.png", stem.to_string_lossy()))
+    } else if video {
+        out.join(format!("{}.mp4", stem.to_string_lossy()))
+    } else {
+        out.join(stem)
+            .join(format!("frame_{:05}.png", frame_count - 1))
+    }
+}
+
+fn is_newer(path: &Path, than: SystemTime) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| modified >= than)
+        .unwrap_or(false)
+}
@@ -0,0 +1,211 @@
+// This file is part of Stampede.
+//
+// Stampede is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Stampede is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Stampede.  If not, see <http://www.gnu.org/licenses/>.
+use crate::{compute::OffscreenLayer, error::Fallible, tree::Tree};
+use gpu::GPU;
+use rand::prelude::*;
+use std::time::{Duration, Instant};
+use wgpu;
+
+// The interpreter is built once per candidate size, since wgpu cannot specialize
+// the workgroup size at pipeline creation.
+struct Candidate {
+    size: [u32; 2],
+    spirv: &'static [u8],
+    spirv_f16: &'static [u8],
+}
+
+const CANDIDATES: [Candidate; 4] = [
+    Candidate {
+        size: [8, 8],
+        spirv: include_bytes!("../target/uni_shader.comp.spirv"),
+        spirv_f16: include_bytes!("../target/uni_shader_f16.comp.spirv"),
+    },
+    Candidate {
+        size: [16, 8],
+        spirv: include_bytes!("../target/uni_shader_16x8.comp.spirv"),
+        spirv_f16: include_bytes!("../target/uni_shader_f16_16x8.comp.spirv"),
+    },
+    Candidate {
+        size: [16, 16],
+        spirv: include_bytes!("../target/uni_shader_16x16.comp.spirv"),
+        spirv_f16: include_bytes!("../target/uni_shader_f16_16x16.comp.spirv"),
+    },
+    Candidate {
+        size: [32, 8],
+        spirv: include_bytes!("../target/uni_shader_32x8.comp.spirv"),
+        spirv_f16: include_bytes!("../target/uni_shader_f16_32x8.comp.spirv"),
+    },
+];
+
+// A fixed tree, so that every candidate gets timed on the same work.
+const TUNING_SEED: u64 = 0x5354_414d_5045_4445;
+const TUNING_DISPATCHES: usize = 8;
+
+// The interpreter pipeline, built for one workgroup size.
+pub struct Interpreter {
+    pipeline: wgpu::ComputePipeline,
+    size: [u32; 2],
+}
+
+impl Interpreter {
+    // Accepts sizes like "16x8"; only the sizes we ship shaders for are valid.
+    pub fn parse_size(s: &str) -> Fallible<[u32; 2]> {
+        for candidate in &CANDIDATES {
+            if s == format!("{}x{}", candidate.size[0], candidate.size[1]) {
+                return Ok(candidate.size);
+            }
+        }
+        let sizes = CANDIDATES
+            .iter()
+            .map(|c| format!("{}x{}", c.size[0], c.size[1]))
+            .collect::<Vec<_>>();
+        bail!(
+            Invalid,
+            "unknown workgroup size {}; expected one of {}",
+            s,
+            sizes.join(", ")
+        )
+    }
+
+    pub fn new(
+        gpu: &GPU,
+        layout: &wgpu::BindGroupLayout,
+        size: [u32; 2],
+        half: bool,
+    ) -> Fallible<Self> {
+        let candidate = match CANDIDATES.iter().find(|c| c.size == size) {
+            Some(candidate) => candidate,
+            None => bail!(
+                Invalid,
+                "no interpreter built for workgroup size {:?}",
+                size
+            ),
+        };
+        let module = gpu.create_shader_module(if half {
+            candidate.spirv_f16
+        } else {
+            candidate.spirv
+        })?;
+        let pipeline = gpu
+            .device()
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                layout: &gpu
+                    .device()
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        bind_group_layouts: &[layout],
+                    }),
+                compute_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &module,
+                    entry_point: "main",
+                },
+            });
+        Ok(Self { pipeline, size })
+    }
+
+    // Time every candidate on this GPU and keep the fastest; the best size varies
+    // a great deal between vendors.
+    pub fn tune(
+        gpu: &mut GPU,
+        layout: &wgpu::BindGroupLayout,
+        config_buffer: &wgpu::Buffer,
+        format: wgpu::TextureFormat,
+        extent: wgpu::Extent3d,
+        half: bool,
+    ) -> Fallible<Self> {
+        let tree = Tree::new(&mut StdRng::seed_from_u64(TUNING_SEED))?;
+        let mut layer = OffscreenLayer::new(gpu, layout, config_buffer, format, extent);
+        let mut encoder = gpu
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        layer.update(tree.encode_layer(0)?, gpu.device(), &mut encoder);
+        gpu.queue_mut().submit(&[encoder.finish()]);
+
+        let mut best: Option<(Duration, Self)> = None;
+        for candidate in &CANDIDATES {
+            let interpreter = Self::new(gpu, layout, candidate.size, half)?;
+            // The first dispatch pays for any lazy pipeline compilation in the driver.
+            let mut elapsed = Duration::from_secs(0);
+            for &count in &[1, TUNING_DISPATCHES] {
+                let mut encoder = gpu
+                    .device()
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+                for _ in 0..count {
+                    let mut cpass = encoder.begin_compute_pass();
+                    cpass.set_pipeline(interpreter.pipeline());
+                    cpass.set_bind_group(0, layer.bind_group(), &[]);
+                    interpreter.dispatch(&mut cpass, extent);
+                }
+                let start = Instant::now();
+                gpu.queue_mut().submit(&[encoder.finish()]);
+                gpu.device().poll(true);
+                elapsed = start.elapsed();
+            }
+            if best.as_ref().map(|(t, _)| elapsed < *t).unwrap_or(true) {
+                best = Some((elapsed, interpreter));
+            }
+        }
+        Ok(best.expect("at least one candidate").1)
+    }
+
+    // Use the requested size if there is one, otherwise measure.
+    pub fn select(
+        gpu: &mut GPU,
+        layout: &wgpu::BindGroupLayout,
+        config_buffer: &wgpu::Buffer,
+        format: wgpu::TextureFormat,
+        extent: wgpu::Extent3d,
+        half: bool,
+        requested: Option<&str>,
+    ) -> Fallible<Self> {
+        if let Some(size) = requested {
+            return Self::new(gpu, layout, Self::parse_size(size)?, half);
+        }
+        let interpreter = Self::tune(gpu, layout, config_buffer, format, extent, half)?;
+        println!(
+            "workgroup size: {}x{}",
+            interpreter.size[0], interpreter.size[1]
+        );
+        Ok(interpreter)
+    }
+
+    pub fn pipeline(&self) -> &wgpu::ComputePipeline {
+        &self.pipeline
+    }
+
+    // Enough invocations to cover extent; the groups on the right and bottom edges
+    // may hang over, and the shader skips texels outside the texture.
+    pub fn dispatch(&self, cpass: &mut wgpu::ComputePass, extent: wgpu::Extent3d) {
+        self.dispatch_strided(cpass, extent, 1);
+    }
+
+    // Enough invocations for one per stride texels on each side, the last of which
+    // may hang over the edges; see Configuration::pass_stride.
+    pub fn dispatch_strided(
+        &self,
+        cpass: &mut wgpu::ComputePass,
+        extent: wgpu::Extent3d,
+        stride: u32,
+    ) {
+        let groups = |side: u32, group: u32| {
+            let blocks = (side + stride - 1) / stride;
+            (blocks + group - 1) / group
+        };
+        cpass.dispatch(
+            groups(extent.width, self.size[0]),
+            groups(extent.height, self.size[1]),
+            1,
+        );
+    }
+}